@@ -0,0 +1,307 @@
+use std::collections::BTreeMap;
+
+use stable_fs::fs::Fd;
+
+#[cfg(feature = "access_rules")]
+use stable_fs::storage::types::FileType;
+
+#[cfg(feature = "access_rules")]
+use crate::{ic_is_controller, ic_msg_caller, wasi, ACCESS_POLICIES, FD_PATHS, FS};
+
+/// Kind of access checked by the policies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    /// Opening files for reading and linking them.
+    Read,
+    /// Opening files for writing, creating, renaming and removing entries.
+    Write,
+    /// Opening and reading directories.
+    List,
+}
+
+/// Principals allowed to perform an operation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Principals {
+    Anyone,
+    Controllers,
+    /// The listed principals in their textual form.
+    Only(Vec<String>),
+    Nobody,
+}
+
+impl Principals {
+    pub fn allows(&self, caller: &str, is_controller: impl FnOnce() -> bool) -> bool {
+        match self {
+            Principals::Anyone => true,
+            Principals::Controllers => is_controller(),
+            Principals::Only(principals) => principals.iter().any(|p| p == caller),
+            Principals::Nobody => false,
+        }
+    }
+}
+
+/// Access rule of a directory subtree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccessRule {
+    pub read: Principals,
+    pub write: Principals,
+    pub list: Principals,
+}
+
+impl AccessRule {
+    /// Anyone can read and list, only the given principals can write.
+    pub fn writable_by(principals: Principals) -> AccessRule {
+        AccessRule {
+            read: Principals::Anyone,
+            write: principals,
+            list: Principals::Anyone,
+        }
+    }
+
+    pub fn principals(&self, access: Access) -> &Principals {
+        match access {
+            Access::Read => &self.read,
+            Access::Write => &self.write,
+            Access::List => &self.list,
+        }
+    }
+}
+
+/// Policy table mapping the path prefixes to the access rules.
+///
+/// The rule with the longest prefix matching the whole path components applies,
+/// paths without a matching rule are not restricted.
+#[derive(Default)]
+pub struct AccessPolicies {
+    rules: BTreeMap<String, AccessRule>,
+}
+
+impl AccessPolicies {
+    pub fn new() -> AccessPolicies {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn set_rule(&mut self, prefix: &str, rule: AccessRule) {
+        self.rules.insert(normalize_path("", prefix), rule);
+    }
+
+    pub fn remove_rule(&mut self, prefix: &str) -> bool {
+        self.rules.remove(&normalize_path("", prefix)).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.rules.clear();
+    }
+
+    // Find the rule of the path relative to the root.
+    pub fn rule(&self, path: &str) -> Option<&AccessRule> {
+        self.rules
+            .iter()
+            .filter(|(prefix, _)| is_prefix(prefix, path))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, rule)| rule)
+    }
+}
+
+/// Paths of the opened descriptors relative to the root, needed to resolve the paths opened relative to them.
+#[derive(Default)]
+pub struct FdPaths {
+    paths: BTreeMap<Fd, String>,
+}
+
+impl FdPaths {
+    pub fn new() -> FdPaths {
+        Self::default()
+    }
+
+    // Resolve the path opened relative to the descriptor, `None` if the descriptor path is unknown.
+    pub fn resolve(&self, root_fd: Fd, fd: Fd, path: &str) -> Option<String> {
+        let base = if fd == root_fd {
+            ""
+        } else {
+            self.paths.get(&fd)?
+        };
+
+        Some(normalize_path(base, path))
+    }
+
+    pub fn open_fd(&mut self, fd: Fd, path: String) {
+        self.paths.insert(fd, path);
+    }
+
+    pub fn close_fd(&mut self, fd: Fd) {
+        self.paths.remove(&fd);
+    }
+
+    pub fn renumber_fd(&mut self, from: Fd, to: Fd) {
+        match self.paths.remove(&from) {
+            Some(path) => self.paths.insert(to, path),
+            None => self.paths.remove(&to),
+        };
+    }
+
+    // Move the paths of the descriptors opened at or under the renamed entry.
+    pub fn rename(&mut self, old_path: &str, new_path: &str) {
+        for path in self.paths.values_mut() {
+            if !old_path.is_empty() && is_prefix(old_path, path) {
+                *path = format!("{new_path}{}", &path[old_path.len()..]);
+            }
+        }
+    }
+}
+
+/// Restrict the access to the paths starting with `prefix` (relative to the file system root) depending on the caller.
+/// The rule with the longest matching prefix applies, the violating `path_open`, `fd_readdir`, `path_create_directory`,
+/// `path_link`, `path_remove_directory`, `path_rename` and `path_unlink_file` calls return `ERRNO_ACCES`.
+#[cfg(feature = "access_rules")]
+pub fn set_access_rule(prefix: &str, rule: AccessRule) {
+    ACCESS_POLICIES.with_borrow_mut(|policies| policies.set_rule(prefix, rule))
+}
+
+/// Remove the access rule of the prefix
+#[cfg(feature = "access_rules")]
+pub fn remove_access_rule(prefix: &str) {
+    ACCESS_POLICIES.with_borrow_mut(|policies| policies.remove_rule(prefix));
+}
+
+/// Remove all the access rules
+#[cfg(feature = "access_rules")]
+pub fn clear_access_rules() {
+    ACCESS_POLICIES.with_borrow_mut(|policies| policies.clear())
+}
+
+// Paths opened relative to descriptors with unknown paths are denied.
+#[cfg(feature = "access_rules")]
+pub(crate) fn is_access_allowed(fd: Fd, path: &str, access: Access) -> bool {
+    let root_fd = FS.with_borrow(|fs| fs.root_fd());
+
+    let Some(path) = FD_PATHS.with_borrow(|paths| paths.resolve(root_fd, fd, path)) else {
+        return false;
+    };
+
+    ACCESS_POLICIES.with_borrow(|policies| match policies.rule(&path) {
+        Some(rule) => rule
+            .principals(access)
+            .allows(&ic_msg_caller(), ic_is_controller),
+        None => true,
+    })
+}
+
+// Kind of access requested by `path_open`, opening an existing directory lists it even without `OFLAGS_DIRECTORY`.
+#[cfg(feature = "access_rules")]
+pub(crate) fn open_access(fd: Fd, path: &str, oflags: i32, rights_base: wasi::Rights) -> Access {
+    if oflags & (wasi::OFLAGS_CREAT | wasi::OFLAGS_TRUNC) as i32 != 0
+        || rights_base & wasi::RIGHTS_FD_WRITE != 0
+    {
+        return Access::Write;
+    }
+
+    let is_dir = oflags & wasi::OFLAGS_DIRECTORY as i32 != 0
+        || FS.with_borrow_mut(|fs| {
+            fs.open_metadata(fd, path)
+                .is_ok_and(|metadata| metadata.file_type == FileType::Directory)
+        });
+
+    if is_dir {
+        Access::List
+    } else {
+        Access::Read
+    }
+}
+
+// Join the paths and resolve `.` and `..` the same way the file system does, the result never goes above the root.
+fn normalize_path(base: &str, path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+
+    for part in base.split('/').chain(path.split('/')) {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+
+    parts.join("/")
+}
+
+fn is_prefix(prefix: &str, path: &str) -> bool {
+    prefix.is_empty()
+        || path == prefix
+        || (path.starts_with(prefix) && path.as_bytes()[prefix.len()] == b'/')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize_path, AccessPolicies, AccessRule, FdPaths, Principals};
+
+    #[test]
+    fn longest_prefix_rule_applies() {
+        let mut policies = AccessPolicies::new();
+        policies.set_rule("/config/", AccessRule::writable_by(Principals::Controllers));
+        policies.set_rule("config/public", AccessRule::writable_by(Principals::Anyone));
+
+        assert_eq!(policies.rule("data/file.txt"), None);
+        assert_eq!(policies.rule("configs/file.txt"), None);
+        assert_eq!(
+            policies.rule("config/file.txt").unwrap().write,
+            Principals::Controllers
+        );
+        assert_eq!(
+            policies.rule("config").unwrap().write,
+            Principals::Controllers
+        );
+        assert_eq!(
+            policies.rule("config/public/file.txt").unwrap().write,
+            Principals::Anyone
+        );
+
+        assert!(policies.remove_rule("config/public"));
+        assert_eq!(
+            policies.rule("config/public/file.txt").unwrap().write,
+            Principals::Controllers
+        );
+    }
+
+    #[test]
+    fn resolves_relative_paths() {
+        let mut paths = FdPaths::new();
+        paths.open_fd(5, "data".to_string());
+
+        assert_eq!(paths.resolve(3, 3, "./a/../b"), Some("b".to_string()));
+        assert_eq!(
+            paths.resolve(3, 5, "../config/x"),
+            Some("config/x".to_string())
+        );
+        assert_eq!(paths.resolve(3, 6, "x"), None);
+
+        paths.renumber_fd(5, 6);
+        assert_eq!(paths.resolve(3, 6, "x"), Some("data/x".to_string()));
+        paths.close_fd(6);
+        assert_eq!(paths.resolve(3, 6, "x"), None);
+
+        paths.open_fd(7, "data/dir".to_string());
+        paths.open_fd(8, "data/dir/sub".to_string());
+        paths.open_fd(9, "data/directory".to_string());
+        paths.rename("data/dir", "moved");
+        assert_eq!(paths.resolve(3, 7, "x"), Some("moved/x".to_string()));
+        assert_eq!(paths.resolve(3, 8, "x"), Some("moved/sub/x".to_string()));
+        assert_eq!(
+            paths.resolve(3, 9, "x"),
+            Some("data/directory/x".to_string())
+        );
+
+        assert_eq!(normalize_path("", "../../x"), "x");
+
+        let only = Principals::Only(vec!["aaaaa-aa".to_string()]);
+        assert!(only.allows("aaaaa-aa", || false));
+        assert!(!only.allows("2vxsx-fae", || true));
+        assert!(Principals::Controllers.allows("2vxsx-fae", || true));
+        assert!(!Principals::Nobody.allows("aaaaa-aa", || true));
+    }
+}
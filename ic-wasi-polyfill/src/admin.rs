@@ -0,0 +1,380 @@
+use crate::wasi_helpers::into_errno;
+use crate::*;
+use candid::{CandidType, Deserialize};
+use stable_fs::storage::types::FileType;
+
+/// Largest range read or written by a single call, below the message size limit.
+pub const MAX_TRANSFER_SIZE: u64 = 2_000_000;
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    Other,
+}
+
+impl EntryKind {
+    fn from_filetype(filetype: wasi::Filetype) -> EntryKind {
+        if filetype == wasi::FILETYPE_REGULAR_FILE {
+            EntryKind::File
+        } else if filetype == wasi::FILETYPE_DIRECTORY {
+            EntryKind::Directory
+        } else {
+            EntryKind::Other
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AdminEntry {
+    pub name: String,
+    pub kind: EntryKind,
+    pub size: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AdminStat {
+    pub kind: EntryKind,
+    pub size: u64,
+    pub link_count: u64,
+    /// Times in nanoseconds since the epoch
+    pub accessed: u64,
+    pub modified: u64,
+    pub created: u64,
+}
+
+impl AdminStat {
+    fn from_filestat(stat: &wasi::Filestat) -> AdminStat {
+        AdminStat {
+            kind: EntryKind::from_filetype(stat.filetype),
+            size: stat.size,
+            link_count: stat.nlink,
+            accessed: stat.atim,
+            modified: stat.mtim,
+            created: stat.ctim,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct AdminUsage {
+    pub files: u64,
+    pub directories: u64,
+    /// Sum of the file sizes, the files with several links are counted once per link
+    pub bytes: u64,
+}
+
+/// Guard allowing only the canister controllers, used by `export_admin_interface!` by default.
+pub fn caller_is_controller() -> Result<(), String> {
+    if ic_is_controller() {
+        Ok(())
+    } else {
+        Err("the caller is not a controller of the canister".to_string())
+    }
+}
+
+fn error(operation: &str, errno: i32) -> String {
+    format!("{operation} failed with errno {errno}")
+}
+
+fn check(operation: &str, errno: i32) -> Result<(), String> {
+    if errno == wasi::ERRNO_SUCCESS.raw() as i32 {
+        Ok(())
+    } else {
+        Err(error(operation, errno))
+    }
+}
+
+fn open_dir(path: &str) -> Result<OwnedFd, String> {
+    OwnedFd::open_root_dir(path).map_err(|errno| error("opening the directory", errno))
+}
+
+fn open_parent(path: &str) -> Result<(OwnedFd, &str), String> {
+    OwnedFd::open_parent(path).map_err(|errno| error("opening the parent directory", errno))
+}
+
+fn open_file(path: &str, oflags: i32, rights: wasi::Rights) -> Result<OwnedFd, String> {
+    let (dir, name) = open_parent(path)?;
+
+    let mut fd = 0;
+    check("path_open", unsafe {
+        __ic_custom_path_open(
+            dir.0,
+            0,
+            name.as_ptr(),
+            name.len() as i32,
+            oflags,
+            rights,
+            0,
+            0,
+            &mut fd,
+        )
+    })?;
+
+    Ok(OwnedFd(fd))
+}
+
+fn empty_filestat() -> wasi::Filestat {
+    wasi::Filestat {
+        dev: 0,
+        ino: 0,
+        filetype: wasi::FILETYPE_UNKNOWN,
+        nlink: 0,
+        size: 0,
+        atim: 0,
+        mtim: 0,
+        ctim: 0,
+    }
+}
+
+fn stat_at(dir: &OwnedFd, name: &str) -> Result<AdminStat, String> {
+    let mut stat = empty_filestat();
+
+    check("path_filestat_get", unsafe {
+        __ic_custom_path_filestat_get(dir.0 as i32, 0, name.as_ptr(), name.len() as i32, &mut stat)
+    })?;
+
+    Ok(AdminStat::from_filestat(&stat))
+}
+
+/// List a directory relative to the file system root, the entries are sorted by name
+pub fn list_dir(path: &str) -> Result<Vec<AdminEntry>, String> {
+    let dir = open_dir(path)?;
+
+    let mut names = Vec::new();
+    FS.with_borrow(|fs| {
+        fs.with_direntries(dir.0, Some(0), &mut |_, entry| {
+            let name = &entry.name.bytes[..entry.name.length as usize];
+            names.push(String::from_utf8_lossy(name).into_owned());
+            true
+        })
+    })
+    .map_err(|err| error("reading the directory", into_errno(err)))?;
+
+    names.sort();
+
+    names
+        .into_iter()
+        .map(|name| {
+            let stat = stat_at(&dir, &name)?;
+
+            Ok(AdminEntry {
+                name,
+                kind: stat.kind,
+                size: stat.size,
+            })
+        })
+        .collect()
+}
+
+/// Get the type, size, link count and times of a file or a directory
+pub fn stat(path: &str) -> Result<AdminStat, String> {
+    if path.trim_matches('/').is_empty() {
+        let dir = open_dir("")?;
+        let mut stat = empty_filestat();
+
+        check("fd_filestat_get", unsafe {
+            __ic_custom_fd_filestat_get(dir.0, &mut stat)
+        })?;
+
+        return Ok(AdminStat::from_filestat(&stat));
+    }
+
+    let (dir, name) = open_parent(path)?;
+    stat_at(&dir, name)
+}
+
+/// Read up to `len` bytes of a file from the offset
+pub fn read_range(path: &str, offset: u64, len: u64) -> Result<Vec<u8>, String> {
+    if len > MAX_TRANSFER_SIZE {
+        return Err(format!(
+            "at most {MAX_TRANSFER_SIZE} bytes can be read at once"
+        ));
+    }
+
+    let file = open_file(path, 0, wasi::RIGHTS_FD_READ)?;
+
+    let mut data = vec![0u8; len as usize];
+    let dst = [wasi::Iovec {
+        buf: data.as_mut_ptr(),
+        buf_len: data.len(),
+    }];
+    let mut read = 0;
+
+    check("fd_pread", unsafe {
+        __ic_custom_fd_pread(file.0, dst.as_ptr(), 1, offset as i64, &mut read)
+    })?;
+
+    data.truncate(read);
+
+    Ok(data)
+}
+
+/// Write the data to a file at the offset, the file is created if it does not exist. Returns the number of written bytes.
+pub fn write_range(path: &str, offset: u64, data: &[u8]) -> Result<u64, String> {
+    if data.len() as u64 > MAX_TRANSFER_SIZE {
+        return Err(format!(
+            "at most {MAX_TRANSFER_SIZE} bytes can be written at once"
+        ));
+    }
+
+    let file = open_file(
+        path,
+        wasi::OFLAGS_CREAT as i32,
+        wasi::RIGHTS_FD_READ | wasi::RIGHTS_FD_WRITE,
+    )?;
+
+    let src = [wasi::Ciovec {
+        buf: data.as_ptr(),
+        buf_len: data.len(),
+    }];
+    let mut written = 0;
+
+    check("fd_pwrite", unsafe {
+        __ic_custom_fd_pwrite(file.0, src.as_ptr(), 1, offset as i64, &mut written)
+    })?;
+
+    Ok(written as u64)
+}
+
+/// Delete a file or an empty directory
+pub fn delete(path: &str) -> Result<(), String> {
+    let (dir, name) = open_parent(path)?;
+
+    if stat_at(&dir, name)?.kind == EntryKind::Directory {
+        check("path_remove_directory", unsafe {
+            __ic_custom_path_remove_directory(dir.0, name.as_ptr(), name.len() as i32)
+        })
+    } else {
+        check("path_unlink_file", unsafe {
+            __ic_custom_path_unlink_file(dir.0 as i32, name.as_ptr(), name.len() as i32)
+        })
+    }
+}
+
+/// Create a directory, its parent has to exist
+pub fn mkdir(path: &str) -> Result<(), String> {
+    let (dir, name) = open_parent(path)?;
+
+    check("path_create_directory", unsafe {
+        __ic_custom_path_create_directory(dir.0, name.as_ptr(), name.len() as i32)
+    })
+}
+
+/// Rename or move a file or a directory
+pub fn rename(from: &str, to: &str) -> Result<(), String> {
+    let (from_dir, from_name) = open_parent(from)?;
+    let (to_dir, to_name) = open_parent(to)?;
+
+    check("path_rename", unsafe {
+        __ic_custom_path_rename(
+            from_dir.0 as i32,
+            from_name.as_ptr(),
+            from_name.len() as i32,
+            to_dir.0 as i32,
+            to_name.as_ptr(),
+            to_name.len() as i32,
+        )
+    })
+}
+
+/// Count the files and directories reachable from the root and sum the file sizes
+pub fn usage() -> AdminUsage {
+    FS.with_borrow_mut(|fs| {
+        let storage = fs.storage.as_mut();
+        let mut usage = AdminUsage::default();
+        let mut pending = vec![storage.root_node()];
+
+        while let Some(dir) = pending.pop() {
+            usage.directories += 1;
+
+            let mut nodes = Vec::new();
+            storage.with_direntries(dir, Some(0), &mut |_, entry| {
+                nodes.push(entry.node);
+                true
+            });
+
+            for node in nodes {
+                match storage.get_metadata(node) {
+                    Ok(metadata) if metadata.file_type == FileType::Directory => pending.push(node),
+                    Ok(metadata) => {
+                        usage.files += 1;
+                        usage.bytes += metadata.size;
+                    }
+                    Err(_) => {}
+                }
+            }
+        }
+
+        usage
+    })
+}
+
+/// Export the candid methods for inspecting and editing the file system, guarded to the controllers by default:
+///
+/// | Method | Type |
+/// |--------|------|
+/// | `fs_list_dir(path)` | query, `Result<Vec<AdminEntry>, String>` |
+/// | `fs_stat(path)` | query, `Result<AdminStat, String>` |
+/// | `fs_read(path, offset, len)` | query, `Result<Vec<u8>, String>` |
+/// | `fs_usage()` | query, `Result<AdminUsage, String>` |
+/// | `fs_write(path, offset, data)` | update, `Result<u64, String>` |
+/// | `fs_delete(path)` | update, `Result<(), String>` |
+/// | `fs_mkdir(path)` | update, `Result<(), String>` |
+/// | `fs_rename(from, to)` | update, `Result<(), String>` |
+///
+/// The paths are relative to the file system root. The canister needs the `ic-cdk` and `candid` dependencies.
+///
+/// # Example
+/// ```ignore
+/// ic_wasi_polyfill::export_admin_interface!();
+/// // or with a custom guard function returning `Result<(), String>`
+/// ic_wasi_polyfill::export_admin_interface!(guard = "is_admin");
+/// ```
+#[macro_export]
+macro_rules! export_admin_interface {
+    () => {
+        $crate::export_admin_interface!(guard = "ic_wasi_polyfill::admin::caller_is_controller");
+    };
+    (guard = $guard:tt) => {
+        #[ic_cdk::query(guard = $guard)]
+        fn fs_list_dir(path: String) -> Result<Vec<$crate::admin::AdminEntry>, String> {
+            $crate::admin::list_dir(&path)
+        }
+
+        #[ic_cdk::query(guard = $guard)]
+        fn fs_stat(path: String) -> Result<$crate::admin::AdminStat, String> {
+            $crate::admin::stat(&path)
+        }
+
+        #[ic_cdk::query(guard = $guard)]
+        fn fs_read(path: String, offset: u64, len: u64) -> Result<Vec<u8>, String> {
+            $crate::admin::read_range(&path, offset, len)
+        }
+
+        #[ic_cdk::query(guard = $guard)]
+        fn fs_usage() -> Result<$crate::admin::AdminUsage, String> {
+            Ok($crate::admin::usage())
+        }
+
+        #[ic_cdk::update(guard = $guard)]
+        fn fs_write(path: String, offset: u64, data: Vec<u8>) -> Result<u64, String> {
+            $crate::admin::write_range(&path, offset, &data)
+        }
+
+        #[ic_cdk::update(guard = $guard)]
+        fn fs_delete(path: String) -> Result<(), String> {
+            $crate::admin::delete(&path)
+        }
+
+        #[ic_cdk::update(guard = $guard)]
+        fn fs_mkdir(path: String) -> Result<(), String> {
+            $crate::admin::mkdir(&path)
+        }
+
+        #[ic_cdk::update(guard = $guard)]
+        fn fs_rename(from: String, to: String) -> Result<(), String> {
+            $crate::admin::rename(&from, &to)
+        }
+    };
+}
@@ -0,0 +1,933 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::rc::Rc;
+
+use ic_stable_structures::Memory;
+use stable_fs::error::Error;
+use stable_fs::fs::{Advice, ChunkSize, ChunkType, Fd, FileSystem};
+use stable_fs::storage::types::{
+    DirEntry, DirEntryIndex, FileName, FileSize, Metadata, MountedFileSizePolicy, Node,
+    MAX_FILE_SIZE,
+};
+use stable_fs::storage::Storage;
+
+#[cfg(feature = "cache")]
+use crate::{CACHE, FS};
+
+/// Size of the cached blocks of the file data.
+pub const CACHE_BLOCK_SIZE: usize = 4096;
+
+/// Blocks loaded after a read of a file advised as sequential.
+pub const READ_AHEAD_BLOCKS: u64 = 8;
+
+/// Counters of the cache, the block accesses of the reads and writes are counted as hits or misses.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Blocks loaded ahead of the reads by the sequential and will-need advice
+    pub prefetched: u64,
+    /// Blocks dropped to stay within the memory budget
+    pub evictions: u64,
+    /// Dirty blocks written to the storage
+    pub written_back: u64,
+    /// Size of the cached and the dirty blocks at the time of the call
+    pub cached_bytes: u64,
+    pub dirty_bytes: u64,
+}
+
+struct Block {
+    // the whole block, the bytes past the end of the file are zeros
+    data: Vec<u8>,
+    dirty: bool,
+    used: u64,
+}
+
+/// Cached blocks of the file data and the advice given for the files.
+///
+/// The cache is disabled with a zero memory budget, which is the default.
+#[derive(Default)]
+pub struct Cache {
+    budget: usize,
+    blocks: BTreeMap<(Node, u64), Block>,
+    // the blocks by their last use, the least recently used first
+    lru: BTreeMap<u64, (Node, u64)>,
+    pinned: BTreeSet<(Node, u64)>,
+    // sizes of the files grown by the cached writes, the wrapped storage keeps the size of the written data
+    sizes: BTreeMap<Node, FileSize>,
+    sequential: BTreeSet<Node>,
+    // will-need and dont-need advice applied by the next flush of the node
+    pending: Vec<(Node, Advice, FileSize, FileSize)>,
+    clock: u64,
+    stats: CacheStats,
+}
+
+impl Cache {
+    pub fn new() -> Cache {
+        Self::default()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.budget > 0
+    }
+
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    // Change the memory budget, the caller writes back the dirty blocks first.
+    // Clean blocks are dropped until the cache fits, a zero budget drops everything.
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+
+        if budget == 0 {
+            self.discard();
+            return;
+        }
+
+        while self.pinned.len() * CACHE_BLOCK_SIZE > budget {
+            self.pinned.pop_last();
+        }
+
+        while let Some((_, block)) = self.take_victim() {
+            debug_assert!(!block.dirty);
+        }
+    }
+
+    // Drop the blocks and the advice without writing them back, e.g. when the file system is replaced.
+    pub fn discard(&mut self) {
+        self.blocks.clear();
+        self.lru.clear();
+        self.pinned.clear();
+        self.sizes.clear();
+        self.sequential.clear();
+        self.pending.clear();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let dirty = self.blocks.values().filter(|block| block.dirty).count();
+
+        CacheStats {
+            cached_bytes: (self.blocks.len() * CACHE_BLOCK_SIZE) as u64,
+            dirty_bytes: (dirty * CACHE_BLOCK_SIZE) as u64,
+            ..self.stats
+        }
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = CacheStats::default();
+    }
+
+    // Nodes with cached blocks, a grown size or pending advice.
+    pub fn nodes(&self) -> Vec<Node> {
+        let mut nodes: BTreeSet<Node> = self.blocks.keys().map(|(node, _)| *node).collect();
+        nodes.extend(self.pending.iter().map(|(node, ..)| *node));
+        nodes.extend(self.sizes.keys());
+        nodes.into_iter().collect()
+    }
+
+    pub fn is_sequential(&self, node: Node) -> bool {
+        self.sequential.contains(&node)
+    }
+
+    // Record the advice for a range of a node, returns true if the node has to be flushed to apply it.
+    pub fn advise(&mut self, node: Node, offset: FileSize, len: FileSize, advice: Advice) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+
+        match advice {
+            Advice::Sequential => {
+                self.sequential.insert(node);
+                false
+            }
+            Advice::Normal | Advice::Random => {
+                self.sequential.remove(&node);
+                false
+            }
+            Advice::WillNeed | Advice::DontNeed => {
+                self.pending.push((node, advice, offset, len));
+                true
+            }
+            Advice::NoReuse => false,
+        }
+    }
+
+    // Drop the blocks of a flushed node overlapping a range, e.g. after the range was changed below the cache.
+    pub fn invalidate(&mut self, node: Node, offset: FileSize, end: FileSize) {
+        let first = offset / CACHE_BLOCK_SIZE as u64;
+        let last = end.div_ceil(CACHE_BLOCK_SIZE as u64);
+
+        let removed: Vec<u64> = self
+            .blocks
+            .range((node, first)..(node, last))
+            .map(|((_, index), _)| *index)
+            .collect();
+
+        for index in removed {
+            self.remove(node, index);
+        }
+
+        self.pinned
+            .retain(|(pinned, index)| *pinned != node || *index < first || *index >= last);
+    }
+
+    fn take_pending(&mut self, node: Node) -> Vec<(Advice, FileSize, FileSize)> {
+        let (taken, kept) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|(pending, ..)| *pending == node);
+        self.pending = kept;
+
+        taken
+            .into_iter()
+            .map(|(_, advice, offset, len)| (advice, offset, len))
+            .collect()
+    }
+
+    fn contains(&self, node: Node, index: u64) -> bool {
+        self.blocks.contains_key(&(node, index))
+    }
+
+    fn has_node(&self, node: Node) -> bool {
+        self.blocks
+            .range((node, 0)..=(node, u64::MAX))
+            .next()
+            .is_some()
+    }
+
+    fn touch(&mut self, key: (Node, u64)) -> Option<&mut Block> {
+        self.clock += 1;
+        let block = self.blocks.get_mut(&key)?;
+
+        self.lru.remove(&block.used);
+        block.used = self.clock;
+        self.lru.insert(block.used, key);
+
+        Some(block)
+    }
+
+    fn insert(&mut self, node: Node, index: u64, data: Vec<u8>, dirty: bool) {
+        self.clock += 1;
+
+        if let Some(old) = self.blocks.insert(
+            (node, index),
+            Block {
+                data,
+                dirty,
+                used: self.clock,
+            },
+        ) {
+            self.lru.remove(&old.used);
+        }
+
+        self.lru.insert(self.clock, (node, index));
+    }
+
+    fn remove(&mut self, node: Node, index: u64) -> Option<Block> {
+        let block = self.blocks.remove(&(node, index))?;
+        self.lru.remove(&block.used);
+        Some(block)
+    }
+
+    // Dirty blocks of a node below a block index.
+    fn dirty_blocks(&self, node: Node, below: u64) -> Vec<u64> {
+        self.blocks
+            .range((node, 0)..(node, below))
+            .filter(|(_, block)| block.dirty)
+            .map(|((_, index), _)| *index)
+            .collect()
+    }
+
+    // Pin a block if the pinned blocks stay within the budget.
+    fn pin(&mut self, node: Node, index: u64) -> bool {
+        if self.pinned.contains(&(node, index)) {
+            return true;
+        }
+
+        if (self.pinned.len() + 1) * CACHE_BLOCK_SIZE > self.budget {
+            return false;
+        }
+
+        self.pinned.insert((node, index))
+    }
+
+    // Remove the least recently used unpinned block if the cache is over the budget.
+    fn take_victim(&mut self) -> Option<((Node, u64), Block)> {
+        if self.blocks.len() * CACHE_BLOCK_SIZE <= self.budget {
+            return None;
+        }
+
+        let key = *self.lru.values().find(|key| !self.pinned.contains(key))?;
+        let block = self.remove(key.0, key.1)?;
+        self.stats.evictions += 1;
+
+        Some((key, block))
+    }
+
+    // Drop the blocks above the new size of a node and clear the end of the last block.
+    fn truncate(&mut self, node: Node, size: FileSize) {
+        let first = size.div_ceil(CACHE_BLOCK_SIZE as u64);
+
+        let removed: Vec<u64> = self
+            .blocks
+            .range((node, first)..=(node, u64::MAX))
+            .map(|((_, index), _)| *index)
+            .collect();
+
+        for index in removed {
+            self.remove(node, index);
+        }
+
+        self.pinned
+            .retain(|(pinned, index)| *pinned != node || *index < first);
+
+        let tail = (size % CACHE_BLOCK_SIZE as u64) as usize;
+
+        if tail > 0 {
+            if let Some(block) = self.blocks.get_mut(&(node, size / CACHE_BLOCK_SIZE as u64)) {
+                block.data[tail..].fill(0);
+            }
+        }
+    }
+
+    // Drop everything kept for a node without writing it back.
+    fn forget(&mut self, node: Node) {
+        self.truncate(node, 0);
+        self.sizes.remove(&node);
+        self.sequential.remove(&node);
+        self.take_pending(node);
+    }
+}
+
+/// Storage caching the file data of the wrapped storage in heap blocks.
+///
+/// The reads load whole blocks, the writes stay in the cache until the file is flushed
+/// (`fd_sync`, `fd_datasync`, `fd_close`) or the block is evicted. A file grown by the writes keeps its
+/// stored size until the data is written back, so the stored size never covers data still in the cache.
+/// The mounted files are not cached.
+pub struct CacheStorage {
+    inner: Box<dyn Storage>,
+    cache: Rc<RefCell<Cache>>,
+}
+
+impl CacheStorage {
+    pub fn new(inner: Box<dyn Storage>, cache: Rc<RefCell<Cache>>) -> CacheStorage {
+        CacheStorage { inner, cache }
+    }
+
+    fn is_cached(&self, node: Node) -> bool {
+        self.cache.borrow().is_enabled() && !self.inner.is_mounted(node)
+    }
+
+    // Read a block from the wrapped storage, the bytes from `valid_size` on are zeros.
+    fn load_block(
+        &mut self,
+        node: Node,
+        index: u64,
+        valid_size: FileSize,
+    ) -> Result<Vec<u8>, Error> {
+        let offset = index * CACHE_BLOCK_SIZE as u64;
+        let mut data = vec![0u8; CACHE_BLOCK_SIZE];
+
+        if offset < valid_size {
+            let len = (valid_size - offset).min(CACHE_BLOCK_SIZE as u64) as usize;
+            let read = self.inner.read(node, offset, &mut data[..len])? as usize;
+            data[read..].fill(0);
+        }
+
+        Ok(data)
+    }
+
+    fn write_back(&mut self, node: Node, index: u64, data: &[u8]) -> Result<(), Error> {
+        // the wrapped storage grows the file as the blocks are written
+        let size = self.get_metadata(node)?.size;
+        let offset = index * CACHE_BLOCK_SIZE as u64;
+
+        // the blocks above the file size were truncated
+        if offset < size {
+            let len = (size - offset).min(CACHE_BLOCK_SIZE as u64) as usize;
+            self.inner.write(node, offset, &data[..len])?;
+        }
+
+        self.cache.borrow_mut().stats.written_back += 1;
+
+        Ok(())
+    }
+
+    // Evict the least recently used blocks until the cache fits its budget.
+    fn shrink(&mut self) -> Result<(), Error> {
+        loop {
+            let victim = self.cache.borrow_mut().take_victim();
+
+            match victim {
+                Some(((node, index), block)) if block.dirty => {
+                    // the blocks below are written first, so that the file does not grow over them
+                    if self.cache.borrow().sizes.contains_key(&node) {
+                        self.write_back_blocks(node, index)?;
+                    }
+
+                    self.write_back(node, index, &block.data)?
+                }
+                Some(_) => {}
+                None => return Ok(()),
+            }
+        }
+    }
+
+    fn write_back_node(&mut self, node: Node) -> Result<(), Error> {
+        self.write_back_blocks(node, u64::MAX)?;
+
+        // the size past the written blocks, e.g. after a write far past the end of the file
+        let grown = self.cache.borrow().sizes.get(&node).copied();
+
+        if let Some(size) = grown {
+            let mut metadata = self.inner.get_metadata(node)?;
+
+            if size > metadata.size {
+                metadata.size = size;
+                self.inner.put_metadata(node, &metadata)?;
+            }
+
+            self.cache.borrow_mut().sizes.remove(&node);
+        }
+
+        Ok(())
+    }
+
+    // Write back the dirty blocks of a node below a block index in the order of the file.
+    fn write_back_blocks(&mut self, node: Node, below: u64) -> Result<(), Error> {
+        let dirty = self.cache.borrow().dirty_blocks(node, below);
+
+        for index in dirty {
+            let data = match self.cache.borrow().blocks.get(&(node, index)) {
+                Some(block) => block.data.clone(),
+                None => continue,
+            };
+
+            self.write_back(node, index, &data)?;
+
+            if let Some(block) = self.cache.borrow_mut().blocks.get_mut(&(node, index)) {
+                block.dirty = false;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn apply_advice(&mut self, node: Node) -> Result<(), Error> {
+        let pending = self.cache.borrow_mut().take_pending(node);
+
+        if pending.is_empty() || !self.is_cached(node) {
+            return Ok(());
+        }
+
+        let size = self.get_metadata(node)?.size;
+
+        for (advice, offset, len) in pending {
+            // a zero length extends to the end of the file
+            let end = if len == 0 {
+                size
+            } else {
+                offset.saturating_add(len).min(size)
+            };
+
+            if offset >= end {
+                continue;
+            }
+
+            let first = offset / CACHE_BLOCK_SIZE as u64;
+            let last = (end - 1) / CACHE_BLOCK_SIZE as u64;
+
+            for index in first..=last {
+                if matches!(advice, Advice::DontNeed) {
+                    let mut cache = self.cache.borrow_mut();
+                    cache.pinned.remove(&(node, index));
+                    cache.remove(node, index);
+                    continue;
+                }
+
+                if !self.cache.borrow_mut().pin(node, index) {
+                    break;
+                }
+
+                if !self.cache.borrow().contains(node, index) {
+                    let data = self.load_block(node, index, size)?;
+                    let mut cache = self.cache.borrow_mut();
+                    cache.insert(node, index, data, false);
+                    cache.stats.prefetched += 1;
+                }
+            }
+        }
+
+        self.shrink()
+    }
+
+    fn read_ahead(&mut self, node: Node, from: u64, file_size: FileSize) -> Result<(), Error> {
+        for index in from..from + READ_AHEAD_BLOCKS {
+            if index * CACHE_BLOCK_SIZE as u64 >= file_size {
+                break;
+            }
+
+            if self.cache.borrow().contains(node, index) {
+                continue;
+            }
+
+            let data = self.load_block(node, index, file_size)?;
+            let mut cache = self.cache.borrow_mut();
+            cache.insert(node, index, data, false);
+            cache.stats.prefetched += 1;
+        }
+
+        Ok(())
+    }
+}
+
+impl Storage for CacheStorage {
+    fn root_node(&self) -> Node {
+        self.inner.root_node()
+    }
+
+    fn get_version(&self) -> u32 {
+        self.inner.get_version()
+    }
+
+    fn new_node(&mut self) -> Node {
+        self.inner.new_node()
+    }
+
+    fn mount_node(
+        &mut self,
+        node: Node,
+        memory: Box<dyn Memory>,
+        mount_policy: MountedFileSizePolicy,
+    ) -> Result<(), Error> {
+        self.write_back_node(node)?;
+        self.cache.borrow_mut().forget(node);
+
+        self.inner.mount_node(node, memory, mount_policy)
+    }
+
+    fn unmount_node(&mut self, node: Node) -> Result<Box<dyn Memory>, Error> {
+        self.inner.unmount_node(node)
+    }
+
+    fn is_mounted(&self, node: Node) -> bool {
+        self.inner.is_mounted(node)
+    }
+
+    fn get_mounted_memory(&self, node: Node) -> Option<&dyn Memory> {
+        self.inner.get_mounted_memory(node)
+    }
+
+    fn init_mounted_memory(&mut self, node: Node) -> Result<(), Error> {
+        self.inner.init_mounted_memory(node)
+    }
+
+    fn store_mounted_memory(&mut self, node: Node) -> Result<(), Error> {
+        self.inner.store_mounted_memory(node)?;
+        self.cache.borrow_mut().forget(node);
+
+        Ok(())
+    }
+
+    fn get_metadata(&self, node: Node) -> Result<Metadata, Error> {
+        let mut metadata = self.inner.get_metadata(node)?;
+
+        if let Some(size) = self.cache.borrow().sizes.get(&node) {
+            metadata.size = *size;
+        }
+
+        Ok(metadata)
+    }
+
+    fn put_metadata(&mut self, node: Node, metadata: &Metadata) -> Result<(), Error> {
+        let grown = self.cache.borrow().sizes.get(&node).copied();
+
+        if grown.is_none() && !self.cache.borrow().has_node(node) {
+            return self.inner.put_metadata(node, metadata);
+        }
+
+        let stored_size = self.inner.get_metadata(node)?.size;
+
+        if metadata.size < grown.unwrap_or(stored_size) {
+            self.cache.borrow_mut().truncate(node, metadata.size);
+        }
+
+        if grown.is_none() {
+            return self.inner.put_metadata(node, metadata);
+        }
+
+        // the file stays grown in the cache until its blocks are written back
+        let mut metadata = metadata.clone();
+        if metadata.size > stored_size {
+            self.cache.borrow_mut().sizes.insert(node, metadata.size);
+            metadata.size = stored_size;
+        } else {
+            self.cache.borrow_mut().sizes.remove(&node);
+        }
+
+        self.inner.put_metadata(node, &metadata)
+    }
+
+    fn get_direntry(&self, node: Node, index: DirEntryIndex) -> Result<DirEntry, Error> {
+        self.inner.get_direntry(node, index)
+    }
+
+    fn get_direntry_index_by_name(&self, el: &(Node, FileName)) -> Option<DirEntryIndex> {
+        self.inner.get_direntry_index_by_name(el)
+    }
+
+    fn with_direntries(
+        &self,
+        node: Node,
+        initial_index: Option<DirEntryIndex>,
+        f: &mut dyn FnMut(&DirEntryIndex, &DirEntry) -> bool,
+    ) {
+        self.inner.with_direntries(node, initial_index, f)
+    }
+
+    fn new_direntry_index(&self, node: Node) -> DirEntryIndex {
+        self.inner.new_direntry_index(node)
+    }
+
+    fn put_direntry(&mut self, node: Node, index: DirEntryIndex, entry: DirEntry) {
+        self.inner.put_direntry(node, index, entry)
+    }
+
+    fn rm_direntry(&mut self, node: Node, index: DirEntryIndex) {
+        self.inner.rm_direntry(node, index)
+    }
+
+    fn read(
+        &mut self,
+        node: Node,
+        read_offset: FileSize,
+        buf: &mut [u8],
+    ) -> Result<FileSize, Error> {
+        if !self.is_cached(node) {
+            return self.inner.read(node, read_offset, buf);
+        }
+
+        let metadata = self.get_metadata(node)?;
+        let max_size = metadata.maximum_size_allowed.unwrap_or(MAX_FILE_SIZE);
+        let file_size = metadata.size.min(max_size);
+
+        if read_offset >= file_size {
+            return Ok(0);
+        }
+
+        let size = (file_size - read_offset).min(buf.len() as FileSize) as usize;
+
+        let mut read = 0;
+        let mut index = 0;
+        while read < size {
+            let offset = read_offset + read as FileSize;
+            index = offset / CACHE_BLOCK_SIZE as u64;
+            let start = (offset % CACHE_BLOCK_SIZE as u64) as usize;
+            let len = (CACHE_BLOCK_SIZE - start).min(size - read);
+            let dst = &mut buf[read..read + len];
+
+            let hit = match self.cache.borrow_mut().touch((node, index)) {
+                Some(block) => {
+                    dst.copy_from_slice(&block.data[start..start + len]);
+                    true
+                }
+                None => false,
+            };
+
+            if hit {
+                self.cache.borrow_mut().stats.hits += 1;
+            } else {
+                let data = self.load_block(node, index, metadata.size)?;
+                dst.copy_from_slice(&data[start..start + len]);
+
+                let mut cache = self.cache.borrow_mut();
+                cache.insert(node, index, data, false);
+                cache.stats.misses += 1;
+            }
+
+            self.shrink()?;
+
+            read += len;
+        }
+
+        if self.cache.borrow().is_sequential(node) {
+            self.read_ahead(node, index + 1, file_size)?;
+            self.shrink()?;
+        }
+
+        Ok(size as FileSize)
+    }
+
+    fn write(&mut self, node: Node, offset: FileSize, buf: &[u8]) -> Result<FileSize, Error> {
+        if !self.is_cached(node) {
+            return self.inner.write(node, offset, buf);
+        }
+
+        let metadata = self.get_metadata(node)?;
+
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let max_size = metadata.maximum_size_allowed.unwrap_or(MAX_FILE_SIZE);
+        let end = offset + buf.len() as FileSize;
+
+        if end > max_size {
+            return Err(Error::FileTooLarge);
+        }
+
+        // the grown size is stored when the blocks are written back
+        let old_size = metadata.size;
+        if end > old_size {
+            self.cache.borrow_mut().sizes.insert(node, end);
+        }
+
+        let mut written = 0;
+        while written < buf.len() {
+            let position = offset + written as FileSize;
+            let index = position / CACHE_BLOCK_SIZE as u64;
+            let start = (position % CACHE_BLOCK_SIZE as u64) as usize;
+            let len = (CACHE_BLOCK_SIZE - start).min(buf.len() - written);
+            let src = &buf[written..written + len];
+
+            let hit = match self.cache.borrow_mut().touch((node, index)) {
+                Some(block) => {
+                    block.data[start..start + len].copy_from_slice(src);
+                    block.dirty = true;
+                    true
+                }
+                None => false,
+            };
+
+            if hit {
+                self.cache.borrow_mut().stats.hits += 1;
+            } else {
+                // a block written as a whole is not read
+                let mut data = if len == CACHE_BLOCK_SIZE {
+                    vec![0u8; CACHE_BLOCK_SIZE]
+                } else {
+                    self.load_block(node, index, old_size)?
+                };
+                data[start..start + len].copy_from_slice(src);
+
+                let mut cache = self.cache.borrow_mut();
+                cache.insert(node, index, data, true);
+                cache.stats.misses += 1;
+            }
+
+            self.shrink()?;
+
+            written += len;
+        }
+
+        Ok(buf.len() as FileSize)
+    }
+
+    fn resize_file(&mut self, node: Node, new_size: FileSize) -> Result<(), Error> {
+        let mut metadata = self.get_metadata(node)?;
+
+        metadata.size = new_size;
+
+        self.put_metadata(node, &metadata)
+    }
+
+    fn rm_file(&mut self, node: Node) -> Result<(), Error> {
+        self.inner.rm_file(node)?;
+        self.cache.borrow_mut().forget(node);
+
+        Ok(())
+    }
+
+    fn set_chunk_size(&mut self, chunk_size: ChunkSize) -> Result<(), Error> {
+        self.inner.set_chunk_size(chunk_size)
+    }
+
+    fn chunk_size(&self) -> usize {
+        self.inner.chunk_size()
+    }
+
+    fn set_chunk_type(&mut self, chunk_type: ChunkType) {
+        self.inner.set_chunk_type(chunk_type)
+    }
+
+    fn chunk_type(&self) -> ChunkType {
+        self.inner.chunk_type()
+    }
+
+    // Write back the dirty blocks of the node and apply its pending advice.
+    // The blocks stay dirty if they cannot be written, the next flush retries them.
+    fn flush(&mut self, node: Node) {
+        if self.write_back_node(node).is_ok() {
+            let _ = self.apply_advice(node);
+        }
+
+        self.inner.flush(node)
+    }
+}
+
+/// Cache the file data on the heap with a memory budget in bytes, `0` disables the cache, which is the default.
+/// The reads load blocks of 4 KiB and the writes stay in the cache until the file is flushed by `fd_sync`, `fd_datasync`
+/// or `fd_close`, or the block is evicted. `fd_advise` controls the cache: `SEQUENTIAL` reads the next blocks ahead,
+/// `WILLNEED` loads and pins the range within the budget, `DONTNEED` writes back and drops the range,
+/// `NORMAL` and `RANDOM` stop the read-ahead. A file grown by the cached writes keeps its stored size until
+/// the written data is stored.
+///
+/// The cache is lost on upgrade, call `flush_cache` in `pre_upgrade` if the files can stay open across calls.
+/// The dirty blocks are written back before the budget changes.
+#[cfg(feature = "cache")]
+pub fn set_cache_budget(bytes: usize) {
+    flush_cache();
+
+    CACHE.with(|cache| cache.borrow_mut().set_budget(bytes))
+}
+
+/// Write back the dirty blocks of all the cached files
+#[cfg(feature = "cache")]
+pub fn flush_cache() {
+    let nodes = CACHE.with(|cache| cache.borrow().nodes());
+
+    FS.with_borrow_mut(|fs| {
+        for node in nodes {
+            fs.storage.flush(node);
+        }
+    })
+}
+
+/// Get the hit, miss, prefetch, eviction and write-back counters and the size of the cached data
+#[cfg(feature = "cache")]
+pub fn get_cache_stats() -> CacheStats {
+    CACHE.with(|cache| cache.borrow().stats())
+}
+
+/// Reset the cache counters, the cached data is kept
+#[cfg(feature = "cache")]
+pub fn reset_cache_stats() {
+    CACHE.with(|cache| cache.borrow_mut().reset_stats())
+}
+
+// Pass the advice to the cache, the will-need and dont-need advice is applied by flushing the file.
+#[cfg(feature = "cache")]
+pub(crate) fn advise_cache(
+    fs: &mut FileSystem,
+    fd: Fd,
+    offset: FileSize,
+    len: FileSize,
+    advice: Advice,
+) {
+    let Ok(metadata) = fs.metadata(fd) else {
+        return;
+    };
+
+    if CACHE.with(|cache| {
+        cache
+            .borrow_mut()
+            .advise(metadata.node, offset, len, advice)
+    }) {
+        fs.storage.flush(metadata.node);
+    }
+}
+
+#[cfg(not(feature = "cache"))]
+pub(crate) fn advise_cache(_: &mut FileSystem, _: Fd, _: FileSize, _: FileSize, _: Advice) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stable_fs::storage::transient::TransientStorage;
+
+    #[test]
+    fn budget_and_eviction() {
+        let mut cache = Cache::new();
+        cache.set_budget(3 * CACHE_BLOCK_SIZE);
+
+        for index in 0..3 {
+            cache.insert(1, index, vec![index as u8; CACHE_BLOCK_SIZE], index == 1);
+        }
+        assert!(cache.take_victim().is_none());
+
+        assert!(cache.pin(1, 0));
+        cache.touch((1, 1));
+        cache.insert(2, 0, vec![0; CACHE_BLOCK_SIZE], false);
+
+        // the pinned block is kept, the least recently used of the others is evicted
+        let ((node, index), block) = cache.take_victim().unwrap();
+        assert_eq!((node, index, block.dirty), (1, 2, false));
+        assert!(cache.take_victim().is_none());
+        assert_eq!(cache.dirty_blocks(1, u64::MAX), vec![1]);
+
+        cache.truncate(1, 100);
+        assert_eq!(cache.nodes(), vec![1, 2]);
+        assert!(cache.blocks[&(1, 0)].data[100..].iter().all(|b| *b == 0));
+        assert!(cache.dirty_blocks(1, u64::MAX).is_empty());
+
+        assert!(cache.advise(1, 0, 0, Advice::WillNeed));
+        assert!(!cache.advise(3, 0, 0, Advice::Sequential));
+        assert!(cache.is_sequential(3));
+        cache.forget(1);
+        assert_eq!(cache.nodes(), vec![2]);
+
+        let stats = cache.stats();
+        assert_eq!((stats.evictions, stats.cached_bytes), (1, 4096));
+
+        cache.set_budget(0);
+        assert!(!cache.is_enabled() && cache.nodes().is_empty());
+    }
+
+    #[test]
+    fn grown_size_follows_written_blocks() {
+        let cache = Rc::new(RefCell::new(Cache::new()));
+        cache.borrow_mut().set_budget(2 * CACHE_BLOCK_SIZE);
+
+        let mut storage = CacheStorage::new(Box::new(TransientStorage::new()), cache.clone());
+        let node = storage.new_node();
+        storage.put_metadata(node, &Metadata::default()).unwrap();
+
+        storage.write(node, 0, &[1; 100]).unwrap();
+        assert_eq!(storage.get_metadata(node).unwrap().size, 100);
+        assert_eq!(storage.inner.get_metadata(node).unwrap().size, 0);
+
+        // the evicted block is written after the dirty blocks below it
+        storage
+            .write(node, 3 * CACHE_BLOCK_SIZE as u64, &[3; 10])
+            .unwrap();
+        let mut buf = [0u8; 100];
+        storage.read(node, 0, &mut buf).unwrap();
+        storage
+            .write(node, 5 * CACHE_BLOCK_SIZE as u64, &[5; 10])
+            .unwrap();
+        assert_eq!(
+            storage.inner.get_metadata(node).unwrap().size,
+            4 * CACHE_BLOCK_SIZE as u64
+        );
+
+        storage.inner.read(node, 0, &mut buf).unwrap();
+        assert_eq!(buf, [1; 100]);
+
+        // the timestamps stored by the file system keep the grown size in the cache
+        let mut metadata = storage.get_metadata(node).unwrap();
+        metadata.times.modified = 7;
+        storage.put_metadata(node, &metadata).unwrap();
+        assert_eq!(storage.inner.get_metadata(node).unwrap().times.modified, 7);
+        assert_eq!(
+            storage.inner.get_metadata(node).unwrap().size,
+            4 * CACHE_BLOCK_SIZE as u64
+        );
+
+        storage
+            .resize_file(node, 8 * CACHE_BLOCK_SIZE as u64)
+            .unwrap();
+        assert_eq!(cache.borrow().nodes(), vec![node]);
+
+        storage.flush(node);
+        let size = storage.inner.get_metadata(node).unwrap().size;
+        assert_eq!(size, 8 * CACHE_BLOCK_SIZE as u64);
+        assert_eq!(storage.get_metadata(node).unwrap().size, size);
+
+        storage
+            .inner
+            .read(node, 5 * CACHE_BLOCK_SIZE as u64, &mut buf)
+            .unwrap();
+        assert_eq!(buf[..12], [5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 0, 0]);
+    }
+}
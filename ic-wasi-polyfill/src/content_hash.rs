@@ -0,0 +1,374 @@
+#[cfg(feature = "content_hashes")]
+use std::collections::{BTreeMap, BTreeSet};
+
+use stable_fs::fs::{Fd, FileSystem};
+use stable_fs::storage::types::FileSize;
+
+#[cfg(feature = "content_hashes")]
+use sha2::{Digest, Sha256};
+#[cfg(feature = "content_hashes")]
+use stable_fs::{
+    error::Error,
+    storage::{
+        types::{FileType, Metadata, Node},
+        Storage,
+    },
+};
+
+#[cfg(feature = "content_hashes")]
+use crate::{wasi, wasi_helpers::into_errno, CONTENT_HASHES, FS, XATTRS};
+
+#[cfg(feature = "http")]
+use crate::http::certify_http_file;
+
+/// Extended attribute keeping the SHA-256 hash of the file content.
+pub const HASH_XATTR: &str = "system.sha256";
+
+/// Size of the content hashes in bytes.
+pub const HASH_SIZE: usize = 32;
+
+// Size of the buffer used for hashing the whole file.
+#[cfg(feature = "content_hashes")]
+const READ_CHUNK_SIZE: usize = 65536;
+
+/// Hash the file content read directly from the storage.
+#[cfg(feature = "content_hashes")]
+pub fn hash_file(
+    storage: &mut dyn Storage,
+    node: Node,
+    size: FileSize,
+) -> Result<[u8; HASH_SIZE], Error> {
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; READ_CHUNK_SIZE];
+    let mut offset = 0;
+
+    while offset < size {
+        let len = (size - offset).min(READ_CHUNK_SIZE as FileSize) as usize;
+        let read = storage.read(node, offset, &mut buf[..len])?;
+
+        if read == 0 {
+            return Err(Error::IOError);
+        }
+
+        hasher.update(&buf[..read as usize]);
+        offset += read;
+    }
+
+    Ok(hasher.finalize().into())
+}
+
+/// Content hashes of the files changed since their hash was stored.
+///
+/// A file becomes stale with its first write and its stored hash is removed, so a stored hash always
+/// matches the content. The writes appending to the file are hashed as they come, other writes
+/// leave the hash to be computed from the whole file when it is closed or requested.
+#[cfg(feature = "content_hashes")]
+#[derive(Default)]
+pub struct ContentHashes {
+    enabled: bool,
+    // the number of bytes hashed so far and the hash state of the files written sequentially
+    running: BTreeMap<Node, (FileSize, Sha256)>,
+    stale: BTreeSet<Node>,
+}
+
+#[cfg(feature = "content_hashes")]
+impl ContentHashes {
+    pub fn new() -> ContentHashes {
+        Self::default()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+
+        if !enabled {
+            self.running.clear();
+        }
+    }
+
+    pub fn is_stale(&self, node: Node) -> bool {
+        self.stale.contains(&node)
+    }
+
+    // Record the written data, returns true if the file just became stale.
+    pub fn write(&mut self, node: Node, offset: FileSize, bufs: &[&[u8]], written: usize) -> bool {
+        let became_stale = self.stale.insert(node);
+
+        if !self.enabled {
+            return became_stale;
+        }
+
+        let appended = self
+            .running
+            .get(&node)
+            .is_some_and(|(hashed, _)| *hashed == offset);
+
+        if !appended {
+            if offset == 0 {
+                // the hashed data is compared with the file size, so older data after it does not matter
+                self.running.insert(node, (0, Sha256::new()));
+            } else {
+                self.running.remove(&node);
+                return became_stale;
+            }
+        }
+
+        let (hashed, hasher) = self.running.get_mut(&node).unwrap();
+        let mut remaining = written;
+
+        for buf in bufs {
+            let len = buf.len().min(remaining);
+            hasher.update(&buf[..len]);
+            remaining -= len;
+        }
+
+        *hashed += written as FileSize;
+
+        became_stale
+    }
+
+    // Record the new file size, returns true if the file just became stale.
+    pub fn truncate(&mut self, node: Node, size: FileSize) -> bool {
+        if size == 0 && self.enabled {
+            self.running.insert(node, (0, Sha256::new()));
+        } else if self
+            .running
+            .get(&node)
+            .is_none_or(|(hashed, _)| *hashed > size)
+        {
+            self.running.remove(&node);
+        }
+
+        self.stale.insert(node)
+    }
+
+    // Compute the hash of a file, the running hash is used if it covers the whole file.
+    pub fn hash(
+        &self,
+        storage: &mut dyn Storage,
+        node: Node,
+        size: FileSize,
+    ) -> Result<[u8; HASH_SIZE], Error> {
+        match self.running.get(&node) {
+            Some((hashed, hasher)) if *hashed == size => Ok(hasher.clone().finalize().into()),
+            _ => hash_file(storage, node, size),
+        }
+    }
+
+    // Compute the hash of a stale file, which is no longer stale.
+    pub fn finish(
+        &mut self,
+        storage: &mut dyn Storage,
+        node: Node,
+        size: FileSize,
+    ) -> Result<[u8; HASH_SIZE], Error> {
+        let hash = self.hash(storage, node, size)?;
+        self.stale.remove(&node);
+
+        Ok(hash)
+    }
+
+    // Forget the change of a file without computing its hash.
+    pub fn mark_unchanged(&mut self, node: Node) {
+        self.stale.remove(&node);
+    }
+
+    pub fn forget(&mut self, node: Node) {
+        self.running.remove(&node);
+        self.stale.remove(&node);
+    }
+}
+
+/// Maintain the SHA-256 hashes of the file contents. The writes appending to a file are hashed as they happen,
+/// the hash is completed when the file is closed and kept in the `system.sha256` extended attribute,
+/// other writes leave the hash to be computed from the whole file on close.
+///
+/// The hashes persist with the extended attributes, see `init_xattrs_with_memory`, so hashing should be enabled
+/// in `init` and `post_upgrade`. A stored hash is removed by the first change of the file even when hashing is disabled,
+/// the changes made directly to a mounted memory are not noticed.
+#[cfg(feature = "content_hashes")]
+pub fn set_content_hashing(enabled: bool) {
+    CONTENT_HASHES.with_borrow_mut(|hashes| hashes.set_enabled(enabled))
+}
+
+/// Get the SHA-256 hash of a file content, the stored hash is returned if there is one, otherwise the file is hashed
+/// and the hash is stored if content hashing is enabled and the file was not changed since it was last closed.
+/// Returns `ERRNO_ISDIR` for directories.
+#[cfg(feature = "content_hashes")]
+pub fn get_file_hash(path: &str) -> Result<[u8; HASH_SIZE], i32> {
+    let metadata = FS
+        .with_borrow_mut(|fs| {
+            let root_fd = fs.root_fd();
+            fs.open_metadata(root_fd, path)
+        })
+        .map_err(into_errno)?;
+
+    if metadata.file_type == FileType::Directory {
+        return Err(wasi::ERRNO_ISDIR.raw() as i32);
+    }
+
+    FS.with_borrow_mut(|fs| file_content_hash(fs, &metadata))
+        .map_err(into_errno)
+}
+
+#[cfg(feature = "content_hashes")]
+pub(crate) fn is_content_hashing() -> bool {
+    CONTENT_HASHES.with_borrow(|hashes| hashes.is_enabled())
+}
+
+// Content changes only need tracking if the hashes are maintained or a stored hash might have to be removed.
+#[cfg(feature = "content_hashes")]
+fn tracks_content_changes() -> bool {
+    is_content_hashing() || !XATTRS.with_borrow(|xattrs| xattrs.is_empty())
+}
+
+// Record the data written to a file, the stored hash is removed with the first change.
+#[cfg(feature = "content_hashes")]
+pub(crate) fn hash_written(
+    fs: &FileSystem,
+    fd: Fd,
+    offset: FileSize,
+    bufs: &[&[u8]],
+    written: usize,
+) {
+    if !tracks_content_changes() {
+        return;
+    }
+
+    if let Ok(metadata) = fs.metadata(fd) {
+        if metadata.file_type == FileType::RegularFile
+            && CONTENT_HASHES
+                .with_borrow_mut(|hashes| hashes.write(metadata.node, offset, bufs, written))
+        {
+            XATTRS.with_borrow_mut(|xattrs| xattrs.remove(metadata.node, HASH_XATTR));
+        }
+    }
+}
+
+// Record the new size of a file after it was truncated or extended.
+#[cfg(feature = "content_hashes")]
+pub(crate) fn hash_resized(fs: &FileSystem, fd: Fd) {
+    if !tracks_content_changes() {
+        return;
+    }
+
+    if let Ok(metadata) = fs.metadata(fd) {
+        if metadata.file_type == FileType::RegularFile
+            && CONTENT_HASHES
+                .with_borrow_mut(|hashes| hashes.truncate(metadata.node, metadata.size))
+        {
+            XATTRS.with_borrow_mut(|xattrs| xattrs.remove(metadata.node, HASH_XATTR));
+        }
+    }
+}
+
+// Compute and store the hash of a changed file when one of its descriptors is closed.
+#[cfg(feature = "content_hashes")]
+pub(crate) fn store_content_hash(fs: &mut FileSystem, fd: Fd) {
+    if !tracks_content_changes() {
+        return;
+    }
+
+    if let Ok(metadata) = fs.metadata(fd) {
+        let hash = CONTENT_HASHES.with_borrow_mut(|hashes| {
+            if !hashes.is_stale(metadata.node) {
+                return None;
+            }
+
+            if !hashes.is_enabled() {
+                hashes.mark_unchanged(metadata.node);
+                return None;
+            }
+
+            hashes
+                .finish(fs.storage.as_mut(), metadata.node, metadata.size)
+                .ok()
+        });
+
+        if let Some(hash) = hash {
+            XATTRS.with_borrow_mut(|xattrs| xattrs.set(metadata.node, HASH_XATTR, &hash));
+
+            #[cfg(feature = "http")]
+            certify_http_file(fs, fd, hash);
+        }
+    }
+}
+
+// Stored hash of a file or the hash computed from its content. The computed hash is stored if content hashing
+// is enabled, unless the file is being changed, then it is stored when the file is closed.
+#[cfg(feature = "content_hashes")]
+pub(crate) fn file_content_hash(
+    fs: &mut FileSystem,
+    metadata: &Metadata,
+) -> Result<[u8; HASH_SIZE], Error> {
+    let stored = XATTRS.with_borrow(|xattrs| xattrs.get(metadata.node, HASH_XATTR));
+    if let Some(hash) = stored.and_then(|hash| hash.try_into().ok()) {
+        return Ok(hash);
+    }
+
+    let (hash, is_stale) = CONTENT_HASHES.with_borrow(|hashes| {
+        hashes
+            .hash(fs.storage.as_mut(), metadata.node, metadata.size)
+            .map(|hash| (hash, hashes.is_stale(metadata.node)))
+    })?;
+
+    if is_content_hashing() && !is_stale {
+        XATTRS.with_borrow_mut(|xattrs| xattrs.set(metadata.node, HASH_XATTR, &hash));
+    }
+
+    Ok(hash)
+}
+
+#[cfg(all(feature = "xattrs", not(feature = "content_hashes")))]
+pub(crate) fn is_content_hashing() -> bool {
+    false
+}
+
+#[cfg(not(feature = "content_hashes"))]
+pub(crate) fn hash_written(_: &FileSystem, _: Fd, _: FileSize, _: &[&[u8]], _: usize) {}
+
+#[cfg(not(feature = "content_hashes"))]
+pub(crate) fn hash_resized(_: &FileSystem, _: Fd) {}
+
+#[cfg(not(feature = "content_hashes"))]
+pub(crate) fn store_content_hash(_: &mut FileSystem, _: Fd) {}
+
+#[cfg(all(test, feature = "content_hashes"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn running_hash() {
+        let mut hashes = ContentHashes::new();
+        hashes.set_enabled(true);
+
+        assert!(hashes.write(5, 0, &[b"hello ", b"world"], 11));
+        assert!(!hashes.write(5, 11, &[b"!!!"], 1));
+        assert!(hashes.is_stale(5));
+
+        let (hashed, hasher) = &hashes.running[&5];
+        assert_eq!(*hashed, 12);
+        assert_eq!(
+            <[u8; HASH_SIZE]>::from(hasher.clone().finalize()),
+            <[u8; HASH_SIZE]>::from(Sha256::digest(b"hello world!"))
+        );
+
+        // writing in the middle drops the running hash
+        hashes.write(5, 3, &[b"p"], 1);
+        assert!(!hashes.running.contains_key(&5));
+
+        // growing keeps it, shrinking below the hashed data drops it
+        hashes.truncate(6, 0);
+        hashes.write(6, 0, &[b"data"], 4);
+        hashes.truncate(6, 10);
+        assert!(hashes.running.contains_key(&6));
+        hashes.truncate(6, 2);
+        assert!(!hashes.running.contains_key(&6));
+
+        hashes.forget(6);
+        assert!(!hashes.is_stale(6));
+    }
+}
@@ -0,0 +1,234 @@
+use stable_fs::error::Error;
+use stable_fs::fs::{Fd, FdFlags, FileSystem, Whence};
+use stable_fs::storage::types::{FileSize, FileType, Node};
+
+use crate::content_hash::hash_written;
+use crate::watch::{file_changed, notify_watches, WatchEventKind};
+use crate::{
+    is_query_write, root_path_filestat, root_path_open_with_rights, sync_if_flagged, wasi,
+    wasi_helpers::into_errno, FS,
+};
+
+/// Progress of a copy: the copied bytes and the requested length. Returning `false` stops the copy after the current chunk.
+pub type CopyProgress<'a> = &'a mut dyn FnMut(u64, u64) -> bool;
+
+// Copy the data of one chunk of the output file from the storage, returns the number of copied bytes,
+// 0 at the end of the input file. A chunk of zeros past the end of the output extends the file without being written.
+fn copy_chunk(
+    fs: &mut FileSystem,
+    node_in: Node,
+    offset_in: FileSize,
+    node_out: Node,
+    offset_out: FileSize,
+    buf: &mut [u8],
+) -> Result<FileSize, Error> {
+    let read = fs.storage.read(node_in, offset_in, buf)?;
+
+    if read == 0 {
+        return Ok(0);
+    }
+
+    let data = &buf[..read as usize];
+    let end = offset_out + read;
+
+    if offset_out >= fs.storage.get_metadata(node_out)?.size && data.iter().all(|b| *b == 0) {
+        fs.storage.resize_file(node_out, end)?;
+        return Ok(read);
+    }
+
+    fs.storage.write(node_out, offset_out, data)
+}
+
+// Find the nodes and the start offsets of a copy and check that the descriptors can be used for it.
+fn copy_offsets(
+    fs: &mut FileSystem,
+    fd_in: Fd,
+    offset_in: Option<u64>,
+    fd_out: Fd,
+    offset_out: Option<u64>,
+    len: u64,
+) -> Result<(Node, u64, Node, u64), i32> {
+    let metadata_in = fs.metadata(fd_in).map_err(into_errno)?;
+    let metadata_out = fs.metadata(fd_out).map_err(into_errno)?;
+
+    if metadata_in.file_type != FileType::RegularFile
+        || metadata_out.file_type != FileType::RegularFile
+    {
+        return Err(wasi::ERRNO_INVAL.raw() as i32);
+    }
+
+    let (_, stat_in) = fs.get_stat(fd_in).map_err(into_errno)?;
+    let (_, stat_out) = fs.get_stat(fd_out).map_err(into_errno)?;
+
+    // the storage is used directly, the rights are checked as by the reads and the writes
+    if stat_in.rights_base & wasi::RIGHTS_FD_READ == 0
+        || stat_out.rights_base & wasi::RIGHTS_FD_WRITE == 0
+    {
+        return Err(into_errno(Error::OperationNotPermitted));
+    }
+
+    if stat_out.flags.contains(FdFlags::APPEND) {
+        return Err(wasi::ERRNO_BADF.raw() as i32);
+    }
+
+    let start_in = match offset_in {
+        Some(offset) => offset,
+        None => fs.tell(fd_in).map_err(into_errno)?,
+    };
+    let start_out = match offset_out {
+        Some(offset) => offset,
+        None => fs.tell(fd_out).map_err(into_errno)?,
+    };
+
+    if metadata_in.node == metadata_out.node
+        && start_in < start_out.saturating_add(len)
+        && start_out < start_in.saturating_add(len)
+    {
+        return Err(wasi::ERRNO_INVAL.raw() as i32);
+    }
+
+    Ok((metadata_in.node, start_in, metadata_out.node, start_out))
+}
+
+/// Copy up to `len` bytes between two files, similar to `copy_file_range` on Linux. The data is copied within the
+/// storage one chunk of the output file at a time, without going through the descriptors or the caller's buffers.
+/// stable-fs has no API to share the chunks between files, so each chunk is duplicated, the chunks of zeros past
+/// the end of the output are not allocated.
+///
+/// The missing offsets are the positions of the descriptors, which are advanced by the copied bytes.
+/// Returns the number of copied bytes, less than `len` at the end of the input file or when the progress callback stops
+/// the copy, so that a copy too large for one message can continue in the next call. The callback is called after each chunk.
+/// Returns `ERRNO_BADF` for an output opened with `FDFLAGS_APPEND` and `ERRNO_INVAL` for overlapping ranges of the same file.
+pub fn copy_file_range(
+    fd_in: Fd,
+    offset_in: Option<u64>,
+    fd_out: Fd,
+    offset_out: Option<u64>,
+    len: u64,
+    mut progress: Option<CopyProgress>,
+) -> Result<u64, i32> {
+    if is_query_write() {
+        return Err(wasi::ERRNO_ROFS.raw() as i32);
+    }
+
+    let (node_in, start_in, node_out, start_out) =
+        FS.with_borrow_mut(|fs| copy_offsets(fs, fd_in, offset_in, fd_out, offset_out, len))?;
+
+    let chunk_size = FS.with_borrow(|fs| fs.storage.chunk_size()) as u64;
+    let mut buf = vec![0u8; len.min(chunk_size) as usize];
+    let mut copied = 0;
+    let mut error = None;
+
+    while copied < len {
+        // the pieces end at the chunk boundaries of the output, so that each chunk is written once
+        let position = start_out + copied;
+        let size = (len - copied).min(chunk_size - position % chunk_size) as usize;
+
+        // the file system is released between the chunks, so that the callback can use it
+        let piece = FS.with_borrow_mut(|fs| {
+            let written = copy_chunk(
+                fs,
+                node_in,
+                start_in + copied,
+                node_out,
+                position,
+                &mut buf[..size],
+            )?;
+
+            hash_written(fs, fd_out, position, &[&buf[..size]], written as usize);
+
+            Ok(written)
+        });
+
+        match piece {
+            Ok(0) => break,
+            Ok(written) => copied += written,
+            Err(er) => {
+                error = Some(into_errno(er));
+                break;
+            }
+        }
+
+        if progress
+            .as_mut()
+            .is_some_and(|progress| !progress(copied, len))
+        {
+            break;
+        }
+    }
+
+    let mut event = None;
+
+    let result = FS.with_borrow_mut(|fs| {
+        if offset_in.is_none() {
+            fs.seek(fd_in, (start_in + copied) as i64, Whence::SET)?;
+        }
+
+        if offset_out.is_none() {
+            fs.seek(fd_out, (start_out + copied) as i64, Whence::SET)?;
+        }
+
+        if copied > 0 {
+            event = file_changed(fs, fd_out, WatchEventKind::Modify);
+        }
+
+        sync_if_flagged(fs, fd_out, FdFlags::SYNC | FdFlags::DSYNC)
+    });
+
+    notify_watches(event);
+
+    // the error is reported if nothing was copied, as with a partial write
+    match (error, result) {
+        (Some(errno), _) if copied == 0 => Err(errno),
+        (_, Err(er)) => Err(into_errno(er)),
+        _ => Ok(copied),
+    }
+}
+
+/// Copy a file to a new or truncated file with `copy_file_range`, the paths are relative to the file system root.
+/// Returns the number of copied bytes, less than the file size if the progress callback stops the copy.
+pub fn copy_file(from: &str, to: &str, progress: Option<CopyProgress>) -> Result<u64, i32> {
+    let size = root_path_filestat(from)?.size;
+
+    let source = root_path_open_with_rights(from, 0, wasi::RIGHTS_FD_READ)?;
+    let target = root_path_open_with_rights(
+        to,
+        wasi::OFLAGS_CREAT | wasi::OFLAGS_TRUNC,
+        wasi::RIGHTS_FD_WRITE,
+    )?;
+
+    copy_file_range(source.0, Some(0), target.0, Some(0), size, progress)
+}
+
+/// Similar to `copy_file_range`, for calling from C or C++. A negative offset stands for the position of the descriptor,
+/// the number of copied bytes is stored in `copied`.
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn raw_copy_file_range(
+    fd_in: Fd,
+    offset_in: i64,
+    fd_out: Fd,
+    offset_out: i64,
+    len: u64,
+    copied: *mut u64,
+) -> i32 {
+    let offset = |offset: i64| u64::try_from(offset).ok();
+
+    match copy_file_range(
+        fd_in,
+        offset(offset_in),
+        fd_out,
+        offset(offset_out),
+        len,
+        None,
+    ) {
+        Ok(count) => {
+            if !copied.is_null() {
+                unsafe { *copied = count };
+            }
+
+            wasi::ERRNO_SUCCESS.raw() as i32
+        }
+        Err(errno) => errno,
+    }
+}
@@ -0,0 +1,273 @@
+#[cfg(feature = "encryption")]
+use std::collections::BTreeMap;
+
+#[cfg(feature = "encryption")]
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+#[cfg(feature = "encryption")]
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use stable_fs::error::Error;
+
+#[cfg(feature = "encryption")]
+use crate::{
+    job::{cancel_job, run_steps},
+    root_node,
+    transform::{transform_file, with_transforms, Rotation, RotationStatus, MODE_ENCRYPTED},
+    wasi,
+    wasi_helpers::into_errno,
+    ROTATION,
+};
+
+/// Size of the encryption keys in bytes.
+pub const KEY_SIZE: usize = 32;
+
+// Size of the authentication tag appended to the encrypted data.
+#[cfg(feature = "encryption")]
+const TAG_SIZE: usize = 16;
+
+// The key id and the nonce counter precede the encrypted data.
+#[cfg(feature = "encryption")]
+const HEADER_SIZE: usize = 4 + 8;
+
+// The 96-bit nonce of the cipher is the counter followed by zeros.
+#[cfg(feature = "encryption")]
+fn nonce(counter: u64) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[..8].copy_from_slice(&counter.to_le_bytes());
+    Nonce::from(nonce)
+}
+
+// The header is authenticated together with the associated data.
+#[cfg(feature = "encryption")]
+fn associated_data(associated: &[u8], header: &[u8]) -> Vec<u8> {
+    [associated, header].concat()
+}
+
+/// Keys encrypting the file data, the data is encrypted with the current key
+/// and decrypted with the key it was encrypted with.
+///
+/// The data is encrypted and authenticated with ChaCha20-Poly1305, the key id and the nonce counter
+/// are stored before the encrypted data and authenticated with the associated data. The keys are only kept on the heap.
+/// Without the `encryption` feature no key can be set and the encrypted data is not readable.
+#[derive(Default)]
+pub struct Keyring {
+    #[cfg(feature = "encryption")]
+    keys: BTreeMap<u32, ChaCha20Poly1305>,
+    current: Option<u32>,
+}
+
+impl Keyring {
+    pub fn new() -> Keyring {
+        Self::default()
+    }
+
+    // Add a key or replace the key with the same id, the key becomes the current one.
+    #[cfg(feature = "encryption")]
+    pub fn set_key(&mut self, key_id: u32, key: &[u8; KEY_SIZE]) {
+        self.keys
+            .insert(key_id, ChaCha20Poly1305::new(&Key::from(*key)));
+        self.current = Some(key_id);
+    }
+
+    #[cfg(feature = "encryption")]
+    pub fn remove_key(&mut self, key_id: u32) -> bool {
+        if self.current == Some(key_id) {
+            self.current = None;
+        }
+
+        self.keys.remove(&key_id).is_some()
+    }
+
+    pub fn current_key(&self) -> Option<u32> {
+        self.current
+    }
+
+    // Id of the key the data was encrypted with.
+    pub fn sealed_key(sealed: &[u8]) -> Option<u32> {
+        sealed
+            .get(..4)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(u32::from_le_bytes)
+    }
+
+    // Encrypt the data with the current key, the nonce counter must never repeat for a key.
+    #[cfg(feature = "encryption")]
+    pub fn seal(&self, counter: u64, associated: &[u8], data: &[u8]) -> Result<Vec<u8>, Error> {
+        let key_id = self.current.ok_or(Error::IOError)?;
+        let cipher = &self.keys[&key_id];
+
+        let mut sealed = Vec::with_capacity(HEADER_SIZE + data.len() + TAG_SIZE);
+        sealed.extend_from_slice(&key_id.to_le_bytes());
+        sealed.extend_from_slice(&counter.to_le_bytes());
+
+        let aad = associated_data(associated, &sealed);
+        let encrypted = cipher
+            .encrypt(
+                &nonce(counter),
+                Payload {
+                    msg: data,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| Error::IOError)?;
+        sealed.extend_from_slice(&encrypted);
+
+        Ok(sealed)
+    }
+
+    // Decrypt the data, fails with `IOError` if the key is missing or the data does not match its tag.
+    #[cfg(feature = "encryption")]
+    pub fn open(&self, associated: &[u8], sealed: &[u8]) -> Result<Vec<u8>, Error> {
+        if sealed.len() < HEADER_SIZE + TAG_SIZE {
+            return Err(Error::IOError);
+        }
+
+        let (header, encrypted) = sealed.split_at(HEADER_SIZE);
+        let key_id = u32::from_le_bytes(header[..4].try_into().unwrap());
+        let counter = u64::from_le_bytes(header[4..].try_into().unwrap());
+
+        let cipher = self.keys.get(&key_id).ok_or(Error::IOError)?;
+
+        let aad = associated_data(associated, header);
+        cipher
+            .decrypt(
+                &nonce(counter),
+                Payload {
+                    msg: encrypted,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| Error::IOError)
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    pub fn seal(&self, _: u64, _: &[u8], _: &[u8]) -> Result<Vec<u8>, Error> {
+        Err(Error::IOError)
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    pub fn open(&self, _: &[u8], _: &[u8]) -> Result<Vec<u8>, Error> {
+        Err(Error::IOError)
+    }
+}
+
+/// Add an encryption key and make it the current one, e.g. a key derived with vetKeys by the application.
+/// The files created afterwards are encrypted with the current key, the existing files are encrypted with
+/// `set_file_encryption`. The data encrypted with the other keys stays readable while their keys are set.
+///
+/// The keys are only kept on the heap and have to be provided again after an upgrade,
+/// reading data without its key or data modified outside of the polyfill returns `ERRNO_IO`.
+///
+/// # Parameters
+/// - `key_id`: Identifier of the key stored with the encrypted data, a key with the same id is replaced
+/// - `key`: The 32-byte key
+#[cfg(feature = "encryption")]
+pub fn set_encryption_key(key_id: u32, key: &[u8; KEY_SIZE]) {
+    with_transforms(|transforms| transforms.keyring().set_key(key_id, key))
+}
+
+/// Remove an encryption key, returns `ERRNO_NOENT` if the key is not set.
+/// New files are not encrypted if the current key is removed.
+#[cfg(feature = "encryption")]
+pub fn remove_encryption_key(key_id: u32) -> i32 {
+    if with_transforms(|transforms| transforms.keyring().remove_key(key_id)) {
+        wasi::ERRNO_SUCCESS.raw() as i32
+    } else {
+        wasi::ERRNO_NOENT.raw() as i32
+    }
+}
+
+/// Encrypt the blocks of the encrypted files with the current key, so that the previous keys can be removed.
+/// Each call encrypts at least one block and returns `RotationStatus::InProgress` once the instruction limit is
+/// reached, the rotation is done when it returns `RotationStatus::Done` with the number of files and blocks encrypted
+/// again. Setting another current key starts the rotation over, the blocks already encrypted with it are skipped.
+///
+/// Returns `ERRNO_IO` if there is no current key or a previous key is missing, the next call starts over.
+///
+/// # Parameters
+/// - `instruction_limit`: Number of instructions after which the call returns `RotationStatus::InProgress`
+#[cfg(feature = "encryption")]
+pub fn rotate_encryption_key(instruction_limit: u64) -> Result<RotationStatus, i32> {
+    let key_id = with_transforms(|transforms| transforms.keyring().current_key())
+        .ok_or(wasi::ERRNO_IO.raw() as i32)?;
+
+    // the cached writes are encrypted when they are stored
+    run_steps(
+        &ROTATION,
+        |rotation| rotation.key_id() == key_id,
+        || Rotation::new(key_id),
+        instruction_limit,
+        |rotation| with_transforms(|transforms| rotation.step(transforms)),
+    )
+    .map_err(into_errno)
+}
+
+/// Stop the key rotation in progress, the next `rotate_encryption_key` call starts from the beginning
+#[cfg(feature = "encryption")]
+pub fn cancel_key_rotation() {
+    cancel_job(&ROTATION)
+}
+
+/// Store a file encrypted or unencrypted, the existing content is converted.
+/// Encrypting a file returns `ERRNO_IO` if there is no encryption key, see `set_encryption_key`,
+/// and `ERRNO_NOTSUP` for the mounted files and if the blocks would not persist, see `init_transforms_with_memory`.
+///
+/// # Parameters
+/// - `path`: Path relative to the file system root
+/// - `encrypted`: `true` to encrypt the file with the current key, `false` to store it unencrypted
+#[cfg(feature = "encryption")]
+pub fn set_file_encryption(path: &str, encrypted: bool) -> i32 {
+    match transform_file(path, MODE_ENCRYPTED, encrypted) {
+        Ok(_) => wasi::ERRNO_SUCCESS.raw() as i32,
+        Err(er) => into_errno(er),
+    }
+}
+
+/// Check if a file is stored encrypted
+#[cfg(feature = "encryption")]
+pub fn is_file_encrypted(path: &str) -> Result<bool, i32> {
+    let node = root_node(path)?;
+
+    Ok(with_transforms(|transforms| {
+        transforms.mode(node) & MODE_ENCRYPTED != 0
+    }))
+}
+
+#[cfg(all(test, feature = "encryption"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seal_open_and_rotate() {
+        let mut keyring = Keyring::new();
+        assert!(keyring.seal(0, b"", b"data").is_err());
+
+        keyring.set_key(1, &[1; KEY_SIZE]);
+        let sealed = keyring.seal(7, b"block 0", b"secret data").unwrap();
+        assert!(!sealed.windows(6).any(|w| w == b"secret"));
+        assert_eq!(sealed.len(), HEADER_SIZE + 11 + TAG_SIZE);
+        assert_eq!(Keyring::sealed_key(&sealed), Some(1));
+        assert_eq!(keyring.open(b"block 0", &sealed).unwrap(), b"secret data");
+
+        // the data is bound to the associated data and protected from changes
+        assert!(keyring.open(b"block 1", &sealed).is_err());
+        let mut tampered = sealed.clone();
+        tampered[HEADER_SIZE] ^= 1;
+        assert!(keyring.open(b"block 0", &tampered).is_err());
+        let mut tampered = sealed.clone();
+        tampered[4] ^= 1;
+        assert!(keyring.open(b"block 0", &tampered).is_err());
+        assert!(keyring.open(b"block 0", &sealed[..10]).is_err());
+
+        // the old data stays readable with the previous key
+        keyring.set_key(2, &[2; KEY_SIZE]);
+        assert_eq!(keyring.current_key(), Some(2));
+        assert_eq!(keyring.open(b"block 0", &sealed).unwrap(), b"secret data");
+
+        assert!(keyring.remove_key(1));
+        assert!(keyring.open(b"block 0", &sealed).is_err());
+
+        // a different key with the same id does not authenticate the data
+        keyring.set_key(1, &[3; KEY_SIZE]);
+        assert!(keyring.open(b"block 0", &sealed).is_err());
+    }
+}
@@ -1,3 +1,4 @@
+use crate::validation::raw_slice_mut;
 use crate::wasi;
 
 pub struct Environment {
@@ -26,8 +27,8 @@ impl Environment {
     // buffer    -   The buffer containing all the pairs. The buffer must have enough memory to fit in all the (name,value) pairs.
     pub unsafe fn environ_get(&self, entries: *mut *mut u8, buffer: *mut u8) -> wasi::Errno {
         unsafe {
-            let entries = std::slice::from_raw_parts_mut(entries, self.data_values.len());
-            let buffer = std::slice::from_raw_parts_mut(buffer, self.data_size);
+            let entries = raw_slice_mut(entries, self.data_values.len());
+            let buffer = raw_slice_mut(buffer, self.data_size);
 
             let mut cursor = 0;
 
@@ -0,0 +1,496 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use stable_fs::error::Error;
+use stable_fs::storage::types::{
+    DirEntry, DirEntryIndex, FileName, FileSize, FileType, Metadata, Node, Times,
+};
+use stable_fs::storage::Storage;
+
+use crate::job::{Job, JobStatus};
+#[cfg(feature = "fsck")]
+use crate::{
+    job::{cancel_job, run_steps},
+    wasi_helpers::into_errno,
+    FS, FSCK,
+};
+
+/// Directory in the root receiving the repaired orphaned nodes.
+pub const LOST_AND_FOUND: &str = "lost+found";
+
+/// Inconsistency found by the file system check.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FsckProblem {
+    /// The directory entry points to a node without metadata, repaired by removing the entry.
+    DanglingEntry { parent: Node, name: String },
+    /// The type stored in the directory entry differs from the node type, repaired by updating the entry.
+    EntryTypeMismatch { parent: Node, name: String },
+    /// The link count differs from the number of entries pointing to the node, repaired by updating the count.
+    LinkCountMismatch { stored: u64, actual: u64 },
+    /// The directory size differs from the number of its entries, repaired by updating the size.
+    DirectorySizeMismatch { stored: u64, actual: u64 },
+    /// The node is not reachable from the root, repaired by linking it into `lost+found`.
+    OrphanedNode,
+    /// A memory is mounted on a node without metadata, repaired by unmounting it.
+    MountWithoutNode,
+    /// The file is larger than its size limit, repaired by truncating it to the limit.
+    SizeAboveLimit { size: FileSize, limit: FileSize },
+    /// The file data cannot be read up to the file size, not repaired.
+    UnreadableData,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FsckIssue {
+    pub node: Node,
+    pub problem: FsckProblem,
+    pub repaired: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FsckReport {
+    /// Number of the checked nodes
+    pub nodes: u64,
+    /// Number of the checked directories
+    pub directories: u64,
+    pub issues: Vec<FsckIssue>,
+}
+
+impl FsckReport {
+    pub fn is_consistent(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+pub type FsckStatus = JobStatus<FsckReport>;
+
+enum Phase {
+    Start,
+    Walk,
+    Orphans { next: Node },
+    LinkCounts { nodes: Vec<Node>, position: usize },
+    Done,
+}
+
+/// File system check in phases: the directories are walked from the root counting the entries pointing to each node,
+/// then the nodes not reached from the root are looked up and finally the link counts are compared.
+///
+/// A step checks one directory, one node or one link count. The nodes created after the check started are not checked.
+pub struct Fsck {
+    repair: bool,
+    phase: Phase,
+    // nodes below this one existed when the check started
+    end: Node,
+    queue: VecDeque<Node>,
+    directories: BTreeSet<Node>,
+    links: BTreeMap<Node, u64>,
+    orphans: BTreeSet<Node>,
+    lost_and_found: Option<Node>,
+    report: FsckReport,
+}
+
+impl Job for Fsck {
+    type Report = FsckReport;
+
+    fn is_done(&self) -> bool {
+        matches!(self.phase, Phase::Done)
+    }
+
+    fn into_report(self) -> FsckReport {
+        self.report
+    }
+}
+
+impl Fsck {
+    pub fn new(repair: bool) -> Fsck {
+        Fsck {
+            repair,
+            phase: Phase::Start,
+            end: 0,
+            queue: VecDeque::new(),
+            directories: BTreeSet::new(),
+            links: BTreeMap::new(),
+            orphans: BTreeSet::new(),
+            lost_and_found: None,
+            report: FsckReport::default(),
+        }
+    }
+
+    pub fn is_repairing(&self) -> bool {
+        self.repair
+    }
+
+    fn issue(&mut self, node: Node, problem: FsckProblem, repaired: bool) {
+        self.report.issues.push(FsckIssue {
+            node,
+            problem,
+            repaired,
+        });
+    }
+
+    // Do one step of the check: a directory, a node or a link count.
+    pub fn step(&mut self, storage: &mut dyn Storage) -> Result<(), Error> {
+        self.phase = match std::mem::replace(&mut self.phase, Phase::Done) {
+            Phase::Start => {
+                // the next node number bounds the nodes to look for orphans, it is allocated and left unused
+                self.end = storage.new_node();
+
+                let root = storage.root_node();
+                self.directories.insert(root);
+                self.queue.push_back(root);
+
+                Phase::Walk
+            }
+            Phase::Walk => match self.queue.pop_front() {
+                Some(dir) => {
+                    self.check_directory(storage, dir)?;
+                    Phase::Walk
+                }
+                None => Phase::Orphans {
+                    next: storage.root_node() + 1,
+                },
+            },
+            Phase::Orphans { next } => {
+                // the directories found orphaned are walked before the next nodes are checked
+                if let Some(dir) = self.queue.pop_front() {
+                    self.check_directory(storage, dir)?;
+                    Phase::Orphans { next }
+                } else if next >= self.end {
+                    let nodes = self
+                        .links
+                        .keys()
+                        .copied()
+                        .filter(|node| !self.orphans.contains(node))
+                        .collect();
+
+                    Phase::LinkCounts { nodes, position: 0 }
+                } else {
+                    self.check_orphan(storage, next)?;
+                    Phase::Orphans { next: next + 1 }
+                }
+            }
+            Phase::LinkCounts { nodes, position } => match nodes.get(position) {
+                Some(&node) => {
+                    self.check_link_count(storage, node)?;
+                    Phase::LinkCounts {
+                        nodes,
+                        position: position + 1,
+                    }
+                }
+                None => Phase::Done,
+            },
+            Phase::Done => Phase::Done,
+        };
+
+        Ok(())
+    }
+
+    fn check_directory(&mut self, storage: &mut dyn Storage, dir: Node) -> Result<(), Error> {
+        self.report.nodes += 1;
+        self.report.directories += 1;
+
+        let mut entries: Vec<(DirEntryIndex, DirEntry)> = Vec::new();
+        storage.with_direntries(dir, Some(0), &mut |index, entry| {
+            entries.push((*index, entry.clone()));
+            true
+        });
+
+        let mut count = 0;
+
+        for (index, entry) in entries {
+            let name = String::from_utf8_lossy(&entry.name.bytes[..entry.name.length as usize])
+                .into_owned();
+
+            let metadata = match storage.get_metadata(entry.node) {
+                Ok(metadata) => metadata,
+                Err(_) => {
+                    if self.repair {
+                        storage.rm_direntry(dir, index);
+                    } else {
+                        count += 1;
+                    }
+
+                    let problem = FsckProblem::DanglingEntry { parent: dir, name };
+                    self.issue(entry.node, problem, self.repair);
+                    continue;
+                }
+            };
+
+            count += 1;
+
+            if entry
+                .entry_type
+                .is_some_and(|entry_type| entry_type != metadata.file_type)
+            {
+                if self.repair {
+                    let entry = DirEntry {
+                        entry_type: Some(metadata.file_type),
+                        ..entry.clone()
+                    };
+                    storage.put_direntry(dir, index, entry);
+                }
+
+                let problem = FsckProblem::EntryTypeMismatch { parent: dir, name };
+                self.issue(entry.node, problem, self.repair);
+            }
+
+            let links = self.links.entry(entry.node).or_insert(0);
+            *links += 1;
+
+            // the nodes with several links are checked once
+            if *links == 1 {
+                self.check_node(storage, metadata)?;
+            }
+        }
+
+        let mut metadata = storage.get_metadata(dir)?;
+
+        if metadata.size != count {
+            let problem = FsckProblem::DirectorySizeMismatch {
+                stored: metadata.size,
+                actual: count,
+            };
+
+            if self.repair {
+                metadata.size = count;
+                storage.put_metadata(dir, &metadata)?;
+            }
+
+            self.issue(dir, problem, self.repair);
+        }
+
+        Ok(())
+    }
+
+    fn check_node(&mut self, storage: &mut dyn Storage, metadata: Metadata) -> Result<(), Error> {
+        match metadata.file_type {
+            FileType::Directory => {
+                if self.directories.insert(metadata.node) {
+                    self.queue.push_back(metadata.node);
+                }
+                Ok(())
+            }
+            _ => self.check_file(storage, metadata),
+        }
+    }
+
+    fn check_file(
+        &mut self,
+        storage: &mut dyn Storage,
+        mut metadata: Metadata,
+    ) -> Result<(), Error> {
+        self.report.nodes += 1;
+
+        let node = metadata.node;
+
+        if let Some(limit) = metadata.maximum_size_allowed {
+            if metadata.size > limit {
+                let problem = FsckProblem::SizeAboveLimit {
+                    size: metadata.size,
+                    limit,
+                };
+
+                if self.repair {
+                    metadata.size = limit;
+                    storage.put_metadata(node, &metadata)?;
+                }
+
+                self.issue(node, problem, self.repair);
+            }
+        }
+
+        let size = metadata
+            .size
+            .min(metadata.maximum_size_allowed.unwrap_or(FileSize::MAX));
+
+        if size > 0 {
+            // reading the last byte goes through the file's chunks or its mounted memory
+            let mut last = [0u8; 1];
+
+            if !matches!(storage.read(node, size - 1, &mut last), Ok(1)) {
+                self.issue(node, FsckProblem::UnreadableData, false);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_orphan(&mut self, storage: &mut dyn Storage, node: Node) -> Result<(), Error> {
+        if self.links.contains_key(&node) {
+            return Ok(());
+        }
+
+        let metadata = match storage.get_metadata(node) {
+            Ok(metadata) => metadata,
+            Err(_) => {
+                if storage.is_mounted(node) {
+                    if self.repair {
+                        storage.unmount_node(node)?;
+                    }
+
+                    self.issue(node, FsckProblem::MountWithoutNode, self.repair);
+                }
+
+                return Ok(());
+            }
+        };
+
+        if self.repair {
+            self.link_lost_and_found(storage, &metadata)?;
+            self.links.insert(node, 1);
+        } else {
+            self.links.insert(node, 0);
+            self.orphans.insert(node);
+        }
+
+        self.issue(node, FsckProblem::OrphanedNode, self.repair);
+
+        self.check_node(storage, metadata)
+    }
+
+    fn check_link_count(&mut self, storage: &mut dyn Storage, node: Node) -> Result<(), Error> {
+        let actual = self.links[&node];
+        let mut metadata = storage.get_metadata(node)?;
+
+        if metadata.link_count != actual {
+            let problem = FsckProblem::LinkCountMismatch {
+                stored: metadata.link_count,
+                actual,
+            };
+
+            if self.repair {
+                metadata.link_count = actual;
+                storage.put_metadata(node, &metadata)?;
+            }
+
+            self.issue(node, problem, self.repair);
+        }
+
+        Ok(())
+    }
+
+    // Find or create the `lost+found` directory in the root.
+    fn lost_and_found(&mut self, storage: &mut dyn Storage) -> Result<Node, Error> {
+        if let Some(node) = self.lost_and_found {
+            return Ok(node);
+        }
+
+        let root = storage.root_node();
+        let name = FileName::new(LOST_AND_FOUND.as_bytes())?;
+
+        let mut found = None;
+        storage.with_direntries(root, Some(0), &mut |_, entry| {
+            if entry.name == name {
+                found = Some(entry.node);
+            }
+            found.is_none()
+        });
+
+        let node = match found {
+            Some(node) => node,
+            None => {
+                let node = storage.new_node();
+                let created = storage.get_metadata(root)?.times.modified;
+
+                storage.put_metadata(
+                    node,
+                    &Metadata {
+                        node,
+                        file_type: FileType::Directory,
+                        link_count: 1,
+                        size: 0,
+                        times: Times {
+                            accessed: created,
+                            modified: created,
+                            created,
+                        },
+                        first_dir_entry: None,
+                        last_dir_entry: None,
+                        chunk_type: None,
+                        maximum_size_allowed: None,
+                    },
+                )?;
+
+                add_entry(storage, root, node, LOST_AND_FOUND, FileType::Directory)?;
+                node
+            }
+        };
+
+        self.lost_and_found = Some(node);
+
+        Ok(node)
+    }
+
+    fn link_lost_and_found(
+        &mut self,
+        storage: &mut dyn Storage,
+        metadata: &Metadata,
+    ) -> Result<(), Error> {
+        let dir = self.lost_and_found(storage)?;
+
+        add_entry(
+            storage,
+            dir,
+            metadata.node,
+            &format!("#{}", metadata.node),
+            metadata.file_type,
+        )?;
+
+        let mut metadata = metadata.clone();
+        metadata.link_count = 1;
+        storage.put_metadata(metadata.node, &metadata)
+    }
+}
+
+fn add_entry(
+    storage: &mut dyn Storage,
+    dir: Node,
+    node: Node,
+    name: &str,
+    file_type: FileType,
+) -> Result<(), Error> {
+    let index = storage.new_direntry_index(dir);
+
+    storage.put_direntry(
+        dir,
+        index,
+        DirEntry {
+            name: FileName::new(name.as_bytes())?,
+            node,
+            entry_type: Some(file_type),
+        },
+    );
+
+    let mut metadata = storage.get_metadata(dir)?;
+    metadata.size += 1;
+    storage.put_metadata(dir, &metadata)
+}
+
+/// Check the consistency of the file system: the directory entries, the link counts, the directory sizes,
+/// the nodes not reachable from the root, the file size limits and the mounted memories.
+/// A large file system is checked across several update calls, each checking directories and nodes until the
+/// instruction limit, until the check returns `FsckStatus::Done` with the report.
+///
+/// With `repair` the problems are fixed as they are found, the orphaned nodes are linked into the `lost+found` directory.
+/// The file system should not be modified between the calls of a repairing check.
+/// A call with a different `repair` value starts a new check. The storage has no node count, so a check starts by
+/// allocating a node number, which is not used by any file afterwards.
+///
+/// # Parameters
+/// - `repair`: Fix the found problems
+/// - `instruction_limit`: Number of instructions after which the call returns `FsckStatus::InProgress`
+#[cfg(feature = "fsck")]
+pub fn fsck(repair: bool, instruction_limit: u64) -> Result<FsckStatus, i32> {
+    run_steps(
+        &FSCK,
+        |checker| checker.is_repairing() == repair,
+        || Fsck::new(repair),
+        instruction_limit,
+        |checker| FS.with_borrow_mut(|fs| checker.step(fs.storage.as_mut())),
+    )
+    .map_err(into_errno)
+}
+
+/// Stop the file system check in progress, the next `fsck` call starts from the beginning
+#[cfg(feature = "fsck")]
+pub fn cancel_fsck() {
+    cancel_job(&FSCK)
+}
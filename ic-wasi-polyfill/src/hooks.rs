@@ -0,0 +1,486 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use stable_fs::fs::Fd;
+
+use crate::validation::{raw_slice, raw_slice_mut};
+use crate::wasi;
+
+#[cfg(feature = "hooks")]
+use crate::HOOKS;
+
+/// Decision of a hook about the intercepted call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HookOutcome {
+    /// Run the built-in implementation after the hook.
+    Continue,
+    /// Skip the built-in implementation and return the given errno to the caller.
+    Return(i32),
+}
+
+/// The parameters of an intercepted WASI call.
+///
+/// Memory regions passed by the caller are available as slices, the output parameters as mutable references.
+/// A hook returning `HookOutcome::Return` is responsible for filling the outputs.
+/// Changing the input values does not affect the built-in implementation.
+pub enum WasiCall<'a> {
+    ArgsGet {
+        argv: *mut *mut u8,
+        argv_buf: *mut u8,
+    },
+    ArgsSizesGet {
+        argc: &'a mut wasi::Size,
+        argv_buf_size: &'a mut wasi::Size,
+    },
+    ClockResGet {
+        id: i32,
+        resolution: &'a mut u64,
+    },
+    ClockTimeGet {
+        id: i32,
+        precision: i64,
+        time: &'a mut u64,
+    },
+    EnvironGet {
+        environ: *mut *mut u8,
+        environ_buf: *mut u8,
+    },
+    EnvironSizesGet {
+        count: &'a mut wasi::Size,
+        buf_size: &'a mut wasi::Size,
+    },
+    FdAdvise {
+        fd: Fd,
+        offset: i64,
+        len: i64,
+        advice: i32,
+    },
+    FdAllocate {
+        fd: Fd,
+        offset: i64,
+        len: i64,
+    },
+    FdClose {
+        fd: Fd,
+    },
+    FdDatasync {
+        fd: Fd,
+    },
+    FdFdstatGet {
+        fd: Fd,
+        stat: &'a mut wasi::Fdstat,
+    },
+    FdFdstatSetFlags {
+        fd: Fd,
+        flags: i32,
+    },
+    FdFdstatSetRights {
+        fd: i32,
+        rights_base: i64,
+        rights_inheriting: i64,
+    },
+    FdFilestatGet {
+        fd: Fd,
+        stat: &'a mut wasi::Filestat,
+    },
+    FdFilestatSetSize {
+        fd: Fd,
+        size: i64,
+    },
+    FdFilestatSetTimes {
+        fd: Fd,
+        atim: i64,
+        mtim: i64,
+        fst_flags: i32,
+    },
+    FdPread {
+        fd: Fd,
+        bufs: Vec<&'a mut [u8]>,
+        offset: i64,
+        read: &'a mut wasi::Size,
+    },
+    FdPrestatGet {
+        fd: i32,
+        prestat: &'a mut wasi::Prestat,
+    },
+    FdPrestatDirName {
+        fd: i32,
+        path: &'a mut [u8],
+    },
+    FdPwrite {
+        fd: Fd,
+        bufs: Vec<&'a [u8]>,
+        offset: i64,
+        written: &'a mut wasi::Size,
+    },
+    FdRead {
+        fd: Fd,
+        bufs: Vec<&'a mut [u8]>,
+        read: &'a mut wasi::Size,
+    },
+    FdReaddir {
+        fd: Fd,
+        buf: &'a mut [u8],
+        cookie: i64,
+        used: &'a mut wasi::Size,
+    },
+    FdRenumber {
+        from: Fd,
+        to: Fd,
+    },
+    FdSeek {
+        fd: Fd,
+        delta: i64,
+        whence: i32,
+        position: &'a mut wasi::Filesize,
+    },
+    FdSync {
+        fd: Fd,
+    },
+    FdTell {
+        fd: Fd,
+        position: &'a mut wasi::Filesize,
+    },
+    FdWrite {
+        fd: Fd,
+        bufs: Vec<&'a [u8]>,
+        written: &'a mut wasi::Size,
+    },
+    PathCreateDirectory {
+        fd: Fd,
+        path: &'a str,
+    },
+    PathFilestatGet {
+        fd: i32,
+        flags: i32,
+        path: &'a str,
+        stat: &'a mut wasi::Filestat,
+    },
+    PathFilestatSetTimes {
+        fd: i32,
+        flags: i32,
+        path: &'a str,
+        atim: i64,
+        mtim: i64,
+        fst_flags: i32,
+    },
+    PathLink {
+        old_fd: Fd,
+        flags: i32,
+        old_path: &'a str,
+        new_fd: Fd,
+        new_path: &'a str,
+    },
+    PathOpen {
+        fd: Fd,
+        dirflags: i32,
+        path: &'a str,
+        oflags: i32,
+        rights_base: wasi::Rights,
+        rights_inheriting: wasi::Rights,
+        fdflags: i32,
+        opened_fd: &'a mut Fd,
+    },
+    /// The output buffer is passed as a raw address in the WASI signature.
+    PathReadlink {
+        fd: i32,
+        path: &'a str,
+        buf: i32,
+        buf_len: i32,
+        used: *mut usize,
+    },
+    PathRemoveDirectory {
+        fd: Fd,
+        path: &'a str,
+    },
+    PathRename {
+        old_fd: i32,
+        old_path: &'a str,
+        new_fd: i32,
+        new_path: &'a str,
+    },
+    PathSymlink {
+        old_path: &'a str,
+        fd: i32,
+        new_path: &'a str,
+    },
+    PathUnlinkFile {
+        fd: i32,
+        path: &'a str,
+    },
+    PollOneoff {
+        subscriptions: *const wasi::Subscription,
+        events: *mut wasi::Event,
+        nsubscriptions: i32,
+        nevents: *mut wasi::Size,
+    },
+    ProcRaise {
+        sig: i32,
+    },
+    RandomGet {
+        buf: &'a mut [u8],
+    },
+    SchedYield,
+    SockAccept {
+        fd: i32,
+        flags: i32,
+        new_fd: *mut u32,
+    },
+    SockRecv {
+        fd: i32,
+        ri_data: *const wasi::Iovec,
+        ri_data_len: i32,
+        ri_flags: i32,
+        ro_datalen: *mut usize,
+        ro_flags: *mut u16,
+    },
+    SockSend {
+        fd: i32,
+        si_data: *const wasi::Ciovec,
+        si_data_len: i32,
+        si_flags: i32,
+        so_datalen: *mut wasi::Size,
+    },
+    SockShutdown {
+        fd: i32,
+        how: i32,
+    },
+}
+
+/// Names of the WASI functions that can be intercepted with a hook.
+pub const HOOKABLE_FUNCTIONS: &[&str] = &[
+    "args_get",
+    "args_sizes_get",
+    "clock_res_get",
+    "clock_time_get",
+    "environ_get",
+    "environ_sizes_get",
+    "fd_advise",
+    "fd_allocate",
+    "fd_close",
+    "fd_datasync",
+    "fd_fdstat_get",
+    "fd_fdstat_set_flags",
+    "fd_fdstat_set_rights",
+    "fd_filestat_get",
+    "fd_filestat_set_size",
+    "fd_filestat_set_times",
+    "fd_pread",
+    "fd_prestat_get",
+    "fd_prestat_dir_name",
+    "fd_pwrite",
+    "fd_read",
+    "fd_readdir",
+    "fd_renumber",
+    "fd_seek",
+    "fd_sync",
+    "fd_tell",
+    "fd_write",
+    "path_create_directory",
+    "path_filestat_get",
+    "path_filestat_set_times",
+    "path_link",
+    "path_open",
+    "path_readlink",
+    "path_remove_directory",
+    "path_rename",
+    "path_symlink",
+    "path_unlink_file",
+    "poll_oneoff",
+    "proc_raise",
+    "random_get",
+    "sched_yield",
+    "sock_accept",
+    "sock_recv",
+    "sock_send",
+    "sock_shutdown",
+];
+
+pub type Hook = Box<dyn FnMut(&mut WasiCall) -> HookOutcome>;
+
+/// Registered hooks, one per WASI function.
+#[derive(Default)]
+pub struct Hooks {
+    hooks: BTreeMap<&'static str, Hook>,
+    // functions whose hook is taken out of the registry while it runs
+    running: BTreeSet<&'static str>,
+    // running hooks removed by themselves or by another hook, they are not restored
+    removed: BTreeSet<&'static str>,
+}
+
+impl Hooks {
+    pub fn new() -> Hooks {
+        Self::default()
+    }
+
+    // Register the hook, replaces the previous hook of the function. Returns false for an unknown function name.
+    pub fn set(&mut self, function: &str, hook: Hook) -> bool {
+        match HOOKABLE_FUNCTIONS.iter().find(|f| **f == function) {
+            Some(function) => {
+                self.hooks.insert(function, hook);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn remove(&mut self, function: &str) -> bool {
+        let removed = self.hooks.remove(function).is_some();
+
+        match self.running.get(function) {
+            Some(running) => self.removed.insert(*running) || removed,
+            None => removed,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.hooks.clear();
+        self.removed.extend(self.running.iter());
+    }
+
+    pub fn contains(&self, function: &str) -> bool {
+        !self.hooks.is_empty() && self.hooks.contains_key(function)
+    }
+
+    // Temporarily take the hook out of the registry, so that it can call the WASI functions itself.
+    pub fn take(&mut self, function: &'static str) -> Option<Hook> {
+        let hook = self.hooks.remove(function)?;
+        self.running.insert(function);
+
+        Some(hook)
+    }
+
+    // Put the hook back unless it was removed or a new one was registered while it was running.
+    pub fn restore(&mut self, function: &'static str, hook: Hook) {
+        self.running.remove(function);
+
+        if !self.removed.remove(function) {
+            self.hooks.entry(function).or_insert(hook);
+        }
+    }
+}
+
+/// Register a hook running before the built-in implementation of a WASI function (e.g. `"fd_write"`),
+/// replaces the previously registered hook. Returns `ERRNO_INVAL` if the function cannot be hooked.
+#[cfg(feature = "hooks")]
+pub fn set_hook<F>(function: &str, hook: F) -> i32
+where
+    F: FnMut(&mut WasiCall) -> HookOutcome + 'static,
+{
+    let added = HOOKS.with_borrow_mut(|hooks| hooks.set(function, Box::new(hook)));
+
+    if added {
+        wasi::ERRNO_SUCCESS.raw() as i32
+    } else {
+        wasi::ERRNO_INVAL.raw() as i32
+    }
+}
+
+/// Remove the hook of a WASI function, the built-in implementation is used again.
+/// A running hook can remove itself, e.g. to replace a single call only.
+#[cfg(feature = "hooks")]
+pub fn remove_hook(function: &str) {
+    HOOKS.with_borrow_mut(|hooks| hooks.remove(function));
+}
+
+/// Remove all the registered hooks
+#[cfg(feature = "hooks")]
+pub fn clear_hooks() {
+    HOOKS.with_borrow_mut(|hooks| hooks.clear());
+}
+
+// Run the hook of the function with the hook taken out of the registry, so that it can call the WASI functions itself.
+#[cfg(feature = "hooks")]
+pub(crate) fn run_hook(function: &'static str, call: &mut WasiCall) -> HookOutcome {
+    let Some(mut hook) = HOOKS.with_borrow_mut(|hooks| hooks.take(function)) else {
+        return HookOutcome::Continue;
+    };
+
+    let outcome = hook(call);
+
+    HOOKS.with_borrow_mut(|hooks| hooks.restore(function, hook));
+
+    outcome
+}
+
+/// Collect the caller's output buffers.
+///
+/// # Safety
+///
+/// `iovs` must point to `len` valid buffer descriptors.
+pub unsafe fn iovec_slices<'a>(iovs: *const wasi::Iovec, len: i32) -> Vec<&'a mut [u8]> {
+    let iovs = unsafe { raw_slice(iovs, len as usize) };
+
+    iovs.iter()
+        .map(|iov| unsafe { raw_slice_mut(iov.buf, iov.buf_len) })
+        .collect()
+}
+
+/// Collect the caller's input buffers.
+///
+/// # Safety
+///
+/// `iovs` must point to `len` valid buffer descriptors.
+pub unsafe fn ciovec_slices<'a>(iovs: *const wasi::Ciovec, len: i32) -> Vec<&'a [u8]> {
+    let iovs = unsafe { raw_slice(iovs, len as usize) };
+
+    iovs.iter()
+        .map(|iov| unsafe { raw_slice(iov.buf, iov.buf_len) })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HookOutcome, Hooks, WasiCall};
+
+    #[test]
+    fn register_take_and_restore() {
+        let mut hooks = Hooks::new();
+
+        assert!(!hooks.set("fd_unknown", Box::new(|_| HookOutcome::Continue)));
+        assert!(hooks.set("sched_yield", Box::new(|_| HookOutcome::Return(1))));
+        assert!(hooks.contains("sched_yield"));
+        assert!(!hooks.contains("fd_write"));
+
+        let mut hook = hooks.take("sched_yield").unwrap();
+        assert!(!hooks.contains("sched_yield"));
+        assert_eq!(hook(&mut WasiCall::SchedYield), HookOutcome::Return(1));
+
+        // a hook registered while the previous one was running is kept
+        hooks.set("sched_yield", Box::new(|_| HookOutcome::Return(2)));
+        hooks.restore("sched_yield", hook);
+
+        let mut hook = hooks.take("sched_yield").unwrap();
+        assert_eq!(hook(&mut WasiCall::SchedYield), HookOutcome::Return(2));
+        hooks.restore("sched_yield", hook);
+
+        assert!(hooks.remove("sched_yield"));
+        assert!(!hooks.remove("sched_yield"));
+    }
+
+    #[test]
+    fn removed_while_running() {
+        let mut hooks = Hooks::new();
+
+        hooks.set("sched_yield", Box::new(|_| HookOutcome::Return(1)));
+        let hook = hooks.take("sched_yield").unwrap();
+
+        // the running hook removes itself
+        assert!(hooks.remove("sched_yield"));
+        assert!(!hooks.remove("sched_yield"));
+        hooks.restore("sched_yield", hook);
+        assert!(!hooks.contains("sched_yield"));
+
+        hooks.set("sched_yield", Box::new(|_| HookOutcome::Return(1)));
+        let hook = hooks.take("sched_yield").unwrap();
+
+        // the hook is removed and a new one registered while it was running
+        hooks.clear();
+        hooks.set("sched_yield", Box::new(|_| HookOutcome::Return(2)));
+        hooks.restore("sched_yield", hook);
+
+        let mut hook = hooks.take("sched_yield").unwrap();
+        assert_eq!(hook(&mut WasiCall::SchedYield), HookOutcome::Return(2));
+        hooks.restore("sched_yield", hook);
+        assert!(hooks.contains("sched_yield"));
+    }
+}
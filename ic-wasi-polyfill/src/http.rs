@@ -0,0 +1,618 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use candid::{CandidType, Deserialize};
+use ic_certification::{label, labeled_hash, AsHashTree, Hash, RbTree};
+use stable_fs::fs::{DstBuf, Fd, FdStat, FileSystem, OpenFlags};
+use stable_fs::storage::types::{FileSize, FileType};
+
+use crate::content_hash::{file_content_hash, set_content_hashing, HASH_SIZE};
+use crate::{
+    ic_certified_data_set, ic_data_certificate, ic_in_replicated_execution, root_path, wasi,
+    wasi_helpers::into_errno, FS, HTTP_FILES,
+};
+
+/// Label of the certified file hashes in the certification tree, as used by the asset certification v1.
+pub const CERTIFICATION_LABEL: &str = "http_assets";
+
+/// File served for the URLs ending with `/`.
+pub const INDEX_FILE: &str = "index.html";
+
+pub type HeaderField = (String, String);
+
+/// Request passed to the canister's `http_request` query.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<HeaderField>,
+    pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Response returned from the canister's `http_request` query.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<HeaderField>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn status(status_code: u16, message: &str) -> HttpResponse {
+        HttpResponse {
+            status_code,
+            headers: vec![
+                header("Content-Type", "text/plain; charset=utf-8"),
+                header("Content-Length", &message.len().to_string()),
+            ],
+            body: message.as_bytes().to_vec(),
+        }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub fn header(name: &str, value: &str) -> HeaderField {
+    (name.to_string(), value.to_string())
+}
+
+/// Content type of a file by its extension.
+pub fn content_type(path: &str) -> &'static str {
+    let extension = path
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_ascii_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" | "log" => "text/plain; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "mp4" => "video/mp4",
+        "mp3" => "audio/mpeg",
+        _ => "application/octet-stream",
+    }
+}
+
+// Decode the `%XX` sequences of a URL path.
+fn percent_decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+/// Path of the requested file relative to the served directory, `None` if the URL leaves the directory.
+/// The query string is ignored and the URLs ending with `/` request the index file.
+pub fn request_path(url: &str) -> Option<String> {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let mut parts = Vec::new();
+
+    for part in path.split('/') {
+        match percent_decode(part)?.as_str() {
+            "" | "." => {}
+            ".." => return None,
+            part if part.contains('/') => return None,
+            part => parts.push(part.to_string()),
+        }
+    }
+
+    if requests_index(url) {
+        parts.push(INDEX_FILE.to_string());
+    }
+
+    Some(parts.join("/"))
+}
+
+/// Whether the URL requests the index file of a directory.
+pub fn requests_index(url: &str) -> bool {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    path.is_empty() || path.ends_with('/')
+}
+
+/// Byte range selected by the `Range` header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ByteRange {
+    Full,
+    /// The first and the last byte of the range
+    Partial(FileSize, FileSize),
+    Unsatisfiable,
+}
+
+/// Parse a single `bytes` range, other units and multiple ranges select the whole file.
+pub fn parse_range(range: Option<&str>, size: FileSize) -> ByteRange {
+    let Some(spec) = range.and_then(|range| range.trim().strip_prefix("bytes=")) else {
+        return ByteRange::Full;
+    };
+
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return ByteRange::Full;
+    };
+
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+
+    let (start, end) = (start.trim(), end.trim());
+
+    let (first, last) = if start.is_empty() {
+        // the suffix of the given length
+        match end.parse::<FileSize>() {
+            Ok(0) | Err(_) => return ByteRange::Unsatisfiable,
+            Ok(len) => (size.saturating_sub(len), size.saturating_sub(1)),
+        }
+    } else {
+        let Ok(first) = start.parse::<FileSize>() else {
+            return ByteRange::Full;
+        };
+
+        let last = match end {
+            "" => size.saturating_sub(1),
+            end => match end.parse::<FileSize>() {
+                Ok(last) if last >= first => last.min(size.saturating_sub(1)),
+                _ => return ByteRange::Full,
+            },
+        };
+
+        (first, last)
+    };
+
+    if size == 0 || first >= size {
+        ByteRange::Unsatisfiable
+    } else {
+        ByteRange::Partial(first, last)
+    }
+}
+
+/// Entity tag of a file content hash.
+pub fn etag(hash: &[u8]) -> String {
+    let hex: String = hash.iter().map(|byte| format!("{byte:02x}")).collect();
+    format!("\"{hex}\"")
+}
+
+/// Directory served over HTTP and the certification tree of its files.
+///
+/// The files are certified by the SHA-256 hash of their content under their URL path, the files named
+/// `index.html` are also certified under the path of their directory.
+#[derive(Default)]
+pub struct HttpFiles {
+    directory: Option<String>,
+    tree: RbTree<String, Hash>,
+}
+
+impl HttpFiles {
+    pub fn new() -> HttpFiles {
+        Self::default()
+    }
+
+    pub fn directory(&self) -> Option<&str> {
+        self.directory.as_deref()
+    }
+
+    // Serve a new directory, the certified files are added again.
+    pub fn set_directory(&mut self, directory: &str) {
+        self.directory = Some(directory.trim_matches('/').to_string());
+        self.tree = RbTree::new();
+    }
+
+    /// Path relative to the file system root of a served file.
+    pub fn file_path(&self, path: &str) -> Option<String> {
+        match self.directory.as_deref()? {
+            "" => Some(path.to_string()),
+            directory => Some(format!("{directory}/{path}")),
+        }
+    }
+
+    /// URL path of a file or a directory, `None` if it is not under the served directory.
+    pub fn url(&self, path: &str) -> Option<String> {
+        let directory = self.directory.as_deref()?;
+        let path = path.trim_matches('/');
+
+        if directory.is_empty() {
+            return Some(format!("/{path}"));
+        }
+
+        match path.strip_prefix(directory)? {
+            "" => Some("/".to_string()),
+            rest => rest.starts_with('/').then(|| rest.to_string()),
+        }
+    }
+
+    pub fn certify(&mut self, url: &str, hash: Hash) {
+        self.tree.insert(url.to_string(), hash);
+
+        if let Some(dir) = url.strip_suffix(INDEX_FILE) {
+            if dir.ends_with('/') {
+                self.tree.insert(dir.to_string(), hash);
+            }
+        }
+    }
+
+    // Remove the URL and the URLs below it, returns false if nothing was certified.
+    pub fn uncertify(&mut self, url: &str) -> bool {
+        let prefix = format!("{}/", url.trim_end_matches('/'));
+
+        let mut urls: Vec<String> = self
+            .tree
+            .iter()
+            .map(|(key, _)| key.clone())
+            .filter(|key| key == url || key.starts_with(&prefix))
+            .collect();
+
+        if let Some(dir) = url.strip_suffix(INDEX_FILE) {
+            if dir.ends_with('/') && self.tree.get(dir.as_bytes()).is_some() {
+                urls.push(dir.to_string());
+            }
+        }
+
+        for key in &urls {
+            self.tree.delete(key.as_bytes());
+        }
+
+        !urls.is_empty()
+    }
+
+    pub fn is_certified(&self, url: &str) -> bool {
+        self.tree.get(url.as_bytes()).is_some()
+    }
+
+    /// Hash to set as the canister's certified data.
+    pub fn root_hash(&self) -> Hash {
+        labeled_hash(CERTIFICATION_LABEL.as_bytes(), &self.tree.root_hash())
+    }
+
+    /// Value of the `IC-Certificate` header proving the response body for the URL.
+    pub fn certificate_header(&self, url: &str, certificate: &[u8]) -> Option<String> {
+        if !self.is_certified(url) {
+            return None;
+        }
+
+        let witness = label(CERTIFICATION_LABEL, self.tree.witness(url.as_bytes()));
+
+        let mut serializer = serde_cbor::Serializer::new(Vec::new());
+        serializer.self_describe().ok()?;
+        serde::Serialize::serialize(&witness, &mut serializer).ok()?;
+
+        Some(format!(
+            "certificate=:{}:, tree=:{}:",
+            BASE64.encode(certificate),
+            BASE64.encode(serializer.into_inner())
+        ))
+    }
+}
+
+// Certified data can only be set in update calls.
+fn set_certified_data(files: &HttpFiles) {
+    if ic_in_replicated_execution() {
+        ic_certified_data_set(files.root_hash());
+    }
+}
+
+// Certify the new content of a served file when its descriptor is closed.
+pub(crate) fn certify_http_file(fs: &FileSystem, fd: Fd, hash: [u8; HASH_SIZE]) {
+    let Some(path) = root_path(fs, fd, "") else {
+        return;
+    };
+
+    HTTP_FILES.with_borrow_mut(|files| {
+        if let Some(url) = files.url(&path) {
+            files.certify(&url, hash);
+            set_certified_data(files);
+        }
+    })
+}
+
+// Certify a created or renamed file or all the files of a directory.
+pub(crate) fn certify_http_path(fs: &mut FileSystem, path: &str) {
+    let Some(url) = HTTP_FILES.with_borrow(|files| files.url(path)) else {
+        return;
+    };
+
+    let root_fd = fs.root_fd();
+    let Ok(metadata) = fs.open_metadata(root_fd, path) else {
+        return;
+    };
+
+    let mut pending = vec![(url, metadata)];
+
+    while let Some((url, metadata)) = pending.pop() {
+        if metadata.file_type == FileType::Directory {
+            let mut entries = Vec::new();
+            fs.storage
+                .with_direntries(metadata.node, Some(0), &mut |_, entry| {
+                    let name = &entry.name.bytes[..entry.name.length as usize];
+                    entries.push((String::from_utf8_lossy(name).into_owned(), entry.node));
+                    true
+                });
+
+            for (name, node) in entries {
+                if let Ok(metadata) = fs.storage.get_metadata(node) {
+                    pending.push((format!("{}/{name}", url.trim_end_matches('/')), metadata));
+                }
+            }
+        } else if let Ok(hash) = file_content_hash(fs, &metadata) {
+            HTTP_FILES.with_borrow_mut(|files| files.certify(&url, hash));
+        }
+    }
+
+    HTTP_FILES.with_borrow(set_certified_data);
+}
+
+// Remove the certification of a removed or renamed file or directory.
+pub(crate) fn uncertify_http_path(path: Option<String>) {
+    HTTP_FILES.with_borrow_mut(|files| {
+        if let Some(url) = path.and_then(|path| files.url(&path)) {
+            if files.uncertify(&url) {
+                set_certified_data(files);
+            }
+        }
+    })
+}
+
+/// Serve the files of a directory from `http_request`, the files are certified with the asset certification v1:
+/// the SHA-256 hash of each file is kept under its URL path in the `http_assets` tree, whose root hash is set
+/// as the canister's certified data. The tree is updated when a changed file is closed and when a file is removed or renamed,
+/// content hashing is enabled for the purpose, see `set_content_hashing`.
+///
+/// Should be called in `init` and `post_upgrade`, the files are certified again.
+///
+/// # Parameters
+/// - `directory`: Path of the served directory relative to the file system root, `""` serves the whole file system
+pub fn init_http_directory(directory: &str) -> i32 {
+    let metadata = FS.with_borrow_mut(|fs| {
+        let root_fd = fs.root_fd();
+        fs.open_metadata(root_fd, directory)
+    });
+
+    match metadata {
+        Ok(metadata) if metadata.file_type == FileType::Directory => {
+            set_content_hashing(true);
+            HTTP_FILES.with_borrow_mut(|files| files.set_directory(directory));
+            FS.with_borrow_mut(|fs| certify_http_path(fs, directory));
+
+            wasi::ERRNO_SUCCESS.raw() as i32
+        }
+        Ok(_) => wasi::ERRNO_NOTDIR.raw() as i32,
+        Err(err) => into_errno(err),
+    }
+}
+
+/// Handle an HTTP request with a file of the served directory, see `init_http_directory`.
+/// Supports `GET` and `HEAD`, a single `Range` and `If-None-Match` with the content hash as the `ETag`.
+/// The URLs ending with `/` are served with the directory's `index.html`.
+///
+/// The full responses carry the `IC-Certificate` header, the partial responses are not certified.
+/// The response has to fit the message size limit, large files should be requested in ranges.
+///
+/// # Example
+/// ```ignore
+/// #[ic_cdk::query]
+/// fn http_request(request: HttpRequest) -> HttpResponse {
+///     ic_wasi_polyfill::http_request(&request)
+/// }
+/// ```
+pub fn http_request(request: &HttpRequest) -> HttpResponse {
+    let is_head = request.method.eq_ignore_ascii_case("HEAD");
+
+    if !is_head && !request.method.eq_ignore_ascii_case("GET") {
+        let mut response = HttpResponse::status(405, "Method Not Allowed");
+        response.headers.push(header("Allow", "GET, HEAD"));
+        return response;
+    }
+
+    let not_found = || HttpResponse::status(404, "Not Found");
+
+    let Some(path) = request_path(&request.url) else {
+        return not_found();
+    };
+
+    let Some(file_path) = HTTP_FILES.with_borrow(|files| files.file_path(&path)) else {
+        return not_found();
+    };
+
+    let metadata = FS.with_borrow_mut(|fs| {
+        let root_fd = fs.root_fd();
+        fs.open_metadata(root_fd, &file_path)
+    });
+
+    let metadata = match metadata {
+        Ok(metadata) if metadata.file_type != FileType::Directory => metadata,
+        _ => return not_found(),
+    };
+
+    let Ok(hash) = FS.with_borrow_mut(|fs| file_content_hash(fs, &metadata)) else {
+        return HttpResponse::status(500, "Internal Server Error");
+    };
+
+    let etag = etag(&hash);
+
+    if request
+        .header("If-None-Match")
+        .is_some_and(|tags| tags.split(',').any(|tag| tag.trim() == etag))
+    {
+        return HttpResponse {
+            status_code: 304,
+            headers: vec![header("ETag", &etag)],
+            body: vec![],
+        };
+    }
+
+    let size = metadata.size;
+    let mut headers = vec![
+        header("Content-Type", content_type(&path)),
+        header("ETag", &etag),
+        header("Accept-Ranges", "bytes"),
+    ];
+
+    let (status_code, offset, len) = match parse_range(request.header("Range"), size) {
+        ByteRange::Full => (200, 0, size),
+        ByteRange::Partial(first, last) => {
+            headers.push(header(
+                "Content-Range",
+                &format!("bytes {first}-{last}/{size}"),
+            ));
+            (206, first, last - first + 1)
+        }
+        ByteRange::Unsatisfiable => {
+            let mut response = HttpResponse::status(416, "Range Not Satisfiable");
+            response
+                .headers
+                .push(header("Content-Range", &format!("bytes */{size}")));
+            return response;
+        }
+    };
+
+    headers.push(header("Content-Length", &len.to_string()));
+
+    if status_code == 200 {
+        // the URLs of the directories are certified with their index file
+        let url = HTTP_FILES.with_borrow(|files| files.url(&file_path));
+        let url = url.map(|url| {
+            if requests_index(&request.url) {
+                url.trim_end_matches(INDEX_FILE).to_string()
+            } else {
+                url
+            }
+        });
+
+        let certificate = url
+            .zip(ic_data_certificate())
+            .and_then(|(url, certificate)| {
+                HTTP_FILES.with_borrow(|files| files.certificate_header(&url, &certificate))
+            });
+
+        if let Some(certificate) = certificate {
+            headers.push(header("IC-Certificate", &certificate));
+        }
+    }
+
+    let body = if is_head {
+        vec![]
+    } else {
+        match read_http_file(&file_path, offset, len) {
+            Ok(body) => body,
+            Err(_) => return HttpResponse::status(500, "Internal Server Error"),
+        }
+    };
+
+    HttpResponse {
+        status_code,
+        headers,
+        body,
+    }
+}
+
+// Read the requested part of a served file, the read goes through the storage, not through the WASI calls.
+fn read_http_file(path: &str, offset: FileSize, len: FileSize) -> Result<Vec<u8>, i32> {
+    let mut body = vec![0u8; len as usize];
+
+    let read = FS
+        .with_borrow_mut(|fs| {
+            let root_fd = fs.root_fd();
+            let fd = fs.open(root_fd, path, FdStat::default(), OpenFlags::empty(), 0)?;
+
+            let dst = [DstBuf {
+                buf: body.as_mut_ptr(),
+                len: body.len(),
+            }];
+            let read = fs.read_vec_with_offset(fd, &dst, offset);
+
+            let _ = fs.close(fd);
+
+            read
+        })
+        .map_err(into_errno)?;
+
+    body.truncate(read as usize);
+
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_and_ranges() {
+        assert_eq!(request_path("/a/b.txt?v=1").as_deref(), Some("a/b.txt"));
+        assert_eq!(request_path("/").as_deref(), Some("index.html"));
+        assert_eq!(request_path("/docs/").as_deref(), Some("docs/index.html"));
+        assert_eq!(
+            request_path("/my%20file.txt").as_deref(),
+            Some("my file.txt")
+        );
+        assert_eq!(request_path("/../secret"), None);
+        assert_eq!(request_path("/%zz"), None);
+
+        assert_eq!(parse_range(None, 10), ByteRange::Full);
+        assert_eq!(parse_range(Some("bytes=2-4"), 10), ByteRange::Partial(2, 4));
+        assert_eq!(parse_range(Some("bytes=2-"), 10), ByteRange::Partial(2, 9));
+        assert_eq!(parse_range(Some("bytes=-3"), 10), ByteRange::Partial(7, 9));
+        assert_eq!(
+            parse_range(Some("bytes=5-100"), 10),
+            ByteRange::Partial(5, 9)
+        );
+        assert_eq!(parse_range(Some("bytes=10-"), 10), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=0-1,4-5"), 10), ByteRange::Full);
+        assert_eq!(parse_range(Some("items=0-1"), 10), ByteRange::Full);
+
+        let mut files = HttpFiles::new();
+        files.set_directory("/www/");
+        assert_eq!(
+            files.url("www/a/index.html").as_deref(),
+            Some("/a/index.html")
+        );
+        assert_eq!(files.url("www"), Some("/".to_string()));
+        assert_eq!(files.url("wwwx/a"), None);
+        assert_eq!(files.file_path("a.txt").as_deref(), Some("www/a.txt"));
+
+        files.certify("/a/index.html", [1; 32]);
+        files.certify("/a/b.txt", [2; 32]);
+        files.certify("/ab.txt", [3; 32]);
+        assert!(files.is_certified("/a/"));
+
+        let root_hash = files.root_hash();
+        assert!(files.uncertify("/a"));
+        assert!(!files.is_certified("/a/") && !files.is_certified("/a/b.txt"));
+        assert!(files.is_certified("/ab.txt"));
+        assert_ne!(files.root_hash(), root_hash);
+    }
+}
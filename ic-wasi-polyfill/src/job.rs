@@ -0,0 +1,114 @@
+#[cfg(any(feature = "fsck", feature = "encryption"))]
+use std::{cell::RefCell, thread::LocalKey};
+
+#[cfg(any(feature = "fsck", feature = "encryption"))]
+use crate::ic_instruction_counter;
+
+/// Progress of a job done in steps, e.g. `fsck` or `rotate_encryption_key`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum JobStatus<R> {
+    /// The job is not finished, it continues with the next call.
+    InProgress,
+    Done(R),
+}
+
+/// Work done in steps of a bounded size, kept between the calls in a thread local until it is done.
+pub trait Job {
+    type Report;
+
+    fn is_done(&self) -> bool;
+
+    fn into_report(self) -> Self::Report;
+}
+
+// Run the steps of the job kept in the slot until it is done or the instruction limit is reached, at least one step
+// runs per call. The kept job continues if `resumes` accepts it, otherwise `start` replaces it. An unfinished job is
+// kept for the next call, a failing step drops it.
+#[cfg(any(feature = "fsck", feature = "encryption"))]
+pub(crate) fn run_steps<J: Job, E>(
+    slot: &'static LocalKey<RefCell<Option<J>>>,
+    resumes: impl FnOnce(&J) -> bool,
+    start: impl FnOnce() -> J,
+    instruction_limit: u64,
+    mut step: impl FnMut(&mut J) -> Result<(), E>,
+) -> Result<JobStatus<J::Report>, E> {
+    let started = ic_instruction_counter();
+
+    let mut job = match slot.with_borrow_mut(|kept| kept.take()) {
+        Some(job) if resumes(&job) => job,
+        _ => start(),
+    };
+
+    loop {
+        step(&mut job)?;
+
+        if job.is_done() || ic_instruction_counter() - started >= instruction_limit {
+            break;
+        }
+    }
+
+    if job.is_done() {
+        Ok(JobStatus::Done(job.into_report()))
+    } else {
+        slot.with_borrow_mut(|kept| *kept = Some(job));
+        Ok(JobStatus::InProgress)
+    }
+}
+
+// Drop the job kept in the slot, the next call starts a new one.
+#[cfg(any(feature = "fsck", feature = "encryption"))]
+pub(crate) fn cancel_job<J>(slot: &'static LocalKey<RefCell<Option<J>>>) {
+    slot.with_borrow_mut(|kept| *kept = None);
+}
+
+#[cfg(all(test, any(feature = "fsck", feature = "encryption")))]
+mod tests {
+    use super::*;
+
+    struct Countdown(u32, u32);
+
+    impl Job for Countdown {
+        type Report = u32;
+
+        fn is_done(&self) -> bool {
+            self.0 == 0
+        }
+
+        fn into_report(self) -> u32 {
+            self.1
+        }
+    }
+
+    thread_local! {
+        static COUNTDOWN: RefCell<Option<Countdown>> = const { RefCell::new(None) };
+    }
+
+    fn count(from: u32, instruction_limit: u64) -> Result<JobStatus<u32>, ()> {
+        run_steps(
+            &COUNTDOWN,
+            |countdown| countdown.0 + countdown.1 == from,
+            || Countdown(from, 0),
+            instruction_limit,
+            |countdown| {
+                countdown.0 -= 1;
+                countdown.1 += 1;
+                Ok(())
+            },
+        )
+    }
+
+    #[test]
+    fn steps_across_calls() {
+        // the instruction counter does not advance outside of a canister, a zero limit runs one step per call
+        assert_eq!(count(2, 0), Ok(JobStatus::InProgress));
+        assert_eq!(count(2, 0), Ok(JobStatus::Done(2)));
+
+        // a job that is not accepted is started over
+        assert_eq!(count(3, 0), Ok(JobStatus::InProgress));
+        assert_eq!(count(4, u64::MAX), Ok(JobStatus::Done(4)));
+
+        assert_eq!(count(3, 0), Ok(JobStatus::InProgress));
+        cancel_job(&COUNTDOWN);
+        assert!(COUNTDOWN.with_borrow(|kept| kept.is_none()));
+    }
+}
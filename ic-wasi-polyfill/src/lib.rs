@@ -1,6 +1,13 @@
+// without the hooks, the calls of most WASI functions never leave their labeled blocks early
+#![cfg_attr(not(feature = "hooks"), allow(unused_labels))]
+
 use std::cell::RefCell;
 use std::ops::Range;
+#[cfg(any(feature = "transforms", feature = "cache"))]
+use std::rc::Rc;
 
+#[cfg(any(feature = "xattrs", feature = "transforms", feature = "transactions"))]
+use ic_stable_structures::memory_manager::MemoryId;
 use ic_stable_structures::memory_manager::MemoryManager;
 use ic_stable_structures::{DefaultMemoryImpl, Memory};
 
@@ -9,7 +16,10 @@ use rand::{RngExt, SeedableRng};
 use stable_fs::fs::{DstBuf, Fd, SrcBuf};
 use stable_fs::fs::{FdFlags, FdStat, FileSize, OpenFlags};
 
+use stable_fs::error::Error;
 use stable_fs::storage::dummy::DummyStorage;
+use stable_fs::storage::types::FileType;
+use stable_fs::storage::Storage;
 
 #[cfg(target_arch = "wasm32")]
 pub mod wasi;
@@ -19,14 +29,134 @@ pub mod wasi_mock;
 #[cfg(not(all(target_arch = "wasm32")))]
 pub use wasi_mock as wasi;
 
+#[cfg(feature = "fd_paths")]
+use access::*;
+use cache::*;
+use content_hash::*;
 use environment::*;
+#[cfg(feature = "fsck")]
+use fsck::*;
+use hooks::*;
+#[cfg(feature = "file_locks")]
+use locks::*;
+use memories::*;
+use namespace::*;
+#[cfg(feature = "permissions")]
+use permissions::*;
+use stdin::*;
+#[cfg(feature = "transactions")]
+use transaction::*;
+#[cfg(feature = "transforms")]
+use transform::*;
+use unsupported::*;
+use validation::*;
 use wasi_helpers::*;
+use watch::*;
+use xattr::*;
+
+#[cfg(feature = "http")]
+use http::*;
+
+#[cfg(feature = "trace_wasi_calls")]
+use tracer::*;
+
+#[cfg(feature = "record_wasi_calls")]
+use recorder::*;
 
+pub mod access;
+#[cfg(feature = "admin")]
+pub mod admin;
+pub mod cache;
+pub mod content_hash;
+pub mod copy;
+pub mod encryption;
 mod environment;
+pub mod fsck;
+pub mod hooks;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod job;
+pub mod locks;
+pub mod memories;
+pub mod namespace;
+pub mod permissions;
+pub mod recorder;
+#[cfg(not(all(target_arch = "wasm32")))]
+pub mod replay;
+pub mod stdin;
+pub mod tracer;
+pub mod transaction;
+pub mod transform;
+pub mod unsupported;
+pub mod validation;
 pub mod wasi_helpers;
+pub mod watch;
+pub mod xattr;
 
 pub use stable_fs::fs::FileSystem;
 
+pub use copy::{copy_file, copy_file_range, raw_copy_file_range, CopyProgress};
+
+#[cfg(feature = "hooks")]
+pub use hooks::{clear_hooks, remove_hook, set_hook};
+
+#[cfg(feature = "access_rules")]
+pub use access::{clear_access_rules, remove_access_rule, set_access_rule};
+
+#[cfg(feature = "content_hashes")]
+pub use content_hash::{get_file_hash, set_content_hashing};
+
+#[cfg(feature = "file_locks")]
+pub use locks::{
+    lock_file, raw_lock_file, raw_test_file_lock, raw_unlock_file, test_file_lock, unlock_file,
+};
+
+#[cfg(feature = "permissions")]
+pub use permissions::{
+    check_file_access, get_file_mode, get_file_owner, set_file_mode, set_file_owner,
+};
+
+#[cfg(feature = "cache")]
+pub use cache::{flush_cache, get_cache_stats, reset_cache_stats, set_cache_budget};
+
+#[cfg(feature = "watches")]
+pub use watch::{add_watch, add_watch_queue, remove_watch, take_watch_events};
+
+#[cfg(feature = "xattrs")]
+pub use xattr::{get_xattr, init_xattrs_with_memory, list_xattrs, remove_xattr, set_xattr};
+
+#[cfg(feature = "http")]
+pub use http::{http_request, init_http_directory};
+
+#[cfg(feature = "caller_namespaces")]
+pub use namespace::{disable_caller_namespaces, enable_caller_namespaces, get_caller_namespace};
+
+#[cfg(feature = "transforms")]
+pub use transform::{get_file_physical_size, init_transforms_with_memory};
+
+#[cfg(feature = "sparse_files")]
+pub use transform::{get_file_allocation, punch_hole};
+
+#[cfg(feature = "encryption")]
+pub use encryption::{
+    cancel_key_rotation, is_file_encrypted, remove_encryption_key, rotate_encryption_key,
+    set_encryption_key, set_file_encryption,
+};
+
+#[cfg(feature = "fsck")]
+pub use fsck::{cancel_fsck, fsck};
+
+#[cfg(feature = "transactions")]
+pub use transaction::{
+    begin_transaction, commit_transaction, open_transactions, rollback_transaction,
+    transaction_delete, transaction_rename, transaction_write,
+};
+
+#[cfg(feature = "compression")]
+pub use transform::{
+    add_compression_pattern, clear_compression_patterns, is_file_compressed, set_file_compression,
+};
+
 pub use stable_fs::fs::{ChunkSize, ChunkType};
 pub use stable_fs::storage::stable::StableStorage;
 pub use stable_fs::storage::transient::TransientStorage;
@@ -61,14 +191,76 @@ fn ic_print(value: &str) {
     println!("{value}");
 }
 
+#[cfg(target_arch = "wasm32")]
+use ic_cdk::api::in_replicated_execution as ic_in_replicated_execution;
+#[cfg(not(all(target_arch = "wasm32")))]
+fn ic_in_replicated_execution() -> bool {
+    MOCK_REPLICATED_EXECUTION.with_borrow(|replicated| *replicated)
+}
+
+#[cfg(all(
+    target_arch = "wasm32",
+    any(
+        feature = "caller_namespaces",
+        feature = "access_rules",
+        feature = "permissions"
+    )
+))]
+fn ic_msg_caller() -> String {
+    ic_cdk::api::msg_caller().to_text()
+}
+#[cfg(all(
+    not(target_arch = "wasm32"),
+    any(
+        feature = "caller_namespaces",
+        feature = "access_rules",
+        feature = "permissions",
+        feature = "admin"
+    )
+))]
+fn ic_msg_caller() -> String {
+    MOCK_CALLER.with_borrow(|caller| caller.clone())
+}
+
+#[cfg(all(
+    target_arch = "wasm32",
+    any(feature = "access_rules", feature = "permissions", feature = "admin")
+))]
+fn ic_is_controller() -> bool {
+    ic_cdk::api::is_controller(&ic_cdk::api::msg_caller())
+}
+#[cfg(all(
+    not(target_arch = "wasm32"),
+    any(feature = "access_rules", feature = "permissions", feature = "admin")
+))]
+fn ic_is_controller() -> bool {
+    let caller = ic_msg_caller();
+    MOCK_CONTROLLERS.with_borrow(|controllers| controllers.contains(&caller))
+}
+
+#[cfg(all(target_arch = "wasm32", feature = "http"))]
+use ic_cdk::api::certified_data_set as ic_certified_data_set;
+#[cfg(all(not(target_arch = "wasm32"), feature = "http"))]
+fn ic_certified_data_set<T: AsRef<[u8]>>(data: T) {
+    MOCK_CERTIFIED_DATA.with_borrow_mut(|certified| *certified = data.as_ref().to_vec())
+}
+
+#[cfg(all(target_arch = "wasm32", feature = "http"))]
+use ic_cdk::api::data_certificate as ic_data_certificate;
+// The certificate is simulated by the certified data itself.
+#[cfg(all(not(target_arch = "wasm32"), feature = "http"))]
+fn ic_data_certificate() -> Option<Vec<u8>> {
+    MOCK_CERTIFIED_DATA.with_borrow(|certified| Some(certified.clone()))
+}
+
 #[allow(clippy::missing_safety_doc)]
 pub unsafe fn forward_to_debug(iovs: *const wasi::Ciovec, len: i32, res: *mut wasi::Size) -> i32 {
-    let iovs = unsafe { std::slice::from_raw_parts(iovs, len as usize) };
+    let iovs = unsafe { raw_slice(iovs, len as usize) };
 
     let mut written = 0;
 
     for iov in iovs {
-        let buf = unsafe { std::slice::from_raw_parts(iov.buf, iov.buf_len) };
+        let buf = unsafe { raw_slice(iov.buf, iov.buf_len) };
         let str = std::str::from_utf8(buf).unwrap_or("");
         ic_print(str);
         written += iov.buf_len;
@@ -79,20 +271,45 @@ pub unsafe fn forward_to_debug(iovs: *const wasi::Ciovec, len: i32, res: *mut wa
     wasi::ERRNO_SUCCESS.raw() as i32
 }
 
+// Read the buffered standard input, `ERRNO_AGAIN` if it is empty in the non-blocking mode.
+unsafe fn read_stdin(iovs: *const wasi::Iovec, len: i32, res: *mut wasi::Size) -> i32 {
+    let mut bufs = unsafe { iovec_slices(iovs, len) };
+
+    match STDIN.with_borrow_mut(|stdin| stdin.read(&mut bufs)) {
+        Some(read) => {
+            unsafe { *res = read };
+            wasi::ERRNO_SUCCESS.raw() as i32
+        }
+        None => {
+            unsafe { *res = 0 };
+            wasi::ERRNO_AGAIN.raw() as i32
+        }
+    }
+}
+
+// Write back the cached data of the file if the descriptor has one of the flags,
+// e.g. `FDFLAGS_SYNC` after a write or `FDFLAGS_RSYNC` before a read.
+fn sync_if_flagged(fs: &mut FileSystem, fd: Fd, flags: FdFlags) -> Result<(), Error> {
+    match fs.get_stat(fd) {
+        Ok((_, stat)) if stat.flags.intersects(flags) => fs.flush(fd),
+        _ => Ok(()),
+    }
+}
+
 thread_local! {
     /// Random number generator
     pub static RNG : RefCell<rand::rngs::StdRng> = RefCell::new(rand::rngs::StdRng::from_seed([0;32]));
 
     /// File system storage
     pub static FS: RefCell<FileSystem> = RefCell::new(
-        FileSystem::new(
+        new_file_system(
             if cfg!(feature = "transient") {
                 // transient feature does not require explicit initialization
                 Box::new(TransientStorage::new())
             } else {
                 Box::new(DummyStorage::new())
             }
-        ).unwrap()
+        )
     );
 
     /// Accumulative instruction counter
@@ -100,6 +317,101 @@ thread_local! {
 
     /// Current environment
     pub static ENV: RefCell<Environment> = RefCell::new(Environment::new());
+
+    /// Hooks registered for the individual WASI functions
+    #[cfg(feature = "hooks")]
+    pub static HOOKS: RefCell<Hooks> = RefCell::new(Hooks::new());
+
+    /// Validation of the pointers, lengths and enumeration values passed to the WASI functions
+    pub static HARDENED: RefCell<bool> = const { RefCell::new(cfg!(feature = "hardened")) };
+
+    /// Mutating calls return `ERRNO_ROFS` during the query calls
+    pub static REJECT_QUERY_WRITES: RefCell<bool> = const { RefCell::new(cfg!(feature = "reject_query_writes")) };
+
+    /// The current message was marked as a query call by the canister
+    pub static QUERY_CALL: RefCell<bool> = const { RefCell::new(false) };
+
+    /// Simulated execution mode on the host, `false` corresponds to a query call
+    #[cfg(not(all(target_arch = "wasm32")))]
+    pub static MOCK_REPLICATED_EXECUTION: RefCell<bool> = const { RefCell::new(true) };
+
+    /// Per-caller directories replacing the preopened root
+    #[cfg(feature = "caller_namespaces")]
+    pub static NAMESPACES: RefCell<Namespaces> = RefCell::new(Namespaces::new());
+
+    /// Simulated message caller on the host
+    #[cfg(not(all(target_arch = "wasm32")))]
+    pub static MOCK_CALLER: RefCell<String> = RefCell::new(String::from("2vxsx-fae"));
+
+    /// Paths of the opened descriptors relative to the root
+    #[cfg(feature = "fd_paths")]
+    pub static FD_PATHS: RefCell<FdPaths> = RefCell::new(FdPaths::new());
+
+    /// Access rules of the paths depending on the caller
+    #[cfg(feature = "access_rules")]
+    pub static ACCESS_POLICIES: RefCell<AccessPolicies> = RefCell::new(AccessPolicies::new());
+
+    /// Simulated canister controllers on the host
+    #[cfg(not(all(target_arch = "wasm32")))]
+    pub static MOCK_CONTROLLERS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+
+    /// Advisory locks held by the file descriptors
+    #[cfg(feature = "file_locks")]
+    pub static LOCKS: RefCell<LockTable> = RefCell::new(LockTable::new());
+
+    /// Extended attributes of the files and directories
+    #[cfg(feature = "xattrs")]
+    pub static XATTRS: RefCell<Xattrs> = RefCell::new(Xattrs::new());
+
+    /// Data blocks of the compressed and encrypted files, shared with the file system storage
+    #[cfg(feature = "transforms")]
+    pub static TRANSFORMS: Rc<RefCell<Transforms>> = Rc::new(RefCell::new(Transforms::new()));
+
+    /// Cached blocks of the file data, shared with the file system storage
+    #[cfg(feature = "cache")]
+    pub static CACHE: Rc<RefCell<Cache>> = Rc::new(RefCell::new(Cache::new()));
+
+    /// Running hashes and stale hashes of the written files
+    #[cfg(feature = "content_hashes")]
+    pub static CONTENT_HASHES: RefCell<ContentHashes> = RefCell::new(ContentHashes::new());
+
+    /// Directory served over HTTP and the certification tree of its files
+    #[cfg(feature = "http")]
+    pub static HTTP_FILES: RefCell<HttpFiles> = RefCell::new(HttpFiles::new());
+
+    /// Simulated certified data of the canister on the host
+    #[cfg(all(not(target_arch = "wasm32"), feature = "http"))]
+    pub static MOCK_CERTIFIED_DATA: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+
+    /// Buffered standard input and the flags of its descriptor
+    pub static STDIN: RefCell<Stdin> = RefCell::new(Stdin::new());
+
+    /// Watched paths and the descriptors that changed their files
+    #[cfg(feature = "watches")]
+    pub static WATCHES: RefCell<Watches> = RefCell::new(Watches::new());
+
+    /// Transactions with their journals
+    #[cfg(feature = "transactions")]
+    pub static TRANSACTIONS: RefCell<Transactions> = RefCell::new(Transactions::new());
+
+    /// File system check in progress
+    #[cfg(feature = "fsck")]
+    pub static FSCK: RefCell<Option<Fsck>> = const { RefCell::new(None) };
+
+    /// Encryption key rotation in progress
+    #[cfg(feature = "encryption")]
+    pub static ROTATION: RefCell<Option<Rotation>> = const { RefCell::new(None) };
+
+    /// Handling of the unimplemented and unsupported WASI calls
+    pub static UNSUPPORTED_CALLS: RefCell<UnsupportedCalls> = RefCell::new(UnsupportedCalls::new());
+
+    /// Ring buffer of the traced WASI calls
+    #[cfg(feature = "trace_wasi_calls")]
+    pub static TRACER: RefCell<Tracer> = RefCell::new(Tracer::default());
+
+    /// Recorder of the WASI call sequence
+    #[cfg(feature = "record_wasi_calls")]
+    pub static RECORDER: RefCell<Recorder> = RefCell::new(Recorder::new());
 }
 
 #[cfg(feature = "count_wasi_calls")]
@@ -109,6 +421,297 @@ fn update_counter(start: u64) {
     })
 }
 
+// Run the hook registered for the function, leaves the labeled block with the errno if the hook handles the call,
+// so that the hooked calls are still traced, recorded and counted.
+#[cfg(feature = "hooks")]
+macro_rules! call_hook {
+    ($label:lifetime, $fn_name:literal, $call:expr) => {
+        if HOOKS.with_borrow(|hooks| hooks.contains($fn_name)) {
+            if let HookOutcome::Return(errno) = run_hook($fn_name, &mut $call) {
+                break $label errno;
+            }
+        }
+    };
+}
+
+#[cfg(not(feature = "hooks"))]
+macro_rules! call_hook {
+    ($label:lifetime, $fn_name:literal, $call:expr) => {};
+}
+
+// Check the arguments in the hardened mode, returns the errno of the first failed check.
+macro_rules! validate {
+    ($fn_name:literal, $($check:expr),* $(,)?) => {
+        if HARDENED.with_borrow(|hardened| *hardened) {
+            let start = ic_instruction_counter();
+
+            $(
+                if let Err(errno) = $check {
+                    return rejected_call($fn_name, errno, start);
+                }
+            )*
+        }
+    };
+}
+
+// Finish a call rejected by the hardened checks: it is reported, traced and counted like the other calls,
+// but its arguments are not read, the recording only keeps the function and the errno.
+#[allow(unused_variables)]
+fn rejected_call(function: &'static str, errno: wasi::Errno, start: u64) -> i32 {
+    let result = errno.raw() as i32;
+
+    #[cfg(feature = "report_wasi_calls")]
+    ic_print(&format!(
+        "\t__ic_custom_{function}\t -> {result}\tinstructions:\t{}\tinvalid arguments\n",
+        ic_instruction_counter() - start
+    ));
+
+    #[cfg(feature = "trace_wasi_calls")]
+    TRACER.with_borrow_mut(|tracer| {
+        if tracer.is_traced(function, None, None) {
+            tracer.push(TraceRecord {
+                function,
+                args: String::from("invalid arguments"),
+                errno: result,
+                instructions: ic_instruction_counter() - start,
+                fd: None,
+                path: None,
+            });
+        }
+    });
+
+    #[cfg(feature = "record_wasi_calls")]
+    RECORDER.with_borrow_mut(|recorder| {
+        if recorder.is_recording() {
+            recorder.record(&CallRecord::rejected(function, result));
+        }
+    });
+
+    #[cfg(feature = "count_wasi_calls")]
+    update_counter(start);
+
+    result
+}
+
+// Return `ERRNO_ROFS` from a mutating call executed in a query, if enabled.
+macro_rules! reject_query_writes {
+    () => {
+        reject_query_writes!(true)
+    };
+    ($mutating:expr) => {
+        if $mutating && is_query_write() {
+            return wasi::ERRNO_ROFS.raw() as i32;
+        }
+    };
+}
+
+// A query called as an update (replicated query) runs in the replicated mode, but still discards its changes,
+// it is only detected if the query method marked itself with `set_query_call`.
+fn is_query_write() -> bool {
+    REJECT_QUERY_WRITES.with_borrow(|reject| *reject)
+        && (!ic_in_replicated_execution() || QUERY_CALL.with_borrow(|query| *query))
+}
+
+// Finish the call with `ERRNO_ACCES` if the permission bits of an existing entry do not allow the access to the caller.
+#[cfg(feature = "permissions")]
+macro_rules! check_mode {
+    ($label:lifetime, $fd:expr, $path:expr, $required:expr) => {
+        if !XATTRS.with_borrow(|xattrs| xattrs.is_empty())
+            && !is_mode_permitted($fd as Fd, &$path, $required)
+        {
+            break $label wasi::ERRNO_ACCES.raw() as i32;
+        }
+    };
+}
+
+#[cfg(not(feature = "permissions"))]
+macro_rules! check_mode {
+    ($label:lifetime, $fd:expr, $path:expr, $required:expr) => {};
+}
+
+// Create the file system over the storage, the data of the compressed and encrypted files is kept separately
+// and the file data is cached on the heap if the cache is enabled.
+fn new_file_system(storage: Box<dyn Storage>) -> FileSystem {
+    #[cfg(feature = "transforms")]
+    let storage: Box<dyn Storage> = Box::new(TransformStorage::new(
+        storage,
+        TRANSFORMS.with(|transforms| transforms.clone()),
+    ));
+
+    #[cfg(feature = "cache")]
+    let storage: Box<dyn Storage> = {
+        let cache = CACHE.with(|cache| cache.clone());
+
+        // the blocks of the replaced file system are not valid for the new storage
+        cache.borrow_mut().discard();
+
+        Box::new(CacheStorage::new(storage, cache))
+    };
+
+    FileSystem::new(storage).unwrap()
+}
+
+#[cfg(not(all(target_arch = "wasm32")))]
+// Replace the file system and forget everything kept for the files and descriptors of the previous one.
+fn reset_file_system(storage: Box<dyn Storage>) {
+    #[cfg(feature = "xattrs")]
+    XATTRS.with_borrow_mut(|xattrs| *xattrs = Xattrs::new());
+    #[cfg(feature = "transforms")]
+    with_transforms(|transforms| *transforms = Transforms::new());
+    #[cfg(feature = "content_hashes")]
+    CONTENT_HASHES.with_borrow_mut(|hashes| *hashes = ContentHashes::new());
+    #[cfg(feature = "file_locks")]
+    LOCKS.with_borrow_mut(|locks| *locks = LockTable::new());
+    #[cfg(feature = "hooks")]
+    HOOKS.with_borrow_mut(|hooks| hooks.clear());
+    #[cfg(feature = "watches")]
+    WATCHES.with_borrow_mut(|watches| *watches = Watches::new());
+    #[cfg(feature = "caller_namespaces")]
+    NAMESPACES.with_borrow_mut(|namespaces| *namespaces = Namespaces::new());
+    #[cfg(feature = "fd_paths")]
+    FD_PATHS.with_borrow_mut(|paths| *paths = FdPaths::new());
+    #[cfg(feature = "access_rules")]
+    ACCESS_POLICIES.with_borrow_mut(|policies| *policies = AccessPolicies::new());
+    #[cfg(feature = "transactions")]
+    TRANSACTIONS.with_borrow_mut(|transactions| *transactions = Transactions::new());
+    #[cfg(feature = "fsck")]
+    FSCK.with_borrow_mut(|fsck| *fsck = None);
+    #[cfg(feature = "encryption")]
+    ROTATION.with_borrow_mut(|rotation| *rotation = None);
+
+    #[cfg(feature = "http")]
+    HTTP_FILES.with_borrow_mut(|files| *files = HttpFiles::new());
+
+    let fs = new_file_system(storage);
+    FS.with_borrow_mut(|current| *current = fs);
+}
+
+// Create the stable storage in the memory index range.
+// The polyfill data with a memory in the polyfill range is loaded from it, an empty range keeps it on the heap.
+#[cfg_attr(
+    not(any(feature = "xattrs", feature = "transforms", feature = "transactions")),
+    allow(unused_variables)
+)]
+fn new_stable_storage<M: Memory + 'static>(
+    memory_manager: &MemoryManager<M>,
+    memory_index_range: Range<u8>,
+    polyfill_memory_index_range: Range<u8>,
+) -> Box<dyn Storage> {
+    if !polyfill_memory_index_range.is_empty()
+        && polyfill_memory_index_range.start < memory_index_range.end
+        && memory_index_range.start < polyfill_memory_index_range.end
+    {
+        panic!(
+            "The polyfill memory index range must not overlap the file system memory index range"
+        );
+    }
+
+    #[cfg(feature = "xattrs")]
+    if let Some(memory) = polyfill_memory(
+        memory_manager,
+        &polyfill_memory_index_range,
+        XATTR_MEMORY_INDEX,
+    ) {
+        XATTRS.with_borrow_mut(|xattrs| *xattrs = Xattrs::with_memory(memory));
+    }
+
+    #[cfg(feature = "transforms")]
+    {
+        let memory = polyfill_memory(
+            memory_manager,
+            &polyfill_memory_index_range,
+            TRANSFORM_MEMORY_INDEX,
+        );
+        with_transforms(|transforms| {
+            if let Some(memory) = memory {
+                transforms.load(memory);
+            }
+            transforms.set_stable_storage(true);
+        });
+    }
+    #[cfg(feature = "encryption")]
+    ROTATION.with_borrow_mut(|rotation| *rotation = None);
+
+    #[cfg(feature = "transactions")]
+    if let Some(memory) = polyfill_memory(
+        memory_manager,
+        &polyfill_memory_index_range,
+        JOURNAL_MEMORY_INDEX,
+    ) {
+        TRANSACTIONS
+            .with_borrow_mut(|transactions| *transactions = Transactions::with_memory(memory));
+    }
+
+    let storage = StableStorage::new_with_memory_manager(memory_manager, memory_index_range);
+
+    Box::new(storage)
+}
+
+// Memory at the position within the polyfill memory index range, none if the range is too short for it.
+#[cfg(any(feature = "xattrs", feature = "transforms", feature = "transactions"))]
+fn polyfill_memory<M: Memory + 'static>(
+    memory_manager: &MemoryManager<M>,
+    memory_index_range: &Range<u8>,
+    index: u8,
+) -> Option<Box<dyn Memory>> {
+    if index >= memory_index_range.end - memory_index_range.start {
+        return None;
+    }
+
+    Some(Box::new(
+        memory_manager.get(MemoryId::new(memory_index_range.start + index)),
+    ))
+}
+
+// Resolve a path relative to a directory descriptor into a path relative to the file system root.
+#[cfg(feature = "fd_paths")]
+fn root_path(fs: &FileSystem, fd: Fd, path: &str) -> Option<String> {
+    FD_PATHS.with_borrow(|paths| paths.resolve(fs.root_fd(), fd, path))
+}
+
+// Replace the root descriptor with the caller's directory if the namespaces are enabled.
+#[cfg(feature = "caller_namespaces")]
+macro_rules! namespace_fd {
+    ($label:lifetime, $fd:ident: $fd_type:ty) => {
+        let $fd = match namespace_fd($fd as Fd) {
+            Ok(fd) => fd as $fd_type,
+            Err(errno) => break $label errno,
+        };
+    };
+}
+
+#[cfg(not(feature = "caller_namespaces"))]
+macro_rules! namespace_fd {
+    ($label:lifetime, $fd:ident: $fd_type:ty) => {};
+}
+
+// Finish the call with `ERRNO_ACCES` if the access rules do not allow the caller to access the path.
+#[cfg(feature = "access_rules")]
+macro_rules! check_access {
+    ($label:lifetime, $fd:expr, $path:expr, $access:expr) => {
+        if !ACCESS_POLICIES.with_borrow(|policies| policies.is_empty())
+            && !is_access_allowed($fd as Fd, &$path, $access)
+        {
+            break $label wasi::ERRNO_ACCES.raw() as i32;
+        }
+    };
+}
+
+#[cfg(not(feature = "access_rules"))]
+macro_rules! check_access {
+    ($label:lifetime, $fd:expr, $path:expr, $access:expr) => {};
+}
+
+// Decode the path passed to the function, returns `ERRNO_ILSEQ` if the path is not a valid file name.
+macro_rules! file_name {
+    ($path:expr, $path_len:expr) => {
+        match unsafe { get_file_name($path, $path_len as wasi::Size) } {
+            Ok(name) => name,
+            Err(errno) => return errno.raw() as i32,
+        }
+    };
+}
+
 #[allow(unused_macros)]
 macro_rules! debug_instructions {
     ($fn_name:literal) => {
@@ -142,6 +745,43 @@ macro_rules! debug_instructions {
     };
 }
 
+#[allow(unused_macros)]
+macro_rules! trace_call {
+    ($fn_name:literal, $result:expr, $stime:expr, $fd:expr, $path:expr, $params:expr) => {
+        TRACER.with_borrow_mut(|tracer| {
+            let path: Option<&str> = $path;
+
+            if tracer.is_traced($fn_name, $fd, path) {
+                tracer.push(TraceRecord {
+                    function: $fn_name,
+                    args: format!($params),
+                    errno: $result,
+                    instructions: ic_instruction_counter() - ($stime),
+                    fd: $fd,
+                    path: path.map(String::from),
+                });
+            }
+        })
+    };
+}
+
+#[allow(unused_macros)]
+macro_rules! record_call {
+    ($fn_name:literal, $result:expr, [$($arg:expr),*], [$($data:expr),*], $output:expr) => {
+        RECORDER.with_borrow_mut(|recorder| {
+            if recorder.is_recording() {
+                recorder.record(&CallRecord {
+                    function: $fn_name.to_string(),
+                    args: vec![$($arg as i64),*],
+                    data: vec![$(Vec::<u8>::from($data)),*],
+                    errno: $result,
+                    output: $output,
+                });
+            }
+        })
+    };
+}
+
 #[unsafe(no_mangle)]
 #[inline(never)]
 #[allow(clippy::missing_safety_doc)]
@@ -151,6 +791,14 @@ pub unsafe extern "C" fn __ic_custom_fd_write(
     len: i32,
     res: *mut wasi::Size,
 ) -> i32 {
+    validate!(
+        "fd_write",
+        unsafe { check_ciovecs(iovs, len) },
+        check_ptr(res)
+    );
+
+    reject_query_writes!(fd >= 3);
+
     #[cfg(feature = "count_wasi_calls")]
     let start = ic_instruction_counter();
 
@@ -158,8 +806,7 @@ pub unsafe extern "C" fn __ic_custom_fd_write(
     debug_instructions!("__ic_custom_fd_write");
 
     let src_io_vec: *const SrcBuf = iovs as *const SrcBuf;
-    let src_io_vec: &[SrcBuf] =
-        unsafe { std::slice::from_raw_parts(src_io_vec, len as wasi::Size) };
+    let src_io_vec: &[SrcBuf] = unsafe { raw_slice(src_io_vec, len as wasi::Size) };
 
     #[cfg(feature = "report_wasi_calls")]
     {
@@ -169,31 +816,75 @@ pub unsafe extern "C" fn __ic_custom_fd_write(
         debug_instructions!("__ic_custom_fd_write", "fd={fd:?} iovs.len={len:?} {l}");
     }
 
-    let result = if fd < 3 {
-        unsafe { forward_to_debug(iovs, len, res) }
-    } else {
-        FS.with(|fs| {
-            let mut fs = fs.borrow_mut();
+    let mut event = None;
 
-            match fs.write_vec(fd as Fd, src_io_vec) {
-                Ok(r) => {
-                    unsafe { *res = r as wasi::Size };
-                    wasi::ERRNO_SUCCESS.raw() as i32
-                }
-                Err(er) => {
-                    unsafe { *res = 0 };
-                    into_errno(er)
-                }
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "fd_write",
+            WasiCall::FdWrite {
+                fd,
+                bufs: unsafe { ciovec_slices(iovs, len) },
+                written: unsafe { &mut *res }
             }
-        })
+        );
+
+        namespace_fd!('call, fd: Fd);
+
+        if fd < 3 {
+            unsafe { forward_to_debug(iovs, len, res) }
+        } else {
+            FS.with(|fs| {
+                let mut fs = fs.borrow_mut();
+
+                match fs.write_vec(fd as Fd, src_io_vec) {
+                    Ok(r) => {
+                        #[cfg(feature = "content_hashes")]
+                        if let Ok(end) = fs.tell(fd as Fd) {
+                            let bufs = unsafe { ciovec_slices(iovs, len) };
+                            hash_written(&fs, fd as Fd, end - r, &bufs, r as usize);
+                        }
+
+                        if r > 0 {
+                            event = file_changed(&fs, fd as Fd, WatchEventKind::Modify);
+                        }
+
+                        unsafe { *res = r as wasi::Size };
+
+                        match sync_if_flagged(&mut fs, fd as Fd, FdFlags::SYNC | FdFlags::DSYNC) {
+                            Ok(()) => wasi::ERRNO_SUCCESS.raw() as i32,
+                            Err(er) => into_errno(er),
+                        }
+                    }
+                    Err(er) => {
+                        unsafe { *res = 0 };
+                        into_errno(er)
+                    }
+                }
+            })
+        }
     };
 
+    notify_watches(event);
+
     #[cfg(feature = "report_wasi_calls")]
     {
         let r = format!("res={}", *res);
         debug_instructions!("__ic_custom_fd_write", result, start, "{r}");
     }
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!("fd_write", result, start, Some(fd), None, "iovs.len={len}");
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!(
+        "fd_write",
+        result,
+        [fd],
+        [unsafe { gather_src_bufs(src_io_vec) }],
+        unsafe { (*res as u64).to_le_bytes().to_vec() }
+    );
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
 
@@ -209,6 +900,12 @@ pub unsafe extern "C" fn __ic_custom_fd_read(
     len: i32,
     res: *mut wasi::Size,
 ) -> i32 {
+    validate!(
+        "fd_read",
+        unsafe { check_iovecs(iovs, len) },
+        check_ptr(res)
+    );
+
     #[cfg(feature = "count_wasi_calls")]
     let start = ic_instruction_counter();
 
@@ -216,8 +913,7 @@ pub unsafe extern "C" fn __ic_custom_fd_read(
     debug_instructions!("__ic_custom_fd_read");
 
     let dst_io_vec = iovs as *const DstBuf;
-    let dst_io_vec: &[DstBuf] =
-        unsafe { std::slice::from_raw_parts(dst_io_vec, len as wasi::Size) };
+    let dst_io_vec: &[DstBuf] = unsafe { raw_slice(dst_io_vec, len as wasi::Size) };
 
     #[cfg(feature = "report_wasi_calls")]
     {
@@ -227,28 +923,45 @@ pub unsafe extern "C" fn __ic_custom_fd_read(
         debug_instructions!("__ic_custom_fd_read", "fd={fd:?} iovs.lengths={l}");
     }
 
-    // for now we don't support reading from the standard streams
-    if fd < 3 {
-        #[cfg(feature = "count_wasi_calls")]
-        update_counter(start);
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "fd_read",
+            WasiCall::FdRead {
+                fd,
+                bufs: unsafe { iovec_slices(iovs, len) },
+                read: unsafe { &mut *res }
+            }
+        );
 
-        return wasi::ERRNO_INVAL.raw() as i32;
-    }
+        namespace_fd!('call, fd: Fd);
 
-    let result = FS.with(|fs| {
-        let mut fs = fs.borrow_mut();
+        // the standard output and error cannot be read
+        if fd == 1 || fd == 2 {
+            break 'call wasi::ERRNO_INVAL.raw() as i32;
+        }
 
-        match fs.read_vec(fd as Fd, dst_io_vec) {
-            Ok(r) => {
-                unsafe { *res = r as wasi::Size };
-                wasi::ERRNO_SUCCESS.raw() as i32
-            }
-            Err(er) => {
-                unsafe { *res = 0 };
-                into_errno(er)
-            }
+        if fd == 0 {
+            unsafe { read_stdin(iovs, len, res) }
+        } else {
+            FS.with(|fs| {
+                let mut fs = fs.borrow_mut();
+
+                match sync_if_flagged(&mut fs, fd as Fd, FdFlags::RSYNC)
+                    .and_then(|()| fs.read_vec(fd as Fd, dst_io_vec))
+                {
+                    Ok(r) => {
+                        unsafe { *res = r as wasi::Size };
+                        wasi::ERRNO_SUCCESS.raw() as i32
+                    }
+                    Err(er) => {
+                        unsafe { *res = 0 };
+                        into_errno(er)
+                    }
+                }
+            })
         }
-    });
+    };
 
     #[cfg(feature = "report_wasi_calls")]
     {
@@ -256,6 +969,18 @@ pub unsafe extern "C" fn __ic_custom_fd_read(
         debug_instructions!("__ic_custom_fd_read", result, start, "{r}");
     }
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!("fd_read", result, start, Some(fd), None, "iovs.len={len}");
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!(
+        "fd_read",
+        result,
+        [fd, dst_io_vec.iter().map(|b| b.len).sum::<usize>()],
+        [],
+        unsafe { gather_dst_bufs(dst_io_vec, *res) }
+    );
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
     result
@@ -271,6 +996,14 @@ pub unsafe extern "C" fn __ic_custom_fd_pwrite(
     offset: i64,
     res: *mut wasi::Size,
 ) -> i32 {
+    validate!(
+        "fd_pwrite",
+        unsafe { check_ciovecs(iovs, len) },
+        check_ptr(res)
+    );
+
+    reject_query_writes!(fd >= 3);
+
     #[cfg(feature = "count_wasi_calls")]
     let start = ic_instruction_counter();
 
@@ -278,8 +1011,7 @@ pub unsafe extern "C" fn __ic_custom_fd_pwrite(
     debug_instructions!("__ic_custom_fd_pwrite");
 
     let src_io_vec: *const SrcBuf = iovs as *const SrcBuf;
-    let src_io_vec: &[SrcBuf] =
-        unsafe { std::slice::from_raw_parts(src_io_vec, len as wasi::Size) };
+    let src_io_vec: &[SrcBuf] = unsafe { raw_slice(src_io_vec, len as wasi::Size) };
 
     #[cfg(feature = "report_wasi_calls")]
     {
@@ -291,31 +1023,82 @@ pub unsafe extern "C" fn __ic_custom_fd_pwrite(
         );
     }
 
-    let result = if fd < 3 {
-        unsafe { forward_to_debug(iovs, len, res) }
-    } else {
-        FS.with(|fs| {
-            let mut fs = fs.borrow_mut();
-            match fs.write_vec_with_offset(fd as Fd, src_io_vec, offset as FileSize) {
-                Ok(r) => {
-                    unsafe { *res = r as wasi::Size };
+    let mut event = None;
+
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "fd_pwrite",
+            WasiCall::FdPwrite {
+                fd,
+                bufs: unsafe { ciovec_slices(iovs, len) },
+                offset,
+                written: unsafe { &mut *res }
+            }
+        );
 
-                    wasi::ERRNO_SUCCESS.raw() as i32
-                }
-                Err(er) => {
-                    unsafe { *res = 0 };
-                    into_errno(er)
+        namespace_fd!('call, fd: Fd);
+
+        if fd < 3 {
+            unsafe { forward_to_debug(iovs, len, res) }
+        } else {
+            FS.with(|fs| {
+                let mut fs = fs.borrow_mut();
+                match fs.write_vec_with_offset(fd as Fd, src_io_vec, offset as FileSize) {
+                    Ok(r) => {
+                        #[cfg(feature = "content_hashes")]
+                        {
+                            let bufs = unsafe { ciovec_slices(iovs, len) };
+                            hash_written(&fs, fd as Fd, offset as FileSize, &bufs, r as usize);
+                        }
+
+                        if r > 0 {
+                            event = file_changed(&fs, fd as Fd, WatchEventKind::Modify);
+                        }
+
+                        unsafe { *res = r as wasi::Size };
+
+                        match sync_if_flagged(&mut fs, fd as Fd, FdFlags::SYNC | FdFlags::DSYNC) {
+                            Ok(()) => wasi::ERRNO_SUCCESS.raw() as i32,
+                            Err(er) => into_errno(er),
+                        }
+                    }
+                    Err(er) => {
+                        unsafe { *res = 0 };
+                        into_errno(er)
+                    }
                 }
-            }
-        })
+            })
+        }
     };
 
+    notify_watches(event);
+
     #[cfg(feature = "report_wasi_calls")]
     {
         let r = format!("res={}", *res);
         debug_instructions!("__ic_custom_fd_pwrite", result, start, "{r}");
     }
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!(
+        "fd_pwrite",
+        result,
+        start,
+        Some(fd),
+        None,
+        "iovs.len={len} offset={offset}"
+    );
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!(
+        "fd_pwrite",
+        result,
+        [fd, offset],
+        [unsafe { gather_src_bufs(src_io_vec) }],
+        unsafe { (*res as u64).to_le_bytes().to_vec() }
+    );
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
 
@@ -332,11 +1115,17 @@ pub unsafe extern "C" fn __ic_custom_fd_pread(
     offset: i64,
     res: *mut wasi::Size,
 ) -> i32 {
+    validate!(
+        "fd_pread",
+        unsafe { check_iovecs(iovs, len) },
+        check_ptr(res)
+    );
+
     #[cfg(feature = "count_wasi_calls")]
     let start = ic_instruction_counter();
 
     let dst_io_vec = iovs as *const DstBuf;
-    let dst_io_vec = unsafe { std::slice::from_raw_parts(dst_io_vec, len as wasi::Size) };
+    let dst_io_vec = unsafe { raw_slice(dst_io_vec, len as wasi::Size) };
 
     #[cfg(feature = "report_wasi_calls")]
     {
@@ -349,30 +1138,43 @@ pub unsafe extern "C" fn __ic_custom_fd_pread(
         );
     }
 
-    // for now we don't support reading from the standard streams
-    if fd < 3 {
-        #[cfg(feature = "count_wasi_calls")]
-        update_counter(start);
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "fd_pread",
+            WasiCall::FdPread {
+                fd,
+                bufs: unsafe { iovec_slices(iovs, len) },
+                offset,
+                read: unsafe { &mut *res }
+            }
+        );
 
-        return wasi::ERRNO_INVAL.raw() as i32;
-    }
+        namespace_fd!('call, fd: Fd);
 
-    let result = FS.with(|fs| {
-        let mut fs = fs.borrow_mut();
+        // for now we don't support reading from the standard streams
+        if fd < 3 {
+            break 'call wasi::ERRNO_INVAL.raw() as i32;
+        }
 
-        let reading_result = fs.read_vec_with_offset(fd as Fd, dst_io_vec, offset as FileSize);
+        FS.with(|fs| {
+            let mut fs = fs.borrow_mut();
 
-        match reading_result {
-            Ok(r) => {
-                unsafe { *res = r as wasi::Size };
-                wasi::ERRNO_SUCCESS.raw() as i32
-            }
-            Err(er) => {
-                unsafe { *res = 0 };
-                into_errno(er)
+            let reading_result = sync_if_flagged(&mut fs, fd as Fd, FdFlags::RSYNC)
+                .and_then(|()| fs.read_vec_with_offset(fd as Fd, dst_io_vec, offset as FileSize));
+
+            match reading_result {
+                Ok(r) => {
+                    unsafe { *res = r as wasi::Size };
+                    wasi::ERRNO_SUCCESS.raw() as i32
+                }
+                Err(er) => {
+                    unsafe { *res = 0 };
+                    into_errno(er)
+                }
             }
-        }
-    });
+        })
+    };
 
     #[cfg(feature = "report_wasi_calls")]
     {
@@ -380,6 +1182,25 @@ pub unsafe extern "C" fn __ic_custom_fd_pread(
         debug_instructions!("__ic_custom_fd_pread", result, start, "{r}");
     }
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!(
+        "fd_pread",
+        result,
+        start,
+        Some(fd),
+        None,
+        "iovs.len={len} offset={offset}"
+    );
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!(
+        "fd_pread",
+        result,
+        [fd, dst_io_vec.iter().map(|b| b.len).sum::<usize>(), offset],
+        [],
+        unsafe { gather_dst_bufs(dst_io_vec, *res) }
+    );
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
 
@@ -395,6 +1216,8 @@ pub unsafe extern "C" fn __ic_custom_fd_seek(
     whence: i32,
     res: *mut wasi::Filesize,
 ) -> i32 {
+    validate!("fd_seek", check_range(whence, 0..=2), check_ptr(res));
+
     #[cfg(feature = "count_wasi_calls")]
     let start = ic_instruction_counter();
 
@@ -404,33 +1227,44 @@ pub unsafe extern "C" fn __ic_custom_fd_seek(
         "fd={fd:?} delta={delta:?} whence={whence:?}"
     );
 
-    // standart streams not supported
-    if fd < 3 {
-        #[cfg(feature = "count_wasi_calls")]
-        update_counter(start);
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "fd_seek",
+            WasiCall::FdSeek {
+                fd,
+                delta,
+                whence,
+                position: unsafe { &mut *res }
+            }
+        );
 
-        return wasi::ERRNO_INVAL.raw() as i32;
-    }
+        namespace_fd!('call, fd: Fd);
 
-    let result = FS.with(|fs| {
-        let mut fs = fs.borrow_mut();
+        // standart streams not supported
+        if fd < 3 {
+            break 'call wasi::ERRNO_INVAL.raw() as i32;
+        }
+
+        FS.with(|fs| {
+            let mut fs = fs.borrow_mut();
 
-        match fs.seek(
-            fd as Fd,
-            delta,
-            wasi_helpers::into_stable_fs_wence(whence as u8),
-        ) {
-            Ok(r) => {
-                unsafe { *res = r as wasi::Filesize };
+            let seek = wasi_helpers::into_stable_fs_wence(whence as u8)
+                .and_then(|whence| fs.seek(fd as Fd, delta, whence));
 
-                wasi::ERRNO_SUCCESS.raw() as i32
-            }
-            Err(er) => {
-                unsafe { *res = 0 };
-                into_errno(er)
+            match seek {
+                Ok(r) => {
+                    unsafe { *res = r as wasi::Filesize };
+
+                    wasi::ERRNO_SUCCESS.raw() as i32
+                }
+                Err(er) => {
+                    unsafe { *res = 0 };
+                    into_errno(er)
+                }
             }
-        }
-    });
+        })
+    };
 
     #[cfg(feature = "report_wasi_calls")]
     {
@@ -438,6 +1272,21 @@ pub unsafe extern "C" fn __ic_custom_fd_seek(
         debug_instructions!("__ic_custom_fd_seek", result, start, "{r}");
     }
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!(
+        "fd_seek",
+        result,
+        start,
+        Some(fd),
+        None,
+        "delta={delta} whence={whence}"
+    );
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!("fd_seek", result, [fd, delta, whence], [], unsafe {
+        (*res).to_le_bytes().to_vec()
+    });
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
 
@@ -460,10 +1309,21 @@ pub unsafe extern "C" fn __ic_custom_path_open(
     fdflags: i32,
     res: *mut Fd,
 ) -> i32 {
+    validate!(
+        "path_open",
+        check_buf(path, path_len),
+        check_ptr(res),
+        check_flags(dirflags, KNOWN_LOOKUPFLAGS),
+        check_flags(oflags, KNOWN_OFLAGS),
+        check_flags(fdflags, KNOWN_FDFLAGS)
+    );
+
+    reject_query_writes!(oflags & (wasi::OFLAGS_CREAT | wasi::OFLAGS_TRUNC) as i32 != 0);
+
     #[cfg(feature = "count_wasi_calls")]
     let start = ic_instruction_counter();
 
-    let file_name = unsafe { get_file_name(path, path_len as wasi::Size) };
+    let file_name = file_name!(path, path_len);
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!(
@@ -475,32 +1335,106 @@ pub unsafe extern "C" fn __ic_custom_path_open(
     // the symlinks are not supported yet by the file system
     prevent_elimination(&[dirflags]);
 
-    let result = FS.with(|fs| {
-        let mut fs = fs.borrow_mut();
+    let mut event = None;
+
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "path_open",
+            WasiCall::PathOpen {
+                fd: parent_fd,
+                dirflags,
+                path: &file_name,
+                oflags,
+                rights_base: fs_rights_base,
+                rights_inheriting: fs_rights_inheriting,
+                fdflags,
+                opened_fd: unsafe { &mut *res }
+            }
+        );
 
-        let fd_stat = FdStat {
-            flags: FdFlags::from_bits_truncate(fdflags as u16),
-            rights_base: fs_rights_base,
-            rights_inheriting: fs_rights_inheriting,
-        };
+        namespace_fd!('call, parent_fd: Fd);
 
-        let open_flags = OpenFlags::from_bits_truncate(oflags as u16);
+        check_access!(
+            'call,
+            parent_fd,
+            file_name,
+            open_access(parent_fd, &file_name, oflags, fs_rights_base)
+        );
+        check_mode!(
+            'call,
+            parent_fd,
+            file_name,
+            open_required_bits(oflags, fs_rights_base)
+        );
+
+        FS.with(|fs| {
+            let mut fs = fs.borrow_mut();
 
-        let now = ic_time();
+            let fd_stat = FdStat {
+                flags: FdFlags::from_bits_truncate(fdflags as u16),
+                rights_base: fs_rights_base,
+                rights_inheriting: fs_rights_inheriting,
+            };
 
-        let r = fs.open(parent_fd as Fd, file_name, fd_stat, open_flags, now);
+            let open_flags = OpenFlags::from_bits_truncate(oflags as u16);
 
-        match r {
-            Ok(r) => {
-                unsafe { *res = r as Fd };
-                wasi::ERRNO_SUCCESS.raw() as i32
-            }
-            Err(er) => {
-                unsafe { *res = 0 };
-                into_errno(er)
+            let now = ic_time();
+
+            let existed = is_watching() && fs.open_metadata(parent_fd, &file_name).is_ok();
+
+            let r = fs.open(parent_fd as Fd, &file_name, fd_stat, open_flags, now);
+
+            match r {
+                Ok(r) => {
+                    // the path is only passed on to choose the transforms of a new file
+                    #[cfg(feature = "fd_paths")]
+                    #[cfg_attr(not(feature = "transforms"), allow(unused_variables))]
+                    let path = FD_PATHS.with_borrow_mut(|paths| {
+                        let path = paths.resolve(fs.root_fd(), parent_fd, &file_name);
+                        if let Some(path) = &path {
+                            paths.open_fd(r, path.clone());
+                        }
+                        path
+                    });
+
+                    #[cfg(feature = "caller_namespaces")]
+                    NAMESPACES
+                        .with_borrow_mut(|namespaces| namespaces.open_fd(r, &ic_msg_caller()));
+
+                    #[cfg(feature = "transforms")]
+                    if oflags & wasi::OFLAGS_CREAT as i32 != 0
+                        && with_transforms(|transforms| transforms.transforms_new_files())
+                    {
+                        apply_new_file_transforms(&fs, r, path.as_deref());
+                    }
+
+                    // the new and truncated files are hashed from the start
+                    if oflags & wasi::OFLAGS_TRUNC as i32 != 0
+                        || (oflags & wasi::OFLAGS_CREAT as i32 != 0
+                            && fs.metadata(r).is_ok_and(|metadata| metadata.size == 0))
+                    {
+                        hash_resized(&fs, r);
+                    }
+
+                    if oflags & wasi::OFLAGS_CREAT as i32 != 0 && !existed {
+                        event = file_changed(&fs, r, WatchEventKind::Create);
+                    } else if oflags & wasi::OFLAGS_TRUNC as i32 != 0 {
+                        event = file_changed(&fs, r, WatchEventKind::Modify);
+                    }
+
+                    unsafe { *res = r as Fd };
+                    wasi::ERRNO_SUCCESS.raw() as i32
+                }
+                Err(er) => {
+                    unsafe { *res = 0 };
+                    into_errno(er)
+                }
             }
-        }
-    });
+        })
+    };
+
+    notify_watches(event);
 
     #[cfg(feature = "report_wasi_calls")]
     {
@@ -508,6 +1442,25 @@ pub unsafe extern "C" fn __ic_custom_path_open(
         debug_instructions!("__ic_custom_path_open", result, start, "{par}");
     }
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!("path_open", result, start, Some(parent_fd), Some(&*file_name), "dirflags={dirflags} oflags={oflags} fdflags={fdflags} rights_base={fs_rights_base} rights_inheriting={fs_rights_inheriting}");
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!(
+        "path_open",
+        result,
+        [
+            parent_fd,
+            dirflags,
+            oflags,
+            fs_rights_base,
+            fs_rights_inheriting,
+            fdflags
+        ],
+        [file_name_bytes(file_name.as_bytes())],
+        unsafe { (*res).to_le_bytes().to_vec() }
+    );
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
 
@@ -523,59 +1476,115 @@ pub extern "C" fn __ic_custom_fd_close(fd: Fd) -> i32 {
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_fd_close", "fd={fd:?}");
 
-    let result = FS.with(|fs| {
-        let res = fs.borrow_mut().close(fd as Fd);
+    let result = 'call: {
+        call_hook!('call, "fd_close", WasiCall::FdClose { fd });
 
-        match res {
-            Ok(_) => wasi::ERRNO_SUCCESS.raw() as i32,
-            Err(er) => into_errno(er),
+        // the descriptors of the namespace directories are managed by the polyfill
+        if is_namespace_dir_fd(fd) {
+            break 'call wasi::ERRNO_NOTSUP.raw() as i32;
         }
-    });
+
+        namespace_fd!('call, fd: Fd);
+
+        close_fd(fd)
+    };
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_fd_close", result, start);
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!("fd_close", result, start, Some(fd), None, "");
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!("fd_close", result, [fd], [], vec![]);
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
     result
 }
 
+// Close a descriptor with the state kept for it: the content hash and the watch event of a written file,
+// the path and the locks of the descriptor.
+fn close_fd(fd: Fd) -> i32 {
+    let mut event = None;
+
+    let result = FS.with(|fs| {
+        let mut fs = fs.borrow_mut();
+
+        store_content_hash(&mut fs, fd);
+
+        event = file_closed(&fs, fd);
+
+        match fs.close(fd) {
+            Ok(_) => {
+                #[cfg(feature = "fd_paths")]
+                FD_PATHS.with_borrow_mut(|paths| paths.close_fd(fd));
+                #[cfg(feature = "caller_namespaces")]
+                NAMESPACES.with_borrow_mut(|namespaces| namespaces.close_fd(fd));
+                #[cfg(feature = "file_locks")]
+                LOCKS.with_borrow_mut(|locks| locks.release(fd));
+                wasi::ERRNO_SUCCESS.raw() as i32
+            }
+            Err(er) => into_errno(er),
+        }
+    });
+
+    notify_watches(event);
+
+    result
+}
+
 #[unsafe(no_mangle)]
 #[inline(never)]
 #[allow(clippy::missing_safety_doc)]
 pub unsafe extern "C" fn __ic_custom_fd_filestat_get(fd: Fd, ret_val: *mut wasi::Filestat) -> i32 {
+    validate!("fd_filestat_get", check_ptr(ret_val));
+
     #[cfg(feature = "count_wasi_calls")]
     let start = ic_instruction_counter();
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_fd_filestat_get", "fd={fd:?}");
 
-    let result = FS.with(|fs| {
-        let fs = fs.borrow();
-        let res = fs.metadata(fd);
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "fd_filestat_get",
+            WasiCall::FdFilestatGet {
+                fd,
+                stat: unsafe { &mut *ret_val }
+            }
+        );
 
-        match res {
-            Ok(metadata) => {
-                let value: wasi::Filestat = wasi::Filestat {
-                    dev: 0,
-                    ino: metadata.node,
-                    filetype: into_wasi_filetype(metadata.file_type),
-                    nlink: metadata.link_count,
-                    size: metadata.size,
-                    atim: metadata.times.accessed,
-                    mtim: metadata.times.modified,
-                    ctim: metadata.times.created,
-                };
+        namespace_fd!('call, fd: Fd);
 
-                unsafe {
-                    *ret_val = value;
-                }
+        FS.with(|fs| {
+            let fs = fs.borrow();
+            let res = fs.metadata(fd);
+
+            match res {
+                Ok(metadata) => {
+                    let value: wasi::Filestat = wasi::Filestat {
+                        dev: 0,
+                        ino: metadata.node,
+                        filetype: into_wasi_filetype(metadata.file_type),
+                        nlink: metadata.link_count,
+                        size: metadata.size,
+                        atim: metadata.times.accessed,
+                        mtim: metadata.times.modified,
+                        ctim: metadata.times.created,
+                    };
+
+                    unsafe {
+                        *ret_val = value;
+                    }
 
-                wasi::ERRNO_SUCCESS.raw() as i32
+                    wasi::ERRNO_SUCCESS.raw() as i32
+                }
+                Err(er) => into_errno(er),
             }
-            Err(er) => into_errno(er),
-        }
-    });
+        })
+    };
 
     #[cfg(feature = "report_wasi_calls")]
     {
@@ -583,6 +1592,18 @@ pub unsafe extern "C" fn __ic_custom_fd_filestat_get(fd: Fd, ret_val: *mut wasi:
         debug_instructions!("__ic_custom_fd_filestat_get", result, start, "{ret}");
     }
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!("fd_filestat_get", result, start, Some(fd), None, "");
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!(
+        "fd_filestat_get",
+        result,
+        [fd],
+        [],
+        filestat_output(unsafe { &*ret_val })
+    );
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
     result
@@ -597,14 +1618,26 @@ pub extern "C" fn __ic_custom_fd_sync(fd: Fd) -> i32 {
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_fd_sync", "fd={fd}");
 
-    let result = FS.with(|fs| match fs.borrow_mut().flush(fd as Fd) {
-        Ok(()) => wasi::ERRNO_SUCCESS.raw() as i32,
-        Err(er) => into_errno(er),
-    });
+    let result = 'call: {
+        call_hook!('call, "fd_sync", WasiCall::FdSync { fd });
+
+        namespace_fd!('call, fd: Fd);
+
+        FS.with(|fs| match fs.borrow_mut().flush(fd as Fd) {
+            Ok(()) => wasi::ERRNO_SUCCESS.raw() as i32,
+            Err(er) => into_errno(er),
+        })
+    };
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_fd_sync", result, start);
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!("fd_sync", result, start, Some(fd), None, "");
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!("fd_sync", result, [fd], [], vec![]);
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
     result
@@ -614,32 +1647,47 @@ pub extern "C" fn __ic_custom_fd_sync(fd: Fd) -> i32 {
 #[inline(never)]
 #[allow(clippy::missing_safety_doc)]
 pub unsafe extern "C" fn __ic_custom_fd_tell(fd: Fd, res: *mut wasi::Filesize) -> i32 {
+    validate!("fd_tell", check_ptr(res));
+
     #[cfg(feature = "count_wasi_calls")]
     let start = ic_instruction_counter();
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_fd_tell", "fd={fd}");
 
-    // standard streams not supported
-    if fd < 3 {
-        return wasi::ERRNO_BADF.raw() as i32;
-    }
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "fd_tell",
+            WasiCall::FdTell {
+                fd,
+                position: unsafe { &mut *res }
+            }
+        );
 
-    let result = FS.with(|fs| {
-        let mut fs = fs.borrow_mut();
+        namespace_fd!('call, fd: Fd);
 
-        match fs.tell(fd as Fd) {
-            Ok(pos) => {
-                unsafe { *res = pos as wasi::Filesize };
+        // standard streams not supported
+        if fd < 3 {
+            break 'call wasi::ERRNO_BADF.raw() as i32;
+        }
 
-                wasi::ERRNO_SUCCESS.raw() as i32
-            }
-            Err(er) => {
-                unsafe { *res = 0 };
-                into_errno(er)
+        FS.with(|fs| {
+            let mut fs = fs.borrow_mut();
+
+            match fs.tell(fd as Fd) {
+                Ok(pos) => {
+                    unsafe { *res = pos as wasi::Filesize };
+
+                    wasi::ERRNO_SUCCESS.raw() as i32
+                }
+                Err(er) => {
+                    unsafe { *res = 0 };
+                    into_errno(er)
+                }
             }
-        }
-    });
+        })
+    };
 
     #[cfg(feature = "report_wasi_calls")]
     {
@@ -647,6 +1695,14 @@ pub unsafe extern "C" fn __ic_custom_fd_tell(fd: Fd, res: *mut wasi::Filesize) -
         debug_instructions!("__ic_custom_fd_tell", result, start, "{r}");
     }
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!("fd_tell", result, start, Some(fd), None, "");
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!("fd_tell", result, [fd], [], unsafe {
+        (*res).to_le_bytes().to_vec()
+    });
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
     result
@@ -656,46 +1712,71 @@ pub unsafe extern "C" fn __ic_custom_fd_tell(fd: Fd, res: *mut wasi::Filesize) -
 #[inline(never)]
 #[allow(clippy::missing_safety_doc)]
 pub unsafe extern "C" fn __ic_custom_fd_prestat_get(fd: i32, prestat: *mut wasi::Prestat) -> i32 {
+    validate!("fd_prestat_get", check_ptr(prestat));
+
     #[cfg(feature = "count_wasi_calls")]
     let start = ic_instruction_counter();
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_fd_prestat_get", "fd={fd:?}");
 
-    let ret = FS.with(|fs| {
-        let fs = fs.borrow();
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "fd_prestat_get",
+            WasiCall::FdPrestatGet {
+                fd,
+                prestat: unsafe { &mut *prestat }
+            }
+        );
+
+        FS.with(|fs| {
+            let fs = fs.borrow();
 
-        if fd as Fd == fs.root_fd() {
-            let root_len = fs.root_path().len();
+            if fd as Fd == fs.root_fd() {
+                let root_len = fs.root_path().len();
 
-            let pstat = wasi::Prestat {
-                tag: 0,
-                u: wasi::PrestatU {
-                    dir: wasi::PrestatDir {
-                        pr_name_len: root_len,
+                let pstat = wasi::Prestat {
+                    tag: 0,
+                    u: wasi::PrestatU {
+                        dir: wasi::PrestatDir {
+                            pr_name_len: root_len,
+                        },
                     },
-                },
-            };
+                };
 
-            unsafe { *prestat = pstat };
+                unsafe { *prestat = pstat };
 
-            wasi::ERRNO_SUCCESS.raw() as i32
-        } else {
-            wasi::ERRNO_BADF.raw() as i32
-        }
-    });
+                wasi::ERRNO_SUCCESS.raw() as i32
+            } else {
+                wasi::ERRNO_BADF.raw() as i32
+            }
+        })
+    };
 
     #[cfg(feature = "report_wasi_calls")]
     {
         let r = format!("prestat.u.dir.pr_name_len={}", unsafe {
             (*prestat).u.dir.pr_name_len
         });
-        debug_instructions!("__ic_custom_fd_prestat_get fd={}", ret, start, "{r}");
+        debug_instructions!("__ic_custom_fd_prestat_get fd={}", result, start, "{r}");
     }
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!("fd_prestat_get", result, start, Some(fd as Fd), None, "");
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!("fd_prestat_get", result, [fd], [], {
+        if result == wasi::ERRNO_SUCCESS.raw() as i32 {
+            unsafe { ((*prestat).u.dir.pr_name_len as u64).to_le_bytes().to_vec() }
+        } else {
+            vec![]
+        }
+    });
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
-    ret
+    result
 }
 
 #[unsafe(no_mangle)]
@@ -706,6 +1787,8 @@ pub unsafe extern "C" fn __ic_custom_fd_prestat_dir_name(
     path: *mut u8,
     max_len: i32,
 ) -> i32 {
+    validate!("fd_prestat_dir_name", check_buf(path, max_len));
+
     #[cfg(feature = "count_wasi_calls")]
     let start = ic_instruction_counter();
 
@@ -717,28 +1800,39 @@ pub unsafe extern "C" fn __ic_custom_fd_prestat_dir_name(
 
     let max_len = max_len as wasi::Size;
 
-    let result = FS.with(|fs| {
-        let fs = fs.borrow();
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "fd_prestat_dir_name",
+            WasiCall::FdPrestatDirName {
+                fd,
+                path: unsafe { raw_slice_mut(path, max_len as usize) }
+            }
+        );
+
+        FS.with(|fs| {
+            let fs = fs.borrow();
 
-        if fd as Fd == fs.root_fd() {
-            let max_len = std::cmp::min(max_len as i32, fs.root_path().len() as i32) as usize;
+            if fd as Fd == fs.root_fd() {
+                let max_len = std::cmp::min(max_len as i32, fs.root_path().len() as i32) as usize;
 
-            for i in 0..max_len {
-                unsafe {
-                    path.add(i).write(fs.root_path().as_bytes()[i]);
+                for i in 0..max_len {
+                    unsafe {
+                        path.add(i).write(fs.root_path().as_bytes()[i]);
+                    }
                 }
-            }
 
-            wasi::ERRNO_SUCCESS.raw() as i32
-        } else {
-            wasi::ERRNO_BADF.raw() as i32
-        }
-    });
+                wasi::ERRNO_SUCCESS.raw() as i32
+            } else {
+                wasi::ERRNO_BADF.raw() as i32
+            }
+        })
+    };
 
     #[cfg(feature = "report_wasi_calls")]
     {
         let mn = std::cmp::min(max_len as usize, 50);
-        let buf = unsafe { std::slice::from_raw_parts_mut(path, mn) };
+        let buf = unsafe { raw_slice_mut(path, mn) };
 
         let ret_path = format!("buf={buf:?}... ");
 
@@ -750,6 +1844,26 @@ pub unsafe extern "C" fn __ic_custom_fd_prestat_dir_name(
         );
     }
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!(
+        "fd_prestat_dir_name",
+        result,
+        start,
+        Some(fd as Fd),
+        None,
+        "max_len={max_len}"
+    );
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!("fd_prestat_dir_name", result, [fd, max_len], [], {
+        if result == wasi::ERRNO_SUCCESS.raw() as i32 {
+            let len = FS.with_borrow(|fs| fs.root_path().len()).min(max_len);
+            unsafe { raw_slice(path, len).to_vec() }
+        } else {
+            vec![]
+        }
+    });
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
     result
@@ -758,6 +1872,8 @@ pub unsafe extern "C" fn __ic_custom_fd_prestat_dir_name(
 #[unsafe(no_mangle)]
 #[inline(never)]
 pub extern "C" fn __ic_custom_fd_advise(fd: Fd, offset: i64, len: i64, advice: i32) -> i32 {
+    validate!("fd_advise", check_range(advice, 0..=5));
+
     #[cfg(feature = "count_wasi_calls")]
     let start = ic_instruction_counter();
 
@@ -767,32 +1883,66 @@ pub extern "C" fn __ic_custom_fd_advise(fd: Fd, offset: i64, len: i64, advice: i
         "fd={fd} offset={offset} len={len} advice={advice}"
     );
 
-    let result = if advice > 5 {
-        wasi::ERRNO_INVAL.raw() as i32
-    } else {
-        FS.with(|fs| {
-            let advice = stable_fs::fs::Advice::try_from(advice as u8);
-
-            match advice {
-                Ok(advice) => {
-                    match fs.borrow_mut().advice(
-                        fd as Fd,
-                        offset as FileSize,
-                        len as FileSize,
-                        advice,
-                    ) {
-                        Ok(()) => wasi::ERRNO_SUCCESS.raw() as i32,
-                        Err(er) => into_errno(er),
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "fd_advise",
+            WasiCall::FdAdvise {
+                fd,
+                offset,
+                len,
+                advice
+            }
+        );
+
+        namespace_fd!('call, fd: Fd);
+
+        if advice > 5 {
+            wasi::ERRNO_INVAL.raw() as i32
+        } else {
+            FS.with(|fs| {
+                let advice = stable_fs::fs::Advice::try_from(advice as u8);
+
+                match advice {
+                    Ok(advice) => {
+                        let mut fs = fs.borrow_mut();
+
+                        match fs.advice(fd as Fd, offset as FileSize, len as FileSize, advice) {
+                            Ok(()) => {
+                                advise_cache(
+                                    &mut fs,
+                                    fd,
+                                    offset as FileSize,
+                                    len as FileSize,
+                                    advice,
+                                );
+                                wasi::ERRNO_SUCCESS.raw() as i32
+                            }
+                            Err(er) => into_errno(er),
+                        }
                     }
+                    Err(err) => into_errno(err),
                 }
-                Err(err) => into_errno(err),
-            }
-        })
+            })
+        }
     };
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_fd_advise", result, start);
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!(
+        "fd_advise",
+        result,
+        start,
+        Some(fd),
+        None,
+        "offset={offset} len={len} advice={advice}"
+    );
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!("fd_advise", result, [fd, offset, len, advice], [], vec![]);
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
     result
@@ -801,6 +1951,14 @@ pub extern "C" fn __ic_custom_fd_advise(fd: Fd, offset: i64, len: i64, advice: i
 #[unsafe(no_mangle)]
 #[inline(never)]
 pub extern "C" fn __ic_custom_fd_allocate(fd: Fd, offset: i64, len: i64) -> i32 {
+    validate!(
+        "fd_allocate",
+        check_range(offset, 0..=i64::MAX),
+        check_range(len, 0..=i64::MAX)
+    );
+
+    reject_query_writes!();
+
     #[cfg(feature = "count_wasi_calls")]
     let start = ic_instruction_counter();
 
@@ -810,19 +1968,38 @@ pub extern "C" fn __ic_custom_fd_allocate(fd: Fd, offset: i64, len: i64) -> i32
         "fd={fd:?} offset={offset:?} len={len:?}"
     );
 
-    let result = FS.with(|fs| {
-        match fs
-            .borrow_mut()
-            .allocate(fd as Fd, offset as FileSize, len as FileSize)
-        {
-            Ok(()) => wasi::ERRNO_SUCCESS.raw() as i32,
-            Err(er) => into_errno(er),
-        }
-    });
+    let result = 'call: {
+        call_hook!('call, "fd_allocate", WasiCall::FdAllocate { fd, offset, len });
+
+        namespace_fd!('call, fd: Fd);
+
+        FS.with(|fs| {
+            match fs
+                .borrow_mut()
+                .allocate(fd as Fd, offset as FileSize, len as FileSize)
+            {
+                Ok(()) => wasi::ERRNO_SUCCESS.raw() as i32,
+                Err(er) => into_errno(er),
+            }
+        })
+    };
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_fd_allocate", result, start);
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!(
+        "fd_allocate",
+        result,
+        start,
+        Some(fd),
+        None,
+        "offset={offset} len={len}"
+    );
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!("fd_allocate", result, [fd, offset, len], [], vec![]);
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
     result
@@ -837,14 +2014,26 @@ pub extern "C" fn __ic_custom_fd_datasync(fd: Fd) -> i32 {
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_fd_datasync", "fd={fd:?}");
 
-    let result = FS.with(|fs| match fs.borrow_mut().flush(fd as Fd) {
-        Ok(()) => wasi::ERRNO_SUCCESS.raw() as i32,
-        Err(er) => into_errno(er),
-    });
+    let result = 'call: {
+        call_hook!('call, "fd_datasync", WasiCall::FdDatasync { fd });
+
+        namespace_fd!('call, fd: Fd);
+
+        FS.with(|fs| match fs.borrow_mut().flush(fd as Fd) {
+            Ok(()) => wasi::ERRNO_SUCCESS.raw() as i32,
+            Err(er) => into_errno(er),
+        })
+    };
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_fd_datasync", result, start);
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!("fd_datasync", result, start, Some(fd), None, "");
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!("fd_datasync", result, [fd], [], vec![]);
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
     result
@@ -854,33 +2043,63 @@ pub extern "C" fn __ic_custom_fd_datasync(fd: Fd) -> i32 {
 #[inline(never)]
 #[allow(clippy::missing_safety_doc)]
 pub unsafe extern "C" fn __ic_custom_fd_fdstat_get(fd: Fd, ret_fdstat: *mut wasi::Fdstat) -> i32 {
+    validate!("fd_fdstat_get", check_ptr(ret_fdstat));
+
     #[cfg(feature = "count_wasi_calls")]
     let start = ic_instruction_counter();
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_fd_fdstat_get", "fd={fd:?}");
 
-    let result = FS.with(|fs| {
-        let fs = fs.borrow();
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "fd_fdstat_get",
+            WasiCall::FdFdstatGet {
+                fd,
+                stat: unsafe { &mut *ret_fdstat }
+            }
+        );
 
-        let stat = fs.get_stat(fd as Fd);
+        namespace_fd!('call, fd: Fd);
 
-        match stat {
-            Ok((ftype, fdstat)) => {
-                let tmp_fd_stat = wasi::Fdstat {
-                    fs_filetype: into_wasi_filetype(ftype),
-                    fs_flags: fdstat.flags.bits(),
-                    fs_rights_base: fdstat.rights_base,
-                    fs_rights_inheriting: fdstat.rights_inheriting,
-                };
+        if fd == 0 {
+            let flags = STDIN.with_borrow(|stdin| stdin.flags());
 
-                unsafe { *ret_fdstat = tmp_fd_stat };
+            unsafe {
+                *ret_fdstat = wasi::Fdstat {
+                    fs_filetype: wasi::FILETYPE_CHARACTER_DEVICE,
+                    fs_flags: flags.bits(),
+                    fs_rights_base: wasi::RIGHTS_FD_READ | wasi::RIGHTS_FD_FDSTAT_SET_FLAGS,
+                    fs_rights_inheriting: 0,
+                }
+            };
 
-                wasi::ERRNO_SUCCESS.raw() as i32
-            }
-            Err(err) => wasi_helpers::into_errno(err),
+            wasi::ERRNO_SUCCESS.raw() as i32
+        } else {
+            FS.with(|fs| {
+                let fs = fs.borrow();
+
+                let stat = fs.get_stat(fd as Fd);
+
+                match stat {
+                    Ok((ftype, fdstat)) => {
+                        let tmp_fd_stat = wasi::Fdstat {
+                            fs_filetype: into_wasi_filetype(ftype),
+                            fs_flags: fdstat.flags.bits(),
+                            fs_rights_base: fdstat.rights_base,
+                            fs_rights_inheriting: fdstat.rights_inheriting,
+                        };
+
+                        unsafe { *ret_fdstat = tmp_fd_stat };
+
+                        wasi::ERRNO_SUCCESS.raw() as i32
+                    }
+                    Err(err) => wasi_helpers::into_errno(err),
+                }
+            })
         }
-    });
+    };
 
     #[cfg(feature = "report_wasi_calls")]
     {
@@ -888,6 +2107,18 @@ pub unsafe extern "C" fn __ic_custom_fd_fdstat_get(fd: Fd, ret_fdstat: *mut wasi
         debug_instructions!("__ic_custom_fd_fdstat_get", result, start, "{r}");
     }
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!("fd_fdstat_get", result, start, Some(fd), None, "");
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!(
+        "fd_fdstat_get",
+        result,
+        [fd],
+        [],
+        fdstat_output(unsafe { &*ret_fdstat })
+    );
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
     result
@@ -896,6 +2127,8 @@ pub unsafe extern "C" fn __ic_custom_fd_fdstat_get(fd: Fd, ret_fdstat: *mut wasi
 #[unsafe(no_mangle)]
 #[inline(never)]
 pub extern "C" fn __ic_custom_fd_fdstat_set_flags(fd: Fd, new_flags: i32) -> i32 {
+    validate!("fd_fdstat_set_flags", check_flags(new_flags, KNOWN_FDFLAGS));
+
     #[cfg(feature = "count_wasi_calls")]
     let start = ic_instruction_counter();
 
@@ -905,33 +2138,70 @@ pub extern "C" fn __ic_custom_fd_fdstat_set_flags(fd: Fd, new_flags: i32) -> i32
         "fd={fd} new_flags={new_flags}"
     );
 
-    let result = FS.with(|fs| {
-        let mut fs = fs.borrow_mut();
-
-        let stat = fs.get_stat(fd as Fd);
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "fd_fdstat_set_flags",
+            WasiCall::FdFdstatSetFlags {
+                fd,
+                flags: new_flags
+            }
+        );
 
-        match stat {
-            Ok((_ftype, mut fdstat)) => {
-                let new_flags = FdFlags::from_bits(new_flags as u16);
+        namespace_fd!('call, fd: Fd);
 
-                if new_flags.is_none() {
-                    return wasi::ERRNO_INVAL.raw() as i32;
+        if fd == 0 {
+            // only `FDFLAGS_NONBLOCK` changes the reads of the standard input
+            match FdFlags::from_bits(new_flags as u16) {
+                Some(flags) => {
+                    STDIN.with_borrow_mut(|stdin| stdin.set_flags(flags));
+                    wasi::ERRNO_SUCCESS.raw() as i32
                 }
+                None => wasi::ERRNO_INVAL.raw() as i32,
+            }
+        } else {
+            FS.with(|fs| {
+                let mut fs = fs.borrow_mut();
+
+                let stat = fs.get_stat(fd as Fd);
+
+                match stat {
+                    Ok((_ftype, mut fdstat)) => {
+                        let new_flags = FdFlags::from_bits(new_flags as u16);
+
+                        if new_flags.is_none() {
+                            return wasi::ERRNO_INVAL.raw() as i32;
+                        }
 
-                fdstat.flags = new_flags.unwrap();
+                        fdstat.flags = new_flags.unwrap();
 
-                match fs.set_stat(fd as Fd, fdstat) {
-                    Ok(_) => wasi::ERRNO_SUCCESS.raw() as i32,
+                        match fs.set_stat(fd as Fd, fdstat) {
+                            Ok(_) => wasi::ERRNO_SUCCESS.raw() as i32,
+                            Err(err) => wasi_helpers::into_errno(err),
+                        }
+                    }
                     Err(err) => wasi_helpers::into_errno(err),
                 }
-            }
-            Err(err) => wasi_helpers::into_errno(err),
+            })
         }
-    });
+    };
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_fd_fdstat_set_flags", result, start);
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!(
+        "fd_fdstat_set_flags",
+        result,
+        start,
+        Some(fd),
+        None,
+        "new_flags={new_flags}"
+    );
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!("fd_fdstat_set_flags", result, [fd, new_flags], [], vec![]);
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
     result
@@ -953,28 +2223,61 @@ pub extern "C" fn __ic_custom_fd_fdstat_set_rights(
         "fd={fd} rights_base={rights_base} rights_inheriting={rights_inheriting}"
     );
 
-    let result = FS.with(|fs| {
-        let mut fs = fs.borrow_mut();
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "fd_fdstat_set_rights",
+            WasiCall::FdFdstatSetRights {
+                fd,
+                rights_base,
+                rights_inheriting
+            }
+        );
+
+        namespace_fd!('call, fd: i32);
+
+        FS.with(|fs| {
+            let mut fs = fs.borrow_mut();
 
-        let stat = fs.get_stat(fd as Fd);
+            let stat = fs.get_stat(fd as Fd);
 
-        match stat {
-            Ok((_ftype, mut fdstat)) => {
-                fdstat.rights_base &= rights_base as u64;
-                fdstat.rights_inheriting &= rights_inheriting as u64;
+            match stat {
+                Ok((_ftype, mut fdstat)) => {
+                    fdstat.rights_base &= rights_base as u64;
+                    fdstat.rights_inheriting &= rights_inheriting as u64;
 
-                match fs.set_stat(fd as Fd, fdstat) {
-                    Ok(_) => wasi::ERRNO_SUCCESS.raw() as i32,
-                    Err(err) => wasi_helpers::into_errno(err),
+                    match fs.set_stat(fd as Fd, fdstat) {
+                        Ok(_) => wasi::ERRNO_SUCCESS.raw() as i32,
+                        Err(err) => wasi_helpers::into_errno(err),
+                    }
                 }
+                Err(err) => wasi_helpers::into_errno(err),
             }
-            Err(err) => wasi_helpers::into_errno(err),
-        }
-    });
+        })
+    };
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_fd_fdstat_set_rights", result, start);
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!(
+        "fd_fdstat_set_rights",
+        result,
+        start,
+        Some(fd as Fd),
+        None,
+        "rights_base={rights_base} rights_inheriting={rights_inheriting}"
+    );
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!(
+        "fd_fdstat_set_rights",
+        result,
+        [fd, rights_base, rights_inheriting],
+        [],
+        vec![]
+    );
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
     result
@@ -983,6 +2286,10 @@ pub extern "C" fn __ic_custom_fd_fdstat_set_rights(
 #[unsafe(no_mangle)]
 #[inline(never)]
 pub extern "C" fn __ic_custom_fd_filestat_set_size(fd: Fd, size: i64) -> i32 {
+    validate!("fd_filestat_set_size", check_range(size, 0..=i64::MAX));
+
+    reject_query_writes!();
+
     #[cfg(feature = "count_wasi_calls")]
     let start = ic_instruction_counter();
 
@@ -992,16 +2299,49 @@ pub extern "C" fn __ic_custom_fd_filestat_set_size(fd: Fd, size: i64) -> i32 {
         "fd={fd:?} size={size:?}"
     );
 
-    let result = FS.with(
-        |fs| match fs.borrow_mut().set_file_size(fd, size as FileSize) {
-            Ok(_) => wasi::ERRNO_SUCCESS.raw() as i32,
-            Err(err) => wasi_helpers::into_errno(err),
-        },
-    );
+    let mut event = None;
+
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "fd_filestat_set_size",
+            WasiCall::FdFilestatSetSize { fd, size }
+        );
+
+        namespace_fd!('call, fd: Fd);
+
+        FS.with(|fs| {
+            let mut fs = fs.borrow_mut();
+
+            match fs.set_file_size(fd, size as FileSize) {
+                Ok(_) => {
+                    hash_resized(&fs, fd);
+                    event = file_changed(&fs, fd, WatchEventKind::Modify);
+                    wasi::ERRNO_SUCCESS.raw() as i32
+                }
+                Err(err) => wasi_helpers::into_errno(err),
+            }
+        })
+    };
+
+    notify_watches(event);
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_fd_filestat_set_size", result, start);
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!(
+        "fd_filestat_set_size",
+        result,
+        start,
+        Some(fd),
+        None,
+        "size={size}"
+    );
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!("fd_filestat_set_size", result, [fd, size], [], vec![]);
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
     result
@@ -1015,6 +2355,13 @@ pub extern "C" fn __ic_custom_fd_filestat_set_times(
     mtim: i64,
     fst_flags: i32,
 ) -> i32 {
+    validate!(
+        "fd_filestat_set_times",
+        check_flags(fst_flags, KNOWN_FSTFLAGS)
+    );
+
+    reject_query_writes!();
+
     #[cfg(feature = "count_wasi_calls")]
     let start = ic_instruction_counter();
 
@@ -1024,44 +2371,78 @@ pub extern "C" fn __ic_custom_fd_filestat_set_times(
         "fd={fd} atim={atim} mtim={mtim} fst_flags={fst_flags}"
     );
 
-    let fst_flags = fst_flags as wasi::Fstflags;
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "fd_filestat_set_times",
+            WasiCall::FdFilestatSetTimes {
+                fd,
+                atim,
+                mtim,
+                fst_flags
+            }
+        );
+
+        namespace_fd!('call, fd: Fd);
 
-    let result = FS.with(|fs| {
-        let mut fs = fs.borrow_mut();
-        let mut atim = atim as u64;
-        let mut mtim = mtim as u64;
+        let fst_flags = fst_flags as wasi::Fstflags;
 
-        let meta = fs.metadata(fd);
+        FS.with(|fs| {
+            let mut fs = fs.borrow_mut();
+            let mut atim = atim as u64;
+            let mut mtim = mtim as u64;
 
-        match meta {
-            Ok(_) => {
-                let now = ic_time();
+            let meta = fs.metadata(fd);
 
-                if fst_flags & wasi::FSTFLAGS_ATIM_NOW > 0 {
-                    atim = now;
-                }
+            match meta {
+                Ok(_) => {
+                    let now = ic_time();
 
-                if fst_flags & wasi::FSTFLAGS_MTIM_NOW > 0 {
-                    mtim = now;
-                }
+                    if fst_flags & wasi::FSTFLAGS_ATIM_NOW > 0 {
+                        atim = now;
+                    }
 
-                if fst_flags & wasi::FSTFLAGS_ATIM > 0 {
-                    let _ = fs.set_accessed_time(fd as Fd, atim);
-                }
+                    if fst_flags & wasi::FSTFLAGS_MTIM_NOW > 0 {
+                        mtim = now;
+                    }
 
-                if fst_flags & wasi::FSTFLAGS_MTIM > 0 {
-                    let _ = fs.set_modified_time(fd as Fd, mtim);
-                }
+                    if fst_flags & wasi::FSTFLAGS_ATIM > 0 {
+                        let _ = fs.set_accessed_time(fd as Fd, atim);
+                    }
 
-                wasi::ERRNO_SUCCESS.raw() as i32
+                    if fst_flags & wasi::FSTFLAGS_MTIM > 0 {
+                        let _ = fs.set_modified_time(fd as Fd, mtim);
+                    }
+
+                    wasi::ERRNO_SUCCESS.raw() as i32
+                }
+                Err(err) => wasi_helpers::into_errno(err),
             }
-            Err(err) => wasi_helpers::into_errno(err),
-        }
-    });
+        })
+    };
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_fd_filestat_set_times", result, start);
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!(
+        "fd_filestat_set_times",
+        result,
+        start,
+        Some(fd),
+        None,
+        "atim={atim} mtim={mtim} fst_flags={fst_flags}"
+    );
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!(
+        "fd_filestat_set_times",
+        result,
+        [fd, atim, mtim, fst_flags],
+        [],
+        vec![]
+    );
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
     result
@@ -1077,6 +2458,8 @@ pub unsafe extern "C" fn __ic_custom_fd_readdir(
     cookie: i64,
     res: *mut wasi::Size,
 ) -> i32 {
+    validate!("fd_readdir", check_buf(bytes, bytes_len), check_ptr(res));
+
     #[cfg(feature = "count_wasi_calls")]
     let start = ic_instruction_counter();
 
@@ -1086,10 +2469,26 @@ pub unsafe extern "C" fn __ic_custom_fd_readdir(
         debug_instructions!("__ic_custom_fd_readdir", "{parms}");
     }
 
-    let result = FS.with(|fs| {
-        let fs = fs.borrow();
-        unsafe { wasi_helpers::fd_readdir(&fs, fd, cookie, bytes, bytes_len, res) }
-    });
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "fd_readdir",
+            WasiCall::FdReaddir {
+                fd,
+                buf: unsafe { raw_slice_mut(bytes, bytes_len as usize) },
+                cookie,
+                used: unsafe { &mut *res }
+            }
+        );
+
+        namespace_fd!('call, fd: Fd);
+        check_access!('call, fd, "", Access::List);
+
+        FS.with(|fs| {
+            let fs = fs.borrow();
+            unsafe { wasi_helpers::fd_readdir(&fs, fd, cookie, bytes, bytes_len, res) }
+        })
+    };
 
     #[cfg(feature = "report_wasi_calls")]
     {
@@ -1099,13 +2498,28 @@ pub unsafe extern "C" fn __ic_custom_fd_readdir(
             50,
         );
 
-        let buf = unsafe { std::slice::from_raw_parts_mut(bytes, mn) };
+        let buf = unsafe { raw_slice_mut(bytes, mn) };
 
         let t = format!("buf={buf:?}... res={}", unsafe { *res });
 
         debug_instructions!("__ic_custom_fd_readdir", result, start, "{t}");
     }
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!(
+        "fd_readdir",
+        result,
+        start,
+        Some(fd),
+        None,
+        "bytes_len={bytes_len} cookie={cookie}"
+    );
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!("fd_readdir", result, [fd, bytes_len, cookie], [], unsafe {
+        raw_slice(bytes, *res).to_vec()
+    });
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
     result
@@ -1120,20 +2534,62 @@ pub extern "C" fn __ic_custom_fd_renumber(fd_from: Fd, fd_to: Fd) -> i32 {
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_fd_renumber", "fd_from={fd_from} fd_to={fd_to}");
 
-    let result = FS.with(|fs| {
-        let mut fs = fs.borrow_mut();
-
-        let result = fs.renumber(fd_from as Fd, fd_to as Fd);
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "fd_renumber",
+            WasiCall::FdRenumber {
+                from: fd_from,
+                to: fd_to
+            }
+        );
 
-        match result {
-            Ok(()) => wasi::ERRNO_SUCCESS.raw() as i32,
-            Err(err) => into_errno(err),
+        // the descriptors of the namespace directories are managed by the polyfill
+        if is_namespace_dir_fd(fd_from) || is_namespace_dir_fd(fd_to) {
+            break 'call wasi::ERRNO_NOTSUP.raw() as i32;
         }
-    });
+
+        namespace_fd!('call, fd_from: Fd);
+        namespace_fd!('call, fd_to: Fd);
+
+        FS.with(|fs| {
+            let mut fs = fs.borrow_mut();
+
+            let result = fs.renumber(fd_from as Fd, fd_to as Fd);
+
+            match result {
+                Ok(()) => {
+                    #[cfg(feature = "fd_paths")]
+                    FD_PATHS.with_borrow_mut(|paths| paths.renumber_fd(fd_from, fd_to));
+                    #[cfg(feature = "caller_namespaces")]
+                    NAMESPACES.with_borrow_mut(|namespaces| namespaces.renumber_fd(fd_from, fd_to));
+                    #[cfg(feature = "file_locks")]
+                    LOCKS.with_borrow_mut(|locks| locks.renumber(fd_from, fd_to));
+                    #[cfg(feature = "watches")]
+                    WATCHES.with_borrow_mut(|watches| watches.renumber(fd_from, fd_to));
+                    wasi::ERRNO_SUCCESS.raw() as i32
+                }
+                Err(err) => into_errno(err),
+            }
+        })
+    };
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_fd_renumber", result, start);
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!(
+        "fd_renumber",
+        result,
+        start,
+        Some(fd_from),
+        None,
+        "fd_to={fd_to}"
+    );
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!("fd_renumber", result, [fd_from, fd_to], [], vec![]);
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
     result
@@ -1143,25 +2599,44 @@ pub extern "C" fn __ic_custom_fd_renumber(fd_from: Fd, fd_to: Fd) -> i32 {
 #[inline(never)]
 #[allow(clippy::missing_safety_doc)]
 pub unsafe extern "C" fn __ic_custom_random_get(buf: *mut u8, buf_len: wasi::Size) -> i32 {
+    validate!("random_get", check_slice(buf, buf_len));
+
     #[cfg(feature = "count_wasi_calls")]
     let start = ic_instruction_counter();
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_random_get");
 
-    let buf = unsafe { std::slice::from_raw_parts_mut(buf, buf_len) };
-    RNG.with(|rng| {
-        let mut rng = rng.borrow_mut();
-        rng.fill(buf);
-    });
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "random_get",
+            WasiCall::RandomGet {
+                buf: unsafe { raw_slice_mut(buf, buf_len) }
+            }
+        );
+
+        let buf = unsafe { raw_slice_mut(buf, buf_len) };
+        RNG.with(|rng| {
+            let mut rng = rng.borrow_mut();
+            rng.fill(buf);
+        });
 
-    let result = wasi::ERRNO_SUCCESS.raw() as i32;
+        wasi::ERRNO_SUCCESS.raw() as i32
+    };
 
     #[cfg(feature = "report_wasi_calls")]
     {
         debug_instructions!("__ic_custom_random_get", result, start, "buf={buf:?}");
     }
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!("random_get", result, start, None, None, "buf_len={buf_len}");
+
+    #[cfg(feature = "record_wasi_calls")]
+    // the random bytes depend on the seed and are not compared on replay
+    record_call!("random_get", result, [buf_len], [], vec![]);
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
     result
@@ -1174,19 +2649,38 @@ pub unsafe extern "C" fn __ic_custom_environ_get(
     environment: *mut *mut u8,
     environment_buffer: *mut u8,
 ) -> i32 {
+    validate!(
+        "environ_get",
+        ENV.with_borrow(|env| {
+            let (count, size) = env.environ_sizes_get();
+            check_slice(environment, count).and(check_slice(environment_buffer, size))
+        })
+    );
+
     #[cfg(feature = "count_wasi_calls")]
     let start = ic_instruction_counter();
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_environ_get");
 
-    let result = ENV.with(|env| {
-        let env = env.borrow();
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "environ_get",
+            WasiCall::EnvironGet {
+                environ: environment,
+                environ_buf: environment_buffer
+            }
+        );
+
+        let result = ENV.with(|env| {
+            let env = env.borrow();
 
-        unsafe { env.environ_get(environment, environment_buffer) }
-    });
+            unsafe { env.environ_get(environment, environment_buffer) }
+        });
 
-    let result = result.raw() as i32;
+        result.raw() as i32
+    };
 
     #[cfg(feature = "report_wasi_calls")]
     {
@@ -1198,6 +2692,20 @@ pub unsafe extern "C" fn __ic_custom_environ_get(
         });
     }
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!("environ_get", result, start, None, None, "");
+
+    #[cfg(feature = "record_wasi_calls")]
+    // the pointers to the values depend on the buffer location, only the buffer is compared on replay
+    record_call!("environ_get", result, [], [], {
+        let size = ENV.with_borrow(|env| env.environ_sizes_get().1);
+        if result == wasi::ERRNO_SUCCESS.raw() as i32 && size > 0 {
+            unsafe { raw_slice(environment_buffer, size).to_vec() }
+        } else {
+            vec![]
+        }
+    });
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
     result
@@ -1210,21 +2718,38 @@ pub unsafe extern "C" fn __ic_custom_environ_sizes_get(
     entry_count: *mut wasi::Size,
     buffer_size: *mut wasi::Size,
 ) -> i32 {
+    validate!(
+        "environ_sizes_get",
+        check_ptr(entry_count),
+        check_ptr(buffer_size)
+    );
+
     #[cfg(feature = "count_wasi_calls")]
     let start = ic_instruction_counter();
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_environ_sizes_get");
 
-    ENV.with(|env| {
-        let env = env.borrow();
-        let (count, size) = env.environ_sizes_get();
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "environ_sizes_get",
+            WasiCall::EnvironSizesGet {
+                count: unsafe { &mut *entry_count },
+                buf_size: unsafe { &mut *buffer_size }
+            }
+        );
 
-        unsafe { *entry_count = count };
-        unsafe { *buffer_size = size };
-    });
+        ENV.with(|env| {
+            let env = env.borrow();
+            let (count, size) = env.environ_sizes_get();
+
+            unsafe { *entry_count = count };
+            unsafe { *buffer_size = size };
+        });
 
-    let result = 0;
+        0
+    };
 
     #[cfg(feature = "report_wasi_calls")]
     {
@@ -1236,6 +2761,18 @@ pub unsafe extern "C" fn __ic_custom_environ_sizes_get(
         });
     }
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!("environ_sizes_get", result, start, None, None, "");
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!("environ_sizes_get", result, [], [], unsafe {
+        [
+            (*entry_count as u64).to_le_bytes(),
+            (*buffer_size as u64).to_le_bytes(),
+        ]
+        .concat()
+    });
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
     result
@@ -1250,12 +2787,43 @@ pub extern "C" fn __ic_custom_proc_exit(code: i32) -> ! {
 #[unsafe(no_mangle)]
 #[inline(never)]
 pub extern "C" fn __ic_custom_args_get(arg_entries: *mut *mut u8, arg_buffer: *mut u8) -> i32 {
+    // there are no arguments, `args_sizes_get` returns zero sizes
+    validate!(
+        "args_get",
+        check_slice(arg_entries, 0),
+        check_slice(arg_buffer, 0)
+    );
+
+    #[cfg(feature = "count_wasi_calls")]
+    let start = ic_instruction_counter();
+
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "args_get",
+            WasiCall::ArgsGet {
+                argv: arg_entries,
+                argv_buf: arg_buffer
+            }
+        );
+
+        prevent_elimination(&[arg_entries as i32, arg_buffer as i32]);
+        // No-op.
+        wasi::ERRNO_SUCCESS.raw() as i32
+    };
+
     #[cfg(feature = "report_wasi_calls")]
-    debug_instructions!("__ic_custom_args_get -> 0");
+    debug_instructions!("__ic_custom_args_get", result, start);
+
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!("args_get", result, start, None, None, "");
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!("args_get", result, [], [], vec![]);
 
-    prevent_elimination(&[arg_entries as i32, arg_buffer as i32]);
-    // No-op.
-    0
+    #[cfg(feature = "count_wasi_calls")]
+    update_counter(start);
+    result
 }
 
 #[unsafe(no_mangle)]
@@ -1265,27 +2833,87 @@ pub unsafe extern "C" fn __ic_custom_args_sizes_get(
     len1: *mut wasi::Size,
     len2: *mut wasi::Size,
 ) -> i32 {
+    validate!("args_sizes_get", check_ptr(len1), check_ptr(len2));
+
+    #[cfg(feature = "count_wasi_calls")]
+    let start = ic_instruction_counter();
+
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "args_sizes_get",
+            WasiCall::ArgsSizesGet {
+                argc: unsafe { &mut *len1 },
+                argv_buf_size: unsafe { &mut *len2 }
+            }
+        );
+
+        unsafe {
+            *len1 = 0;
+            *len2 = 0;
+        }
+        wasi::ERRNO_SUCCESS.raw() as i32
+    };
+
     #[cfg(feature = "report_wasi_calls")]
-    debug_instructions!("__ic_custom_arg_sizes_get -> 0");
+    debug_instructions!("__ic_custom_arg_sizes_get", result, start);
 
-    unsafe {
-        *len1 = 0;
-        *len2 = 0;
-    }
-    0
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!("args_sizes_get", result, start, None, None, "");
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!("args_sizes_get", result, [], [], unsafe {
+        [(*len1 as u64).to_le_bytes(), (*len2 as u64).to_le_bytes()].concat()
+    });
+
+    #[cfg(feature = "count_wasi_calls")]
+    update_counter(start);
+    result
 }
 
 #[unsafe(no_mangle)]
 #[inline(never)]
 #[allow(clippy::missing_safety_doc)]
-pub unsafe extern "C" fn __ic_custom_clock_res_get(id: i32, result: *mut u64) -> i32 {
-    prevent_elimination(&[id]);
+pub unsafe extern "C" fn __ic_custom_clock_res_get(id: i32, resolution: *mut u64) -> i32 {
+    validate!(
+        "clock_res_get",
+        check_range(id, 0..=3),
+        check_ptr(resolution)
+    );
+
+    #[cfg(feature = "count_wasi_calls")]
+    let start = ic_instruction_counter();
+
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "clock_res_get",
+            WasiCall::ClockResGet {
+                id,
+                resolution: unsafe { &mut *resolution }
+            }
+        );
+
+        prevent_elimination(&[id]);
+
+        unsafe { *resolution = 1_000_000_000 }; // 1 second.
+        wasi::ERRNO_SUCCESS.raw() as i32
+    };
 
     #[cfg(feature = "report_wasi_calls")]
-    debug_instructions!("__ic_custom_clock_res_get -> 0");
+    debug_instructions!("__ic_custom_clock_res_get", result, start);
 
-    unsafe { *result = 1_000_000_000 }; // 1 second.
-    wasi::ERRNO_SUCCESS.raw() as i32
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!("clock_res_get", result, start, None, None, "id={id}");
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!("clock_res_get", result, [id], [], unsafe {
+        (*resolution).to_le_bytes().to_vec()
+    });
+
+    #[cfg(feature = "count_wasi_calls")]
+    update_counter(start);
+    result
 }
 
 #[unsafe(no_mangle)]
@@ -1296,20 +2924,48 @@ pub unsafe extern "C" fn __ic_custom_clock_time_get(
     precision: i64,
     time: *mut u64,
 ) -> i32 {
+    validate!("clock_time_get", check_range(id, 0..=3), check_ptr(time));
+
     #[cfg(feature = "count_wasi_calls")]
     let start = ic_instruction_counter();
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_clock_time_get");
 
-    prevent_elimination(&[id, precision as i32]);
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "clock_time_get",
+            WasiCall::ClockTimeGet {
+                id,
+                precision,
+                time: unsafe { &mut *time }
+            }
+        );
+
+        prevent_elimination(&[id, precision as i32]);
 
-    unsafe { *time = ic_time() };
-    let result = wasi::ERRNO_SUCCESS.raw() as i32;
+        unsafe { *time = ic_time() };
+        wasi::ERRNO_SUCCESS.raw() as i32
+    };
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_clock_time_get", result, start);
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!(
+        "clock_time_get",
+        result,
+        start,
+        None,
+        None,
+        "id={id} precision={precision}"
+    );
+
+    #[cfg(feature = "record_wasi_calls")]
+    // the time changes between the recording and the replay and is not compared
+    record_call!("clock_time_get", result, [id, precision], [], vec![]);
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
     result
@@ -1323,13 +2979,17 @@ pub unsafe extern "C" fn __ic_custom_path_create_directory(
     path: *const u8,
     path_len: i32,
 ) -> i32 {
+    validate!("path_create_directory", check_buf(path, path_len));
+
+    reject_query_writes!();
+
     #[cfg(feature = "count_wasi_calls")]
     let start = ic_instruction_counter();
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_path_create_directory");
 
-    let dir_name = unsafe { get_file_name(path, path_len as wasi::Size) };
+    let dir_name = file_name!(path, path_len);
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!(
@@ -1337,22 +2997,62 @@ pub unsafe extern "C" fn __ic_custom_path_create_directory(
         "parent_fd={parent_fd} path={dir_name}"
     );
 
-    let result = FS.with(|fs| {
-        let mut fs = fs.borrow_mut();
+    let mut event = None;
 
-        let fd_stat = FdStat::default();
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "path_create_directory",
+            WasiCall::PathCreateDirectory {
+                fd: parent_fd,
+                path: &dir_name
+            }
+        );
 
-        let now = ic_time();
+        namespace_fd!('call, parent_fd: Fd);
+        check_access!('call, parent_fd, dir_name, Access::Write);
 
-        match fs.mkdir(parent_fd, dir_name, fd_stat, now) {
-            Ok(_) => wasi::ERRNO_SUCCESS.raw() as i32,
-            Err(er) => into_errno(er),
-        }
-    });
+        FS.with(|fs| {
+            let mut fs = fs.borrow_mut();
+
+            let fd_stat = FdStat::default();
+
+            let now = ic_time();
+
+            match fs.mkdir(parent_fd, &dir_name, fd_stat, now) {
+                Ok(_) => {
+                    event = watch_event(&fs, parent_fd, &dir_name, WatchEventKind::Create, true);
+                    wasi::ERRNO_SUCCESS.raw() as i32
+                }
+                Err(er) => into_errno(er),
+            }
+        })
+    };
+
+    notify_watches(event);
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_path_create_directory", result, start);
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!(
+        "path_create_directory",
+        result,
+        start,
+        Some(parent_fd),
+        Some(&*dir_name),
+        ""
+    );
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!(
+        "path_create_directory",
+        result,
+        [parent_fd],
+        [file_name_bytes(dir_name.as_bytes())],
+        vec![]
+    );
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
     result
@@ -1366,15 +3066,22 @@ pub unsafe extern "C" fn __ic_custom_path_filestat_get(
     simlink_flags: i32,
     path: *const u8,
     path_len: i32,
-    result: *mut wasi::Filestat,
+    filestat: *mut wasi::Filestat,
 ) -> i32 {
+    validate!(
+        "path_filestat_get",
+        check_buf(path, path_len),
+        check_flags(simlink_flags, KNOWN_LOOKUPFLAGS),
+        check_ptr(filestat)
+    );
+
     #[cfg(feature = "count_wasi_calls")]
     let start = ic_instruction_counter();
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_path_filestat_get");
 
-    let file_name = unsafe { get_file_name(path, path_len as wasi::Size) };
+    let file_name = file_name!(path, path_len);
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!(
@@ -1384,66 +3091,100 @@ pub unsafe extern "C" fn __ic_custom_path_filestat_get(
 
     prevent_elimination(&[simlink_flags]);
 
-    let r = FS.with(|fs| {
-        let mut fs = fs.borrow_mut();
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "path_filestat_get",
+            WasiCall::PathFilestatGet {
+                fd: parent_fd,
+                flags: simlink_flags,
+                path: &file_name,
+                stat: unsafe { &mut *filestat }
+            }
+        );
+
+        namespace_fd!('call, parent_fd: i32);
 
-        let fd_stat = FdStat::default();
+        FS.with(|fs| {
+            let mut fs = fs.borrow_mut();
 
-        let open_flags = OpenFlags::empty();
+            let fd_stat = FdStat::default();
 
-        let fd = fs.open(parent_fd as Fd, file_name, fd_stat, open_flags, 0);
+            let open_flags = OpenFlags::empty();
 
-        // don't leave result undefined
-        unsafe {
-            *result = wasi::Filestat {
-                dev: 0,
-                ino: 0,
-                filetype: wasi::FILETYPE_UNKNOWN,
-                nlink: 0,
-                size: 0,
-                atim: 0,
-                mtim: 0,
-                ctim: 0,
-            }
-        };
+            let fd = fs.open(parent_fd as Fd, &file_name, fd_stat, open_flags, 0);
 
-        match fd {
-            Ok(fd) => {
-                let res = fs.metadata(fd);
-                let _ = fs.close(fd);
+            // don't leave result undefined
+            unsafe {
+                *filestat = wasi::Filestat {
+                    dev: 0,
+                    ino: 0,
+                    filetype: wasi::FILETYPE_UNKNOWN,
+                    nlink: 0,
+                    size: 0,
+                    atim: 0,
+                    mtim: 0,
+                    ctim: 0,
+                }
+            };
 
-                match res {
-                    Ok(metadata) => {
-                        unsafe {
-                            *result = wasi::Filestat {
-                                dev: 0,
-                                ino: metadata.node,
-                                filetype: into_wasi_filetype(metadata.file_type),
-                                nlink: metadata.link_count,
-                                size: metadata.size,
-                                atim: metadata.times.accessed,
-                                mtim: metadata.times.modified,
-                                ctim: metadata.times.created,
-                            }
-                        };
-                        wasi::ERRNO_SUCCESS.raw() as i32
+            match fd {
+                Ok(fd) => {
+                    let res = fs.metadata(fd);
+                    let _ = fs.close(fd);
+
+                    match res {
+                        Ok(metadata) => {
+                            unsafe {
+                                *filestat = wasi::Filestat {
+                                    dev: 0,
+                                    ino: metadata.node,
+                                    filetype: into_wasi_filetype(metadata.file_type),
+                                    nlink: metadata.link_count,
+                                    size: metadata.size,
+                                    atim: metadata.times.accessed,
+                                    mtim: metadata.times.modified,
+                                    ctim: metadata.times.created,
+                                }
+                            };
+                            wasi::ERRNO_SUCCESS.raw() as i32
+                        }
+                        Err(er) => into_errno(er),
                     }
-                    Err(er) => into_errno(er),
                 }
+                Err(er) => into_errno(er),
             }
-            Err(er) => into_errno(er),
-        }
-    });
+        })
+    };
 
     #[cfg(feature = "report_wasi_calls")]
     {
-        let t = format!("res={:?}", *result);
-        debug_instructions!("__ic_custom_path_filestat_get", r, start, "{t}");
+        let t = format!("res={:?}", *filestat);
+        debug_instructions!("__ic_custom_path_filestat_get", result, start, "{t}");
     }
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!(
+        "path_filestat_get",
+        result,
+        start,
+        Some(parent_fd as Fd),
+        Some(&*file_name),
+        "flags={simlink_flags}"
+    );
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!(
+        "path_filestat_get",
+        result,
+        [parent_fd, simlink_flags],
+        [file_name_bytes(file_name.as_bytes())],
+        filestat_output(unsafe { &*filestat })
+    );
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
-    r
+    result
 }
 
 #[unsafe(no_mangle)]
@@ -1458,6 +3199,15 @@ pub unsafe extern "C" fn __ic_custom_path_filestat_set_times(
     mtim: i64,
     fst_flags: i32,
 ) -> i32 {
+    validate!(
+        "path_filestat_set_times",
+        check_buf(path, path_len),
+        check_flags(flags, KNOWN_LOOKUPFLAGS),
+        check_flags(fst_flags, KNOWN_FSTFLAGS)
+    );
+
+    reject_query_writes!();
+
     #[cfg(feature = "count_wasi_calls")]
     let start = ic_instruction_counter();
 
@@ -1465,7 +3215,7 @@ pub unsafe extern "C" fn __ic_custom_path_filestat_set_times(
     debug_instructions!("__ic_custom_path_filestat_set_times");
 
     prevent_elimination(&[flags]);
-    let file_name = unsafe { get_file_name(path, path_len as wasi::Size) };
+    let file_name = file_name!(path, path_len);
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!(
@@ -1473,72 +3223,109 @@ pub unsafe extern "C" fn __ic_custom_path_filestat_set_times(
         "parent_fd={parent_fd} flags={flags} path={file_name} atim={atim} mtim={mtim} fst_flags={fst_flags}"
     );
 
-    let result = FS.with(|fs| {
-        let mut fs = fs.borrow_mut();
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "path_filestat_set_times",
+            WasiCall::PathFilestatSetTimes {
+                fd: parent_fd,
+                flags,
+                path: &file_name,
+                atim,
+                mtim,
+                fst_flags
+            }
+        );
 
-        let fd_stat = FdStat::default();
+        namespace_fd!('call, parent_fd: i32);
 
-        let fst_flags = fst_flags as wasi::Fstflags;
+        FS.with(|fs| {
+            let mut fs = fs.borrow_mut();
 
-        let atim = atim as u64;
-        let mtim = mtim as u64;
+            let fd_stat = FdStat::default();
 
-        if ((fst_flags & wasi::FSTFLAGS_ATIM_NOW) > 0 && (fst_flags & wasi::FSTFLAGS_ATIM) > 0)
-            || ((fst_flags & wasi::FSTFLAGS_MTIM_NOW) > 0 && (fst_flags & wasi::FSTFLAGS_MTIM) > 0)
-        {
-            return into_errno(stable_fs::error::Error::InvalidArgument);
-        }
+            let fst_flags = fst_flags as wasi::Fstflags;
 
-        let open_flags = OpenFlags::empty();
+            let atim = atim as u64;
+            let mtim = mtim as u64;
 
-        let fd = fs.open(parent_fd as Fd, file_name, fd_stat, open_flags, 0);
+            if ((fst_flags & wasi::FSTFLAGS_ATIM_NOW) > 0 && (fst_flags & wasi::FSTFLAGS_ATIM) > 0)
+                || ((fst_flags & wasi::FSTFLAGS_MTIM_NOW) > 0
+                    && (fst_flags & wasi::FSTFLAGS_MTIM) > 0)
+            {
+                return into_errno(Error::InvalidArgument);
+            }
 
-        match fd {
-            Ok(fd) => {
-                let res = fs.metadata(fd);
+            let open_flags = OpenFlags::empty();
 
-                match res {
-                    Ok(mut metadata) => {
-                        let now = ic_time();
+            let fd = fs.open(parent_fd as Fd, &file_name, fd_stat, open_flags, 0);
 
-                        if fst_flags & wasi::FSTFLAGS_ATIM_NOW > 0 {
-                            metadata.times.accessed = now;
-                        }
+            match fd {
+                Ok(fd) => {
+                    let res = fs.metadata(fd);
 
-                        if fst_flags & wasi::FSTFLAGS_MTIM_NOW > 0 {
-                            metadata.times.modified = now;
-                        }
+                    match res {
+                        Ok(mut metadata) => {
+                            let now = ic_time();
 
-                        if fst_flags & wasi::FSTFLAGS_ATIM > 0 {
-                            metadata.times.accessed = atim;
-                        }
+                            if fst_flags & wasi::FSTFLAGS_ATIM_NOW > 0 {
+                                metadata.times.accessed = now;
+                            }
 
-                        if fst_flags & wasi::FSTFLAGS_MTIM > 0 {
-                            metadata.times.modified = mtim;
-                        }
+                            if fst_flags & wasi::FSTFLAGS_MTIM_NOW > 0 {
+                                metadata.times.modified = now;
+                            }
 
-                        let res = fs.set_metadata(fd, metadata);
+                            if fst_flags & wasi::FSTFLAGS_ATIM > 0 {
+                                metadata.times.accessed = atim;
+                            }
 
-                        let _ = fs.close(fd);
+                            if fst_flags & wasi::FSTFLAGS_MTIM > 0 {
+                                metadata.times.modified = mtim;
+                            }
 
-                        match res {
-                            Ok(_) => wasi::ERRNO_SUCCESS.raw() as i32,
-                            Err(er) => into_errno(er),
+                            let res = fs.set_metadata(fd, metadata);
+
+                            let _ = fs.close(fd);
+
+                            match res {
+                                Ok(_) => wasi::ERRNO_SUCCESS.raw() as i32,
+                                Err(er) => into_errno(er),
+                            }
+                        }
+                        Err(er) => {
+                            let _ = fs.close(fd);
+                            into_errno(er)
                         }
-                    }
-                    Err(er) => {
-                        let _ = fs.close(fd);
-                        into_errno(er)
                     }
                 }
+                Err(er) => into_errno(er),
             }
-            Err(er) => into_errno(er),
-        }
-    });
+        })
+    };
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_path_filestat_set_times", result, start);
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!(
+        "path_filestat_set_times",
+        result,
+        start,
+        Some(parent_fd as Fd),
+        Some(&*file_name),
+        "flags={flags} atim={atim} mtim={mtim} fst_flags={fst_flags}"
+    );
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!(
+        "path_filestat_set_times",
+        result,
+        [parent_fd, flags, atim, mtim, fst_flags],
+        [file_name_bytes(file_name.as_bytes())],
+        vec![]
+    );
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
     result
@@ -1556,6 +3343,15 @@ pub unsafe extern "C" fn __ic_custom_path_link(
     new_path: *const u8,
     new_path_len: i32,
 ) -> i32 {
+    validate!(
+        "path_link",
+        check_buf(old_path, old_path_len),
+        check_buf(new_path, new_path_len),
+        check_flags(sym_flags, KNOWN_LOOKUPFLAGS)
+    );
+
+    reject_query_writes!();
+
     #[cfg(feature = "count_wasi_calls")]
     let start = ic_instruction_counter();
 
@@ -1563,8 +3359,8 @@ pub unsafe extern "C" fn __ic_custom_path_link(
     debug_instructions!("__ic_custom_path_link");
 
     prevent_elimination(&[sym_flags]);
-    let old_path = unsafe { get_file_name(old_path, old_path_len as wasi::Size) };
-    let new_path = unsafe { get_file_name(new_path, new_path_len as wasi::Size) };
+    let old_path = file_name!(old_path, old_path_len);
+    let new_path = file_name!(new_path, new_path_len);
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!(
@@ -1572,23 +3368,70 @@ pub unsafe extern "C" fn __ic_custom_path_link(
         "old_parent_fd={old_fd} sym_flags={sym_flags} old_path={old_path} <- new_parent_fd={new_fd} new_path={new_path}"
     );
 
-    let result = FS.with(|fs| {
-        let mut fs = fs.borrow_mut();
+    let mut event = None;
+
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "path_link",
+            WasiCall::PathLink {
+                old_fd,
+                flags: sym_flags,
+                old_path: &old_path,
+                new_fd,
+                new_path: &new_path
+            }
+        );
 
-        let fd = fs.create_hard_link(old_fd as Fd, old_path, new_fd as Fd, new_path);
+        namespace_fd!('call, old_fd: Fd);
+        namespace_fd!('call, new_fd: Fd);
+        check_access!('call, old_fd, old_path, Access::Read);
+        check_access!('call, new_fd, new_path, Access::Write);
 
-        match fd {
-            Ok(fd) => {
-                let _ = fs.close(fd);
-                wasi::ERRNO_SUCCESS.raw() as i32
+        FS.with(|fs| {
+            let mut fs = fs.borrow_mut();
+
+            let fd = fs.create_hard_link(old_fd as Fd, &old_path, new_fd as Fd, &new_path);
+
+            match fd {
+                Ok(fd) => {
+                    let _ = fs.close(fd);
+                    event =
+                        watch_event(&fs, new_fd as Fd, &new_path, WatchEventKind::Create, false);
+                    wasi::ERRNO_SUCCESS.raw() as i32
+                }
+                Err(er) => into_errno(er),
             }
-            Err(er) => into_errno(er),
-        }
-    });
+        })
+    };
+
+    notify_watches(event);
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_path_link", result, start);
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!(
+        "path_link",
+        result,
+        start,
+        Some(old_fd),
+        Some(&*old_path),
+        "new_fd={new_fd} new_path={new_path}"
+    );
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!(
+        "path_link",
+        result,
+        [old_fd, sym_flags, new_fd],
+        [
+            file_name_bytes(old_path.as_bytes()),
+            file_name_bytes(new_path.as_bytes())
+        ],
+        vec![]
+    );
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
     result
@@ -1606,9 +3449,57 @@ pub unsafe extern "C" fn __ic_custom_path_readlink(
     buf_len: i32,
     rp0: *mut usize,
 ) -> i32 {
-    let _file_name = unsafe { get_file_name(path, path_len as wasi::Size) };
-    prevent_elimination(&[fd, path as i32, path_len, buf, buf_len, rp0 as i32]);
-    unimplemented!("WASI path_readlink is not implemented");
+    validate!("path_readlink", check_buf(path, path_len), check_ptr(rp0));
+
+    #[cfg(feature = "count_wasi_calls")]
+    let start = ic_instruction_counter();
+
+    // the decoded path is only passed to the hook
+    #[cfg_attr(not(feature = "hooks"), allow(unused_variables))]
+    let file_name = file_name!(path, path_len);
+
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "path_readlink",
+            WasiCall::PathReadlink {
+                fd,
+                path: &file_name,
+                buf,
+                buf_len,
+                used: rp0
+            }
+        );
+
+        prevent_elimination(&[fd, path as i32, path_len, buf, buf_len, rp0 as i32]);
+        unsupported_call("path_readlink", wasi::ERRNO_NOSYS)
+    };
+
+    #[cfg(feature = "report_wasi_calls")]
+    debug_instructions!("__ic_custom_path_readlink", result, start);
+
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!(
+        "path_readlink",
+        result,
+        start,
+        Some(fd as Fd),
+        None,
+        "buf_len={buf_len}"
+    );
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!(
+        "path_readlink",
+        result,
+        [fd, buf_len],
+        [file_name_bytes(file_name.as_bytes())],
+        vec![]
+    );
+
+    #[cfg(feature = "count_wasi_calls")]
+    update_counter(start);
+    result
 }
 
 #[unsafe(no_mangle)]
@@ -1619,13 +3510,17 @@ pub unsafe extern "C" fn __ic_custom_path_remove_directory(
     path: *const u8,
     path_len: i32,
 ) -> i32 {
+    validate!("path_remove_directory", check_buf(path, path_len));
+
+    reject_query_writes!();
+
     #[cfg(feature = "count_wasi_calls")]
     let start = ic_instruction_counter();
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_path_remove_directory");
 
-    let file_name = unsafe { get_file_name(path, path_len as wasi::Size) };
+    let file_name = file_name!(path, path_len);
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!(
@@ -1633,19 +3528,68 @@ pub unsafe extern "C" fn __ic_custom_path_remove_directory(
         "parent_fd={parent_fd} path={file_name:?}"
     );
 
-    let result = FS.with(|fs| {
-        let mut fs = fs.borrow_mut();
+    let mut event = None;
 
-        let res = fs.remove_dir(parent_fd as Fd, file_name);
-        match res {
-            Ok(()) => wasi::ERRNO_SUCCESS.raw() as i32,
-            Err(er) => into_errno(er),
-        }
-    });
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "path_remove_directory",
+            WasiCall::PathRemoveDirectory {
+                fd: parent_fd,
+                path: &file_name
+            }
+        );
+
+        namespace_fd!('call, parent_fd: Fd);
+        check_access!('call, parent_fd, file_name, Access::Write);
+
+        FS.with(|fs| {
+            let mut fs = fs.borrow_mut();
+
+            let removed = removed_entry(&mut fs, parent_fd as Fd, &file_name);
+
+            let res = fs.remove_dir(parent_fd as Fd, &file_name);
+            match res {
+                Ok(()) => {
+                    forget_removed_entry(removed);
+                    event = watch_event(
+                        &fs,
+                        parent_fd as Fd,
+                        &file_name,
+                        WatchEventKind::Delete,
+                        true,
+                    );
+                    wasi::ERRNO_SUCCESS.raw() as i32
+                }
+                Err(er) => into_errno(er),
+            }
+        })
+    };
+
+    notify_watches(event);
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_path_remove_directory", result, start);
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!(
+        "path_remove_directory",
+        result,
+        start,
+        Some(parent_fd),
+        Some(&*file_name),
+        ""
+    );
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!(
+        "path_remove_directory",
+        result,
+        [parent_fd],
+        [file_name_bytes(file_name.as_bytes())],
+        vec![]
+    );
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
     result
@@ -1662,14 +3606,22 @@ pub unsafe extern "C" fn __ic_custom_path_rename(
     new_path: *const u8,
     new_path_len: i32,
 ) -> i32 {
+    validate!(
+        "path_rename",
+        check_buf(old_path, old_path_len),
+        check_buf(new_path, new_path_len)
+    );
+
+    reject_query_writes!();
+
     #[cfg(feature = "count_wasi_calls")]
     let start = ic_instruction_counter();
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_path_rename");
 
-    let old_path = unsafe { get_file_name(old_path, old_path_len as wasi::Size) };
-    let new_path = unsafe { get_file_name(new_path, new_path_len as wasi::Size) };
+    let old_path = file_name!(old_path, old_path_len);
+    let new_path = file_name!(new_path, new_path_len);
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!(
@@ -1677,23 +3629,110 @@ pub unsafe extern "C" fn __ic_custom_path_rename(
         "old_parent_fd={old_fd} old_path={old_path} -> new_parent_fd={new_fd} new_path={new_path}"
     );
 
-    let result = FS.with(|fs| {
-        let mut fs = fs.borrow_mut();
+    let mut event = None;
+
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "path_rename",
+            WasiCall::PathRename {
+                old_fd,
+                old_path: &old_path,
+                new_fd,
+                new_path: &new_path
+            }
+        );
 
-        let fd = fs.rename(old_fd as Fd, old_path, new_fd as Fd, new_path);
+        namespace_fd!('call, old_fd: i32);
+        namespace_fd!('call, new_fd: i32);
+        check_access!('call, old_fd, old_path, Access::Write);
+        check_access!('call, new_fd, new_path, Access::Write);
 
-        match fd {
-            Ok(fd) => {
-                let _ = fs.close(fd);
-                wasi::ERRNO_SUCCESS.raw() as i32
+        FS.with(|fs| {
+            let mut fs = fs.borrow_mut();
+
+            // the replaced destination entry is removed
+            let removed = removed_entry(&mut fs, new_fd as Fd, &new_path).filter(|removed| {
+                fs.open_metadata(old_fd as Fd, &old_path)
+                    .is_ok_and(|metadata| metadata.node != removed.node)
+            });
+
+            #[cfg(feature = "fd_paths")]
+            let renamed_paths = (
+                root_path(&fs, old_fd as Fd, &old_path),
+                root_path(&fs, new_fd as Fd, &new_path),
+            );
+
+            let is_dir = is_watching()
+                && fs
+                    .open_metadata(old_fd as Fd, &old_path)
+                    .is_ok_and(|metadata| metadata.file_type == FileType::Directory);
+
+            let fd = fs.rename(old_fd as Fd, &old_path, new_fd as Fd, &new_path);
+
+            match fd {
+                Ok(fd) => {
+                    let _ = fs.close(fd);
+                    forget_removed_entry(removed);
+
+                    event = rename_event(
+                        &fs,
+                        (old_fd as Fd, &old_path),
+                        (new_fd as Fd, &new_path),
+                        is_dir,
+                    );
+
+                    // the descriptors opened under a renamed directory keep resolving their paths
+                    #[cfg(feature = "fd_paths")]
+                    if let (Some(old_path), Some(new_path)) = &renamed_paths {
+                        FD_PATHS.with_borrow_mut(|paths| paths.rename(old_path, new_path));
+                    }
+
+                    #[cfg(feature = "http")]
+                    {
+                        let (old_path, new_path) = renamed_paths;
+                        uncertify_http_path(old_path);
+                        uncertify_http_path(new_path.clone());
+
+                        if let Some(new_path) = new_path {
+                            certify_http_path(&mut fs, &new_path);
+                        }
+                    }
+
+                    wasi::ERRNO_SUCCESS.raw() as i32
+                }
+                Err(er) => into_errno(er),
             }
-            Err(er) => into_errno(er),
-        }
-    });
+        })
+    };
+
+    notify_watches(event);
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_path_rename", result, start);
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!(
+        "path_rename",
+        result,
+        start,
+        Some(old_fd as Fd),
+        Some(&*old_path),
+        "new_fd={new_fd} new_path={new_path}"
+    );
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!(
+        "path_rename",
+        result,
+        [old_fd, new_fd],
+        [
+            file_name_bytes(old_path.as_bytes()),
+            file_name_bytes(new_path.as_bytes())
+        ],
+        vec![]
+    );
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
     result
@@ -1710,17 +3749,63 @@ pub unsafe extern "C" fn __ic_custom_path_symlink(
     new_path: *const u8,
     new_path_len: i32,
 ) -> i32 {
-    let _old_path = unsafe { get_file_name(old_path, old_path_len as wasi::Size) };
-    let _new_path = unsafe { get_file_name(new_path, new_path_len as wasi::Size) };
+    validate!(
+        "path_symlink",
+        check_buf(old_path, old_path_len),
+        check_buf(new_path, new_path_len)
+    );
+
+    #[cfg(feature = "count_wasi_calls")]
+    let start = ic_instruction_counter();
+
+    // the decoded paths are only passed to the hook
+    #[cfg_attr(not(feature = "hooks"), allow(unused_variables))]
+    let old_path_name = file_name!(old_path, old_path_len);
+    #[cfg_attr(not(feature = "hooks"), allow(unused_variables))]
+    let new_path_name = file_name!(new_path, new_path_len);
+
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "path_symlink",
+            WasiCall::PathSymlink {
+                old_path: &old_path_name,
+                fd,
+                new_path: &new_path_name
+            }
+        );
 
-    prevent_elimination(&[
-        old_path as i32,
-        old_path_len,
-        fd,
-        new_path as i32,
-        new_path_len,
-    ]);
-    unimplemented!("WASI path_symlink is not implemented");
+        prevent_elimination(&[
+            old_path as i32,
+            old_path_len,
+            fd,
+            new_path as i32,
+            new_path_len,
+        ]);
+        unsupported_call("path_symlink", wasi::ERRNO_NOSYS)
+    };
+
+    #[cfg(feature = "report_wasi_calls")]
+    debug_instructions!("__ic_custom_path_symlink", result, start);
+
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!("path_symlink", result, start, Some(fd as Fd), None, "");
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!(
+        "path_symlink",
+        result,
+        [fd],
+        [
+            file_name_bytes(old_path_name.as_bytes()),
+            file_name_bytes(new_path_name.as_bytes())
+        ],
+        vec![]
+    );
+
+    #[cfg(feature = "count_wasi_calls")]
+    update_counter(start);
+    result
 }
 
 #[unsafe(no_mangle)]
@@ -1731,13 +3816,17 @@ pub unsafe extern "C" fn __ic_custom_path_unlink_file(
     path: *const u8,
     path_len: i32,
 ) -> i32 {
+    validate!("path_unlink_file", check_buf(path, path_len));
+
+    reject_query_writes!();
+
     #[cfg(feature = "count_wasi_calls")]
     let start = ic_instruction_counter();
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_path_unlink");
 
-    let file_name = unsafe { get_file_name(path, path_len as wasi::Size) };
+    let file_name = file_name!(path, path_len);
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!(
@@ -1745,19 +3834,75 @@ pub unsafe extern "C" fn __ic_custom_path_unlink_file(
         "parent_fd={parent_fd:?} file_name={file_name:?}"
     );
 
-    let result = FS.with(|fs| {
-        let mut fs = fs.borrow_mut();
+    let mut event = None;
 
-        let res = fs.remove_file(parent_fd as Fd, file_name);
-        match res {
-            Ok(()) => wasi::ERRNO_SUCCESS.raw() as i32,
-            Err(er) => into_errno(er),
-        }
-    });
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "path_unlink_file",
+            WasiCall::PathUnlinkFile {
+                fd: parent_fd,
+                path: &file_name
+            }
+        );
+
+        namespace_fd!('call, parent_fd: i32);
+        check_access!('call, parent_fd, file_name, Access::Write);
+
+        FS.with(|fs| {
+            let mut fs = fs.borrow_mut();
+
+            let removed = removed_entry(&mut fs, parent_fd as Fd, &file_name);
+
+            #[cfg(feature = "http")]
+            let http_path = root_path(&fs, parent_fd as Fd, &file_name);
+
+            let res = fs.remove_file(parent_fd as Fd, &file_name);
+            match res {
+                Ok(()) => {
+                    forget_removed_entry(removed);
+                    event = watch_event(
+                        &fs,
+                        parent_fd as Fd,
+                        &file_name,
+                        WatchEventKind::Delete,
+                        false,
+                    );
+
+                    #[cfg(feature = "http")]
+                    uncertify_http_path(http_path);
+
+                    wasi::ERRNO_SUCCESS.raw() as i32
+                }
+                Err(er) => into_errno(er),
+            }
+        })
+    };
+
+    notify_watches(event);
 
     #[cfg(feature = "report_wasi_calls")]
     debug_instructions!("__ic_custom_path_unlink", result, start);
 
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!(
+        "path_unlink_file",
+        result,
+        start,
+        Some(parent_fd as Fd),
+        Some(&*file_name),
+        ""
+    );
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!(
+        "path_unlink_file",
+        result,
+        [parent_fd],
+        [file_name_bytes(file_name.as_bytes())],
+        vec![]
+    );
+
     #[cfg(feature = "count_wasi_calls")]
     update_counter(start);
     result
@@ -1773,36 +3918,157 @@ pub unsafe extern "C" fn __ic_custom_poll_oneoff(
     nsubscriptions: i32,
     neventsp: *mut wasi::Size,
 ) -> i32 {
-    prevent_elimination(&[in_ as i32, out as i32, nsubscriptions, neventsp as i32]);
+    validate!(
+        "poll_oneoff",
+        check_range(nsubscriptions, 0..=i32::MAX),
+        check_slice(in_, nsubscriptions as usize),
+        check_slice(out, nsubscriptions as usize),
+        check_ptr(neventsp)
+    );
+
+    #[cfg(feature = "count_wasi_calls")]
+    let start = ic_instruction_counter();
+
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "poll_oneoff",
+            WasiCall::PollOneoff {
+                subscriptions: in_,
+                events: out,
+                nsubscriptions,
+                nevents: neventsp
+            }
+        );
+
+        prevent_elimination(&[in_ as i32, out as i32, nsubscriptions, neventsp as i32]);
+
+        unsupported_call("poll_oneoff", wasi::ERRNO_NOSYS)
+    };
 
     #[cfg(feature = "report_wasi_calls")]
-    debug_instructions!("__ic_custom_poll_oneoff");
+    debug_instructions!("__ic_custom_poll_oneoff", result, start);
+
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!(
+        "poll_oneoff",
+        result,
+        start,
+        None,
+        None,
+        "nsubscriptions={nsubscriptions}"
+    );
 
-    // avoid panic, just return an error because the function is not supported yet
-    wasi::ERRNO_IO.raw() as i32
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!("poll_oneoff", result, [nsubscriptions], [], vec![]);
+
+    #[cfg(feature = "count_wasi_calls")]
+    update_counter(start);
+    result
 }
 
 #[unsafe(no_mangle)]
 #[inline(never)]
 #[cfg(not(feature = "skip_unimplemented_functions"))]
 pub extern "C" fn __ic_custom_proc_raise(sig: i32) -> i32 {
-    prevent_elimination(&[sig]);
-    unimplemented!("WASI proc_raise is not implemented");
+    #[cfg(feature = "count_wasi_calls")]
+    let start = ic_instruction_counter();
+
+    let result = 'call: {
+        call_hook!('call, "proc_raise", WasiCall::ProcRaise { sig });
+
+        prevent_elimination(&[sig]);
+        unsupported_call("proc_raise", wasi::ERRNO_NOSYS)
+    };
+
+    #[cfg(feature = "report_wasi_calls")]
+    debug_instructions!("__ic_custom_proc_raise", result, start);
+
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!("proc_raise", result, start, None, None, "sig={sig}");
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!("proc_raise", result, [sig], [], vec![]);
+
+    #[cfg(feature = "count_wasi_calls")]
+    update_counter(start);
+    result
 }
 
 #[unsafe(no_mangle)]
 #[inline(never)]
 pub extern "C" fn __ic_custom_sched_yield() -> i32 {
-    // No-op.
-    0
+    #[cfg(feature = "count_wasi_calls")]
+    let start = ic_instruction_counter();
+
+    let result = 'call: {
+        call_hook!('call, "sched_yield", WasiCall::SchedYield);
+
+        // No-op.
+        wasi::ERRNO_SUCCESS.raw() as i32
+    };
+
+    #[cfg(feature = "report_wasi_calls")]
+    debug_instructions!("__ic_custom_sched_yield", result, start);
+
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!("sched_yield", result, start, None, None, "");
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!("sched_yield", result, [], [], vec![]);
+
+    #[cfg(feature = "count_wasi_calls")]
+    update_counter(start);
+    result
 }
 
 #[unsafe(no_mangle)]
 #[inline(never)]
 #[cfg(not(feature = "skip_unimplemented_functions"))]
 pub extern "C" fn __ic_custom_sock_accept(arg0: i32, arg1: i32, arg2: *mut u32) -> i32 {
-    prevent_elimination(&[arg0, arg1, arg2 as i32]);
-    unimplemented!("WASI sock_accept is not supported");
+    validate!(
+        "sock_accept",
+        check_flags(arg1, KNOWN_FDFLAGS),
+        check_ptr(arg2)
+    );
+
+    #[cfg(feature = "count_wasi_calls")]
+    let start = ic_instruction_counter();
+
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "sock_accept",
+            WasiCall::SockAccept {
+                fd: arg0,
+                flags: arg1,
+                new_fd: arg2
+            }
+        );
+
+        prevent_elimination(&[arg0, arg1, arg2 as i32]);
+        unsupported_call("sock_accept", wasi::ERRNO_NOTSUP)
+    };
+
+    #[cfg(feature = "report_wasi_calls")]
+    debug_instructions!("__ic_custom_sock_accept", result, start);
+
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!(
+        "sock_accept",
+        result,
+        start,
+        Some(arg0 as Fd),
+        None,
+        "flags={arg1}"
+    );
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!("sock_accept", result, [arg0, arg1], [], vec![]);
+
+    #[cfg(feature = "count_wasi_calls")]
+    update_counter(start);
+    result
 }
 
 #[unsafe(no_mangle)]
@@ -1817,31 +4083,184 @@ pub unsafe extern "C" fn __ic_custom_sock_recv(
     arg4: *mut usize,
     arg5: *mut u16,
 ) -> i32 {
-    prevent_elimination(&[arg0, arg1 as i32, arg2, arg3, arg4 as i32, arg5 as i32]);
-    unimplemented!("WASI sock_recv is not supported");
+    validate!(
+        "sock_recv",
+        unsafe { check_iovecs(arg1, arg2) },
+        check_ptr(arg4),
+        check_ptr(arg5)
+    );
+
+    #[cfg(feature = "count_wasi_calls")]
+    let start = ic_instruction_counter();
+
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "sock_recv",
+            WasiCall::SockRecv {
+                fd: arg0,
+                ri_data: arg1,
+                ri_data_len: arg2,
+                ri_flags: arg3,
+                ro_datalen: arg4,
+                ro_flags: arg5
+            }
+        );
+
+        prevent_elimination(&[arg0, arg1 as i32, arg2, arg3, arg4 as i32, arg5 as i32]);
+        unsupported_call("sock_recv", wasi::ERRNO_NOTSUP)
+    };
+
+    #[cfg(feature = "report_wasi_calls")]
+    debug_instructions!("__ic_custom_sock_recv", result, start);
+
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!(
+        "sock_recv",
+        result,
+        start,
+        Some(arg0 as Fd),
+        None,
+        "ri_data.len={arg2} ri_flags={arg3}"
+    );
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!("sock_recv", result, [arg0, arg2, arg3], [], vec![]);
+
+    #[cfg(feature = "count_wasi_calls")]
+    update_counter(start);
+    result
 }
 
 #[unsafe(no_mangle)]
 #[inline(never)]
 #[cfg(not(feature = "skip_unimplemented_functions"))]
 #[allow(clippy::missing_safety_doc)]
-pub extern "C" fn __ic_custom_sock_send(
+pub unsafe extern "C" fn __ic_custom_sock_send(
     arg0: i32,
     arg1: *const wasi::Ciovec,
     arg2: i32,
     arg3: i32,
     arg4: *mut wasi::Size,
 ) -> i32 {
-    prevent_elimination(&[arg0, arg1 as i32, arg2, arg3, arg4 as i32]);
-    unimplemented!("WASI sock_send is not supported");
+    validate!(
+        "sock_send",
+        unsafe { check_ciovecs(arg1, arg2) },
+        check_ptr(arg4)
+    );
+
+    #[cfg(feature = "count_wasi_calls")]
+    let start = ic_instruction_counter();
+
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "sock_send",
+            WasiCall::SockSend {
+                fd: arg0,
+                si_data: arg1,
+                si_data_len: arg2,
+                si_flags: arg3,
+                so_datalen: arg4
+            }
+        );
+
+        prevent_elimination(&[arg0, arg1 as i32, arg2, arg3, arg4 as i32]);
+        unsupported_call("sock_send", wasi::ERRNO_NOTSUP)
+    };
+
+    #[cfg(feature = "report_wasi_calls")]
+    debug_instructions!("__ic_custom_sock_send", result, start);
+
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!(
+        "sock_send",
+        result,
+        start,
+        Some(arg0 as Fd),
+        None,
+        "si_data.len={arg2} si_flags={arg3}"
+    );
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!("sock_send", result, [arg0, arg2, arg3], [], vec![]);
+
+    #[cfg(feature = "count_wasi_calls")]
+    update_counter(start);
+    result
 }
 
 #[unsafe(no_mangle)]
 #[inline(never)]
 #[cfg(not(feature = "skip_unimplemented_functions"))]
 pub extern "C" fn __ic_custom_sock_shutdown(arg0: i32, arg1: i32) -> i32 {
-    prevent_elimination(&[arg0, arg1]);
-    unimplemented!("WASI sock_shutdown is not supported");
+    validate!(
+        "sock_shutdown",
+        check_flags(arg1, (wasi::SDFLAGS_RD | wasi::SDFLAGS_WR) as i32)
+    );
+
+    #[cfg(feature = "count_wasi_calls")]
+    let start = ic_instruction_counter();
+
+    let result = 'call: {
+        call_hook!(
+            'call,
+            "sock_shutdown",
+            WasiCall::SockShutdown {
+                fd: arg0,
+                how: arg1
+            }
+        );
+
+        prevent_elimination(&[arg0, arg1]);
+        unsupported_call("sock_shutdown", wasi::ERRNO_NOTSUP)
+    };
+
+    #[cfg(feature = "report_wasi_calls")]
+    debug_instructions!("__ic_custom_sock_shutdown", result, start);
+
+    #[cfg(feature = "trace_wasi_calls")]
+    trace_call!(
+        "sock_shutdown",
+        result,
+        start,
+        Some(arg0 as Fd),
+        None,
+        "how={arg1}"
+    );
+
+    #[cfg(feature = "record_wasi_calls")]
+    record_call!("sock_shutdown", result, [arg0, arg1], [], vec![]);
+
+    #[cfg(feature = "count_wasi_calls")]
+    update_counter(start);
+    result
+}
+
+// Handle the call of an unimplemented or unsupported function according to the current policy.
+#[cfg(not(feature = "skip_unimplemented_functions"))]
+fn unsupported_call(function: &'static str, errno: wasi::Errno) -> i32 {
+    let (policy, should_log) =
+        UNSUPPORTED_CALLS.with_borrow_mut(|calls| (calls.policy(), calls.should_log(function)));
+
+    let reason = if errno == wasi::ERRNO_NOSYS {
+        "implemented"
+    } else {
+        "supported"
+    };
+
+    if policy == UnsupportedCallPolicy::Panic {
+        panic!("WASI {function} is not {reason}");
+    }
+
+    if should_log {
+        ic_print(&format!(
+            "WASI {function} is not {reason}, returning {}",
+            errno.name()
+        ));
+    }
+
+    errno.raw() as i32
 }
 
 fn prevent_elimination(args: &[i32]) {
@@ -1972,9 +4391,14 @@ pub unsafe extern "C" fn raw_init(seed: *const u8, len: usize) {
 
         if fs.get_storage_version() == 0 {
             *fs = if cfg!(feature = "transient") {
-                FileSystem::new(Box::new(TransientStorage::new())).unwrap()
+                new_file_system(Box::new(TransientStorage::new()))
             } else {
-                FileSystem::new(Box::new(StableStorage::new(DefaultMemoryImpl::default()))).unwrap()
+                let memory_manager = MemoryManager::init(DefaultMemoryImpl::default());
+                new_file_system(new_stable_storage(
+                    &memory_manager,
+                    DEFAULT_MEMORY_INDEX_RANGE,
+                    0..0,
+                ))
             }
         }
     });
@@ -1985,6 +4409,187 @@ pub unsafe extern "C" fn raw_init(seed: *const u8, len: usize) {
     __dummy_wasi_calls();
 }
 
+/// Enable or disable the validation of the arguments passed to the WASI functions.
+/// In the hardened mode invalid pointers, lengths and enumeration values return `ERRNO_FAULT` or `ERRNO_INVAL` instead of trapping.
+pub fn set_hardened_mode(enabled: bool) {
+    HARDENED.with_borrow_mut(|hardened| *hardened = enabled)
+}
+
+/// Check if the hardened mode is enabled
+pub fn is_hardened_mode() -> bool {
+    HARDENED.with_borrow(|hardened| *hardened)
+}
+
+/// Make the mutating WASI calls (writing to files, creating, truncating, renaming and removing entries,
+/// changing sizes and timestamps) return `ERRNO_ROFS` when executed in a query or composite query,
+/// where the IC silently discards all the changes.
+pub fn set_reject_query_writes(enabled: bool) {
+    REJECT_QUERY_WRITES.with_borrow_mut(|reject| *reject = enabled)
+}
+
+/// Check if the mutating calls are rejected in the queries
+pub fn is_rejecting_query_writes() -> bool {
+    REJECT_QUERY_WRITES.with_borrow(|reject| *reject)
+}
+
+/// Mark the current message as a query call, so that the writes are also rejected when the query
+/// is executed in the replicated mode (called as an update or from another canister).
+/// Call it at the beginning of the query methods sharing code with the updates,
+/// the mark is discarded together with the other changes made by the query.
+pub fn set_query_call(is_query: bool) {
+    QUERY_CALL.with_borrow_mut(|query| *query = is_query)
+}
+
+/// Simulate the execution mode on the host, `false` makes the polyfill behave as in a query call
+#[cfg(not(all(target_arch = "wasm32")))]
+pub fn set_mock_replicated_execution(replicated: bool) {
+    MOCK_REPLICATED_EXECUTION.with_borrow_mut(|value| *value = replicated)
+}
+
+/// Simulate the message caller on the host, the principal is given in its textual form
+#[cfg(not(all(target_arch = "wasm32")))]
+pub fn set_mock_caller(principal: &str) {
+    MOCK_CALLER.with_borrow_mut(|caller| *caller = principal.to_string())
+}
+
+/// Simulate the canister controllers on the host, the principals are given in their textual form
+#[cfg(not(all(target_arch = "wasm32")))]
+pub fn set_mock_controllers(principals: &[&str]) {
+    MOCK_CONTROLLERS.with_borrow_mut(|controllers| {
+        *controllers = principals.iter().map(|p| p.to_string()).collect()
+    })
+}
+
+// Find the node of a path relative to the file system root.
+#[cfg(any(feature = "compression", feature = "encryption", feature = "xattrs"))]
+fn root_node(path: &str) -> Result<u64, i32> {
+    FS.with_borrow_mut(|fs| {
+        let root_fd = fs.root_fd();
+        fs.open_metadata(root_fd, path)
+    })
+    .map(|metadata| metadata.node)
+    .map_err(into_errno)
+}
+
+/// Append data to the standard input read by `fd_read` on descriptor 0.
+/// The canister cannot wait for input, so a read of the empty input returns the end of file,
+/// unless `FDFLAGS_NONBLOCK` is set with `fd_fdstat_set_flags`, then it returns `ERRNO_AGAIN` until `close_stdin` is called.
+pub fn push_stdin(data: &[u8]) {
+    STDIN.with_borrow_mut(|stdin| stdin.push(data))
+}
+
+/// Mark the end of the standard input, the reads return the end of file after the buffered data
+pub fn close_stdin() {
+    STDIN.with_borrow_mut(|stdin| stdin.close())
+}
+
+// Descriptor closed when dropped.
+pub(crate) struct OwnedFd(pub(crate) Fd);
+
+impl OwnedFd {
+    // Open a directory relative to the file system root, the caller namespaces do not apply.
+    pub(crate) fn open_root_dir(path: &str) -> Result<OwnedFd, i32> {
+        let path = path.trim_matches('/');
+
+        let fd = FS
+            .with_borrow_mut(|fs| {
+                let root_fd = fs.root_fd();
+                let dir = if path.is_empty() { "." } else { path };
+
+                fs.open(root_fd, dir, FdStat::default(), OpenFlags::DIRECTORY, 0)
+            })
+            .map_err(into_errno)?;
+
+        #[cfg(feature = "fd_paths")]
+        FD_PATHS.with_borrow_mut(|paths| paths.open_fd(fd, path.to_string()));
+
+        Ok(OwnedFd(fd))
+    }
+
+    // Open the parent directory of a path relative to the file system root, returns it with the name.
+    pub(crate) fn open_parent(path: &str) -> Result<(OwnedFd, &str), i32> {
+        let path = path.trim_matches('/');
+        let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+
+        if name.is_empty() {
+            return Err(wasi::ERRNO_INVAL.raw() as i32);
+        }
+
+        Ok((OwnedFd::open_root_dir(parent)?, name))
+    }
+}
+
+// The descriptor is closed without a call of `fd_close`, which would run the hooks and appear in the traces and
+// recordings of the application calls.
+impl Drop for OwnedFd {
+    fn drop(&mut self) {
+        close_fd(self.0);
+    }
+}
+
+fn errno_result(errno: i32) -> Result<(), i32> {
+    if errno == wasi::ERRNO_SUCCESS.raw() as i32 {
+        Ok(())
+    } else {
+        Err(errno)
+    }
+}
+
+fn root_path_filestat(path: &str) -> Result<wasi::Filestat, i32> {
+    let (dir, name) = OwnedFd::open_parent(path)?;
+    let mut stat = wasi::Filestat {
+        dev: 0,
+        ino: 0,
+        filetype: wasi::FILETYPE_UNKNOWN,
+        nlink: 0,
+        size: 0,
+        atim: 0,
+        mtim: 0,
+        ctim: 0,
+    };
+
+    errno_result(unsafe {
+        __ic_custom_path_filestat_get(dir.0 as i32, 0, name.as_ptr(), name.len() as i32, &mut stat)
+    })?;
+
+    Ok(stat)
+}
+
+fn root_path_open_with_rights(
+    path: &str,
+    oflags: wasi::Oflags,
+    rights: wasi::Rights,
+) -> Result<OwnedFd, i32> {
+    let (dir, name) = OwnedFd::open_parent(path)?;
+    let mut fd = 0;
+
+    errno_result(unsafe {
+        __ic_custom_path_open(
+            dir.0,
+            0,
+            name.as_ptr(),
+            name.len() as i32,
+            oflags as i32,
+            rights,
+            0,
+            0,
+            &mut fd,
+        )
+    })?;
+
+    Ok(OwnedFd(fd))
+}
+
+/// Set how the calls of unimplemented and unsupported WASI functions are handled
+pub fn set_unsupported_call_policy(policy: UnsupportedCallPolicy) {
+    UNSUPPORTED_CALLS.with_borrow_mut(|calls| calls.set_policy(policy))
+}
+
+/// Get the current policy for the unimplemented and unsupported WASI functions
+pub fn get_unsupported_call_policy() -> UnsupportedCallPolicy {
+    UNSUPPORTED_CALLS.with_borrow(|calls| calls.policy())
+}
+
 /// Reset the cumulative instruction counter
 #[cfg(feature = "count_wasi_calls")]
 pub fn reset_counter() {
@@ -1999,6 +4604,95 @@ pub fn get_counter() -> u64 {
     COUNTER.with_borrow(|counter| *counter)
 }
 
+/// Enable or disable recording of the WASI calls into the trace buffer
+#[cfg(feature = "trace_wasi_calls")]
+pub fn set_tracing_enabled(enabled: bool) {
+    TRACER.with_borrow_mut(|tracer| tracer.set_enabled(enabled))
+}
+
+/// Set the maximum number of records kept in the trace buffer, the oldest records are dropped first
+#[cfg(feature = "trace_wasi_calls")]
+pub fn set_trace_capacity(capacity: usize) {
+    TRACER.with_borrow_mut(|tracer| tracer.set_capacity(capacity))
+}
+
+/// Set the filter selecting the WASI calls to record
+#[cfg(feature = "trace_wasi_calls")]
+pub fn set_trace_filter(filter: TraceFilter) {
+    TRACER.with_borrow_mut(|tracer| tracer.set_filter(filter))
+}
+
+/// Get the recorded WASI calls, oldest first
+#[cfg(feature = "trace_wasi_calls")]
+pub fn get_trace_records() -> Vec<TraceRecord> {
+    TRACER.with_borrow(|tracer| tracer.records())
+}
+
+/// Get and remove the recorded WASI calls, oldest first
+#[cfg(feature = "trace_wasi_calls")]
+pub fn take_trace_records() -> Vec<TraceRecord> {
+    TRACER.with_borrow_mut(|tracer| tracer.take_records())
+}
+
+/// Remove all the recorded WASI calls
+#[cfg(feature = "trace_wasi_calls")]
+pub fn clear_trace() {
+    TRACER.with_borrow_mut(|tracer| tracer.clear())
+}
+
+/// Start recording the WASI calls, the previous recording is discarded
+#[cfg(feature = "record_wasi_calls")]
+pub fn start_recording() {
+    RECORDER.with_borrow_mut(|recorder| recorder.start())
+}
+
+/// Stop recording the WASI calls, the recording collected so far is kept
+#[cfg(feature = "record_wasi_calls")]
+pub fn stop_recording() {
+    RECORDER.with_borrow_mut(|recorder| recorder.stop())
+}
+
+/// Stop recording and return the serialized WASI call sequence
+#[cfg(feature = "record_wasi_calls")]
+pub fn take_recording() -> Vec<u8> {
+    RECORDER.with_borrow_mut(|recorder| recorder.take_log())
+}
+
+/// Stop recording and store the serialized WASI call sequence into a file.
+/// The recording stops first so that storing the file does not change the recorded sequence.
+///
+/// # Parameters
+/// `file_name`    -  Name of the file to store the recording in
+///
+#[cfg(feature = "record_wasi_calls")]
+pub fn store_recording(file_name: &str) -> i32 {
+    let log = take_recording();
+
+    FS.with(|fs| {
+        let mut fs = fs.borrow_mut();
+        let root_fd = fs.root_fd();
+
+        let result = fs
+            .open(
+                root_fd,
+                file_name,
+                FdStat::default(),
+                OpenFlags::CREATE | OpenFlags::TRUNCATE,
+                ic_time(),
+            )
+            .and_then(|fd| {
+                let written = fs.write(fd, &log);
+                let _ = fs.close(fd);
+                written
+            });
+
+        match result {
+            Ok(_) => wasi::ERRNO_SUCCESS.raw() as i32,
+            Err(er) => into_errno(er),
+        }
+    })
+}
+
 /// Initializes the runtime environment and the random number generator.
 ///
 /// # Parameters
@@ -2031,7 +4725,12 @@ pub fn init_with_memory<M: Memory + 'static>(seed: &[u8], env_pairs: &[(&str, &s
     FS.with(|fs| {
         let mut fs = fs.borrow_mut();
 
-        *fs = FileSystem::new(Box::new(StableStorage::new(memory))).unwrap();
+        let memory_manager = MemoryManager::init(memory);
+        *fs = new_file_system(new_stable_storage(
+            &memory_manager,
+            DEFAULT_MEMORY_INDEX_RANGE,
+            0..0,
+        ));
     });
 
     init(seed, env_pairs);
@@ -2050,15 +4749,37 @@ pub fn init_with_memory_manager<M: Memory + 'static>(
     env_pairs: &[(&str, &str)],
     memory_manager: &MemoryManager<M>,
     memory_index_range: Range<u8>,
+) {
+    init_with_polyfill_memories(seed, env_pairs, memory_manager, memory_index_range, 0..0);
+}
+
+/// Initializes the file system like `init_with_memory_manager` and keeps the polyfill data (the extended attributes,
+/// the compressed and encrypted file data and the transaction journals) in the memories of a second index range.
+/// The other initializations keep this data on the heap, where it does not persist across upgrades.
+///
+/// # Parameters
+/// - `seed`: A byte slice (up to 32 bytes) used to seed the random number generator.
+/// - `env_pairs`: A list of key-value pairs representing environment variables to initialize.
+/// - `memory_manager`: A memory manager used to create memories.
+/// - `memory_index_range`: A range of memory IDs used for file system storage.
+/// - `polyfill_memory_index_range`: A range of memory IDs used for the polyfill data, see `memories.rs` for
+///   the positions. The data without a memory in a shorter range is kept on the heap.
+#[allow(clippy::missing_safety_doc)]
+pub fn init_with_polyfill_memories<M: Memory + 'static>(
+    seed: &[u8],
+    env_pairs: &[(&str, &str)],
+    memory_manager: &MemoryManager<M>,
+    memory_index_range: Range<u8>,
+    polyfill_memory_index_range: Range<u8>,
 ) {
     FS.with(|fs| {
         let mut fs = fs.borrow_mut();
 
-        *fs = FileSystem::new(Box::new(StableStorage::new_with_memory_manager(
+        *fs = new_file_system(new_stable_storage(
             memory_manager,
             memory_index_range,
-        )))
-        .unwrap();
+            polyfill_memory_index_range,
+        ));
     });
 
     init(seed, env_pairs);
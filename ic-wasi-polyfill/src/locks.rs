@@ -0,0 +1,277 @@
+use std::collections::BTreeMap;
+
+use stable_fs::fs::Fd;
+use stable_fs::storage::types::{FileSize, Node};
+
+#[cfg(feature = "file_locks")]
+use crate::{wasi, wasi_helpers::into_errno, FS, LOCKS};
+
+/// Kind of an advisory lock.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockType {
+    /// Several descriptors can hold a shared lock on the same range (read lock).
+    Shared,
+    /// Only one descriptor can hold a lock on the range (write lock).
+    Exclusive,
+}
+
+impl LockType {
+    pub fn from_raw(value: i32) -> Option<LockType> {
+        match value {
+            0 => Some(LockType::Shared),
+            1 => Some(LockType::Exclusive),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Lock {
+    owner: Fd,
+    lock_type: LockType,
+    start: FileSize,
+    // exclusive end of the range, `FileSize::MAX` locks up to the end of the file however it grows
+    end: FileSize,
+}
+
+impl Lock {
+    fn overlaps(&self, start: FileSize, end: FileSize) -> bool {
+        self.start < end && start < self.end
+    }
+}
+
+/// Advisory byte range locks of the file system nodes, owned by the file descriptors.
+///
+/// The locks are not enforced by the read and write calls, they only coordinate the code that checks them.
+#[derive(Default)]
+pub struct LockTable {
+    locks: BTreeMap<Node, Vec<Lock>>,
+}
+
+impl LockTable {
+    pub fn new() -> LockTable {
+        Self::default()
+    }
+
+    // Find the owner of a lock conflicting with the requested one.
+    pub fn conflict(
+        &self,
+        node: Node,
+        owner: Fd,
+        lock_type: LockType,
+        start: FileSize,
+        end: FileSize,
+    ) -> Option<Fd> {
+        self.locks.get(&node)?.iter().find_map(|lock| {
+            let conflicting = lock.owner != owner
+                && lock.overlaps(start, end)
+                && (lock_type == LockType::Exclusive || lock.lock_type == LockType::Exclusive);
+
+            conflicting.then_some(lock.owner)
+        })
+    }
+
+    // Acquire the lock, the locks of the same owner in the range are replaced. Returns the owner of a conflicting lock on failure.
+    pub fn lock(
+        &mut self,
+        node: Node,
+        owner: Fd,
+        lock_type: LockType,
+        start: FileSize,
+        end: FileSize,
+    ) -> Result<(), Fd> {
+        if let Some(other) = self.conflict(node, owner, lock_type, start, end) {
+            return Err(other);
+        }
+
+        self.unlock(node, owner, start, end);
+
+        self.locks.entry(node).or_default().push(Lock {
+            owner,
+            lock_type,
+            start,
+            end,
+        });
+
+        Ok(())
+    }
+
+    // Release the range of the owner's locks, the locks partially covered by the range are split.
+    pub fn unlock(&mut self, node: Node, owner: Fd, start: FileSize, end: FileSize) {
+        let Some(locks) = self.locks.get_mut(&node) else {
+            return;
+        };
+
+        let mut remaining = Vec::with_capacity(locks.len());
+
+        for lock in locks.drain(..) {
+            if lock.owner != owner || !lock.overlaps(start, end) {
+                remaining.push(lock);
+                continue;
+            }
+
+            if lock.start < start {
+                remaining.push(Lock { end: start, ..lock });
+            }
+
+            if end < lock.end {
+                remaining.push(Lock { start: end, ..lock });
+            }
+        }
+
+        if remaining.is_empty() {
+            self.locks.remove(&node);
+        } else {
+            *locks = remaining;
+        }
+    }
+
+    // Release all the locks of a closed descriptor.
+    pub fn release(&mut self, owner: Fd) {
+        self.locks.retain(|_, locks| {
+            locks.retain(|lock| lock.owner != owner);
+            !locks.is_empty()
+        });
+    }
+
+    // The locks move to the new descriptor number, the locks of the replaced descriptor are released.
+    pub fn renumber(&mut self, from: Fd, to: Fd) {
+        self.release(to);
+
+        for lock in self.locks.values_mut().flatten() {
+            if lock.owner == from {
+                lock.owner = to;
+            }
+        }
+    }
+}
+
+// Find the node of the descriptor and the end of the locked range, the zero length locks up to the end of the file.
+#[cfg(feature = "file_locks")]
+fn lock_range(fd: Fd, start: u64, len: u64) -> Result<(u64, u64), i32> {
+    let end = if len == 0 {
+        u64::MAX
+    } else {
+        start
+            .checked_add(len)
+            .ok_or(wasi::ERRNO_INVAL.raw() as i32)?
+    };
+
+    let node = FS
+        .with_borrow(|fs| fs.metadata(fd).map(|metadata| metadata.node))
+        .map_err(into_errno)?;
+
+    Ok((node, end))
+}
+
+/// Acquire an advisory lock on the byte range of the file opened with `fd`, `len` 0 locks up to the end of the file.
+/// The locks of the same descriptor in the range are replaced, the locks are released by `fd_close`.
+/// Returns `ERRNO_AGAIN` if another descriptor holds a conflicting lock.
+#[cfg(feature = "file_locks")]
+pub fn lock_file(fd: Fd, lock_type: LockType, start: u64, len: u64) -> i32 {
+    let (node, end) = match lock_range(fd, start, len) {
+        Ok(range) => range,
+        Err(errno) => return errno,
+    };
+
+    match LOCKS.with_borrow_mut(|locks| locks.lock(node, fd, lock_type, start, end)) {
+        Ok(()) => wasi::ERRNO_SUCCESS.raw() as i32,
+        Err(_) => wasi::ERRNO_AGAIN.raw() as i32,
+    }
+}
+
+/// Release the advisory locks of the descriptor in the byte range, `len` 0 unlocks up to the end of the file
+#[cfg(feature = "file_locks")]
+pub fn unlock_file(fd: Fd, start: u64, len: u64) -> i32 {
+    let (node, end) = match lock_range(fd, start, len) {
+        Ok(range) => range,
+        Err(errno) => return errno,
+    };
+
+    LOCKS.with_borrow_mut(|locks| locks.unlock(node, fd, start, end));
+
+    wasi::ERRNO_SUCCESS.raw() as i32
+}
+
+/// Check if the lock can be acquired without acquiring it, returns `ERRNO_AGAIN` if another descriptor holds a conflicting lock
+#[cfg(feature = "file_locks")]
+pub fn test_file_lock(fd: Fd, lock_type: LockType, start: u64, len: u64) -> i32 {
+    let (node, end) = match lock_range(fd, start, len) {
+        Ok(range) => range,
+        Err(errno) => return errno,
+    };
+
+    match LOCKS.with_borrow(|locks| locks.conflict(node, fd, lock_type, start, end)) {
+        None => wasi::ERRNO_SUCCESS.raw() as i32,
+        Some(_) => wasi::ERRNO_AGAIN.raw() as i32,
+    }
+}
+
+/// Similar to `lock_file`, the `lock_type` is 0 for a shared and 1 for an exclusive lock.
+#[cfg(feature = "file_locks")]
+#[unsafe(no_mangle)]
+pub extern "C" fn raw_lock_file(fd: Fd, lock_type: i32, start: u64, len: u64) -> i32 {
+    match LockType::from_raw(lock_type) {
+        Some(lock_type) => lock_file(fd, lock_type, start, len),
+        None => wasi::ERRNO_INVAL.raw() as i32,
+    }
+}
+
+/// Similar to `unlock_file`, for calling from C or C++.
+#[cfg(feature = "file_locks")]
+#[unsafe(no_mangle)]
+pub extern "C" fn raw_unlock_file(fd: Fd, start: u64, len: u64) -> i32 {
+    unlock_file(fd, start, len)
+}
+
+/// Similar to `test_file_lock`, the `lock_type` is 0 for a shared and 1 for an exclusive lock.
+#[cfg(feature = "file_locks")]
+#[unsafe(no_mangle)]
+pub extern "C" fn raw_test_file_lock(fd: Fd, lock_type: i32, start: u64, len: u64) -> i32 {
+    match LockType::from_raw(lock_type) {
+        Some(lock_type) => test_file_lock(fd, lock_type, start, len),
+        None => wasi::ERRNO_INVAL.raw() as i32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LockTable, LockType};
+
+    #[test]
+    fn shared_exclusive_and_ranges() {
+        let mut table = LockTable::new();
+
+        assert_eq!(table.lock(1, 5, LockType::Shared, 0, 100), Ok(()));
+        assert_eq!(table.lock(1, 6, LockType::Shared, 50, 150), Ok(()));
+        assert_eq!(table.lock(1, 7, LockType::Exclusive, 90, 95), Err(5));
+        assert_eq!(table.lock(1, 7, LockType::Exclusive, 150, u64::MAX), Ok(()));
+
+        // other nodes are independent
+        assert_eq!(table.lock(2, 7, LockType::Exclusive, 0, u64::MAX), Ok(()));
+
+        // upgrade of the owned range conflicts with the other shared lock only where they overlap
+        assert_eq!(table.lock(1, 5, LockType::Exclusive, 0, 60), Err(6));
+        assert_eq!(table.lock(1, 5, LockType::Exclusive, 0, 50), Ok(()));
+        assert_eq!(table.conflict(1, 6, LockType::Shared, 40, 45), Some(5));
+        assert_eq!(table.conflict(1, 6, LockType::Shared, 50, 55), None);
+
+        // unlocking the middle splits the lock
+        table.unlock(1, 5, 10, 20);
+        assert_eq!(table.conflict(1, 6, LockType::Shared, 12, 15), None);
+        assert_eq!(table.conflict(1, 6, LockType::Shared, 5, 15), Some(5));
+        assert_eq!(table.conflict(1, 6, LockType::Shared, 25, 30), Some(5));
+
+        table.renumber(7, 8);
+        assert_eq!(table.conflict(2, 5, LockType::Shared, 0, 1), Some(8));
+
+        table.release(5);
+        table.release(8);
+        assert_eq!(
+            table.conflict(1, 9, LockType::Exclusive, 0, u64::MAX),
+            Some(6)
+        );
+        table.release(6);
+        assert!(table.locks.is_empty());
+    }
+}
@@ -0,0 +1,25 @@
+use std::ops::Range;
+
+/// Number of memory indices used by the stable storage of stable-fs.
+pub const FS_MEMORY_INDEX_COUNT: u8 = 10;
+
+/// Position of the extended attribute memory within the polyfill memory index range.
+pub const XATTR_MEMORY_INDEX: u8 = 0;
+
+/// Position of the memory of the compressed and encrypted file data within the polyfill memory index range.
+pub const TRANSFORM_MEMORY_INDEX: u8 = XATTR_MEMORY_INDEX + 1;
+
+/// Position of the memory of the transaction journals within the polyfill memory index range.
+pub const JOURNAL_MEMORY_INDEX: u8 = TRANSFORM_MEMORY_INDEX + 1;
+
+/// Number of memory indices keeping all the polyfill data in stable memory.
+/// The data without a memory in a shorter range is kept on the heap.
+pub const POLYFILL_MEMORY_INDEX_COUNT: u8 = JOURNAL_MEMORY_INDEX + 1;
+
+/// Memory indices of the stable storage created over a single memory, starting at the default first index of stable-fs.
+pub const DEFAULT_MEMORY_INDEX_RANGE: Range<u8> = 229..229 + FS_MEMORY_INDEX_COUNT;
+
+/// Memory indices following `DEFAULT_MEMORY_INDEX_RANGE`, for the applications opting in to keep the polyfill data
+/// in the same memory as the file system. They are only used when passed to `init_with_polyfill_memories`.
+pub const DEFAULT_POLYFILL_MEMORY_INDEX_RANGE: Range<u8> =
+    DEFAULT_MEMORY_INDEX_RANGE.end..DEFAULT_MEMORY_INDEX_RANGE.end + POLYFILL_MEMORY_INDEX_COUNT;
@@ -0,0 +1,279 @@
+use std::collections::BTreeMap;
+
+use stable_fs::fs::Fd;
+
+#[cfg(feature = "caller_namespaces")]
+use stable_fs::{
+    error::Error,
+    fs::{FdStat, FileSystem, OpenFlags},
+};
+
+#[cfg(feature = "caller_namespaces")]
+use crate::{ic_msg_caller, ic_time, wasi, wasi_helpers::into_errno, FD_PATHS, FS, NAMESPACES};
+
+/// Per-caller namespaces: the preopened root directory is replaced with a subdirectory of the message caller.
+///
+/// The paths are resolved relative to the caller's directory and `..` cannot go above it,
+/// so the callers only see their own part of the file system.
+#[derive(Default)]
+pub struct Namespaces {
+    base_dir: Option<String>,
+    // the caller whose directory is currently open and its descriptor
+    bound: Option<(String, Fd)>,
+    // the callers that opened the descriptors, the other callers cannot use them
+    owners: BTreeMap<Fd, String>,
+}
+
+impl Namespaces {
+    pub fn new() -> Namespaces {
+        Self::default()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.base_dir.is_some()
+    }
+
+    pub fn base_dir(&self) -> Option<&str> {
+        self.base_dir.as_deref()
+    }
+
+    // Enable the namespaces, returns the descriptor of the previously bound directory to close.
+    pub fn enable(&mut self, base_dir: &str) -> Option<Fd> {
+        self.base_dir = Some(base_dir.trim_matches('/').to_string());
+        self.owners.clear();
+        self.bound.take().map(|(_, fd)| fd)
+    }
+
+    // Disable the namespaces, returns the descriptor of the previously bound directory to close.
+    pub fn disable(&mut self) -> Option<Fd> {
+        self.base_dir = None;
+        self.owners.clear();
+        self.bound.take().map(|(_, fd)| fd)
+    }
+
+    // Path of the caller's directory relative to the root.
+    pub fn caller_dir(&self, caller: &str) -> Option<String> {
+        self.base_dir.as_ref().map(|base| {
+            if base.is_empty() {
+                caller.to_string()
+            } else {
+                format!("{base}/{caller}")
+            }
+        })
+    }
+
+    pub fn bound_fd(&self, caller: &str) -> Option<Fd> {
+        match &self.bound {
+            Some((bound, fd)) if bound == caller => Some(*fd),
+            _ => None,
+        }
+    }
+
+    // Descriptor of the currently open caller's directory.
+    pub fn bound_dir_fd(&self) -> Option<Fd> {
+        self.bound.as_ref().map(|(_, fd)| *fd)
+    }
+
+    // Bind the caller's directory, returns the descriptor of the previously bound directory to close.
+    pub fn bind(&mut self, caller: &str, fd: Fd) -> Option<Fd> {
+        self.bound
+            .replace((caller.to_string(), fd))
+            .map(|(_, fd)| fd)
+    }
+
+    // Remember the caller that opened the descriptor while the namespaces are enabled.
+    pub fn open_fd(&mut self, fd: Fd, caller: &str) {
+        if self.is_enabled() {
+            self.owners.insert(fd, caller.to_string());
+        }
+    }
+
+    pub fn close_fd(&mut self, fd: Fd) {
+        self.owners.remove(&fd);
+    }
+
+    pub fn renumber_fd(&mut self, from: Fd, to: Fd) {
+        match self.owners.remove(&from) {
+            Some(owner) => self.owners.insert(to, owner),
+            None => self.owners.remove(&to),
+        };
+    }
+
+    // Whether the caller can use the descriptor: it was opened by the caller or before the namespaces were enabled.
+    pub fn is_owned_by(&self, fd: Fd, caller: &str) -> bool {
+        match &self.bound {
+            Some((bound, bound_fd)) if *bound_fd == fd => bound == caller,
+            _ => self.owners.get(&fd).is_none_or(|owner| owner == caller),
+        }
+    }
+}
+
+/// Confine the file system of each caller to its own directory `<base_dir>/<principal>`.
+/// The preopened root descriptor refers to the directory of the current message caller,
+/// the directory is created on the first access. Paths cannot escape it with `..`.
+#[cfg(feature = "caller_namespaces")]
+pub fn enable_caller_namespaces(base_dir: &str) {
+    let previous = NAMESPACES.with_borrow_mut(|namespaces| namespaces.enable(base_dir));
+    close_namespace_dir(previous);
+}
+
+/// Make the preopened root descriptor refer to the root of the file system again
+#[cfg(feature = "caller_namespaces")]
+pub fn disable_caller_namespaces() {
+    let previous = NAMESPACES.with_borrow_mut(|namespaces| namespaces.disable());
+    close_namespace_dir(previous);
+}
+
+/// Get the directory of the current caller relative to the file system root, `None` if the namespaces are disabled
+#[cfg(feature = "caller_namespaces")]
+pub fn get_caller_namespace() -> Option<String> {
+    NAMESPACES.with_borrow(|namespaces| namespaces.caller_dir(&ic_msg_caller()))
+}
+
+#[cfg(feature = "caller_namespaces")]
+fn close_namespace_dir(fd: Option<Fd>) {
+    if let Some(fd) = fd {
+        FS.with_borrow_mut(|fs| {
+            let _ = fs.close(fd);
+        });
+        FD_PATHS.with_borrow_mut(|paths| paths.close_fd(fd));
+    }
+}
+
+// Descriptor of the caller's directory in place of the root descriptor, the directory is opened on the first use.
+// The descriptors opened by the other callers are reported as bad descriptors.
+#[cfg(feature = "caller_namespaces")]
+pub(crate) fn namespace_fd(fd: Fd) -> Result<Fd, i32> {
+    if !NAMESPACES.with_borrow(|namespaces| namespaces.is_enabled()) {
+        return Ok(fd);
+    }
+
+    let caller = ic_msg_caller();
+
+    FS.with(|fs| {
+        let mut fs = fs.borrow_mut();
+        let root_fd = fs.root_fd();
+
+        if fd != root_fd {
+            return if NAMESPACES.with_borrow(|namespaces| namespaces.is_owned_by(fd, &caller)) {
+                Ok(fd)
+            } else {
+                Err(wasi::ERRNO_BADF.raw() as i32)
+            };
+        }
+
+        NAMESPACES.with_borrow_mut(|namespaces| {
+            if let Some(dir_fd) = namespaces.bound_fd(&caller) {
+                return Ok(dir_fd);
+            }
+
+            let Some(dir) = namespaces.caller_dir(&caller) else {
+                return Ok(fd);
+            };
+
+            let dir_fd = open_namespace_dir(&mut fs, &dir).map_err(into_errno)?;
+
+            if let Some(previous) = namespaces.bind(&caller, dir_fd) {
+                let _ = fs.close(previous);
+                FD_PATHS.with_borrow_mut(|paths| paths.close_fd(previous));
+            }
+
+            FD_PATHS.with_borrow_mut(|paths| paths.open_fd(dir_fd, dir));
+
+            Ok(dir_fd)
+        })
+    })
+}
+
+// The root descriptor and the opened caller's directory cannot be closed or renumbered while the namespaces are enabled.
+#[cfg(feature = "caller_namespaces")]
+pub(crate) fn is_namespace_dir_fd(fd: Fd) -> bool {
+    NAMESPACES.with_borrow(|namespaces| {
+        namespaces.is_enabled()
+            && (fd == FS.with_borrow(|fs| fs.root_fd()) || namespaces.bound_dir_fd() == Some(fd))
+    })
+}
+
+// Open the namespace directory, creating the missing path components.
+#[cfg(feature = "caller_namespaces")]
+fn open_namespace_dir(fs: &mut FileSystem, dir: &str) -> Result<Fd, Error> {
+    let root_fd = fs.root_fd();
+    let now = ic_time();
+
+    let mut end = 0;
+    for component in dir.split('/') {
+        end += component.len();
+
+        match fs.mkdir(root_fd, &dir[..end], FdStat::default(), now) {
+            Ok(()) | Err(Error::FileExists) => {}
+            Err(er) => return Err(er),
+        }
+
+        end += 1;
+    }
+
+    fs.open(root_fd, dir, FdStat::default(), OpenFlags::DIRECTORY, now)
+}
+
+#[cfg(not(feature = "caller_namespaces"))]
+pub(crate) fn is_namespace_dir_fd(_fd: Fd) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Namespaces;
+
+    #[test]
+    fn bind_and_rebind() {
+        let mut namespaces = Namespaces::new();
+        assert!(!namespaces.is_enabled());
+        assert_eq!(namespaces.caller_dir("aaaaa-aa"), None);
+
+        assert_eq!(namespaces.enable("/users/"), None);
+        assert_eq!(namespaces.base_dir(), Some("users"));
+        assert_eq!(
+            namespaces.caller_dir("aaaaa-aa"),
+            Some("users/aaaaa-aa".to_string())
+        );
+
+        assert_eq!(namespaces.bind("aaaaa-aa", 5), None);
+        assert_eq!(namespaces.bound_fd("aaaaa-aa"), Some(5));
+        assert_eq!(namespaces.bound_fd("2vxsx-fae"), None);
+        assert_eq!(namespaces.bind("2vxsx-fae", 6), Some(5));
+
+        assert_eq!(namespaces.disable(), Some(6));
+        assert_eq!(namespaces.bound_fd("2vxsx-fae"), None);
+
+        namespaces.enable("");
+        assert_eq!(
+            namespaces.caller_dir("aaaaa-aa"),
+            Some("aaaaa-aa".to_string())
+        );
+    }
+
+    #[test]
+    fn descriptor_owners() {
+        let mut namespaces = Namespaces::new();
+
+        // the descriptors opened without the namespaces are shared
+        namespaces.open_fd(4, "aaaaa-aa");
+        assert!(namespaces.is_owned_by(4, "2vxsx-fae"));
+
+        namespaces.enable("users");
+        namespaces.bind("aaaaa-aa", 5);
+        assert!(!namespaces.is_owned_by(5, "2vxsx-fae"));
+
+        namespaces.open_fd(7, "aaaaa-aa");
+        assert!(namespaces.is_owned_by(7, "aaaaa-aa"));
+        assert!(!namespaces.is_owned_by(7, "2vxsx-fae"));
+        assert!(namespaces.is_owned_by(8, "2vxsx-fae"));
+
+        namespaces.renumber_fd(7, 8);
+        assert!(namespaces.is_owned_by(7, "2vxsx-fae"));
+        assert!(!namespaces.is_owned_by(8, "2vxsx-fae"));
+
+        namespaces.close_fd(8);
+        assert!(namespaces.is_owned_by(8, "2vxsx-fae"));
+    }
+}
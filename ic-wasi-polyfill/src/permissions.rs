@@ -0,0 +1,186 @@
+use crate::wasi;
+
+#[cfg(feature = "permissions")]
+use stable_fs::fs::Fd;
+
+#[cfg(feature = "permissions")]
+use crate::{ic_is_controller, ic_msg_caller, root_node, FS, XATTRS};
+
+/// Extended attribute keeping the permission bits of a node.
+pub const MODE_XATTR: &str = "system.mode";
+
+/// Extended attribute keeping the owner principal of a node.
+pub const OWNER_XATTR: &str = "system.owner";
+
+/// Prefix of the extended attributes reserved by the polyfill.
+pub const SYSTEM_XATTR_PREFIX: &str = "system.";
+
+/// Read permission, also the `R_OK` mode of `access`.
+pub const MODE_READ: u32 = 4;
+/// Write permission, also the `W_OK` mode of `access`.
+pub const MODE_WRITE: u32 = 2;
+/// Execute permission, also the `X_OK` mode of `access`.
+pub const MODE_EXECUTE: u32 = 1;
+
+/// All the permission bits including setuid, setgid and sticky.
+pub const MODE_MASK: u32 = 0o7777;
+
+/// Permissions of the caller: the owner bits apply to the owner, the group bits to the controllers
+/// and the other bits to everyone else.
+pub fn permitted_bits(mode: u32, is_owner: bool, is_controller: bool) -> u32 {
+    let shift = if is_owner {
+        6
+    } else if is_controller {
+        3
+    } else {
+        0
+    };
+
+    (mode >> shift) & 0o7
+}
+
+/// Permissions needed to open an existing file with the given flags and rights.
+pub fn open_required_bits(oflags: i32, rights_base: wasi::Rights) -> u32 {
+    let mut required = 0;
+
+    if rights_base & (wasi::RIGHTS_FD_READ | wasi::RIGHTS_FD_READDIR) != 0 {
+        required |= MODE_READ;
+    }
+
+    if rights_base & wasi::RIGHTS_FD_WRITE != 0 || oflags & wasi::OFLAGS_TRUNC as i32 != 0 {
+        required |= MODE_WRITE;
+    }
+
+    required
+}
+
+pub fn encode_mode(mode: u32) -> [u8; 4] {
+    mode.to_le_bytes()
+}
+
+pub fn decode_mode(bytes: &[u8]) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// Set the permission bits of a file or a directory (like `chmod`), e.g. `set_file_mode("config.json", 0o444)`.
+/// The bits are enforced by `path_open`: the owner bits apply to the owner, the group bits to the controllers
+/// and the other bits to the rest of the callers, the owner bits apply to everyone if the owner is not set.
+/// Entries without the permission bits are not restricted.
+/// The permissions are kept with the extended attributes, see `init_xattrs_with_memory`.
+#[cfg(feature = "permissions")]
+pub fn set_file_mode(path: &str, mode: u32) -> i32 {
+    if mode & !MODE_MASK != 0 {
+        return wasi::ERRNO_INVAL.raw() as i32;
+    }
+
+    match root_node(path) {
+        Ok(node) => {
+            XATTRS.with_borrow_mut(|xattrs| xattrs.set(node, MODE_XATTR, &encode_mode(mode)));
+            wasi::ERRNO_SUCCESS.raw() as i32
+        }
+        Err(errno) => errno,
+    }
+}
+
+/// Get the permission bits of a file or a directory, `None` if they are not set
+#[cfg(feature = "permissions")]
+pub fn get_file_mode(path: &str) -> Result<Option<u32>, i32> {
+    let node = root_node(path)?;
+
+    Ok(XATTRS.with_borrow(|xattrs| {
+        xattrs
+            .get(node, MODE_XATTR)
+            .and_then(|mode| decode_mode(&mode))
+    }))
+}
+
+/// Set the owner principal of a file or a directory (like `chown`), the principal is given in its textual form
+#[cfg(feature = "permissions")]
+pub fn set_file_owner(path: &str, owner: &str) -> i32 {
+    match root_node(path) {
+        Ok(node) => {
+            XATTRS.with_borrow_mut(|xattrs| xattrs.set(node, OWNER_XATTR, owner.as_bytes()));
+            wasi::ERRNO_SUCCESS.raw() as i32
+        }
+        Err(errno) => errno,
+    }
+}
+
+/// Get the owner principal of a file or a directory, `None` if it is not set
+#[cfg(feature = "permissions")]
+pub fn get_file_owner(path: &str) -> Result<Option<String>, i32> {
+    let node = root_node(path)?;
+
+    Ok(XATTRS
+        .with_borrow(|xattrs| xattrs.get(node, OWNER_XATTR))
+        .map(|owner| String::from_utf8_lossy(&owner).into_owned()))
+}
+
+/// Check if the caller can access the file (like `access`), `amode` is a combination of
+/// `MODE_READ`, `MODE_WRITE` and `MODE_EXECUTE`, 0 only checks the existence.
+/// Returns `ERRNO_ACCES` if the permission bits do not allow the access.
+#[cfg(feature = "permissions")]
+pub fn check_file_access(path: &str, amode: u32) -> i32 {
+    if amode & !(MODE_READ | MODE_WRITE | MODE_EXECUTE) != 0 {
+        return wasi::ERRNO_INVAL.raw() as i32;
+    }
+
+    match root_node(path) {
+        Ok(node) if node_mode_permits(node, amode) => wasi::ERRNO_SUCCESS.raw() as i32,
+        Ok(_) => wasi::ERRNO_ACCES.raw() as i32,
+        Err(errno) => errno,
+    }
+}
+
+// The entries without the permission bits and the missing entries are not restricted.
+#[cfg(feature = "permissions")]
+pub(crate) fn is_mode_permitted(fd: Fd, path: &str, required: u32) -> bool {
+    match FS.with_borrow_mut(|fs| fs.open_metadata(fd, path)) {
+        Ok(metadata) => node_mode_permits(metadata.node, required),
+        Err(_) => true,
+    }
+}
+
+#[cfg(feature = "permissions")]
+fn node_mode_permits(node: u64, required: u32) -> bool {
+    let (mode, owner) = XATTRS.with_borrow(|xattrs| {
+        (
+            xattrs
+                .get(node, MODE_XATTR)
+                .and_then(|mode| decode_mode(&mode)),
+            xattrs.get(node, OWNER_XATTR),
+        )
+    });
+
+    let Some(mode) = mode else {
+        return true;
+    };
+
+    // without the owner, the owner bits apply to all the callers
+    let is_owner = owner.is_none_or(|owner| owner == ic_msg_caller().as_bytes());
+
+    permitted_bits(mode, is_owner, ic_is_controller()) & required == required
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caller_classes_and_required_bits() {
+        assert_eq!(permitted_bits(0o640, true, true), 6);
+        assert_eq!(permitted_bits(0o640, false, true), 4);
+        assert_eq!(permitted_bits(0o640, false, false), 0);
+
+        assert_eq!(open_required_bits(0, wasi::RIGHTS_FD_READ), MODE_READ);
+        assert_eq!(
+            open_required_bits(0, wasi::RIGHTS_FD_READ | wasi::RIGHTS_FD_WRITE),
+            MODE_READ | MODE_WRITE
+        );
+        assert_eq!(open_required_bits(wasi::OFLAGS_TRUNC as i32, 0), MODE_WRITE);
+        assert_eq!(open_required_bits(0, 0), 0);
+
+        assert_eq!(decode_mode(&encode_mode(0o755)), Some(0o755));
+        assert_eq!(decode_mode(b"x"), None);
+    }
+}
@@ -0,0 +1,334 @@
+use stable_fs::error::Error;
+use stable_fs::fs::{DstBuf, SrcBuf};
+
+use crate::validation::raw_slice;
+use crate::wasi;
+
+/// Recording format header, followed by the format version.
+const RECORDING_MAGIC: &[u8; 4] = b"WREC";
+const RECORDING_VERSION: u8 = 1;
+
+/// A single recorded WASI call: the function name, its inputs and the observed result.
+///
+/// Integer parameters are stored in `args` and byte parameters (paths, written data) in `data`,
+/// in the order of the function signature. `output` holds the function specific result data
+/// (returned descriptor, number of bytes processed, data read etc.) used for comparison during replay.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CallRecord {
+    pub function: String,
+    pub args: Vec<i64>,
+    pub data: Vec<Vec<u8>>,
+    pub errno: i32,
+    pub output: Vec<u8>,
+}
+
+impl CallRecord {
+    // A call rejected by the hardened argument checks, its arguments are not safe to read and are left out.
+    pub fn rejected(function: &str, errno: i32) -> CallRecord {
+        CallRecord {
+            function: function.to_string(),
+            errno,
+            ..Default::default()
+        }
+    }
+
+    /// Whether the call was rejected by the hardened argument checks without changing anything.
+    pub fn is_rejected(&self) -> bool {
+        let rejected = [
+            wasi::ERRNO_FAULT.raw() as i32,
+            wasi::ERRNO_INVAL.raw() as i32,
+        ];
+
+        self.args.is_empty()
+            && self.data.is_empty()
+            && self.output.is_empty()
+            && rejected.contains(&self.errno)
+    }
+}
+
+/// Collects the serialized WASI calls while recording is active.
+pub struct Recorder {
+    recording: bool,
+    log: Vec<u8>,
+    count: u64,
+}
+
+impl Recorder {
+    pub fn new() -> Recorder {
+        Recorder {
+            recording: false,
+            log: Vec::new(),
+            count: 0,
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    // Start a new recording, the previously recorded calls are discarded.
+    pub fn start(&mut self) {
+        self.log.clear();
+        self.log.extend_from_slice(RECORDING_MAGIC);
+        self.log.push(RECORDING_VERSION);
+        self.count = 0;
+        self.recording = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.recording = false;
+    }
+
+    // Number of calls recorded since the recording was started.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn record(&mut self, record: &CallRecord) {
+        if !self.recording {
+            return;
+        }
+
+        encode_record(record, &mut self.log);
+        self.count += 1;
+    }
+
+    // The serialized recording.
+    pub fn log(&self) -> &[u8] {
+        &self.log
+    }
+
+    // Return the serialized recording and reset the recorder.
+    pub fn take_log(&mut self) -> Vec<u8> {
+        self.recording = false;
+        self.count = 0;
+        std::mem::take(&mut self.log)
+    }
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Concatenate the contents of the source buffers.
+///
+/// # Safety
+///
+/// Every buffer must point to a valid memory region of its declared length.
+pub unsafe fn gather_src_bufs(bufs: &[SrcBuf]) -> Vec<u8> {
+    let mut result = Vec::new();
+
+    for buf in bufs {
+        result.extend_from_slice(unsafe { raw_slice(buf.buf, buf.len) });
+    }
+
+    result
+}
+
+/// Concatenate the first `len` bytes stored in the destination buffers.
+///
+/// # Safety
+///
+/// Every buffer must point to a valid memory region of its declared length.
+pub unsafe fn gather_dst_bufs(bufs: &[DstBuf], len: usize) -> Vec<u8> {
+    let mut result = Vec::new();
+
+    for buf in bufs {
+        let remaining = len - result.len();
+        let to_copy = remaining.min(buf.len);
+
+        result.extend_from_slice(unsafe { raw_slice(buf.buf, to_copy) });
+
+        if result.len() == len {
+            break;
+        }
+    }
+
+    result
+}
+
+// Deterministic part of the file stat, the timestamps are skipped as they depend on the call time.
+pub fn filestat_output(stat: &wasi::Filestat) -> Vec<u8> {
+    let mut result = vec![stat.filetype.raw()];
+    result.extend_from_slice(&stat.ino.to_le_bytes());
+    result.extend_from_slice(&stat.nlink.to_le_bytes());
+    result.extend_from_slice(&stat.size.to_le_bytes());
+    result
+}
+
+pub fn fdstat_output(stat: &wasi::Fdstat) -> Vec<u8> {
+    let mut result = vec![stat.fs_filetype.raw()];
+    result.extend_from_slice(&stat.fs_flags.to_le_bytes());
+    result.extend_from_slice(&stat.fs_rights_base.to_le_bytes());
+    result.extend_from_slice(&stat.fs_rights_inheriting.to_le_bytes());
+    result
+}
+
+fn put_bytes(bytes: &[u8], out: &mut Vec<u8>) {
+    out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    out.extend_from_slice(bytes);
+}
+
+pub fn encode_record(record: &CallRecord, out: &mut Vec<u8>) {
+    put_bytes(record.function.as_bytes(), out);
+
+    out.push(record.args.len() as u8);
+    for arg in record.args.iter() {
+        out.extend_from_slice(&arg.to_le_bytes());
+    }
+
+    out.push(record.data.len() as u8);
+    for data in record.data.iter() {
+        put_bytes(data, out);
+    }
+
+    out.extend_from_slice(&record.errno.to_le_bytes());
+    put_bytes(&record.output, out);
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.buf.len() - self.pos < len {
+            return Err(Error::InvalidArgument);
+        }
+
+        let result = &self.buf[self.pos..self.pos + len];
+        self.pos += len;
+
+        Ok(result)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, Error> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i64(&mut self) -> Result<i64, Error> {
+        Ok(i64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>, Error> {
+        let len = self.u32()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos == self.buf.len()
+    }
+}
+
+/// Decode a recording produced by the recorder into the list of calls.
+pub fn decode_recording(log: &[u8]) -> Result<Vec<CallRecord>, Error> {
+    let mut reader = Reader { buf: log, pos: 0 };
+
+    if reader.take(RECORDING_MAGIC.len())? != RECORDING_MAGIC {
+        return Err(Error::InvalidArgument);
+    }
+
+    if reader.u8()? != RECORDING_VERSION {
+        return Err(Error::NotSupportedOrOperationNotSupportedOnSocket);
+    }
+
+    let mut records = Vec::new();
+
+    while !reader.is_empty() {
+        let function =
+            String::from_utf8(reader.bytes()?).map_err(|_| Error::IllegalByteSequence)?;
+
+        let args_len = reader.u8()?;
+        let mut args = Vec::with_capacity(args_len as usize);
+        for _ in 0..args_len {
+            args.push(reader.i64()?);
+        }
+
+        let data_len = reader.u8()?;
+        let mut data = Vec::with_capacity(data_len as usize);
+        for _ in 0..data_len {
+            data.push(reader.bytes()?);
+        }
+
+        let errno = reader.i32()?;
+        let output = reader.bytes()?;
+
+        records.push(CallRecord {
+            function,
+            args,
+            data,
+            errno,
+            output,
+        });
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_recording, CallRecord, Recorder};
+
+    #[test]
+    fn encode_decode_roundtrip() {
+        let mut recorder = Recorder::new();
+
+        let open = CallRecord {
+            function: "path_open".to_string(),
+            args: vec![3, 0, 1, -1, -1, 0],
+            data: vec![b"file.txt".to_vec()],
+            errno: 0,
+            output: 4u32.to_le_bytes().to_vec(),
+        };
+
+        let write = CallRecord {
+            function: "fd_write".to_string(),
+            args: vec![4],
+            data: vec![b"hello".to_vec()],
+            errno: 0,
+            output: 5u64.to_le_bytes().to_vec(),
+        };
+
+        // not recorded before the recording starts
+        recorder.record(&open);
+
+        recorder.start();
+        recorder.record(&open);
+        recorder.record(&write);
+        recorder.stop();
+        recorder.record(&write);
+
+        assert_eq!(recorder.count(), 2);
+
+        let log = recorder.take_log();
+        assert_eq!(decode_recording(&log).unwrap(), vec![open, write]);
+    }
+
+    #[test]
+    fn decode_rejects_corrupted_log() {
+        let mut recorder = Recorder::new();
+        recorder.start();
+        recorder.record(&CallRecord {
+            function: "fd_close".to_string(),
+            args: vec![4],
+            ..Default::default()
+        });
+
+        let log = recorder.take_log();
+
+        assert!(decode_recording(&log[..log.len() - 1]).is_err());
+        assert!(decode_recording(b"XXXX").is_err());
+        assert_eq!(decode_recording(&log[..5]).unwrap(), vec![]);
+    }
+}
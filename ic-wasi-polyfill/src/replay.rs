@@ -0,0 +1,521 @@
+use stable_fs::error::Error;
+use stable_fs::fs::Fd;
+use stable_fs::storage::transient::TransientStorage;
+
+use crate::recorder::{decode_recording, fdstat_output, filestat_output, CallRecord};
+use crate::*;
+
+/// A replayed call which produced a different result than the recorded one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReplayMismatch {
+    /// Position of the call in the recording.
+    pub index: usize,
+    pub function: String,
+    pub expected_errno: i32,
+    pub actual_errno: i32,
+    pub expected_output: Vec<u8>,
+    pub actual_output: Vec<u8>,
+}
+
+/// Summary of a replayed recording.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReplayReport {
+    /// Number of replayed calls.
+    pub calls: usize,
+    /// Calls which did not reproduce the recorded errno or output.
+    pub mismatches: Vec<ReplayMismatch>,
+}
+
+impl ReplayReport {
+    pub fn is_consistent(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+/// Replay a recording on a fresh transient file system.
+/// The extended attributes, locks, hooks, watches, namespaces and the other state of the previous file system are discarded.
+///
+/// The recording should be started right after the file system initialization,
+/// otherwise use `replay_wasi_calls_on_current_fs` on a file system prepared with the same initial state.
+pub fn replay_wasi_calls(log: &[u8]) -> Result<ReplayReport, Error> {
+    reset_file_system(Box::new(TransientStorage::new()));
+
+    replay_wasi_calls_on_current_fs(log)
+}
+
+/// Replay a recording on the currently initialized file system.
+pub fn replay_wasi_calls_on_current_fs(log: &[u8]) -> Result<ReplayReport, Error> {
+    let records = decode_recording(log)?;
+
+    let mut report = ReplayReport::default();
+
+    for (index, record) in records.iter().enumerate() {
+        // the arguments of a rejected call are not recorded, it did not change the state
+        if record.is_rejected() {
+            continue;
+        }
+
+        let (errno, output) = replay_call(record)?;
+
+        report.calls += 1;
+
+        if errno != record.errno || output != record.output {
+            report.mismatches.push(ReplayMismatch {
+                index,
+                function: record.function.clone(),
+                expected_errno: record.errno,
+                actual_errno: errno,
+                expected_output: record.output.clone(),
+                actual_output: output,
+            });
+        }
+    }
+
+    Ok(report)
+}
+
+fn arg(record: &CallRecord, index: usize) -> Result<i64, Error> {
+    record
+        .args
+        .get(index)
+        .copied()
+        .ok_or(Error::InvalidArgument)
+}
+
+fn data(record: &CallRecord, index: usize) -> Result<&[u8], Error> {
+    record
+        .data
+        .get(index)
+        .map(|d| d.as_slice())
+        .ok_or(Error::InvalidArgument)
+}
+
+fn empty_filestat() -> wasi::Filestat {
+    wasi::Filestat {
+        dev: 0,
+        ino: 0,
+        filetype: wasi::FILETYPE_UNKNOWN,
+        nlink: 0,
+        size: 0,
+        atim: 0,
+        mtim: 0,
+        ctim: 0,
+    }
+}
+
+fn sizes_output(count: wasi::Size, size: wasi::Size) -> Vec<u8> {
+    [(count as u64).to_le_bytes(), (size as u64).to_le_bytes()].concat()
+}
+
+/// Execute a single recorded call, returns the errno and the call output.
+pub fn replay_call(record: &CallRecord) -> Result<(i32, Vec<u8>), Error> {
+    let r = record;
+
+    let result = unsafe {
+        match r.function.as_str() {
+            "fd_write" | "fd_pwrite" => {
+                let buf = data(r, 0)?;
+                let iov = wasi::Ciovec {
+                    buf: buf.as_ptr(),
+                    buf_len: buf.len(),
+                };
+                let mut res: wasi::Size = 0;
+
+                let errno = if r.function == "fd_write" {
+                    __ic_custom_fd_write(arg(r, 0)? as Fd, &iov, 1, &mut res)
+                } else {
+                    __ic_custom_fd_pwrite(arg(r, 0)? as Fd, &iov, 1, arg(r, 1)?, &mut res)
+                };
+
+                (errno, (res as u64).to_le_bytes().to_vec())
+            }
+            "fd_read" | "fd_pread" => {
+                let mut buf = vec![0u8; arg(r, 1)? as usize];
+                let iov = wasi::Iovec {
+                    buf: buf.as_mut_ptr(),
+                    buf_len: buf.len(),
+                };
+                let mut res: wasi::Size = 0;
+
+                let errno = if r.function == "fd_read" {
+                    __ic_custom_fd_read(arg(r, 0)? as Fd, &iov, 1, &mut res)
+                } else {
+                    __ic_custom_fd_pread(arg(r, 0)? as Fd, &iov, 1, arg(r, 2)?, &mut res)
+                };
+
+                buf.truncate(res);
+                (errno, buf)
+            }
+            "fd_seek" => {
+                let mut res: wasi::Filesize = 0;
+                let errno =
+                    __ic_custom_fd_seek(arg(r, 0)? as Fd, arg(r, 1)?, arg(r, 2)? as i32, &mut res);
+                (errno, res.to_le_bytes().to_vec())
+            }
+            "path_open" => {
+                let path = data(r, 0)?;
+                let mut res: Fd = 0;
+                let errno = __ic_custom_path_open(
+                    arg(r, 0)? as Fd,
+                    arg(r, 1)? as i32,
+                    path.as_ptr(),
+                    path.len() as i32,
+                    arg(r, 2)? as i32,
+                    arg(r, 3)? as wasi::Rights,
+                    arg(r, 4)? as wasi::Rights,
+                    arg(r, 5)? as i32,
+                    &mut res,
+                );
+                (errno, res.to_le_bytes().to_vec())
+            }
+            "fd_close" => (__ic_custom_fd_close(arg(r, 0)? as Fd), vec![]),
+            "fd_filestat_get" => {
+                let mut stat = empty_filestat();
+                let errno = __ic_custom_fd_filestat_get(arg(r, 0)? as Fd, &mut stat);
+                (errno, filestat_output(&stat))
+            }
+            "fd_sync" => (__ic_custom_fd_sync(arg(r, 0)? as Fd), vec![]),
+            "fd_tell" => {
+                let mut res: wasi::Filesize = 0;
+                let errno = __ic_custom_fd_tell(arg(r, 0)? as Fd, &mut res);
+                (errno, res.to_le_bytes().to_vec())
+            }
+            "fd_advise" => (
+                __ic_custom_fd_advise(arg(r, 0)? as Fd, arg(r, 1)?, arg(r, 2)?, arg(r, 3)? as i32),
+                vec![],
+            ),
+            "fd_allocate" => (
+                __ic_custom_fd_allocate(arg(r, 0)? as Fd, arg(r, 1)?, arg(r, 2)?),
+                vec![],
+            ),
+            "fd_datasync" => (__ic_custom_fd_datasync(arg(r, 0)? as Fd), vec![]),
+            "fd_fdstat_get" => {
+                let mut stat = wasi::Fdstat {
+                    fs_filetype: wasi::FILETYPE_UNKNOWN,
+                    fs_flags: 0,
+                    fs_rights_base: 0,
+                    fs_rights_inheriting: 0,
+                };
+                let errno = __ic_custom_fd_fdstat_get(arg(r, 0)? as Fd, &mut stat);
+                (errno, fdstat_output(&stat))
+            }
+            "fd_fdstat_set_flags" => (
+                __ic_custom_fd_fdstat_set_flags(arg(r, 0)? as Fd, arg(r, 1)? as i32),
+                vec![],
+            ),
+            "fd_fdstat_set_rights" => (
+                __ic_custom_fd_fdstat_set_rights(arg(r, 0)? as i32, arg(r, 1)?, arg(r, 2)?),
+                vec![],
+            ),
+            "fd_filestat_set_size" => (
+                __ic_custom_fd_filestat_set_size(arg(r, 0)? as Fd, arg(r, 1)?),
+                vec![],
+            ),
+            "fd_filestat_set_times" => (
+                __ic_custom_fd_filestat_set_times(
+                    arg(r, 0)? as Fd,
+                    arg(r, 1)?,
+                    arg(r, 2)?,
+                    arg(r, 3)? as i32,
+                ),
+                vec![],
+            ),
+            "fd_readdir" => {
+                let mut buf = vec![0u8; arg(r, 1)? as usize];
+                let mut res: wasi::Size = 0;
+                let errno = __ic_custom_fd_readdir(
+                    arg(r, 0)? as Fd,
+                    buf.as_mut_ptr(),
+                    buf.len() as i32,
+                    arg(r, 2)?,
+                    &mut res,
+                );
+                buf.truncate(res);
+                (errno, buf)
+            }
+            "fd_renumber" => (
+                __ic_custom_fd_renumber(arg(r, 0)? as Fd, arg(r, 1)? as Fd),
+                vec![],
+            ),
+            "path_create_directory" => {
+                let path = data(r, 0)?;
+                (
+                    __ic_custom_path_create_directory(
+                        arg(r, 0)? as Fd,
+                        path.as_ptr(),
+                        path.len() as i32,
+                    ),
+                    vec![],
+                )
+            }
+            "path_filestat_get" => {
+                let path = data(r, 0)?;
+                let mut stat = empty_filestat();
+                let errno = __ic_custom_path_filestat_get(
+                    arg(r, 0)? as i32,
+                    arg(r, 1)? as i32,
+                    path.as_ptr(),
+                    path.len() as i32,
+                    &mut stat,
+                );
+                (errno, filestat_output(&stat))
+            }
+            "path_filestat_set_times" => {
+                let path = data(r, 0)?;
+                (
+                    __ic_custom_path_filestat_set_times(
+                        arg(r, 0)? as i32,
+                        arg(r, 1)? as i32,
+                        path.as_ptr(),
+                        path.len() as i32,
+                        arg(r, 2)?,
+                        arg(r, 3)?,
+                        arg(r, 4)? as i32,
+                    ),
+                    vec![],
+                )
+            }
+            "path_link" => {
+                let old_path = data(r, 0)?;
+                let new_path = data(r, 1)?;
+                (
+                    __ic_custom_path_link(
+                        arg(r, 0)? as Fd,
+                        arg(r, 1)? as i32,
+                        old_path.as_ptr(),
+                        old_path.len() as i32,
+                        arg(r, 2)? as Fd,
+                        new_path.as_ptr(),
+                        new_path.len() as i32,
+                    ),
+                    vec![],
+                )
+            }
+            "path_remove_directory" => {
+                let path = data(r, 0)?;
+                (
+                    __ic_custom_path_remove_directory(
+                        arg(r, 0)? as Fd,
+                        path.as_ptr(),
+                        path.len() as i32,
+                    ),
+                    vec![],
+                )
+            }
+            "path_rename" => {
+                let old_path = data(r, 0)?;
+                let new_path = data(r, 1)?;
+                (
+                    __ic_custom_path_rename(
+                        arg(r, 0)? as i32,
+                        old_path.as_ptr(),
+                        old_path.len() as i32,
+                        arg(r, 1)? as i32,
+                        new_path.as_ptr(),
+                        new_path.len() as i32,
+                    ),
+                    vec![],
+                )
+            }
+            "path_unlink_file" => {
+                let path = data(r, 0)?;
+                (
+                    __ic_custom_path_unlink_file(
+                        arg(r, 0)? as i32,
+                        path.as_ptr(),
+                        path.len() as i32,
+                    ),
+                    vec![],
+                )
+            }
+            "fd_prestat_get" => {
+                let mut prestat = wasi::Prestat {
+                    tag: 0,
+                    u: wasi::PrestatU {
+                        dir: wasi::PrestatDir { pr_name_len: 0 },
+                    },
+                };
+                let errno = __ic_custom_fd_prestat_get(arg(r, 0)? as i32, &mut prestat);
+                let output = if errno == wasi::ERRNO_SUCCESS.raw() as i32 {
+                    (prestat.u.dir.pr_name_len as u64).to_le_bytes().to_vec()
+                } else {
+                    vec![]
+                };
+                (errno, output)
+            }
+            "fd_prestat_dir_name" => {
+                let mut buf = vec![0u8; arg(r, 1)? as usize];
+                let errno = __ic_custom_fd_prestat_dir_name(
+                    arg(r, 0)? as i32,
+                    buf.as_mut_ptr(),
+                    buf.len() as i32,
+                );
+                let len = FS.with_borrow(|fs| fs.root_path().len());
+                buf.truncate(if errno == wasi::ERRNO_SUCCESS.raw() as i32 {
+                    len
+                } else {
+                    0
+                });
+                (errno, buf)
+            }
+            "random_get" => {
+                let mut buf = vec![0u8; arg(r, 0)? as usize];
+                (__ic_custom_random_get(buf.as_mut_ptr(), buf.len()), vec![])
+            }
+            "environ_get" => {
+                let (count, size) = ENV.with_borrow(|env| env.environ_sizes_get());
+                let mut entries = vec![std::ptr::null_mut::<u8>(); count];
+                let mut buf = vec![0u8; size];
+                let errno = __ic_custom_environ_get(entries.as_mut_ptr(), buf.as_mut_ptr());
+                if errno != wasi::ERRNO_SUCCESS.raw() as i32 {
+                    buf.clear();
+                }
+                (errno, buf)
+            }
+            "environ_sizes_get" => {
+                let mut count: wasi::Size = 0;
+                let mut size: wasi::Size = 0;
+                let errno = __ic_custom_environ_sizes_get(&mut count, &mut size);
+                (errno, sizes_output(count, size))
+            }
+            "args_get" => {
+                let mut entries = [std::ptr::null_mut::<u8>(); 1];
+                let mut buf = [0u8; 1];
+                (
+                    __ic_custom_args_get(entries.as_mut_ptr(), buf.as_mut_ptr()),
+                    vec![],
+                )
+            }
+            "args_sizes_get" => {
+                let mut count: wasi::Size = 0;
+                let mut size: wasi::Size = 0;
+                let errno = __ic_custom_args_sizes_get(&mut count, &mut size);
+                (errno, sizes_output(count, size))
+            }
+            "clock_res_get" => {
+                let mut resolution = 0;
+                let errno = __ic_custom_clock_res_get(arg(r, 0)? as i32, &mut resolution);
+                (errno, resolution.to_le_bytes().to_vec())
+            }
+            "clock_time_get" => {
+                let mut time = 0;
+                (
+                    __ic_custom_clock_time_get(arg(r, 0)? as i32, arg(r, 1)?, &mut time),
+                    vec![],
+                )
+            }
+            // the skipped functions are provided by the application and not recorded
+            #[cfg(not(feature = "skip_unimplemented_functions"))]
+            "path_readlink" => {
+                let path = data(r, 0)?;
+                let mut used = 0;
+                (
+                    __ic_custom_path_readlink(
+                        arg(r, 0)? as i32,
+                        path.as_ptr(),
+                        path.len() as i32,
+                        0,
+                        arg(r, 1)? as i32,
+                        &mut used,
+                    ),
+                    vec![],
+                )
+            }
+            #[cfg(not(feature = "skip_unimplemented_functions"))]
+            "path_symlink" => {
+                let old_path = data(r, 0)?;
+                let new_path = data(r, 1)?;
+                (
+                    __ic_custom_path_symlink(
+                        old_path.as_ptr(),
+                        old_path.len() as i32,
+                        arg(r, 0)? as i32,
+                        new_path.as_ptr(),
+                        new_path.len() as i32,
+                    ),
+                    vec![],
+                )
+            }
+            #[cfg(not(feature = "skip_unimplemented_functions"))]
+            "poll_oneoff" => {
+                let count = arg(r, 0)? as usize;
+                let subscriptions: Vec<wasi::Subscription> =
+                    (0..count).map(|_| std::mem::zeroed()).collect();
+                let mut events: Vec<wasi::Event> = (0..count).map(|_| std::mem::zeroed()).collect();
+                let mut nevents = 0;
+                (
+                    __ic_custom_poll_oneoff(
+                        subscriptions.as_ptr(),
+                        events.as_mut_ptr(),
+                        count as i32,
+                        &mut nevents,
+                    ),
+                    vec![],
+                )
+            }
+            #[cfg(not(feature = "skip_unimplemented_functions"))]
+            "proc_raise" => (__ic_custom_proc_raise(arg(r, 0)? as i32), vec![]),
+            "sched_yield" => (__ic_custom_sched_yield(), vec![]),
+            #[cfg(not(feature = "skip_unimplemented_functions"))]
+            "sock_accept" => {
+                let mut fd = 0;
+                (
+                    __ic_custom_sock_accept(arg(r, 0)? as i32, arg(r, 1)? as i32, &mut fd),
+                    vec![],
+                )
+            }
+            #[cfg(not(feature = "skip_unimplemented_functions"))]
+            "sock_recv" => {
+                let mut buf = [0u8; 0];
+                let iovs: Vec<wasi::Iovec> = (0..arg(r, 1)?)
+                    .map(|_| wasi::Iovec {
+                        buf: buf.as_mut_ptr(),
+                        buf_len: 0,
+                    })
+                    .collect();
+                let mut len = 0;
+                let mut flags = 0;
+                (
+                    __ic_custom_sock_recv(
+                        arg(r, 0)? as i32,
+                        iovs.as_ptr(),
+                        iovs.len() as i32,
+                        arg(r, 2)? as i32,
+                        &mut len,
+                        &mut flags,
+                    ),
+                    vec![],
+                )
+            }
+            #[cfg(not(feature = "skip_unimplemented_functions"))]
+            "sock_send" => {
+                let buf = [0u8; 0];
+                let iovs: Vec<wasi::Ciovec> = (0..arg(r, 1)?)
+                    .map(|_| wasi::Ciovec {
+                        buf: buf.as_ptr(),
+                        buf_len: 0,
+                    })
+                    .collect();
+                let mut len = 0;
+                (
+                    __ic_custom_sock_send(
+                        arg(r, 0)? as i32,
+                        iovs.as_ptr(),
+                        iovs.len() as i32,
+                        arg(r, 2)? as i32,
+                        &mut len,
+                    ),
+                    vec![],
+                )
+            }
+            #[cfg(not(feature = "skip_unimplemented_functions"))]
+            "sock_shutdown" => (
+                __ic_custom_sock_shutdown(arg(r, 0)? as i32, arg(r, 1)? as i32),
+                vec![],
+            ),
+            _ => return Err(Error::FunctionNotSupported),
+        }
+    };
+
+    Ok(result)
+}
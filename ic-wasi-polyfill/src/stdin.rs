@@ -0,0 +1,103 @@
+use std::collections::VecDeque;
+
+use stable_fs::fs::FdFlags;
+
+/// Data provided to the standard input and the flags of its descriptor.
+///
+/// The canister cannot wait for input: a read of the empty input returns the end of file,
+/// with `FDFLAGS_NONBLOCK` it returns `ERRNO_AGAIN` until the input is closed.
+pub struct Stdin {
+    buffer: VecDeque<u8>,
+    closed: bool,
+    flags: FdFlags,
+}
+
+impl Default for Stdin {
+    fn default() -> Self {
+        Stdin {
+            buffer: VecDeque::new(),
+            closed: false,
+            flags: FdFlags::empty(),
+        }
+    }
+}
+
+impl Stdin {
+    pub fn new() -> Stdin {
+        Self::default()
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend(data);
+    }
+
+    // Mark the end of the input, the buffered data can still be read.
+    pub fn close(&mut self) {
+        self.closed = true;
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    pub fn flags(&self) -> FdFlags {
+        self.flags
+    }
+
+    pub fn set_flags(&mut self, flags: FdFlags) {
+        self.flags = flags;
+    }
+
+    // Move the buffered data into the buffers, `None` if a non-blocking read would have to wait.
+    pub fn read(&mut self, bufs: &mut [&mut [u8]]) -> Option<usize> {
+        if self.buffer.is_empty() {
+            return if self.closed || !self.flags.contains(FdFlags::NONBLOCK) {
+                Some(0)
+            } else {
+                None
+            };
+        }
+
+        let mut read = 0;
+        for buf in bufs.iter_mut() {
+            let len = buf.len().min(self.buffer.len());
+
+            for (dst, src) in buf.iter_mut().zip(self.buffer.drain(..len)) {
+                *dst = src;
+            }
+
+            read += len;
+        }
+
+        Some(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocking_and_nonblocking_reads() {
+        let mut stdin = Stdin::new();
+        let (mut a, mut b) = ([0u8; 2], [0u8; 4]);
+
+        assert_eq!(stdin.read(&mut [&mut a]), Some(0));
+
+        stdin.set_flags(FdFlags::NONBLOCK);
+        assert_eq!(stdin.read(&mut [&mut a]), None);
+
+        stdin.push(b"hello");
+        assert_eq!(stdin.read(&mut [&mut a, &mut b]), Some(5));
+        assert_eq!((&a, &b), (b"he", b"llo\0"));
+
+        stdin.push(b"!");
+        stdin.close();
+        assert_eq!(stdin.read(&mut [&mut a]), Some(1));
+        assert_eq!(stdin.read(&mut [&mut a]), Some(0));
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::VecDeque;
+
+use stable_fs::fs::Fd;
+
+/// Default number of records kept in the trace buffer.
+pub const DEFAULT_TRACE_CAPACITY: usize = 1024;
+
+/// A single traced WASI call.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceRecord {
+    /// WASI function name, e.g. `fd_write`.
+    pub function: &'static str,
+    /// Formatted input parameters of the call.
+    pub args: String,
+    /// The errno returned to the caller.
+    pub errno: i32,
+    /// Instructions spent inside the polyfill function.
+    pub instructions: u64,
+    /// File descriptor the call operated on, if any.
+    pub fd: Option<Fd>,
+    /// Path the call operated on, if any.
+    pub path: Option<String>,
+}
+
+/// Selects which calls are recorded. An empty filter accepts every call.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    /// Record only these WASI functions (empty means all functions).
+    pub functions: Vec<String>,
+    /// Record only calls operating on this file descriptor.
+    pub fd: Option<Fd>,
+    /// Record only calls with a path starting with this prefix.
+    pub path_prefix: Option<String>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, function: &str, fd: Option<Fd>, path: Option<&str>) -> bool {
+        if !self.functions.is_empty() && !self.functions.iter().any(|f| f == function) {
+            return false;
+        }
+
+        if self.fd.is_some() && self.fd != fd {
+            return false;
+        }
+
+        if let Some(prefix) = &self.path_prefix {
+            match path {
+                Some(path) if path.starts_with(prefix.as_str()) => {}
+                _ => return false,
+            }
+        }
+
+        true
+    }
+}
+
+/// Bounded ring buffer of traced WASI calls, the oldest records are evicted first.
+pub struct Tracer {
+    enabled: bool,
+    capacity: usize,
+    filter: TraceFilter,
+    records: VecDeque<TraceRecord>,
+    dropped: u64,
+}
+
+impl Tracer {
+    pub fn new(capacity: usize) -> Tracer {
+        Tracer {
+            enabled: true,
+            capacity,
+            filter: TraceFilter::default(),
+            records: VecDeque::new(),
+            dropped: 0,
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // Change the buffer capacity, drops the oldest records if the buffer is too large.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict();
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn set_filter(&mut self, filter: TraceFilter) {
+        self.filter = filter;
+    }
+
+    pub fn filter(&self) -> &TraceFilter {
+        &self.filter
+    }
+
+    // Check if a call should be recorded. This is done before formatting the arguments to keep filtered calls cheap.
+    pub fn is_traced(&self, function: &str, fd: Option<Fd>, path: Option<&str>) -> bool {
+        self.enabled && self.capacity > 0 && self.filter.matches(function, fd, path)
+    }
+
+    pub fn push(&mut self, record: TraceRecord) {
+        if self.capacity == 0 {
+            self.dropped += 1;
+            return;
+        }
+
+        self.records.push_back(record);
+        self.evict();
+    }
+
+    // Return the recorded calls, oldest first.
+    pub fn records(&self) -> Vec<TraceRecord> {
+        self.records.iter().cloned().collect()
+    }
+
+    // Return and remove the recorded calls, oldest first.
+    pub fn take_records(&mut self) -> Vec<TraceRecord> {
+        self.records.drain(..).collect()
+    }
+
+    // Number of records evicted from the buffer since the last clear.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    pub fn clear(&mut self) {
+        self.records.clear();
+        self.dropped = 0;
+    }
+
+    fn evict(&mut self) {
+        while self.records.len() > self.capacity {
+            self.records.pop_front();
+            self.dropped += 1;
+        }
+    }
+}
+
+impl Default for Tracer {
+    fn default() -> Self {
+        Self::new(DEFAULT_TRACE_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TraceFilter, TraceRecord, Tracer};
+
+    fn record(function: &'static str, fd: Option<u32>, path: Option<&str>) -> TraceRecord {
+        TraceRecord {
+            function,
+            args: String::new(),
+            errno: 0,
+            instructions: 0,
+            fd,
+            path: path.map(String::from),
+        }
+    }
+
+    #[test]
+    fn ring_buffer_evicts_oldest() {
+        let mut tracer = Tracer::new(3);
+
+        for fd in 0..5 {
+            tracer.push(record("fd_close", Some(fd), None));
+        }
+
+        let fds: Vec<_> = tracer.records().iter().map(|r| r.fd.unwrap()).collect();
+        assert_eq!(fds, vec![2, 3, 4]);
+        assert_eq!(tracer.dropped(), 2);
+
+        tracer.set_capacity(1);
+        let fds: Vec<_> = tracer
+            .take_records()
+            .iter()
+            .map(|r| r.fd.unwrap())
+            .collect();
+        assert_eq!(fds, vec![4]);
+        assert!(tracer.records().is_empty());
+    }
+
+    #[test]
+    fn filter_by_function_fd_and_path() {
+        let mut tracer = Tracer::default();
+        assert!(tracer.is_traced("fd_write", Some(5), None));
+
+        tracer.set_filter(TraceFilter {
+            functions: vec!["path_open".to_string()],
+            fd: None,
+            path_prefix: Some("data/".to_string()),
+        });
+
+        assert!(tracer.is_traced("path_open", Some(3), Some("data/file.txt")));
+        assert!(!tracer.is_traced("path_open", Some(3), Some("tmp/file.txt")));
+        assert!(!tracer.is_traced("path_open", Some(3), None));
+        assert!(!tracer.is_traced("fd_write", Some(3), Some("data/file.txt")));
+
+        tracer.set_filter(TraceFilter {
+            functions: vec![],
+            fd: Some(4),
+            path_prefix: None,
+        });
+
+        assert!(tracer.is_traced("fd_read", Some(4), None));
+        assert!(!tracer.is_traced("fd_read", Some(5), None));
+
+        tracer.set_enabled(false);
+        assert!(!tracer.is_traced("fd_read", Some(4), None));
+    }
+}
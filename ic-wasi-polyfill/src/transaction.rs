@@ -0,0 +1,669 @@
+use std::collections::BTreeMap;
+
+use ic_stable_structures::{Memory, StableBTreeMap};
+use stable_fs::storage::types::FileSize;
+
+use crate::xattr::BoxedMemory;
+
+#[cfg(feature = "transactions")]
+use stable_fs::{
+    error::Error,
+    fs::{Fd, FdStat},
+};
+
+#[cfg(feature = "transactions")]
+use crate::{
+    __ic_custom_fd_filestat_set_size, __ic_custom_fd_pread, __ic_custom_fd_pwrite,
+    __ic_custom_path_create_directory, __ic_custom_path_remove_directory, __ic_custom_path_rename,
+    __ic_custom_path_unlink_file, errno_result, ic_time, root_path_filestat,
+    root_path_open_with_rights, wasi, wasi_helpers::into_errno, OwnedFd, FS, TRANSACTIONS,
+};
+
+/// Directory of the file system root keeping the files replaced or deleted by a commit until it completes,
+/// it only exists during the commit.
+pub const BACKUP_DIR: &str = ".transactions";
+
+pub type TransactionId = u64;
+
+const WRITE_TAG: u8 = 1;
+const RENAME_TAG: u8 = 2;
+const DELETE_TAG: u8 = 3;
+
+/// Change staged in the journal of a transaction, the paths are relative to the file system root.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum JournalEntry {
+    Write {
+        path: String,
+        offset: FileSize,
+        data: Vec<u8>,
+    },
+    Rename {
+        from: String,
+        to: String,
+    },
+    Delete {
+        path: String,
+    },
+}
+
+fn put_bytes(record: &mut Vec<u8>, bytes: &[u8]) {
+    record.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+    record.extend_from_slice(bytes);
+}
+
+impl JournalEntry {
+    /// Journal record of the change: a tag followed by the fields, the strings and the data are prefixed with their length.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut record = Vec::new();
+
+        match self {
+            JournalEntry::Write { path, offset, data } => {
+                record.push(WRITE_TAG);
+                put_bytes(&mut record, path.as_bytes());
+                record.extend_from_slice(&offset.to_le_bytes());
+                put_bytes(&mut record, data);
+            }
+            JournalEntry::Rename { from, to } => {
+                record.push(RENAME_TAG);
+                put_bytes(&mut record, from.as_bytes());
+                put_bytes(&mut record, to.as_bytes());
+            }
+            JournalEntry::Delete { path } => {
+                record.push(DELETE_TAG);
+                put_bytes(&mut record, path.as_bytes());
+            }
+        }
+
+        record
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Option<&[u8]> {
+        let end = self.pos.checked_add(len)?;
+        let bytes = self.bytes.get(self.pos..end)?;
+        self.pos = end;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn bytes(&mut self) -> Option<Vec<u8>> {
+        let len = usize::try_from(self.u64()?).ok()?;
+        Some(self.take(len)?.to_vec())
+    }
+
+    fn string(&mut self) -> Option<String> {
+        String::from_utf8(self.bytes()?).ok()
+    }
+
+    fn entry(&mut self) -> Option<JournalEntry> {
+        match self.u8()? {
+            WRITE_TAG => Some(JournalEntry::Write {
+                path: self.string()?,
+                offset: self.u64()?,
+                data: self.bytes()?,
+            }),
+            RENAME_TAG => Some(JournalEntry::Rename {
+                from: self.string()?,
+                to: self.string()?,
+            }),
+            DELETE_TAG => Some(JournalEntry::Delete {
+                path: self.string()?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Decode a journal record, returns `None` for an incomplete or unknown record.
+pub fn decode_record(record: &[u8]) -> Option<JournalEntry> {
+    let mut reader = Reader {
+        bytes: record,
+        pos: 0,
+    };
+
+    reader.entry().filter(|_| reader.pos == record.len())
+}
+
+/// Path keeping a file replaced or deleted by a change until the commit completes.
+pub fn backup_path(id: TransactionId, index: usize) -> String {
+    format!("{BACKUP_DIR}/{id}.{index}")
+}
+
+// The records of a transaction are keyed by its id and their position, the record 0 marks the transaction as open.
+enum JournalStore {
+    Heap(BTreeMap<(TransactionId, u64), Vec<u8>>),
+    Stable(StableBTreeMap<(TransactionId, u64), Vec<u8>, BoxedMemory>),
+}
+
+impl JournalStore {
+    fn insert(&mut self, key: (TransactionId, u64), record: Vec<u8>) {
+        match self {
+            JournalStore::Heap(map) => map.insert(key, record),
+            JournalStore::Stable(map) => map.insert(key, record),
+        };
+    }
+
+    fn remove(&mut self, key: (TransactionId, u64)) {
+        match self {
+            JournalStore::Heap(map) => map.remove(&key),
+            JournalStore::Stable(map) => map.remove(&key),
+        };
+    }
+
+    fn keys(&self) -> Vec<(TransactionId, u64)> {
+        match self {
+            JournalStore::Heap(map) => map.keys().copied().collect(),
+            JournalStore::Stable(map) => map.keys().collect(),
+        }
+    }
+
+    fn records(&self, id: TransactionId) -> Vec<Vec<u8>> {
+        match self {
+            JournalStore::Heap(map) => map
+                .range((id, 1)..=(id, u64::MAX))
+                .map(|(_, record)| record.clone())
+                .collect(),
+            JournalStore::Stable(map) => map
+                .range((id, 1)..=(id, u64::MAX))
+                .map(|entry| entry.value())
+                .collect(),
+        }
+    }
+}
+
+/// Transactions begun and not yet committed or rolled back, with the journals of their staged changes.
+///
+/// The journals are kept on the heap unless a stable memory is provided.
+pub struct Transactions {
+    store: JournalStore,
+    next_id: TransactionId,
+    // the open transactions and the position of their next record
+    open: BTreeMap<TransactionId, u64>,
+}
+
+impl Default for Transactions {
+    fn default() -> Self {
+        Transactions {
+            store: JournalStore::Heap(BTreeMap::new()),
+            next_id: 0,
+            open: BTreeMap::new(),
+        }
+    }
+}
+
+impl Transactions {
+    pub fn new() -> Transactions {
+        Self::default()
+    }
+
+    // Load the journals from the memory and reopen their transactions, an empty memory is initialized.
+    pub fn with_memory(memory: Box<dyn Memory>) -> Transactions {
+        let store = JournalStore::Stable(StableBTreeMap::init(BoxedMemory(memory)));
+
+        let mut open = BTreeMap::new();
+        for (id, position) in store.keys() {
+            open.insert(id, position + 1);
+        }
+
+        Transactions {
+            store,
+            next_id: open.keys().last().map_or(0, |id| id + 1),
+            open,
+        }
+    }
+
+    pub fn begin(&mut self) -> TransactionId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.store.insert((id, 0), Vec::new());
+        self.open.insert(id, 1);
+        id
+    }
+
+    pub fn is_open(&self, id: TransactionId) -> bool {
+        self.open.contains_key(&id)
+    }
+
+    // Append a change to the journal of an open transaction.
+    pub fn stage(&mut self, id: TransactionId, entry: &JournalEntry) -> bool {
+        let Some(position) = self.open.get_mut(&id) else {
+            return false;
+        };
+
+        self.store.insert((id, *position), entry.encode());
+        *position += 1;
+
+        true
+    }
+
+    // Staged changes of a transaction in their order.
+    pub fn entries(&self, id: TransactionId) -> Vec<JournalEntry> {
+        self.store
+            .records(id)
+            .iter()
+            .filter_map(|record| decode_record(record))
+            .collect()
+    }
+
+    // Close a transaction and remove its journal.
+    pub fn close(&mut self, id: TransactionId) {
+        if let Some(next) = self.open.remove(&id) {
+            for position in 0..next {
+                self.store.remove((id, position));
+            }
+        }
+    }
+
+    pub fn open_ids(&self) -> Vec<TransactionId> {
+        self.open.keys().copied().collect()
+    }
+}
+
+#[cfg(feature = "transactions")]
+fn root_path_open(path: &str, oflags: wasi::Oflags) -> Result<OwnedFd, i32> {
+    root_path_open_with_rights(
+        path,
+        oflags,
+        wasi::RIGHTS_FD_READ | wasi::RIGHTS_FD_WRITE | wasi::RIGHTS_FD_FILESTAT_SET_SIZE,
+    )
+}
+
+#[cfg(feature = "transactions")]
+fn root_path_rename(from: &str, to: &str) -> Result<(), i32> {
+    let (from_dir, from_name) = OwnedFd::open_parent(from)?;
+    let (to_dir, to_name) = OwnedFd::open_parent(to)?;
+
+    errno_result(unsafe {
+        __ic_custom_path_rename(
+            from_dir.0 as i32,
+            from_name.as_ptr(),
+            from_name.len() as i32,
+            to_dir.0 as i32,
+            to_name.as_ptr(),
+            to_name.len() as i32,
+        )
+    })
+}
+
+#[cfg(feature = "transactions")]
+fn root_path_unlink(path: &str) -> Result<(), i32> {
+    let (dir, name) = OwnedFd::open_parent(path)?;
+
+    errno_result(unsafe {
+        __ic_custom_path_unlink_file(dir.0 as i32, name.as_ptr(), name.len() as i32)
+    })
+}
+
+#[cfg(feature = "transactions")]
+fn root_path_create_directory(path: &str) -> Result<(), i32> {
+    let (dir, name) = OwnedFd::open_parent(path)?;
+
+    errno_result(unsafe {
+        __ic_custom_path_create_directory(dir.0, name.as_ptr(), name.len() as i32)
+    })
+}
+
+#[cfg(feature = "transactions")]
+fn root_path_remove_directory(path: &str) -> Result<(), i32> {
+    let (dir, name) = OwnedFd::open_parent(path)?;
+
+    errno_result(unsafe {
+        __ic_custom_path_remove_directory(dir.0, name.as_ptr(), name.len() as i32)
+    })
+}
+
+#[cfg(feature = "transactions")]
+fn pread_all(fd: Fd, offset: FileSize, len: FileSize) -> Result<Vec<u8>, i32> {
+    let mut data = vec![0u8; len as usize];
+    let dst = [wasi::Iovec {
+        buf: data.as_mut_ptr(),
+        buf_len: data.len(),
+    }];
+    let mut read = 0;
+
+    errno_result(unsafe { __ic_custom_fd_pread(fd, dst.as_ptr(), 1, offset as i64, &mut read) })?;
+    data.truncate(read);
+
+    Ok(data)
+}
+
+#[cfg(feature = "transactions")]
+fn pwrite_all(fd: Fd, offset: FileSize, data: &[u8]) -> Result<(), i32> {
+    let src = [wasi::Ciovec {
+        buf: data.as_ptr(),
+        buf_len: data.len(),
+    }];
+    let mut written = 0;
+
+    errno_result(unsafe {
+        __ic_custom_fd_pwrite(fd, src.as_ptr(), 1, offset as i64, &mut written)
+    })?;
+
+    if written != data.len() {
+        return Err(wasi::ERRNO_IO.raw() as i32);
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "transactions")]
+fn stage_change(id: TransactionId, entry: JournalEntry) -> i32 {
+    if TRANSACTIONS.with_borrow_mut(|transactions| transactions.stage(id, &entry)) {
+        wasi::ERRNO_SUCCESS.raw() as i32
+    } else {
+        wasi::ERRNO_INVAL.raw() as i32
+    }
+}
+
+// Change applied by a commit and how it is reverted.
+#[cfg(feature = "transactions")]
+enum Undo {
+    Remove(String),
+    Restore {
+        path: String,
+        offset: FileSize,
+        data: Vec<u8>,
+        size: FileSize,
+    },
+    Rename {
+        from: String,
+        to: String,
+    },
+    CreateDirectory(String),
+}
+
+#[cfg(feature = "transactions")]
+fn apply_change(
+    id: TransactionId,
+    index: usize,
+    entry: &JournalEntry,
+    undo: &mut Vec<Undo>,
+    backups: &mut Vec<String>,
+) -> Result<(), i32> {
+    match entry {
+        JournalEntry::Write { path, offset, data } => {
+            let existing = root_path_filestat(path).ok();
+            let file = root_path_open(path, wasi::OFLAGS_CREAT)?;
+
+            undo.push(match existing {
+                Some(stat) => Undo::Restore {
+                    path: path.clone(),
+                    offset: *offset,
+                    data: pread_all(
+                        file.0,
+                        *offset,
+                        stat.size
+                            .saturating_sub(*offset)
+                            .min(data.len() as FileSize),
+                    )?,
+                    size: stat.size,
+                },
+                None => Undo::Remove(path.clone()),
+            });
+
+            pwrite_all(file.0, *offset, data)
+        }
+        JournalEntry::Rename { from, to } => {
+            match root_path_filestat(to) {
+                Ok(stat) if stat.filetype == wasi::FILETYPE_DIRECTORY => {
+                    // an empty directory can be replaced
+                    undo.push(Undo::CreateDirectory(to.clone()));
+                }
+                Ok(_) => {
+                    let backup = backup_path(id, index);
+                    root_path_rename(to, &backup)?;
+                    undo.push(Undo::Rename {
+                        from: backup.clone(),
+                        to: to.clone(),
+                    });
+                    backups.push(backup);
+                }
+                Err(_) => {}
+            }
+
+            root_path_rename(from, to)?;
+            undo.push(Undo::Rename {
+                from: to.clone(),
+                to: from.clone(),
+            });
+
+            Ok(())
+        }
+        JournalEntry::Delete { path } => {
+            if root_path_filestat(path)?.filetype == wasi::FILETYPE_DIRECTORY {
+                root_path_remove_directory(path)?;
+                undo.push(Undo::CreateDirectory(path.clone()));
+            } else {
+                // the file is kept until the commit completes
+                let backup = backup_path(id, index);
+                root_path_rename(path, &backup)?;
+                undo.push(Undo::Rename {
+                    from: backup.clone(),
+                    to: path.clone(),
+                });
+                backups.push(backup);
+            }
+
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "transactions")]
+fn revert_change(undo: Undo) -> Result<(), i32> {
+    match undo {
+        Undo::Remove(path) => root_path_unlink(&path),
+        Undo::Restore {
+            path,
+            offset,
+            data,
+            size,
+        } => {
+            let file = root_path_open(&path, 0)?;
+            pwrite_all(file.0, offset, &data)?;
+            errno_result(__ic_custom_fd_filestat_set_size(file.0, size as i64))
+        }
+        Undo::Rename { from, to } => root_path_rename(&from, &to),
+        Undo::CreateDirectory(path) => root_path_create_directory(&path),
+    }
+}
+
+// Create or remove the directory of the files kept during a commit in the file system root directly. The files are
+// moved in and out of it with `path_rename` like the other changes, so the hooks and the access rules apply to them.
+#[cfg(feature = "transactions")]
+fn backup_dir(create: bool) -> Result<(), i32> {
+    FS.with_borrow_mut(|fs| {
+        let root_fd = fs.root_fd();
+
+        if create {
+            match fs.mkdir(root_fd, BACKUP_DIR, FdStat::default(), ic_time()) {
+                Ok(()) | Err(Error::FileExists) => Ok(()),
+                Err(err) => Err(err),
+            }
+        } else {
+            fs.remove_dir(root_fd, BACKUP_DIR)
+        }
+    })
+    .map_err(into_errno)
+}
+
+/// Begin a transaction staging writes, renames and deletes in a journal, the staged changes are applied
+/// together by `commit_transaction`, e.g. in a later message after an inter-canister call, or discarded by `rollback_transaction`.
+/// The staged changes are not visible until the commit.
+///
+/// `init_with_polyfill_memories` keeps the journals in a stable memory of the polyfill memory index range outside of
+/// the file system, so the open transactions survive upgrades and are reloaded by the initialization, see
+/// `open_transactions`. The other initializations keep the journals on the heap.
+#[cfg(feature = "transactions")]
+pub fn begin_transaction() -> Result<TransactionId, i32> {
+    Ok(TRANSACTIONS.with_borrow_mut(|transactions| transactions.begin()))
+}
+
+/// Stage a write of the data at the offset of a file, the file is created if it does not exist at the commit.
+/// The paths of the transactions are relative to the file system root. Returns `ERRNO_INVAL` for an unknown transaction.
+#[cfg(feature = "transactions")]
+pub fn transaction_write(id: TransactionId, path: &str, offset: FileSize, data: &[u8]) -> i32 {
+    stage_change(
+        id,
+        JournalEntry::Write {
+            path: path.to_string(),
+            offset,
+            data: data.to_vec(),
+        },
+    )
+}
+
+/// Stage a rename of a file or a directory, an existing destination is replaced like with `path_rename`
+#[cfg(feature = "transactions")]
+pub fn transaction_rename(id: TransactionId, from: &str, to: &str) -> i32 {
+    stage_change(
+        id,
+        JournalEntry::Rename {
+            from: from.to_string(),
+            to: to.to_string(),
+        },
+    )
+}
+
+/// Stage a delete of a file or an empty directory
+#[cfg(feature = "transactions")]
+pub fn transaction_delete(id: TransactionId, path: &str) -> i32 {
+    stage_change(
+        id,
+        JournalEntry::Delete {
+            path: path.to_string(),
+        },
+    )
+}
+
+/// Apply the staged changes in their order within the current message and close the transaction.
+/// If a change fails, the changes already applied are reverted and its error is returned, so the files are either
+/// all changed or left as they were. The changes go through the WASI functions, so the hooks and the access rules apply.
+/// If a change cannot be reverted, the call traps and the IC discards all the changes of the message.
+#[cfg(feature = "transactions")]
+pub fn commit_transaction(id: TransactionId) -> i32 {
+    if !TRANSACTIONS.with_borrow(|transactions| transactions.is_open(id)) {
+        return wasi::ERRNO_INVAL.raw() as i32;
+    }
+
+    let entries = TRANSACTIONS.with_borrow(|transactions| transactions.entries(id));
+
+    if let Err(errno) = backup_dir(true) {
+        return errno;
+    }
+
+    let mut undo = Vec::new();
+    let mut backups = Vec::new();
+
+    let result = entries
+        .iter()
+        .enumerate()
+        .try_for_each(|(index, entry)| apply_change(id, index, entry, &mut undo, &mut backups));
+
+    match result {
+        Ok(()) => {
+            for backup in backups {
+                let _ = root_path_unlink(&backup);
+            }
+        }
+        Err(_) => {
+            for step in undo.into_iter().rev() {
+                if let Err(errno) = revert_change(step) {
+                    // trapping discards all the changes of the message, including the ones already applied
+                    panic!("WASI transaction {id} could not be reverted: errno {errno}");
+                }
+            }
+        }
+    }
+
+    let _ = backup_dir(false);
+    TRANSACTIONS.with_borrow_mut(|transactions| transactions.close(id));
+
+    match result {
+        Ok(()) => wasi::ERRNO_SUCCESS.raw() as i32,
+        Err(errno) => errno,
+    }
+}
+
+/// Discard the staged changes and close the transaction
+#[cfg(feature = "transactions")]
+pub fn rollback_transaction(id: TransactionId) -> i32 {
+    if !TRANSACTIONS.with_borrow(|transactions| transactions.is_open(id)) {
+        return wasi::ERRNO_INVAL.raw() as i32;
+    }
+
+    TRANSACTIONS.with_borrow_mut(|transactions| transactions.close(id));
+
+    wasi::ERRNO_SUCCESS.raw() as i32
+}
+
+/// Transactions not yet committed or rolled back, including the ones reloaded from their journals by `init`
+#[cfg(feature = "transactions")]
+pub fn open_transactions() -> Vec<TransactionId> {
+    TRANSACTIONS.with_borrow(|transactions| transactions.open_ids())
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_stable_structures::DefaultMemoryImpl;
+
+    use super::*;
+
+    #[test]
+    fn journal_records() {
+        let entries = vec![
+            JournalEntry::Write {
+                path: "dir/a.txt".to_string(),
+                offset: 7,
+                data: b"data".to_vec(),
+            },
+            JournalEntry::Rename {
+                from: "a".to_string(),
+                to: "b".to_string(),
+            },
+            JournalEntry::Delete {
+                path: "c".to_string(),
+            },
+        ];
+
+        for entry in &entries {
+            assert_eq!(decode_record(&entry.encode()).as_ref(), Some(entry));
+        }
+
+        // a record cut short is not decoded
+        assert_eq!(decode_record(&entries[0].encode()[..10]), None);
+
+        let memory = DefaultMemoryImpl::default();
+        let mut transactions = Transactions::with_memory(Box::new(memory.clone()));
+        let discarded = transactions.begin();
+        let id = transactions.begin();
+        assert!(transactions.stage(id, &entries[0]));
+        assert!(transactions.stage(id, &entries[1]));
+        transactions.close(discarded);
+        assert!(!transactions.stage(discarded, &entries[2]));
+
+        // the open transactions are reloaded from the memory
+        let mut transactions = Transactions::with_memory(Box::new(memory));
+        assert_eq!(transactions.open_ids(), vec![id]);
+        assert!(transactions.stage(id, &entries[2]));
+        assert_eq!(transactions.entries(id), entries);
+        assert_eq!(transactions.begin(), id + 1);
+
+        transactions.close(id);
+        assert_eq!(transactions.open_ids(), vec![id + 1]);
+        assert!(transactions.entries(id).is_empty());
+    }
+}
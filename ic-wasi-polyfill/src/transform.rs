@@ -0,0 +1,1373 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use ic_stable_structures::{Memory, StableBTreeMap};
+use stable_fs::error::Error;
+use stable_fs::fs::{ChunkSize, ChunkType};
+use stable_fs::storage::types::{
+    DirEntry, DirEntryIndex, FileName, FileSize, Metadata, MountedFileSizePolicy, Node,
+    MAX_FILE_SIZE,
+};
+use stable_fs::storage::Storage;
+
+use crate::encryption::Keyring;
+use crate::job::{Job, JobStatus};
+use crate::xattr::BoxedMemory;
+#[cfg(feature = "transforms")]
+use stable_fs::{
+    fs::{Fd, FileSystem},
+    storage::types::FileType,
+};
+
+#[cfg(feature = "transforms")]
+use crate::{wasi_helpers::into_errno, FS, TRANSFORMS};
+
+#[cfg(all(
+    feature = "cache",
+    any(
+        feature = "compression",
+        feature = "encryption",
+        feature = "sparse_files"
+    )
+))]
+use crate::CACHE;
+
+#[cfg(any(feature = "compression", feature = "sparse_files"))]
+use crate::wasi;
+
+#[cfg(feature = "compression")]
+use crate::root_node;
+
+#[cfg(feature = "sparse_files")]
+use crate::{
+    content_hash::hash_written, file_changed, ic_time, is_query_write, notify_watches,
+    watch::WatchEventKind,
+};
+
+/// Logical size of the blocks the file data is split into before compressing and encrypting.
+pub const BLOCK_SIZE: usize = 16384;
+
+/// The file data is compressed.
+pub const MODE_COMPRESSED: u8 = 1;
+
+/// The file data is encrypted, see `Keyring`.
+pub const MODE_ENCRYPTED: u8 = 2;
+
+/// The file data is kept in blocks to release the ranges of zeros, see `punch`.
+pub const MODE_SPARSE: u8 = 4;
+
+// The block is stored as is, used when compressing does not make it smaller.
+const BLOCK_RAW: u8 = 0;
+// The block is compressed with deflate.
+#[cfg(feature = "compression")]
+const BLOCK_DEFLATE: u8 = 1;
+// The block is encrypted after compressing, combined with one of the flags above.
+const BLOCK_ENCRYPTED: u8 = 2;
+
+#[cfg(feature = "compression")]
+const COMPRESSION_LEVEL: u8 = 6;
+
+// The modes of the transformed nodes are kept under the root node, which is always a directory.
+const MODE_NODE: Node = 0;
+
+// The counter of the encryption nonces is kept at the unused mode entry of the root node,
+// it is stored with the blocks so that a nonce is not used again after an upgrade.
+const NONCE_KEY: (Node, u64) = (MODE_NODE, 0);
+
+/// Check if a path matches a pattern, `*` matches any sequence of characters and `?` a single character.
+pub fn matches_pattern(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let path: Vec<char> = path.chars().collect();
+
+    let (mut p, mut s) = (0, 0);
+    // position of the last `*` and the path position it was matched to
+    let mut backtrack = None;
+
+    while s < path.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == path[s]) {
+            p += 1;
+            s += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, s));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            s = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(feature = "compression")]
+pub fn encode_block(data: &[u8]) -> Vec<u8> {
+    let compressed = miniz_oxide::deflate::compress_to_vec(data, COMPRESSION_LEVEL);
+
+    let mut block = Vec::with_capacity(1 + compressed.len().min(data.len()));
+
+    if compressed.len() < data.len() {
+        block.push(BLOCK_DEFLATE);
+        block.extend_from_slice(&compressed);
+    } else {
+        block.push(BLOCK_RAW);
+        block.extend_from_slice(data);
+    }
+
+    block
+}
+
+// Without the `compression` feature the blocks are stored as they are.
+#[cfg(not(feature = "compression"))]
+pub fn encode_block(data: &[u8]) -> Vec<u8> {
+    [&[BLOCK_RAW], data].concat()
+}
+
+// The compressed blocks are not readable without the `compression` feature.
+pub fn decode_block(block: &[u8]) -> Result<Vec<u8>, Error> {
+    match block.split_first() {
+        Some((&BLOCK_RAW, data)) => Ok(data.to_vec()),
+        #[cfg(feature = "compression")]
+        Some((&BLOCK_DEFLATE, data)) => {
+            miniz_oxide::inflate::decompress_to_vec_with_limit(data, BLOCK_SIZE)
+                .map_err(|_| Error::IOError)
+        }
+        _ => Err(Error::IOError),
+    }
+}
+
+enum BlockStore {
+    Heap(BTreeMap<(Node, u64), Vec<u8>>),
+    Stable(StableBTreeMap<(Node, u64), Vec<u8>, BoxedMemory>),
+}
+
+impl BlockStore {
+    fn get(&self, key: (Node, u64)) -> Option<Vec<u8>> {
+        match self {
+            BlockStore::Heap(map) => map.get(&key).cloned(),
+            BlockStore::Stable(map) => map.get(&key),
+        }
+    }
+
+    fn insert(&mut self, key: (Node, u64), value: Vec<u8>) -> Option<Vec<u8>> {
+        match self {
+            BlockStore::Heap(map) => map.insert(key, value),
+            BlockStore::Stable(map) => map.insert(key, value),
+        }
+    }
+
+    fn remove(&mut self, key: (Node, u64)) -> Option<Vec<u8>> {
+        match self {
+            BlockStore::Heap(map) => map.remove(&key),
+            BlockStore::Stable(map) => map.remove(&key),
+        }
+    }
+
+    // Index of the first entry of a node starting from the given index, the value is not read.
+    fn next_index(&self, node: Node, from: u64) -> Option<u64> {
+        match self {
+            BlockStore::Heap(map) => map
+                .range((node, from)..=(node, u64::MAX))
+                .next()
+                .map(|((_, index), _)| *index),
+            BlockStore::Stable(map) => map
+                .keys_range((node, from)..=(node, u64::MAX))
+                .next()
+                .map(|(_, index)| index),
+        }
+    }
+
+    // Entries of a node starting from the given index.
+    fn entries(&self, node: Node, from: u64) -> Vec<(u64, Vec<u8>)> {
+        match self {
+            BlockStore::Heap(map) => map
+                .range((node, from)..=(node, u64::MAX))
+                .map(|((_, index), value)| (*index, value.clone()))
+                .collect(),
+            BlockStore::Stable(map) => map
+                .range((node, from)..=(node, u64::MAX))
+                .map(|entry| (entry.key().1, entry.value()))
+                .collect(),
+        }
+    }
+}
+
+// Data authenticated with an encrypted block, so that the blocks cannot be swapped.
+fn block_associated_data(node: Node, index: u64, flags: u8) -> [u8; 17] {
+    let mut data = [0u8; 17];
+    data[..8].copy_from_slice(&node.to_be_bytes());
+    data[8..16].copy_from_slice(&index.to_be_bytes());
+    data[16] = flags;
+    data
+}
+
+// Mode of a node with the stored size and the number of its blocks, kept in the mode entry of the node.
+#[derive(Clone, Copy, Default)]
+struct NodeBlocks {
+    mode: u8,
+    size: FileSize,
+    count: u64,
+}
+
+impl NodeBlocks {
+    fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = vec![self.mode];
+        bytes.extend_from_slice(&self.size.to_le_bytes());
+        bytes.extend_from_slice(&self.count.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> NodeBlocks {
+        let word = |at: usize| {
+            bytes
+                .get(at..at + 8)
+                .and_then(|word| word.try_into().ok())
+                .map(u64::from_le_bytes)
+                .unwrap_or(0)
+        };
+
+        NodeBlocks {
+            mode: bytes.first().copied().unwrap_or(0),
+            size: word(1),
+            count: word(9),
+        }
+    }
+}
+
+/// Data blocks of the transformed files and the settings choosing the files to transform.
+///
+/// The blocks are kept on the heap unless a stable memory is provided.
+pub struct Transforms {
+    store: BlockStore,
+    nodes: BTreeMap<Node, NodeBlocks>,
+    patterns: Vec<String>,
+    keyring: Keyring,
+    stable_storage: bool,
+}
+
+impl Default for Transforms {
+    fn default() -> Self {
+        Transforms {
+            store: BlockStore::Heap(BTreeMap::new()),
+            nodes: BTreeMap::new(),
+            patterns: Vec::new(),
+            keyring: Keyring::new(),
+            stable_storage: false,
+        }
+    }
+}
+
+impl Transforms {
+    pub fn new() -> Transforms {
+        Self::default()
+    }
+
+    // Load the blocks from the memory, an empty memory is initialized. The patterns and the keys are kept.
+    pub fn load(&mut self, memory: Box<dyn Memory>) {
+        let store = BlockStore::Stable(StableBTreeMap::init(BoxedMemory(memory)));
+
+        self.nodes = store
+            .entries(MODE_NODE, 1)
+            .into_iter()
+            .map(|(node, bytes)| (node, NodeBlocks::from_bytes(&bytes)))
+            .collect();
+        self.store = store;
+    }
+
+    // Note that the file system is kept in stable memory, the blocks on the heap would not persist with it.
+    pub fn set_stable_storage(&mut self, stable_storage: bool) {
+        self.stable_storage = stable_storage;
+    }
+
+    // Check if the blocks would be lost on upgrade while the file system persists.
+    pub fn is_volatile(&self) -> bool {
+        self.stable_storage && matches!(self.store, BlockStore::Heap(_))
+    }
+
+    pub fn mode(&self, node: Node) -> u8 {
+        self.nodes.get(&node).map(|blocks| blocks.mode).unwrap_or(0)
+    }
+
+    // Change the mode of a node, the caller is responsible for moving the existing data.
+    pub fn set_mode(&mut self, node: Node, mode: u8) {
+        self.update_node(node, |blocks| blocks.mode = mode);
+    }
+
+    fn update_node(&mut self, node: Node, f: impl FnOnce(&mut NodeBlocks)) {
+        let mut blocks = self.nodes.get(&node).copied().unwrap_or_default();
+        f(&mut blocks);
+
+        if blocks.mode == 0 && blocks.count == 0 {
+            self.nodes.remove(&node);
+            self.store.remove((MODE_NODE, node));
+        } else {
+            self.nodes.insert(node, blocks);
+            self.store.insert((MODE_NODE, node), blocks.to_bytes());
+        }
+    }
+
+    pub fn add_pattern(&mut self, pattern: &str) {
+        if !self.patterns.iter().any(|p| p == pattern) {
+            self.patterns.push(pattern.to_string());
+        }
+    }
+
+    pub fn clear_patterns(&mut self) {
+        self.patterns.clear();
+    }
+
+    pub fn has_patterns(&self) -> bool {
+        !self.patterns.is_empty()
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        self.patterns
+            .iter()
+            .any(|pattern| matches_pattern(pattern, path))
+    }
+
+    pub fn keyring(&mut self) -> &mut Keyring {
+        &mut self.keyring
+    }
+
+    // Check if the new files can be transformed, either by the patterns or by the encryption.
+    // The new files are not transformed if their blocks would not persist.
+    pub fn transforms_new_files(&self) -> bool {
+        !self.is_volatile() && (self.has_patterns() || self.keyring.current_key().is_some())
+    }
+
+    // Mode of a new file: compressed if the path matches a pattern and encrypted if there is an encryption key.
+    pub fn new_file_mode(&self, path: Option<&str>) -> u8 {
+        let mut mode = 0;
+
+        if path.is_some_and(|path| self.matches(path)) {
+            mode |= MODE_COMPRESSED;
+        }
+
+        if self.keyring.current_key().is_some() {
+            mode |= MODE_ENCRYPTED;
+        }
+
+        mode
+    }
+
+    // First node with the encryption mode starting from the given node.
+    pub fn next_encrypted_node(&self, from: Node) -> Option<Node> {
+        self.nodes
+            .range(from..)
+            .find(|(_, blocks)| blocks.mode & MODE_ENCRYPTED != 0)
+            .map(|(node, _)| *node)
+    }
+
+    // Encrypt a block again if it was encrypted with another key than the current one.
+    pub fn rotate_block(&mut self, node: Node, index: u64) -> Result<bool, Error> {
+        let current = self.keyring.current_key().ok_or(Error::IOError)?;
+
+        let rotated = match self.store.get((node, index)) {
+            Some(block) => match block.split_first() {
+                Some((flags, sealed)) if flags & BLOCK_ENCRYPTED != 0 => {
+                    Keyring::sealed_key(sealed) != Some(current)
+                }
+                _ => true,
+            },
+            None => false,
+        };
+
+        if rotated {
+            self.reencode_block(node, index)?;
+        }
+
+        Ok(rotated)
+    }
+
+    // Stored size of a node's blocks.
+    pub fn physical_size(&self, node: Node) -> FileSize {
+        self.nodes.get(&node).map(|blocks| blocks.size).unwrap_or(0)
+    }
+
+    // Number of stored blocks of a node, the missing blocks are holes.
+    pub fn allocated_blocks(&self, node: Node) -> u64 {
+        self.nodes
+            .get(&node)
+            .map(|blocks| blocks.count)
+            .unwrap_or(0)
+    }
+
+    // Index of the first stored block of a node starting from the given index.
+    pub fn next_block(&self, node: Node, from: u64) -> Option<u64> {
+        self.store.next_index(node, from)
+    }
+
+    // Store a block of a file whose data is moved here in the given mode, before the mode of the node is set.
+    pub fn import_block(
+        &mut self,
+        node: Node,
+        index: u64,
+        mode: u8,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        if data.iter().all(|b| *b == 0) {
+            self.remove_block(node, index);
+        } else {
+            let block = self.encode(node, index, mode, data)?;
+            self.store_block(node, index, block);
+        }
+
+        Ok(())
+    }
+
+    // Store a block again in the current mode of the node, the blocks can be read in any mode.
+    pub fn reencode_block(&mut self, node: Node, index: u64) -> Result<(), Error> {
+        let data = self.read_block(node, index)?;
+        self.write_block(node, index, &data)
+    }
+
+    // Remove the blocks of a node, its mode is kept.
+    pub fn remove_blocks(&mut self, node: Node) {
+        while let Some(index) = self.next_block(node, 0) {
+            self.remove_block(node, index);
+        }
+    }
+
+    // Release the blocks within a range of a node, the blocks at the ends of the range are cleared.
+    pub fn punch(&mut self, node: Node, offset: FileSize, end: FileSize) -> Result<(), Error> {
+        let block_size = BLOCK_SIZE as u64;
+
+        for index in offset / block_size..end.div_ceil(block_size) {
+            let start = index * block_size;
+            let from = offset.max(start) - start;
+            let to = end.min(start + block_size) - start;
+
+            if from == 0 && to == block_size {
+                self.remove_block(node, index);
+                continue;
+            }
+
+            let mut data = self.read_block(node, index)?;
+
+            if (from as usize) < data.len() {
+                let to = (to as usize).min(data.len());
+                data[from as usize..to].fill(0);
+                self.write_block(node, index, &data)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn next_nonce(&mut self) -> u64 {
+        let nonce = self
+            .store
+            .get(NONCE_KEY)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(u64::from_le_bytes)
+            .unwrap_or(0);
+
+        self.store
+            .insert(NONCE_KEY, (nonce + 1).to_le_bytes().to_vec());
+
+        nonce
+    }
+
+    fn encode(&mut self, node: Node, index: u64, mode: u8, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut block = if mode & MODE_COMPRESSED != 0 {
+            encode_block(data)
+        } else {
+            [&[BLOCK_RAW], data].concat()
+        };
+
+        if mode & MODE_ENCRYPTED != 0 {
+            // the nonce counter would start over with the file system still holding the encrypted data
+            if self.is_volatile() {
+                return Err(Error::NotSupportedOrOperationNotSupportedOnSocket);
+            }
+
+            let flags = block[0] | BLOCK_ENCRYPTED;
+            let nonce = self.next_nonce();
+            let associated = block_associated_data(node, index, flags);
+            let sealed = self.keyring.seal(nonce, &associated, &block[1..])?;
+
+            block = [&[flags], &sealed[..]].concat();
+        }
+
+        Ok(block)
+    }
+
+    fn decode(&self, node: Node, index: u64, block: &[u8]) -> Result<Vec<u8>, Error> {
+        match block.split_first() {
+            Some((&flags, sealed)) if flags & BLOCK_ENCRYPTED != 0 => {
+                let associated = block_associated_data(node, index, flags);
+                let payload = self.keyring.open(&associated, sealed)?;
+
+                decode_block(&[&[flags & !BLOCK_ENCRYPTED], &payload[..]].concat())
+            }
+            _ => decode_block(block),
+        }
+    }
+
+    // Data of a block, the missing blocks are empty.
+    pub fn read_block(&self, node: Node, index: u64) -> Result<Vec<u8>, Error> {
+        match self.store.get((node, index)) {
+            Some(block) => self.decode(node, index, &block),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn write_block(&mut self, node: Node, index: u64, data: &[u8]) -> Result<(), Error> {
+        // missing blocks are read as zeros
+        self.import_block(node, index, self.mode(node), data)
+    }
+
+    fn store_block(&mut self, node: Node, index: u64, block: Vec<u8>) {
+        let size = block.len() as FileSize;
+        let old = self.store.insert((node, index), block);
+
+        self.update_node(node, |blocks| {
+            blocks.size += size;
+            match old {
+                Some(old) => blocks.size -= old.len() as FileSize,
+                None => blocks.count += 1,
+            }
+        });
+    }
+
+    fn remove_block(&mut self, node: Node, index: u64) {
+        if let Some(old) = self.store.remove((node, index)) {
+            self.update_node(node, |blocks| {
+                blocks.size -= old.len() as FileSize;
+                blocks.count -= 1;
+            });
+        }
+    }
+
+    // Remove the data above the new size.
+    fn truncate(&mut self, node: Node, size: FileSize) -> Result<(), Error> {
+        let first = size.div_ceil(BLOCK_SIZE as u64);
+
+        while let Some(index) = self.next_block(node, first) {
+            self.remove_block(node, index);
+        }
+
+        let tail = (size % BLOCK_SIZE as u64) as usize;
+
+        if tail > 0 {
+            let index = size / BLOCK_SIZE as u64;
+            let mut data = self.read_block(node, index)?;
+
+            if data.len() > tail {
+                data.truncate(tail);
+                self.write_block(node, index, &data)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove_node(&mut self, node: Node) {
+        self.remove_blocks(node);
+        self.set_mode(node, 0);
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RotationReport {
+    /// Number of files with blocks encrypted again
+    pub files: u64,
+    /// Number of blocks encrypted again
+    pub blocks: u64,
+}
+
+pub type RotationStatus = JobStatus<RotationReport>;
+
+/// Key rotation walking the encrypted nodes in order and encrypting again their blocks sealed with another key than
+/// the one the rotation started with, one block per step.
+///
+/// The rotation keeps its position as a node and a block index, the blocks written between the calls already use the
+/// current key and the files encrypted between the calls are visited if they come after the position.
+pub struct Rotation {
+    key_id: u32,
+    node: Node,
+    index: u64,
+    // the current node has a block encrypted again
+    rotated: bool,
+    done: bool,
+    report: RotationReport,
+}
+
+impl Rotation {
+    pub fn new(key_id: u32) -> Rotation {
+        Rotation {
+            key_id,
+            node: 0,
+            index: 0,
+            rotated: false,
+            done: false,
+            report: RotationReport::default(),
+        }
+    }
+
+    // The key the blocks are encrypted with.
+    pub fn key_id(&self) -> u32 {
+        self.key_id
+    }
+
+    // Encrypt the next block or move on to the next encrypted node.
+    pub fn step(&mut self, transforms: &mut Transforms) -> Result<(), Error> {
+        let Some(node) = transforms.next_encrypted_node(self.node) else {
+            self.done = true;
+            return Ok(());
+        };
+
+        if node != self.node {
+            self.node = node;
+            self.index = 0;
+            self.rotated = false;
+        }
+
+        match transforms.next_block(node, self.index) {
+            Some(index) => {
+                if transforms.rotate_block(node, index)? {
+                    self.report.blocks += 1;
+
+                    if !self.rotated {
+                        self.rotated = true;
+                        self.report.files += 1;
+                    }
+                }
+
+                self.index = index + 1;
+            }
+            None => {
+                self.node = node + 1;
+                self.index = 0;
+                self.rotated = false;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Job for Rotation {
+    type Report = RotationReport;
+
+    fn is_done(&self) -> bool {
+        self.done
+    }
+
+    fn into_report(self) -> RotationReport {
+        self.report
+    }
+}
+
+/// Storage keeping the data of the transformed files in blocks outside of the wrapped storage.
+///
+/// The metadata, directories, mounted files and the data of the other files stay in the wrapped storage.
+pub struct TransformStorage {
+    inner: Box<dyn Storage>,
+    transforms: Rc<RefCell<Transforms>>,
+}
+
+impl TransformStorage {
+    pub fn new(inner: Box<dyn Storage>, transforms: Rc<RefCell<Transforms>>) -> TransformStorage {
+        TransformStorage { inner, transforms }
+    }
+
+    fn is_transformed(&self, node: Node) -> bool {
+        self.transforms.borrow().mode(node) != 0 && !self.inner.is_mounted(node)
+    }
+}
+
+impl Storage for TransformStorage {
+    fn root_node(&self) -> Node {
+        self.inner.root_node()
+    }
+
+    fn get_version(&self) -> u32 {
+        self.inner.get_version()
+    }
+
+    fn new_node(&mut self) -> Node {
+        self.inner.new_node()
+    }
+
+    fn mount_node(
+        &mut self,
+        node: Node,
+        memory: Box<dyn Memory>,
+        mount_policy: MountedFileSizePolicy,
+    ) -> Result<(), Error> {
+        self.inner.mount_node(node, memory, mount_policy)
+    }
+
+    fn unmount_node(&mut self, node: Node) -> Result<Box<dyn Memory>, Error> {
+        self.inner.unmount_node(node)
+    }
+
+    fn is_mounted(&self, node: Node) -> bool {
+        self.inner.is_mounted(node)
+    }
+
+    fn get_mounted_memory(&self, node: Node) -> Option<&dyn Memory> {
+        self.inner.get_mounted_memory(node)
+    }
+
+    fn init_mounted_memory(&mut self, node: Node) -> Result<(), Error> {
+        self.inner.init_mounted_memory(node)
+    }
+
+    fn store_mounted_memory(&mut self, node: Node) -> Result<(), Error> {
+        self.inner.store_mounted_memory(node)
+    }
+
+    fn get_metadata(&self, node: Node) -> Result<Metadata, Error> {
+        self.inner.get_metadata(node)
+    }
+
+    fn put_metadata(&mut self, node: Node, metadata: &Metadata) -> Result<(), Error> {
+        if self.is_transformed(node) {
+            let old_size = self.inner.get_metadata(node)?.size;
+
+            if metadata.size < old_size {
+                self.transforms.borrow_mut().truncate(node, metadata.size)?;
+            }
+        }
+
+        self.inner.put_metadata(node, metadata)
+    }
+
+    fn get_direntry(&self, node: Node, index: DirEntryIndex) -> Result<DirEntry, Error> {
+        self.inner.get_direntry(node, index)
+    }
+
+    fn get_direntry_index_by_name(&self, el: &(Node, FileName)) -> Option<DirEntryIndex> {
+        self.inner.get_direntry_index_by_name(el)
+    }
+
+    fn with_direntries(
+        &self,
+        node: Node,
+        initial_index: Option<DirEntryIndex>,
+        f: &mut dyn FnMut(&DirEntryIndex, &DirEntry) -> bool,
+    ) {
+        self.inner.with_direntries(node, initial_index, f)
+    }
+
+    fn new_direntry_index(&self, node: Node) -> DirEntryIndex {
+        self.inner.new_direntry_index(node)
+    }
+
+    fn put_direntry(&mut self, node: Node, index: DirEntryIndex, entry: DirEntry) {
+        self.inner.put_direntry(node, index, entry)
+    }
+
+    fn rm_direntry(&mut self, node: Node, index: DirEntryIndex) {
+        self.inner.rm_direntry(node, index)
+    }
+
+    fn read(
+        &mut self,
+        node: Node,
+        read_offset: FileSize,
+        buf: &mut [u8],
+    ) -> Result<FileSize, Error> {
+        if !self.is_transformed(node) {
+            return self.inner.read(node, read_offset, buf);
+        }
+
+        let metadata = self.inner.get_metadata(node)?;
+        let max_size = metadata.maximum_size_allowed.unwrap_or(MAX_FILE_SIZE);
+        let file_size = metadata.size.min(max_size);
+
+        if read_offset >= file_size {
+            return Ok(0);
+        }
+
+        let size = (file_size - read_offset).min(buf.len() as FileSize) as usize;
+        let transforms = self.transforms.borrow();
+
+        let mut read = 0;
+        while read < size {
+            let offset = read_offset + read as FileSize;
+            let index = offset / BLOCK_SIZE as u64;
+            let start = (offset % BLOCK_SIZE as u64) as usize;
+            let len = (BLOCK_SIZE - start).min(size - read);
+
+            let data = transforms.read_block(node, index)?;
+            let dst = &mut buf[read..read + len];
+
+            // the data missing in the block is zeros
+            let available = data.len().saturating_sub(start).min(len);
+            dst[..available].copy_from_slice(&data[start..start + available]);
+            dst[available..].fill(0);
+
+            read += len;
+        }
+
+        Ok(size as FileSize)
+    }
+
+    fn write(&mut self, node: Node, offset: FileSize, buf: &[u8]) -> Result<FileSize, Error> {
+        if !self.is_transformed(node) {
+            return self.inner.write(node, offset, buf);
+        }
+
+        let mut metadata = self.inner.get_metadata(node)?;
+
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let max_size = metadata.maximum_size_allowed.unwrap_or(MAX_FILE_SIZE);
+        let end = offset + buf.len() as FileSize;
+
+        if end > max_size {
+            return Err(Error::FileTooLarge);
+        }
+
+        let mut written = 0;
+        while written < buf.len() {
+            let position = offset + written as FileSize;
+            let index = position / BLOCK_SIZE as u64;
+            let start = (position % BLOCK_SIZE as u64) as usize;
+            let len = (BLOCK_SIZE - start).min(buf.len() - written);
+
+            let mut data = self.transforms.borrow().read_block(node, index)?;
+            if data.len() < start + len {
+                data.resize(start + len, 0);
+            }
+            data[start..start + len].copy_from_slice(&buf[written..written + len]);
+
+            self.transforms
+                .borrow_mut()
+                .write_block(node, index, &data)?;
+
+            written += len;
+        }
+
+        if end > metadata.size {
+            metadata.size = end;
+            self.inner.put_metadata(node, &metadata)?;
+        }
+
+        Ok(buf.len() as FileSize)
+    }
+
+    fn resize_file(&mut self, node: Node, new_size: FileSize) -> Result<(), Error> {
+        let mut metadata = self.inner.get_metadata(node)?;
+
+        metadata.size = new_size;
+
+        self.put_metadata(node, &metadata)
+    }
+
+    fn rm_file(&mut self, node: Node) -> Result<(), Error> {
+        self.inner.rm_file(node)?;
+
+        if self.transforms.borrow().mode(node) != 0 {
+            self.transforms.borrow_mut().remove_node(node);
+        }
+
+        Ok(())
+    }
+
+    fn set_chunk_size(&mut self, chunk_size: ChunkSize) -> Result<(), Error> {
+        self.inner.set_chunk_size(chunk_size)
+    }
+
+    fn chunk_size(&self) -> usize {
+        self.inner.chunk_size()
+    }
+
+    fn set_chunk_type(&mut self, chunk_type: ChunkType) {
+        self.inner.set_chunk_type(chunk_type)
+    }
+
+    fn chunk_type(&self) -> ChunkType {
+        self.inner.chunk_type()
+    }
+
+    fn flush(&mut self, node: Node) {
+        self.inner.flush(node)
+    }
+}
+
+#[cfg(feature = "transforms")]
+pub(crate) fn with_transforms<R>(f: impl FnOnce(&mut Transforms) -> R) -> R {
+    TRANSFORMS.with(|transforms| f(&mut transforms.borrow_mut()))
+}
+
+// Mark a newly created file for compression if its path matches one of the patterns
+// and for encryption if there is an encryption key.
+#[cfg(feature = "transforms")]
+pub(crate) fn apply_new_file_transforms(fs: &FileSystem, fd: Fd, path: Option<&str>) {
+    with_transforms(|transforms| {
+        let mode = transforms.new_file_mode(path);
+        if mode == 0 {
+            return;
+        }
+
+        if let Ok(metadata) = fs.metadata(fd) {
+            if metadata.file_type == FileType::RegularFile
+                && metadata.size == 0
+                && transforms.mode(metadata.node) == 0
+            {
+                transforms.set_mode(metadata.node, mode);
+            }
+        }
+    })
+}
+
+/// Keep the data of the compressed and encrypted files in the provided stable memory, the data already stored in the memory is loaded.
+/// `init_with_polyfill_memories` keeps the data in a memory of the polyfill memory index range. Otherwise, without
+/// calling this function, the files of a stable file system are not compressed or encrypted, as the data would not persist.
+///
+/// # Parameters
+/// - `memory`: A memory used for the compressed and encrypted files only, it should be provided again after an upgrade
+#[cfg(feature = "transforms")]
+pub fn init_transforms_with_memory<M: Memory + 'static>(memory: M) {
+    with_transforms(|transforms| transforms.load(Box::new(memory)))
+}
+
+// Change how the file data is stored, the existing data is converted block by block.
+// The data stays in its old form until the conversion is complete, a failed conversion keeps the file as it was.
+#[cfg(any(feature = "compression", feature = "encryption"))]
+pub(crate) fn transform_file(path: &str, flag: u8, enabled: bool) -> Result<(), Error> {
+    FS.with_borrow_mut(|fs| {
+        let root_fd = fs.root_fd();
+        let metadata = fs.open_metadata(root_fd, path)?;
+
+        if metadata.file_type == FileType::Directory {
+            return Err(Error::IsDirectory);
+        }
+
+        if fs.storage.is_mounted(metadata.node) {
+            return Err(Error::NotSupportedOrOperationNotSupportedOnSocket);
+        }
+
+        let old_mode = with_transforms(|transforms| transforms.mode(metadata.node));
+        let mode = if enabled {
+            old_mode | flag
+        } else {
+            old_mode & !flag
+        };
+
+        if mode == old_mode {
+            return Ok(());
+        }
+
+        // the blocks on the heap would be lost on upgrade
+        if old_mode == 0 && with_transforms(|transforms| transforms.is_volatile()) {
+            return Err(Error::NotSupportedOrOperationNotSupportedOnSocket);
+        }
+
+        // fail before the data is converted, the data cannot be written without a key
+        if mode & MODE_ENCRYPTED != 0
+            && with_transforms(|transforms| transforms.keyring().current_key().is_none())
+        {
+            return Err(Error::IOError);
+        }
+
+        // the cached writes are stored before the data moves
+        fs.storage.flush(metadata.node);
+
+        let node = metadata.node;
+        let size = metadata.size;
+
+        let result = if old_mode == 0 {
+            import_file(fs, node, size, mode)
+        } else if mode == 0 {
+            export_file(fs, node, size, old_mode)
+        } else {
+            with_transforms(|transforms| {
+                transforms.set_mode(node, mode);
+
+                let mut next = transforms.next_block(node, 0);
+                while let Some(index) = next {
+                    if let Err(er) = transforms.reencode_block(node, index) {
+                        // the converted blocks stay readable, the new writes use the old mode again
+                        transforms.set_mode(node, old_mode);
+                        return Err(er);
+                    }
+                    next = transforms.next_block(node, index + 1);
+                }
+
+                Ok(())
+            })
+        };
+
+        #[cfg(feature = "cache")]
+        CACHE.with(|cache| cache.borrow_mut().invalidate(node, 0, FileSize::MAX));
+
+        result
+    })
+}
+
+// Read the data of a node from the storage, short reads are continued until the buffer is full or the file ends.
+#[cfg(any(
+    feature = "compression",
+    feature = "encryption",
+    feature = "sparse_files"
+))]
+pub(crate) fn read_node(
+    fs: &mut FileSystem,
+    node: Node,
+    offset: FileSize,
+    buf: &mut [u8],
+) -> Result<usize, Error> {
+    let mut read = 0;
+
+    while read < buf.len() {
+        let len = fs
+            .storage
+            .read(node, offset + read as FileSize, &mut buf[read..])? as usize;
+        if len == 0 {
+            break;
+        }
+        read += len;
+    }
+
+    Ok(read)
+}
+
+// Move the data of a plain file into blocks of the mode, the chunks are released once all the blocks are stored.
+#[cfg(any(
+    feature = "compression",
+    feature = "encryption",
+    feature = "sparse_files"
+))]
+fn import_file(fs: &mut FileSystem, node: Node, size: FileSize, mode: u8) -> Result<(), Error> {
+    let mut buf = vec![0u8; BLOCK_SIZE];
+
+    with_transforms(|transforms| transforms.remove_blocks(node));
+
+    for index in 0..size.div_ceil(BLOCK_SIZE as FileSize) {
+        let stored =
+            read_node(fs, node, index * BLOCK_SIZE as FileSize, &mut buf).and_then(|read| {
+                with_transforms(|transforms| {
+                    transforms.import_block(node, index, mode, &buf[..read])
+                })
+            });
+
+        if let Err(er) = stored {
+            with_transforms(|transforms| transforms.remove_blocks(node));
+            return Err(er);
+        }
+    }
+
+    // the freed storage is not cleared, so the content to encrypt is overwritten first
+    if mode & MODE_ENCRYPTED != 0 {
+        buf.fill(0);
+
+        for index in 0..size.div_ceil(BLOCK_SIZE as FileSize) {
+            let offset = index * BLOCK_SIZE as FileSize;
+            let len = (size - offset).min(BLOCK_SIZE as FileSize) as usize;
+            fs.storage.write(node, offset, &buf[..len])?;
+        }
+
+        fs.storage.flush(node);
+    }
+
+    fs.storage.resize_file(node, 0)?;
+    with_transforms(|transforms| transforms.set_mode(node, mode));
+    fs.storage.resize_file(node, size)
+}
+
+// Move the data of the blocks back into the storage, the blocks are released once all the data is written.
+#[cfg(any(feature = "compression", feature = "encryption"))]
+fn export_file(fs: &mut FileSystem, node: Node, size: FileSize, old_mode: u8) -> Result<(), Error> {
+    with_transforms(|transforms| transforms.set_mode(node, 0));
+
+    let mut next = with_transforms(|transforms| transforms.next_block(node, 0));
+
+    while let Some(index) = next {
+        let written =
+            with_transforms(|transforms| transforms.read_block(node, index)).and_then(|data| {
+                fs.storage
+                    .write(node, index * BLOCK_SIZE as FileSize, &data)
+            });
+
+        if let Err(er) = written {
+            // release the data written so far and read the blocks again
+            fs.storage.flush(node);
+            fs.storage.resize_file(node, 0)?;
+            with_transforms(|transforms| transforms.set_mode(node, old_mode));
+            fs.storage.resize_file(node, size)?;
+
+            return Err(er);
+        }
+
+        next = with_transforms(|transforms| transforms.next_block(node, index + 1));
+    }
+
+    fs.storage.flush(node);
+    with_transforms(|transforms| transforms.remove_blocks(node));
+
+    Ok(())
+}
+
+/// Store a file compressed or uncompressed, the existing content is converted.
+/// The compressed files are read and written as usual, `fd_filestat_get` reports their uncompressed size.
+/// The data is compressed in blocks of 16 KiB, see `get_file_physical_size` for the stored size.
+/// Returns `ERRNO_NOTSUP` for the mounted files and if the blocks would not persist, see `init_transforms_with_memory`.
+///
+/// # Parameters
+/// - `path`: Path relative to the file system root
+/// - `compressed`: `true` to compress the file, `false` to store it as is
+#[cfg(feature = "compression")]
+pub fn set_file_compression(path: &str, compressed: bool) -> i32 {
+    match transform_file(path, MODE_COMPRESSED, compressed) {
+        Ok(_) => wasi::ERRNO_SUCCESS.raw() as i32,
+        Err(er) => into_errno(er),
+    }
+}
+
+/// Check if a file is stored compressed
+#[cfg(feature = "compression")]
+pub fn is_file_compressed(path: &str) -> Result<bool, i32> {
+    let node = root_node(path)?;
+
+    Ok(with_transforms(|transforms| {
+        transforms.mode(node) & MODE_COMPRESSED != 0
+    }))
+}
+
+/// Compress the files created with a path matching the pattern, e.g. `add_compression_pattern("logs/*.log")`.
+/// The pattern is matched against the whole path relative to the file system root,
+/// `*` matches any sequence of characters including `/` and `?` matches a single character.
+#[cfg(feature = "compression")]
+pub fn add_compression_pattern(pattern: &str) {
+    with_transforms(|transforms| transforms.add_pattern(pattern))
+}
+
+/// Remove the compression patterns, the files already compressed stay compressed
+#[cfg(feature = "compression")]
+pub fn clear_compression_patterns() {
+    with_transforms(|transforms| transforms.clear_patterns())
+}
+
+/// Get the number of bytes a file occupies in the storage, for the compressed and encrypted files
+/// it is the size of their stored blocks, otherwise the file size
+#[cfg(feature = "transforms")]
+pub fn get_file_physical_size(path: &str) -> Result<FileSize, i32> {
+    let metadata = FS
+        .with_borrow_mut(|fs| {
+            let root_fd = fs.root_fd();
+            let metadata = fs.open_metadata(root_fd, path)?;
+
+            // the cached writes are not stored yet
+            fs.storage.flush(metadata.node);
+
+            Ok(metadata)
+        })
+        .map_err(into_errno)?;
+
+    Ok(with_transforms(|transforms| {
+        if transforms.mode(metadata.node) == 0 {
+            metadata.size
+        } else {
+            transforms.physical_size(metadata.node)
+        }
+    }))
+}
+
+/// Release the storage backing a range of the file opened with `fd`, the range reads back as zeros and
+/// the file size is kept, like `fallocate` with `FALLOC_FL_PUNCH_HOLE | FALLOC_FL_KEEP_SIZE` on Linux.
+///
+/// A file with holes keeps its data in blocks of 16 KiB, the blocks within the range are released and the blocks at
+/// its ends are cleared, see `get_file_allocation`. stable-fs can only release the chunks at the end of a file, so the
+/// first hole in a plain file moves all its data into blocks and releases its chunks. The move reads and writes the
+/// whole file within the call, a large file may need a dedicated update call for its first hole.
+/// Returns `ERRNO_BADF` if the descriptor cannot write, `ERRNO_INVAL` if it is not a regular file
+/// and `ERRNO_NOTSUP` for mounted files and if the blocks would not persist, see `init_transforms_with_memory`.
+#[cfg(feature = "sparse_files")]
+pub fn punch_hole(fd: Fd, offset: FileSize, len: FileSize) -> i32 {
+    if is_query_write() {
+        return wasi::ERRNO_ROFS.raw() as i32;
+    }
+
+    let mut event = None;
+
+    let result = FS.with_borrow_mut(|fs| {
+        let metadata = fs.metadata(fd).map_err(into_errno)?;
+        let (_, stat) = fs.get_stat(fd).map_err(into_errno)?;
+
+        if metadata.file_type != FileType::RegularFile {
+            return Err(wasi::ERRNO_INVAL.raw() as i32);
+        }
+
+        if stat.rights_base & wasi::RIGHTS_FD_WRITE == 0 {
+            return Err(wasi::ERRNO_BADF.raw() as i32);
+        }
+
+        if fs.storage.is_mounted(metadata.node) {
+            return Err(wasi::ERRNO_NOTSUP.raw() as i32);
+        }
+
+        let end = offset.saturating_add(len).min(metadata.size);
+
+        if offset >= end {
+            return Ok(());
+        }
+
+        // the cached writes are stored before the blocks below the cache change
+        fs.storage.flush(metadata.node);
+
+        let node = metadata.node;
+
+        // the blocks on the heap would be lost on upgrade
+        if with_transforms(|transforms| transforms.mode(node)) == 0 {
+            if with_transforms(|transforms| transforms.is_volatile()) {
+                return Err(wasi::ERRNO_NOTSUP.raw() as i32);
+            }
+
+            import_file(fs, node, metadata.size, MODE_SPARSE).map_err(into_errno)?;
+        }
+
+        with_transforms(|transforms| transforms.punch(node, offset, end)).map_err(into_errno)?;
+        #[cfg(feature = "cache")]
+        CACHE.with(|cache| cache.borrow_mut().invalidate(metadata.node, offset, end));
+
+        fs.set_modified_time(fd, ic_time()).map_err(into_errno)?;
+        hash_written(fs, fd, offset, &[], 0);
+        event = file_changed(fs, fd, WatchEventKind::Modify);
+
+        Ok(())
+    });
+
+    notify_watches(event);
+
+    match result {
+        Ok(()) => wasi::ERRNO_SUCCESS.raw() as i32,
+        Err(errno) => errno,
+    }
+}
+
+/// The size of a file and the bytes allocated for its data, see `get_file_allocation`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileAllocation {
+    pub logical_size: FileSize,
+    pub allocated_size: FileSize,
+}
+
+/// Get the logical size of a file and the bytes allocated for its data, which is smaller for the files with holes.
+/// The files stored in blocks (sparse, compressed or encrypted) allocate 16 KiB for each stored block, the plain files
+/// their size rounded up to the storage chunk size, the mounted files their size.
+#[cfg(feature = "sparse_files")]
+pub fn get_file_allocation(path: &str) -> Result<FileAllocation, i32> {
+    FS.with_borrow_mut(|fs| {
+        let root_fd = fs.root_fd();
+        let metadata = fs.open_metadata(root_fd, path)?;
+
+        // the cached writes are not stored yet
+        fs.storage.flush(metadata.node);
+
+        let allocated_size = if fs.storage.is_mounted(metadata.node) {
+            metadata.size
+        } else {
+            match with_transforms(|transforms| transforms.mode(metadata.node)) {
+                0 => {
+                    let chunk_size = fs.storage.chunk_size() as FileSize;
+                    metadata.size.div_ceil(chunk_size) * chunk_size
+                }
+                _ => {
+                    with_transforms(|transforms| transforms.allocated_blocks(metadata.node))
+                        * BLOCK_SIZE as FileSize
+                }
+            }
+        };
+
+        Ok(FileAllocation {
+            logical_size: metadata.size,
+            allocated_size,
+        })
+    })
+    .map_err(into_errno)
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "encryption")]
+    use ic_stable_structures::DefaultMemoryImpl;
+
+    use super::*;
+
+    #[test]
+    fn patterns() {
+        assert!(matches_pattern("*.log", "app.log"));
+        assert!(matches_pattern("*.log", "logs/app.log"));
+        assert!(matches_pattern("logs/*.json", "logs/a.json"));
+        assert!(matches_pattern("data?.csv", "data1.csv"));
+        assert!(!matches_pattern("*.log", "app.log.1"));
+        assert!(!matches_pattern("data?.csv", "data.csv"));
+        assert!(matches_pattern("*", ""));
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn compressed_blocks() {
+        let text = b"log line\n".repeat(100);
+        let block = encode_block(&text);
+        assert!(block.len() < text.len());
+        assert_eq!(decode_block(&block).unwrap(), text);
+
+        // incompressible data is kept as is
+        let block = encode_block(b"ab");
+        assert_eq!(block, vec![0, b'a', b'b']);
+        assert_eq!(decode_block(&block).unwrap(), b"ab");
+
+        assert!(decode_block(&[]).is_err());
+        assert!(decode_block(&[1, 0xff, 0xff]).is_err());
+    }
+
+    #[cfg(feature = "encryption")]
+    fn nonce(transforms: &Transforms) -> u64 {
+        transforms
+            .store
+            .get(NONCE_KEY)
+            .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+            .unwrap_or(0)
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn nonce_counter_persists_with_blocks() {
+        let memory = DefaultMemoryImpl::default();
+
+        let mut transforms = Transforms::new();
+        transforms.load(Box::new(memory.clone()));
+        transforms.keyring().set_key(1, &[1; 32]);
+        transforms.set_mode(1, MODE_ENCRYPTED);
+        transforms.write_block(1, 0, b"secret").unwrap();
+        transforms.write_block(1, 1, b"secret").unwrap();
+        assert_eq!(nonce(&transforms), 2);
+
+        // loading the memory again, as after an upgrade
+        let mut transforms = Transforms::new();
+        transforms.load(Box::new(memory));
+        transforms.keyring().set_key(1, &[1; 32]);
+        assert_eq!(transforms.mode(1), MODE_ENCRYPTED);
+        assert_eq!(transforms.read_block(1, 0).unwrap(), b"secret");
+
+        transforms.write_block(1, 0, b"changed").unwrap();
+        assert_eq!(nonce(&transforms), 3);
+        assert_eq!(transforms.physical_size(1), 2 * (1 + 12 + 16) + 6 + 7);
+        assert_eq!(transforms.allocated_blocks(1), 2);
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn no_encryption_on_heap_with_stable_storage() {
+        let mut transforms = Transforms::new();
+        transforms.keyring().set_key(1, &[1; 32]);
+        transforms.set_mode(1, MODE_ENCRYPTED);
+        assert!(transforms.write_block(1, 0, b"secret").is_ok());
+
+        transforms.set_stable_storage(true);
+        assert!(transforms.is_volatile());
+        assert!(transforms.write_block(1, 1, b"secret").is_err());
+    }
+}
@@ -0,0 +1,73 @@
+use std::collections::BTreeSet;
+
+/// Defines what happens when a WASI function is called that is not implemented by the polyfill
+/// or not supported on the IC (`path_symlink`, `path_readlink`, `poll_oneoff`, `proc_raise`, `sock_*`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnsupportedCallPolicy {
+    /// Trap the canister (panic).
+    Panic,
+    /// Return `ERRNO_NOSYS` for unimplemented and `ERRNO_NOTSUP` for unsupported functions.
+    ReturnError,
+    /// Return the error and print a debug message the first time each function is called.
+    ReturnErrorAndLog,
+}
+
+impl Default for UnsupportedCallPolicy {
+    fn default() -> Self {
+        if cfg!(feature = "unsupported_functions_return_errors") {
+            UnsupportedCallPolicy::ReturnError
+        } else {
+            UnsupportedCallPolicy::Panic
+        }
+    }
+}
+
+/// Keeps the active policy and the functions already reported to the debug output.
+#[derive(Default)]
+pub struct UnsupportedCalls {
+    policy: UnsupportedCallPolicy,
+    logged: BTreeSet<&'static str>,
+}
+
+impl UnsupportedCalls {
+    pub fn new() -> UnsupportedCalls {
+        Self::default()
+    }
+
+    pub fn policy(&self) -> UnsupportedCallPolicy {
+        self.policy
+    }
+
+    // Changing the policy allows to log the functions again.
+    pub fn set_policy(&mut self, policy: UnsupportedCallPolicy) {
+        self.policy = policy;
+        self.logged.clear();
+    }
+
+    // Returns true, if the call of the function has to be logged.
+    pub fn should_log(&mut self, function: &'static str) -> bool {
+        self.policy == UnsupportedCallPolicy::ReturnErrorAndLog && self.logged.insert(function)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{UnsupportedCallPolicy, UnsupportedCalls};
+
+    #[test]
+    fn log_each_function_once() {
+        let mut calls = UnsupportedCalls::new();
+        assert!(!calls.should_log("sock_accept"));
+
+        calls.set_policy(UnsupportedCallPolicy::ReturnErrorAndLog);
+        assert!(calls.should_log("sock_accept"));
+        assert!(!calls.should_log("sock_accept"));
+        assert!(calls.should_log("sock_recv"));
+
+        calls.set_policy(UnsupportedCallPolicy::ReturnErrorAndLog);
+        assert!(calls.should_log("sock_accept"));
+
+        calls.set_policy(UnsupportedCallPolicy::ReturnError);
+        assert!(!calls.should_log("sock_send"));
+    }
+}
@@ -0,0 +1,220 @@
+use std::ops::RangeInclusive;
+
+use crate::wasi;
+
+/// Known lookup flags.
+pub const KNOWN_LOOKUPFLAGS: i32 = wasi::LOOKUPFLAGS_SYMLINK_FOLLOW as i32;
+
+/// Known open flags.
+pub const KNOWN_OFLAGS: i32 =
+    (wasi::OFLAGS_CREAT | wasi::OFLAGS_DIRECTORY | wasi::OFLAGS_EXCL | wasi::OFLAGS_TRUNC) as i32;
+
+/// Known file descriptor flags.
+pub const KNOWN_FDFLAGS: i32 = (wasi::FDFLAGS_APPEND
+    | wasi::FDFLAGS_DSYNC
+    | wasi::FDFLAGS_NONBLOCK
+    | wasi::FDFLAGS_RSYNC
+    | wasi::FDFLAGS_SYNC) as i32;
+
+/// Known timestamp flags.
+pub const KNOWN_FSTFLAGS: i32 =
+    (wasi::FSTFLAGS_ATIM | wasi::FSTFLAGS_ATIM_NOW | wasi::FSTFLAGS_MTIM | wasi::FSTFLAGS_MTIM_NOW)
+        as i32;
+
+/// Size of a WebAssembly memory page.
+#[cfg(target_arch = "wasm32")]
+const WASM_PAGE_SIZE: usize = 65536;
+
+// Check that the memory region lies within the linear memory of the canister.
+#[cfg(target_arch = "wasm32")]
+fn check_bounds(addr: usize, len: usize) -> Result<(), wasi::Errno> {
+    let memory_end = core::arch::wasm32::memory_size::<0>() * WASM_PAGE_SIZE;
+
+    match addr.checked_add(len) {
+        Some(end) if end <= memory_end => Ok(()),
+        _ => Err(wasi::ERRNO_FAULT),
+    }
+}
+
+// The host memory is not bounded, only the address overflow is checked.
+#[cfg(not(target_arch = "wasm32"))]
+fn check_bounds(addr: usize, len: usize) -> Result<(), wasi::Errno> {
+    match addr.checked_add(len) {
+        Some(_) => Ok(()),
+        None => Err(wasi::ERRNO_FAULT),
+    }
+}
+
+/// Check a pointer to a single value: it must not be null, it must be aligned and point to valid memory.
+pub fn check_ptr<T>(ptr: *const T) -> Result<(), wasi::Errno> {
+    if ptr.is_null() || !(ptr as usize).is_multiple_of(std::mem::align_of::<T>()) {
+        return Err(wasi::ERRNO_FAULT);
+    }
+
+    check_bounds(ptr as usize, std::mem::size_of::<T>())
+}
+
+/// Check a buffer passed with a signed length.
+pub fn check_buf(ptr: *const u8, len: i32) -> Result<(), wasi::Errno> {
+    if len < 0 {
+        return Err(wasi::ERRNO_INVAL);
+    }
+
+    check_slice(ptr, len as usize)
+}
+
+/// Check a memory region of `len` elements, an empty region may be passed as a null pointer.
+pub fn check_slice<T>(ptr: *const T, len: usize) -> Result<(), wasi::Errno> {
+    if len == 0 {
+        return Ok(());
+    }
+
+    if ptr.is_null() || !(ptr as usize).is_multiple_of(std::mem::align_of::<T>()) {
+        return Err(wasi::ERRNO_FAULT);
+    }
+
+    let size = len
+        .checked_mul(std::mem::size_of::<T>())
+        .ok_or(wasi::ERRNO_INVAL)?;
+
+    check_bounds(ptr as usize, size)
+}
+
+/// Create a slice of `len` elements passed by the caller, a null pointer is accepted for an empty slice.
+///
+/// # Safety
+///
+/// A non-empty region must satisfy the requirements of `std::slice::from_raw_parts`.
+pub unsafe fn raw_slice<'a, T>(ptr: *const T, len: usize) -> &'a [T] {
+    if len == 0 {
+        &[]
+    } else {
+        unsafe { std::slice::from_raw_parts(ptr, len) }
+    }
+}
+
+/// Create a mutable slice of `len` elements passed by the caller, a null pointer is accepted for an empty slice.
+///
+/// # Safety
+///
+/// A non-empty region must satisfy the requirements of `std::slice::from_raw_parts_mut`.
+pub unsafe fn raw_slice_mut<'a, T>(ptr: *mut T, len: usize) -> &'a mut [T] {
+    if len == 0 {
+        &mut []
+    } else {
+        unsafe { std::slice::from_raw_parts_mut(ptr, len) }
+    }
+}
+
+/// Check the input buffers, the total length of the buffers must not overflow.
+///
+/// # Safety
+///
+/// If the descriptor array passes the checks, its entries are read.
+pub unsafe fn check_ciovecs(iovs: *const wasi::Ciovec, len: i32) -> Result<(), wasi::Errno> {
+    if len < 0 {
+        return Err(wasi::ERRNO_INVAL);
+    }
+
+    check_slice(iovs, len as usize)?;
+
+    let iovs = unsafe { raw_slice(iovs, len as usize) };
+
+    let mut total: usize = 0;
+
+    for iov in iovs {
+        check_slice(iov.buf, iov.buf_len)?;
+        total = total.checked_add(iov.buf_len).ok_or(wasi::ERRNO_INVAL)?;
+    }
+
+    Ok(())
+}
+
+/// Check the output buffers, the total length of the buffers must not overflow.
+///
+/// # Safety
+///
+/// If the descriptor array passes the checks, its entries are read.
+pub unsafe fn check_iovecs(iovs: *const wasi::Iovec, len: i32) -> Result<(), wasi::Errno> {
+    if len < 0 {
+        return Err(wasi::ERRNO_INVAL);
+    }
+
+    check_slice(iovs, len as usize)?;
+
+    let iovs = unsafe { raw_slice(iovs, len as usize) };
+
+    let mut total: usize = 0;
+
+    for iov in iovs {
+        check_slice(iov.buf as *const u8, iov.buf_len)?;
+        total = total.checked_add(iov.buf_len).ok_or(wasi::ERRNO_INVAL)?;
+    }
+
+    Ok(())
+}
+
+/// Check an enumeration value.
+pub fn check_range<T: PartialOrd>(value: T, range: RangeInclusive<T>) -> Result<(), wasi::Errno> {
+    if range.contains(&value) {
+        Ok(())
+    } else {
+        Err(wasi::ERRNO_INVAL)
+    }
+}
+
+/// Check that only the known flag bits are set.
+pub fn check_flags(flags: i32, known: i32) -> Result<(), wasi::Errno> {
+    if flags & !known == 0 {
+        Ok(())
+    } else {
+        Err(wasi::ERRNO_INVAL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_buf, check_ciovecs, check_flags, check_ptr, check_range};
+    use crate::wasi;
+
+    #[test]
+    fn rejects_invalid_arguments() {
+        let value = 0u64;
+        assert_eq!(check_ptr(&value as *const u64), Ok(()));
+        assert_eq!(check_ptr(std::ptr::null::<u64>()), Err(wasi::ERRNO_FAULT));
+        let misaligned = (&value as *const u64 as *const u8).wrapping_add(1) as *const u64;
+        assert_eq!(check_ptr(misaligned), Err(wasi::ERRNO_FAULT));
+
+        let buf = [0u8; 4];
+        assert_eq!(check_buf(buf.as_ptr(), 4), Ok(()));
+        assert_eq!(check_buf(buf.as_ptr(), -1), Err(wasi::ERRNO_INVAL));
+        assert_eq!(check_buf(std::ptr::null(), 1), Err(wasi::ERRNO_FAULT));
+        assert_eq!(check_buf(std::ptr::null(), 0), Ok(()));
+
+        let iovs = [
+            wasi::Ciovec {
+                buf: buf.as_ptr(),
+                buf_len: usize::MAX,
+            },
+            wasi::Ciovec {
+                buf: buf.as_ptr(),
+                buf_len: usize::MAX,
+            },
+        ];
+        assert_eq!(
+            unsafe { check_ciovecs(iovs.as_ptr(), 2) },
+            Err(wasi::ERRNO_FAULT)
+        );
+        assert_eq!(
+            unsafe { check_ciovecs(iovs.as_ptr(), -2) },
+            Err(wasi::ERRNO_INVAL)
+        );
+        assert_eq!(unsafe { check_ciovecs(iovs.as_ptr(), 0) }, Ok(()));
+        assert_eq!(unsafe { check_ciovecs(std::ptr::null(), 0) }, Ok(()));
+
+        assert_eq!(check_range(2, 0..=2), Ok(()));
+        assert_eq!(check_range(3, 0..=2), Err(wasi::ERRNO_INVAL));
+        assert_eq!(check_flags(0b101, 0b111), Ok(()));
+        assert_eq!(check_flags(0b1000, 0b111), Err(wasi::ERRNO_INVAL));
+    }
+}
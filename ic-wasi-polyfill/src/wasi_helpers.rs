@@ -1,3 +1,5 @@
+use std::borrow::Cow;
+
 use stable_fs::{
     error::Error,
     fs::{Fd, FileSystem},
@@ -6,27 +8,123 @@ use stable_fs::{
     },
 };
 
+use crate::validation::{raw_slice, raw_slice_mut};
 #[cfg(target_arch = "wasm32")]
 use crate::wasi;
 #[cfg(not(all(target_arch = "wasm32")))]
 use crate::wasi_mock as wasi;
 
-/// Returns a UTF-8 string slice from a raw pointer and length.
+/// Returns the file name from a raw pointer and length.
 ///
-/// # Safety
+/// Invalid UTF-8 sequences are rejected with `ERRNO_ILSEQ`. With the `byte_paths` feature
+/// such names are accepted and the invalid bytes are stored as characters of a private use area
+/// (see `encode_byte_path`), these are converted back to the original bytes by `fd_readdir`.
 ///
-/// This function is `unsafe` because it dereferences a raw pointer and
-/// uses `from_utf8_unchecked`, both of which can lead to undefined behavior
-/// if used improperly.
+/// # Safety
 ///
 /// The caller must ensure:
 /// - `path` points to a valid memory region that is at least `path_len` bytes long.
 /// - The memory referenced by `path` must remain valid for the returned lifetime `'a`.
-/// - The memory must contain valid UTF-8 data; otherwise, the behavior is undefined.
-/// - The pointer must not be null.
-pub unsafe fn get_file_name<'a>(path: *const u8, path_len: wasi::Size) -> &'a str {
-    let path_bytes = unsafe { std::slice::from_raw_parts(path, path_len as wasi::Size) };
-    unsafe { std::str::from_utf8_unchecked(path_bytes) }
+/// - The pointer must not be null unless `path_len` is 0.
+pub unsafe fn get_file_name<'a>(
+    path: *const u8,
+    path_len: wasi::Size,
+) -> Result<Cow<'a, str>, wasi::Errno> {
+    let path_bytes = unsafe { raw_slice(path, path_len as wasi::Size) };
+
+    match std::str::from_utf8(path_bytes) {
+        // escape characters are not allowed in valid names, otherwise they would be decoded into different bytes
+        Ok(name) if cfg!(feature = "byte_paths") && name.chars().any(is_byte_escape) => {
+            Err(wasi::ERRNO_ILSEQ)
+        }
+        Ok(name) => Ok(Cow::Borrowed(name)),
+        Err(_) if cfg!(feature = "byte_paths") => Ok(Cow::Owned(encode_byte_path(path_bytes))),
+        Err(_) => Err(wasi::ERRNO_ILSEQ),
+    }
+}
+
+/// First character of the range used to store pairs of bytes of invalid UTF-8 sequences.
+pub const BYTE_PAIR_ESCAPE_START: u32 = 0x100000;
+
+/// First character of the range used to store a single byte of an invalid UTF-8 sequence.
+pub const BYTE_ESCAPE_START: u32 = 0x104000;
+
+// The bytes of invalid sequences are always non-ASCII, a pair of them fits into a single character.
+fn is_byte_escape(c: char) -> bool {
+    (BYTE_PAIR_ESCAPE_START..BYTE_ESCAPE_START + 256).contains(&(c as u32))
+}
+
+/// Convert a byte path into a string. The bytes of invalid UTF-8 sequences are stored in pairs as the character
+/// `U+100000 + (first & 0x7F) * 128 + (second & 0x7F)`, a remaining single byte as `U+104000 + byte`.
+/// A run of `n` invalid bytes takes at most `2 * n + 2` bytes of the stored name.
+pub fn encode_byte_path(bytes: &[u8]) -> String {
+    let mut result = String::with_capacity(bytes.len());
+    let mut invalid = Vec::new();
+
+    for chunk in bytes.utf8_chunks() {
+        if !chunk.valid().is_empty() {
+            push_byte_escapes(&mut result, &invalid);
+            invalid.clear();
+
+            result.push_str(chunk.valid());
+        }
+
+        invalid.extend_from_slice(chunk.invalid());
+    }
+
+    push_byte_escapes(&mut result, &invalid);
+
+    result
+}
+
+fn push_byte_escapes(result: &mut String, bytes: &[u8]) {
+    let mut pairs = bytes.chunks_exact(2);
+
+    for pair in pairs.by_ref() {
+        let code =
+            BYTE_PAIR_ESCAPE_START + ((pair[0] as u32 & 0x7F) << 7) + (pair[1] as u32 & 0x7F);
+        result.push(char::from_u32(code).unwrap());
+    }
+
+    if let [byte] = pairs.remainder() {
+        result.push(char::from_u32(BYTE_ESCAPE_START + *byte as u32).unwrap());
+    }
+}
+
+/// The bytes of a stored file name as they were passed by the caller.
+pub fn file_name_bytes(name: &[u8]) -> Cow<'_, [u8]> {
+    if cfg!(feature = "byte_paths") {
+        decode_byte_path(name)
+    } else {
+        Cow::Borrowed(name)
+    }
+}
+
+/// Restore the original bytes of a name produced by `encode_byte_path`.
+pub fn decode_byte_path(bytes: &[u8]) -> Cow<'_, [u8]> {
+    let name = match std::str::from_utf8(bytes) {
+        Ok(name) if name.chars().any(is_byte_escape) => name,
+        _ => return Cow::Borrowed(bytes),
+    };
+
+    let mut result = Vec::with_capacity(bytes.len());
+
+    for c in name.chars() {
+        let code = c as u32;
+
+        if code >= BYTE_ESCAPE_START && is_byte_escape(c) {
+            result.push((code - BYTE_ESCAPE_START) as u8);
+        } else if is_byte_escape(c) {
+            let pair = code - BYTE_PAIR_ESCAPE_START;
+            result.push(0x80 | (pair >> 7) as u8);
+            result.push(0x80 | (pair & 0x7F) as u8);
+        } else {
+            let mut buf = [0u8; 4];
+            result.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+        }
+    }
+
+    Cow::Owned(result)
 }
 
 pub const DIRENT_SIZE: usize = std::mem::size_of::<wasi::Dirent>();
@@ -162,7 +260,7 @@ pub unsafe fn fd_readdir(
     let bytes_len = bytes_len as usize;
     let mut result = 0usize;
 
-    let buf = unsafe { std::slice::from_raw_parts_mut(bytes, bytes_len) };
+    let buf = unsafe { raw_slice_mut(bytes, bytes_len) };
 
     let entry_index = if cookie == 0 {
         None
@@ -170,10 +268,16 @@ pub unsafe fn fd_readdir(
         Some(cookie as DirEntryIndex)
     };
 
-    let r = fs.with_direntries(fd, entry_index, &mut |idx, entry| {
-        let put_result = put_single_entry(fs, *idx, entry, &mut buf[result..]);
+    let mut put_error = None;
 
-        let put_result = put_result.unwrap();
+    let r = fs.with_direntries(fd, entry_index, &mut |idx, entry| {
+        let put_result = match put_single_entry(fs, *idx, entry, &mut buf[result..]) {
+            Ok(put_result) => put_result,
+            Err(err) => {
+                put_error = Some(err);
+                return false;
+            }
+        };
 
         result += put_result;
 
@@ -190,6 +294,10 @@ pub unsafe fn fd_readdir(
         return into_errno(err);
     }
 
+    if let Some(err) = put_error {
+        return into_errno(err);
+    }
+
     unsafe { *res = std::cmp::min(result, bytes_len) };
 
     wasi::ERRNO_SUCCESS.raw() as i32
@@ -220,23 +328,21 @@ pub fn put_single_entry(
 
     //let file_type = fs.metadata_from_node(dir_entry.node)?.file_type;
 
+    let name = file_name_bytes(&dir_entry.name.bytes[0..dir_entry.name.length as usize]);
+
     let wasi_dirent = wasi::Dirent {
         d_next: next_index as u64,
         d_ino: dir_entry.node,
-        d_namlen: (dir_entry.name.length as wasi::Dirnamlen),
+        d_namlen: (name.len() as wasi::Dirnamlen),
         d_type: into_wasi_filetype(file_type),
     };
 
-    let result = fill_buffer(wasi_dirent, buf, &dir_entry.name);
+    let result = fill_buffer(wasi_dirent, buf, &name);
 
     Ok(result)
 }
 
-fn fill_buffer(
-    wasi_dirent: wasi::Dirent,
-    buf: &mut [u8],
-    filename: &stable_fs::storage::types::FileName,
-) -> usize {
+fn fill_buffer(wasi_dirent: wasi::Dirent, buf: &mut [u8], filename: &[u8]) -> usize {
     use std::slice;
 
     let p: *const wasi::Dirent = &wasi_dirent;
@@ -255,27 +361,25 @@ fn fill_buffer(
     let buf_len = buf.len();
     let buf = &mut buf[result..buf_len];
 
-    let filename = &filename.bytes[0..filename.length as usize];
-
     let result2 = usize::min(filename.len(), buf.len());
     buf[0..result2].copy_from_slice(&filename[0..result2]);
     result + result2
 }
 
-pub fn into_stable_fs_wence(whence: u8) -> stable_fs::fs::Whence {
+pub fn into_stable_fs_wence(whence: u8) -> Result<stable_fs::fs::Whence, Error> {
     if whence == wasi::WHENCE_SET.raw() {
-        return stable_fs::fs::Whence::SET;
+        return Ok(stable_fs::fs::Whence::SET);
     }
 
     if whence == wasi::WHENCE_CUR.raw() {
-        return stable_fs::fs::Whence::CUR;
+        return Ok(stable_fs::fs::Whence::CUR);
     }
 
     if whence == wasi::WHENCE_END.raw() {
-        return stable_fs::fs::Whence::END;
+        return Ok(stable_fs::fs::Whence::END);
     }
 
-    panic!("Unsupported whence type!");
+    Err(Error::InvalidArgument)
 }
 
 #[cfg(test)]
@@ -291,7 +395,7 @@ mod tests {
         },
     };
 
-    use super::{fd_readdir, fill_buffer};
+    use super::{decode_byte_path, encode_byte_path, fd_readdir, fill_buffer, get_file_name};
 
     fn test_fs() -> FileSystem {
         FileSystem::new(Box::new(StableStorage::new(DefaultMemoryImpl::default()))).unwrap()
@@ -322,7 +426,11 @@ mod tests {
         ];
 
         let mut buf = [0u8; 100];
-        let len = fill_buffer(wasi_dirent, &mut buf, &direntry.name);
+        let len = fill_buffer(
+            wasi_dirent,
+            &mut buf,
+            &direntry.name.bytes[..direntry.name.length as usize],
+        );
 
         // stabilize test, the three bytes can take random value here...
         buf[DIRENT_SIZE - 3] = 243;
@@ -333,7 +441,11 @@ mod tests {
         assert_eq!(len, expected.len());
 
         let mut buf = [0u8; 27];
-        let len = fill_buffer(wasi_dirent, &mut buf, &direntry.name);
+        let len = fill_buffer(
+            wasi_dirent,
+            &mut buf,
+            &direntry.name.bytes[..direntry.name.length as usize],
+        );
 
         // stabilize test, the three bytes can take random value here...
         buf[DIRENT_SIZE - 3] = 243;
@@ -344,7 +456,11 @@ mod tests {
         assert_eq!(len, buf.len());
 
         let mut buf = [0u8; 3];
-        let len = fill_buffer(wasi_dirent, &mut buf, &direntry.name);
+        let len = fill_buffer(
+            wasi_dirent,
+            &mut buf,
+            &direntry.name.bytes[..direntry.name.length as usize],
+        );
 
         assert_eq!(&expected[0..len], &buf[0..len]);
         assert_eq!(len, buf.len());
@@ -449,4 +565,51 @@ mod tests {
             assert_eq!(bytes_used, expected_bytes);
         }
     }
+
+    #[test]
+    fn test_byte_path_roundtrip() {
+        let bytes = b"caf\xe9/\xff\xfe.txt";
+        let encoded = encode_byte_path(bytes);
+
+        assert!(encoded.starts_with("caf"));
+        assert!(encoded.ends_with("/\u{103FFE}.txt"));
+        assert_eq!(decode_byte_path(encoded.as_bytes()), bytes.to_vec());
+
+        // consecutive invalid sequences are stored in pairs
+        let bytes = [0x80u8; 127];
+        let encoded = encode_byte_path(&bytes);
+
+        assert_eq!(encoded.len(), 64 * 4);
+        assert_eq!(decode_byte_path(encoded.as_bytes()), bytes.to_vec());
+
+        // valid names are not changed
+        assert_eq!(encode_byte_path("Ünïcode".as_bytes()), "Ünïcode");
+        assert_eq!(decode_byte_path("Ünïcode".as_bytes()), "Ünïcode".as_bytes());
+    }
+
+    #[test]
+    fn test_get_file_name_validates_utf8() {
+        let name = "dir/file.txt";
+        let result = unsafe { get_file_name(name.as_ptr(), name.len()) };
+        assert_eq!(result.unwrap(), name);
+
+        let name = b"file\xc3.txt";
+        let result = unsafe { get_file_name(name.as_ptr(), name.len()) };
+
+        if cfg!(feature = "byte_paths") {
+            assert_eq!(result.unwrap(), "file\u{1040C3}.txt");
+        } else {
+            assert_eq!(result.unwrap_err(), wasi::ERRNO_ILSEQ);
+        }
+
+        // the escape characters are reserved in the byte path mode
+        let name = "file\u{1040C3}\u{100001}.txt";
+        let result = unsafe { get_file_name(name.as_ptr(), name.len()) };
+
+        if cfg!(feature = "byte_paths") {
+            assert_eq!(result.unwrap_err(), wasi::ERRNO_ILSEQ);
+        } else {
+            assert_eq!(result.unwrap(), name);
+        }
+    }
 }
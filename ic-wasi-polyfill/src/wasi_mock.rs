@@ -2588,7 +2588,7 @@ pub mod wasi_snapshot_preview1 {
     /// Send a message on a socket.
     /// Note: This is similar to `send` in POSIX, though it also supports writing
     /// the data from multiple buffers in the manner of `writev`.
-    pub fn sock_send(
+    pub unsafe fn sock_send(
         arg0: i32,
         arg1: *const wasi::Ciovec,
         arg2: i32,
@@ -0,0 +1,450 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use stable_fs::fs::{Fd, FileSystem};
+
+#[cfg(feature = "watches")]
+use crate::{root_path, wasi, WATCHES};
+
+/// A file was created or a hard link was added.
+pub const WATCH_CREATE: u32 = 1 << 0;
+/// A file was written or resized.
+pub const WATCH_MODIFY: u32 = 1 << 1;
+/// A file or a directory was removed.
+pub const WATCH_DELETE: u32 = 1 << 2;
+/// A file or a directory was renamed.
+pub const WATCH_RENAME: u32 = 1 << 3;
+/// A file descriptor that changed the file was closed.
+pub const WATCH_CLOSE_WRITE: u32 = 1 << 4;
+
+pub const WATCH_ALL: u32 =
+    WATCH_CREATE | WATCH_MODIFY | WATCH_DELETE | WATCH_RENAME | WATCH_CLOSE_WRITE;
+
+/// Events kept by a queue watch until they are taken, the later events are dropped.
+pub const MAX_QUEUED_EVENTS: usize = 4096;
+
+pub type WatchId = u64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchEventKind {
+    Create,
+    Modify,
+    Delete,
+    Rename,
+    CloseWrite,
+    /// The queue was full and events were dropped, only delivered to the queue watches.
+    Overflow,
+}
+
+impl WatchEventKind {
+    fn mask(self) -> u32 {
+        match self {
+            WatchEventKind::Create => WATCH_CREATE,
+            WatchEventKind::Modify => WATCH_MODIFY,
+            WatchEventKind::Delete => WATCH_DELETE,
+            WatchEventKind::Rename => WATCH_RENAME,
+            WatchEventKind::CloseWrite => WATCH_CLOSE_WRITE,
+            WatchEventKind::Overflow => WATCH_ALL,
+        }
+    }
+}
+
+/// Change of a watched path.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WatchEvent {
+    pub kind: WatchEventKind,
+    /// Path relative to the file system root, the old path of a renamed entry
+    pub path: String,
+    /// New path of a renamed entry
+    pub new_path: Option<String>,
+    pub is_dir: bool,
+}
+
+impl WatchEvent {
+    pub fn new(kind: WatchEventKind, path: String, is_dir: bool) -> WatchEvent {
+        WatchEvent {
+            kind,
+            path,
+            new_path: None,
+            is_dir,
+        }
+    }
+
+    pub fn rename(path: String, new_path: String, is_dir: bool) -> WatchEvent {
+        WatchEvent {
+            kind: WatchEventKind::Rename,
+            path,
+            new_path: Some(new_path),
+            is_dir,
+        }
+    }
+}
+
+pub type WatchCallback = Box<dyn FnMut(&WatchEvent)>;
+
+enum Target {
+    // the callback is taken out while it runs
+    Callback(Option<WatchCallback>),
+    Queue(VecDeque<WatchEvent>),
+}
+
+struct Watch {
+    prefix: String,
+    mask: u32,
+    target: Target,
+}
+
+impl Watch {
+    fn matches(&self, event: &WatchEvent) -> bool {
+        self.mask & event.kind.mask() != 0
+            && (is_prefix(&self.prefix, &event.path)
+                || event
+                    .new_path
+                    .as_ref()
+                    .is_some_and(|path| is_prefix(&self.prefix, path)))
+    }
+}
+
+fn is_prefix(prefix: &str, path: &str) -> bool {
+    prefix.is_empty()
+        || path == prefix
+        || (path.starts_with(prefix) && path.as_bytes()[prefix.len()] == b'/')
+}
+
+/// Watched path prefixes and the descriptors that changed their file since they were opened.
+#[derive(Default)]
+pub struct Watches {
+    next_id: WatchId,
+    watches: BTreeMap<WatchId, Watch>,
+    written: BTreeSet<Fd>,
+}
+
+impl Watches {
+    pub fn new() -> Watches {
+        Self::default()
+    }
+
+    pub fn is_watching(&self) -> bool {
+        !self.watches.is_empty()
+    }
+
+    fn add(&mut self, prefix: &str, mask: u32, target: Target) -> WatchId {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.watches.insert(
+            id,
+            Watch {
+                prefix: prefix.trim_matches('/').to_string(),
+                mask,
+                target,
+            },
+        );
+
+        id
+    }
+
+    pub fn add_callback(&mut self, prefix: &str, mask: u32, callback: WatchCallback) -> WatchId {
+        self.add(prefix, mask, Target::Callback(Some(callback)))
+    }
+
+    pub fn add_queue(&mut self, prefix: &str, mask: u32) -> WatchId {
+        self.add(prefix, mask, Target::Queue(VecDeque::new()))
+    }
+
+    pub fn remove(&mut self, id: WatchId) -> bool {
+        let removed = self.watches.remove(&id).is_some();
+
+        if self.watches.is_empty() {
+            self.written.clear();
+        }
+
+        removed
+    }
+
+    // Take the queued events, `None` if the watch does not exist or has a callback.
+    pub fn take_events(&mut self, id: WatchId) -> Option<Vec<WatchEvent>> {
+        match &mut self.watches.get_mut(&id)?.target {
+            Target::Queue(events) => Some(events.drain(..).collect()),
+            Target::Callback(_) => None,
+        }
+    }
+
+    pub fn mark_written(&mut self, fd: Fd) {
+        self.written.insert(fd);
+    }
+
+    // Forget the descriptor, returns true if it changed its file.
+    pub fn take_written(&mut self, fd: Fd) -> bool {
+        self.written.remove(&fd)
+    }
+
+    pub fn renumber(&mut self, from: Fd, to: Fd) {
+        if self.written.remove(&from) {
+            self.written.insert(to);
+        } else {
+            self.written.remove(&to);
+        }
+    }
+
+    // Queue the event for the matching queue watches and take out the matching callbacks,
+    // which are run by the caller and then restored.
+    pub fn notify(&mut self, event: &WatchEvent) -> Vec<(WatchId, WatchCallback)> {
+        let mut callbacks = Vec::new();
+
+        for (id, watch) in self.watches.iter_mut() {
+            if !watch.matches(event) {
+                continue;
+            }
+
+            match &mut watch.target {
+                Target::Callback(callback) => {
+                    if let Some(callback) = callback.take() {
+                        callbacks.push((*id, callback));
+                    }
+                }
+                Target::Queue(events) => {
+                    // repeated writes of a file are reported once
+                    if events.back() == Some(event) && event.kind == WatchEventKind::Modify {
+                        continue;
+                    }
+
+                    if events.len() + 1 < MAX_QUEUED_EVENTS {
+                        events.push_back(event.clone());
+                    } else if events
+                        .back()
+                        .is_none_or(|last| last.kind != WatchEventKind::Overflow)
+                    {
+                        events.push_back(WatchEvent::new(
+                            WatchEventKind::Overflow,
+                            String::new(),
+                            false,
+                        ));
+                    }
+                }
+            }
+        }
+
+        callbacks
+    }
+
+    // Put the callback back unless the watch was removed while it was running.
+    pub fn restore(&mut self, id: WatchId, callback: WatchCallback) {
+        if let Some(Watch {
+            target: Target::Callback(slot @ None),
+            ..
+        }) = self.watches.get_mut(&id)
+        {
+            *slot = Some(callback);
+        }
+    }
+}
+
+/// Call the function for the changes of the paths starting with `prefix` (relative to the file system root, `""` watches everything).
+/// The `mask` selects the events, e.g. `WATCH_CREATE | WATCH_DELETE` or `WATCH_ALL`: creating, writing or resizing, deleting,
+/// renaming a file or a directory and closing a descriptor that changed its file. A rename is reported if either path is watched.
+///
+/// The events are reported by the WASI functions after the change, so the callback can use the file system.
+/// The events caused by the callback itself are not reported to it.
+#[cfg(feature = "watches")]
+pub fn add_watch<F>(prefix: &str, mask: u32, callback: F) -> WatchId
+where
+    F: FnMut(&WatchEvent) + 'static,
+{
+    WATCHES.with_borrow_mut(|watches| watches.add_callback(prefix, mask, Box::new(callback)))
+}
+
+/// Queue the changes of the paths starting with `prefix` until they are taken with `take_watch_events`, see `add_watch`.
+/// Repeated writes of a file are queued once, a full queue ends with an `Overflow` event (`MAX_QUEUED_EVENTS`).
+#[cfg(feature = "watches")]
+pub fn add_watch_queue(prefix: &str, mask: u32) -> WatchId {
+    WATCHES.with_borrow_mut(|watches| watches.add_queue(prefix, mask))
+}
+
+/// Take the queued events of a watch, returns `ERRNO_INVAL` for an unknown watch or a watch with a callback
+#[cfg(feature = "watches")]
+pub fn take_watch_events(id: WatchId) -> Result<Vec<WatchEvent>, i32> {
+    WATCHES
+        .with_borrow_mut(|watches| watches.take_events(id))
+        .ok_or(wasi::ERRNO_INVAL.raw() as i32)
+}
+
+/// Stop watching, returns `ERRNO_INVAL` for an unknown watch
+#[cfg(feature = "watches")]
+pub fn remove_watch(id: WatchId) -> i32 {
+    if WATCHES.with_borrow_mut(|watches| watches.remove(id)) {
+        wasi::ERRNO_SUCCESS.raw() as i32
+    } else {
+        wasi::ERRNO_INVAL.raw() as i32
+    }
+}
+
+#[cfg(feature = "watches")]
+pub(crate) fn is_watching() -> bool {
+    WATCHES.with_borrow(|watches| watches.is_watching())
+}
+
+// Event of a path relative to the descriptor, `None` if nothing is watched or the path is unknown.
+#[cfg(feature = "watches")]
+pub(crate) fn watch_event(
+    fs: &FileSystem,
+    fd: Fd,
+    path: &str,
+    kind: WatchEventKind,
+    is_dir: bool,
+) -> Option<WatchEvent> {
+    if !is_watching() {
+        return None;
+    }
+
+    Some(WatchEvent::new(kind, root_path(fs, fd, path)?, is_dir))
+}
+
+// Event of a file changed through the descriptor, which reports `CloseWrite` when it is closed.
+#[cfg(feature = "watches")]
+pub(crate) fn file_changed(fs: &FileSystem, fd: Fd, kind: WatchEventKind) -> Option<WatchEvent> {
+    if !is_watching() {
+        return None;
+    }
+
+    WATCHES.with_borrow_mut(|watches| watches.mark_written(fd));
+    watch_event(fs, fd, "", kind, false)
+}
+
+// Event of a descriptor closed after it changed its file.
+#[cfg(feature = "watches")]
+pub(crate) fn file_closed(fs: &FileSystem, fd: Fd) -> Option<WatchEvent> {
+    if !WATCHES.with_borrow_mut(|watches| watches.take_written(fd)) {
+        return None;
+    }
+
+    watch_event(fs, fd, "", WatchEventKind::CloseWrite, false)
+}
+
+// Event of an entry renamed between two directories, given as descriptors with the paths relative to them.
+#[cfg(feature = "watches")]
+pub(crate) fn rename_event(
+    fs: &FileSystem,
+    (old_fd, old_path): (Fd, &str),
+    (new_fd, new_path): (Fd, &str),
+    is_dir: bool,
+) -> Option<WatchEvent> {
+    if !is_watching() {
+        return None;
+    }
+
+    Some(WatchEvent::rename(
+        root_path(fs, old_fd, old_path)?,
+        root_path(fs, new_fd, new_path)?,
+        is_dir,
+    ))
+}
+
+// Deliver the event once the file system is released, so that the callbacks can use it.
+#[cfg(feature = "watches")]
+pub(crate) fn notify_watches(event: Option<WatchEvent>) {
+    let Some(event) = event else {
+        return;
+    };
+
+    let callbacks = WATCHES.with_borrow_mut(|watches| watches.notify(&event));
+
+    for (id, mut callback) in callbacks {
+        callback(&event);
+        WATCHES.with_borrow_mut(|watches| watches.restore(id, callback));
+    }
+}
+
+#[cfg(not(feature = "watches"))]
+pub(crate) fn is_watching() -> bool {
+    false
+}
+
+#[cfg(not(feature = "watches"))]
+pub(crate) fn watch_event(
+    _: &FileSystem,
+    _: Fd,
+    _: &str,
+    _: WatchEventKind,
+    _: bool,
+) -> Option<WatchEvent> {
+    None
+}
+
+#[cfg(not(feature = "watches"))]
+pub(crate) fn file_changed(_: &FileSystem, _: Fd, _: WatchEventKind) -> Option<WatchEvent> {
+    None
+}
+
+#[cfg(not(feature = "watches"))]
+pub(crate) fn file_closed(_: &FileSystem, _: Fd) -> Option<WatchEvent> {
+    None
+}
+
+#[cfg(not(feature = "watches"))]
+pub(crate) fn rename_event(
+    _: &FileSystem,
+    _: (Fd, &str),
+    _: (Fd, &str),
+    _: bool,
+) -> Option<WatchEvent> {
+    None
+}
+
+#[cfg(not(feature = "watches"))]
+pub(crate) fn notify_watches(_: Option<WatchEvent>) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queue_and_callback_watches() {
+        let mut watches = Watches::new();
+        let queue = watches.add_queue("/data/", WATCH_MODIFY | WATCH_RENAME);
+        let callback = watches.add_callback("", WATCH_ALL, Box::new(|_| {}));
+
+        let modify = WatchEvent::new(WatchEventKind::Modify, "data/a.txt".to_string(), false);
+        assert_eq!(watches.notify(&modify).len(), 1);
+        // the callback is running
+        assert!(watches.notify(&modify).is_empty());
+
+        watches.notify(&WatchEvent::new(
+            WatchEventKind::Modify,
+            "database".to_string(),
+            false,
+        ));
+        watches.notify(&WatchEvent::rename(
+            "tmp/b".to_string(),
+            "data/b".to_string(),
+            false,
+        ));
+
+        assert_eq!(
+            watches.take_events(queue).unwrap(),
+            vec![
+                modify,
+                WatchEvent::rename("tmp/b".to_string(), "data/b".to_string(), false)
+            ]
+        );
+        assert_eq!(watches.take_events(callback), None);
+
+        for _ in 0..MAX_QUEUED_EVENTS + 1 {
+            watches.notify(&WatchEvent::rename(
+                "data/a".to_string(),
+                "data/b".to_string(),
+                false,
+            ));
+        }
+        let events = watches.take_events(queue).unwrap();
+        assert_eq!(events.len(), MAX_QUEUED_EVENTS);
+        assert_eq!(events.last().unwrap().kind, WatchEventKind::Overflow);
+
+        watches.mark_written(5);
+        watches.renumber(5, 6);
+        assert!(!watches.take_written(5) && watches.take_written(6));
+
+        assert!(watches.remove(callback));
+        watches.restore(callback, Box::new(|_| {}));
+        assert!(!watches.remove(callback));
+    }
+}
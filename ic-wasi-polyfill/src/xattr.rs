@@ -0,0 +1,352 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Memory, StableBTreeMap, Storable};
+use stable_fs::fs::{Fd, FileSystem};
+use stable_fs::storage::types::{Metadata, Node};
+
+#[cfg(feature = "xattrs")]
+use crate::{
+    content_hash::is_content_hashing, permissions::SYSTEM_XATTR_PREFIX, root_node, wasi, XATTRS,
+};
+
+#[cfg(feature = "content_hashes")]
+use crate::{content_hash::*, CONTENT_HASHES};
+
+/// Maximum length of an extended attribute name in bytes.
+pub const XATTR_NAME_MAX: usize = 255;
+
+/// Maximum size of an extended attribute value in bytes.
+pub const XATTR_SIZE_MAX: usize = 65536;
+
+/// Extended attribute of a file system node.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct XattrKey {
+    pub node: Node,
+    pub name: String,
+}
+
+impl Storable for XattrKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(self.clone().into_bytes())
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(8 + self.name.len());
+        bytes.extend_from_slice(&self.node.to_be_bytes());
+        bytes.extend_from_slice(self.name.as_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let (node, name) = bytes.split_at(8);
+
+        XattrKey {
+            node: Node::from_be_bytes(node.try_into().unwrap()),
+            name: String::from_utf8(name.to_vec()).unwrap(),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 8 + XATTR_NAME_MAX as u32,
+        is_fixed_size: false,
+    };
+}
+
+/// Memory provided by the user for storing the extended attributes.
+pub struct BoxedMemory(pub Box<dyn Memory>);
+
+impl Memory for BoxedMemory {
+    fn size(&self) -> u64 {
+        self.0.size()
+    }
+
+    fn grow(&self, pages: u64) -> i64 {
+        self.0.grow(pages)
+    }
+
+    fn read(&self, offset: u64, dst: &mut [u8]) {
+        self.0.read(offset, dst)
+    }
+
+    fn write(&self, offset: u64, src: &[u8]) {
+        self.0.write(offset, src)
+    }
+}
+
+enum XattrStore {
+    Heap(BTreeMap<XattrKey, Vec<u8>>),
+    Stable(StableBTreeMap<XattrKey, Vec<u8>, BoxedMemory>),
+}
+
+/// Extended attributes of the file system nodes.
+///
+/// The attributes are keyed by the node, so they stay with the file when it is renamed or linked.
+/// They are kept on the heap unless a stable memory is provided.
+pub struct Xattrs {
+    store: XattrStore,
+}
+
+impl Default for Xattrs {
+    fn default() -> Self {
+        Xattrs {
+            store: XattrStore::Heap(BTreeMap::new()),
+        }
+    }
+}
+
+impl Xattrs {
+    pub fn new() -> Xattrs {
+        Self::default()
+    }
+
+    // Load the attributes from the memory, an empty memory is initialized.
+    pub fn with_memory(memory: Box<dyn Memory>) -> Xattrs {
+        Xattrs {
+            store: XattrStore::Stable(StableBTreeMap::init(BoxedMemory(memory))),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match &self.store {
+            XattrStore::Heap(map) => map.is_empty(),
+            XattrStore::Stable(map) => map.is_empty(),
+        }
+    }
+
+    pub fn get(&self, node: Node, name: &str) -> Option<Vec<u8>> {
+        let key = XattrKey {
+            node,
+            name: name.to_string(),
+        };
+
+        match &self.store {
+            XattrStore::Heap(map) => map.get(&key).cloned(),
+            XattrStore::Stable(map) => map.get(&key),
+        }
+    }
+
+    pub fn set(&mut self, node: Node, name: &str, value: &[u8]) {
+        let key = XattrKey {
+            node,
+            name: name.to_string(),
+        };
+
+        match &mut self.store {
+            XattrStore::Heap(map) => map.insert(key, value.to_vec()),
+            XattrStore::Stable(map) => map.insert(key, value.to_vec()),
+        };
+    }
+
+    pub fn remove(&mut self, node: Node, name: &str) -> bool {
+        let key = XattrKey {
+            node,
+            name: name.to_string(),
+        };
+
+        match &mut self.store {
+            XattrStore::Heap(map) => map.remove(&key).is_some(),
+            XattrStore::Stable(map) => map.remove(&key).is_some(),
+        }
+    }
+
+    // Names of the node's attributes in the sorted order.
+    pub fn list(&self, node: Node) -> Vec<String> {
+        let start = XattrKey {
+            node,
+            name: String::new(),
+        };
+
+        match &self.store {
+            XattrStore::Heap(map) => map
+                .range(start..)
+                .take_while(|(key, _)| key.node == node)
+                .map(|(key, _)| key.name.clone())
+                .collect(),
+            XattrStore::Stable(map) => map
+                .keys_range(start..)
+                .take_while(|key| key.node == node)
+                .map(|key| key.name)
+                .collect(),
+        }
+    }
+
+    // Remove all the attributes of a deleted node.
+    pub fn remove_node(&mut self, node: Node) {
+        for name in self.list(node) {
+            self.remove(node, &name);
+        }
+    }
+}
+
+/// Keep the extended attributes in the provided stable memory, the attributes already stored in the memory are loaded.
+/// `init_with_polyfill_memories` keeps them in a memory of the polyfill memory index range, otherwise
+/// without calling this function the attributes are kept on the heap and do not persist across upgrades.
+///
+/// # Parameters
+/// - `memory`: A memory used for the extended attributes only, it should be provided again after an upgrade
+#[cfg(feature = "xattrs")]
+pub fn init_xattrs_with_memory<M: Memory + 'static>(memory: M) {
+    XATTRS.with_borrow_mut(|xattrs| *xattrs = Xattrs::with_memory(Box::new(memory)))
+}
+
+// The system attributes are only changed by the polyfill.
+#[cfg(feature = "xattrs")]
+fn check_xattr_name(name: &str) -> Result<(), i32> {
+    if name.starts_with(SYSTEM_XATTR_PREFIX) {
+        Err(wasi::ERRNO_PERM.raw() as i32)
+    } else if name.is_empty() {
+        Err(wasi::ERRNO_INVAL.raw() as i32)
+    } else if name.len() > XATTR_NAME_MAX {
+        Err(wasi::ERRNO_NAMETOOLONG.raw() as i32)
+    } else {
+        Ok(())
+    }
+}
+
+/// Set an extended attribute of a file or a directory, e.g. `set_xattr("data/file.txt", "user.mime", b"text/plain")`.
+/// The names are limited to 255 bytes and the values to 64 KiB (`ERRNO_NAMETOOLONG` and `ERRNO_2BIG`).
+/// The attributes stay with the file when it is renamed and are deleted with its last link.
+///
+/// # Parameters
+/// - `path`: Path relative to the file system root
+/// - `name`: Name of the attribute
+/// - `value`: Value of the attribute, replaces the previous value
+#[cfg(feature = "xattrs")]
+pub fn set_xattr(path: &str, name: &str, value: &[u8]) -> i32 {
+    if let Err(errno) = check_xattr_name(name) {
+        return errno;
+    }
+
+    if value.len() > XATTR_SIZE_MAX {
+        return wasi::ERRNO_2BIG.raw() as i32;
+    }
+
+    match root_node(path) {
+        Ok(node) => {
+            XATTRS.with_borrow_mut(|xattrs| xattrs.set(node, name, value));
+            wasi::ERRNO_SUCCESS.raw() as i32
+        }
+        Err(errno) => errno,
+    }
+}
+
+/// Get an extended attribute of a file or a directory, returns `ERRNO_NOENT` if the attribute is not set.
+/// The `system.sha256` attribute of a file is always available, see `get_file_hash`.
+#[cfg(feature = "xattrs")]
+pub fn get_xattr(path: &str, name: &str) -> Result<Vec<u8>, i32> {
+    #[cfg(feature = "content_hashes")]
+    if name == HASH_XATTR {
+        return get_file_hash(path).map(|hash| hash.to_vec());
+    }
+
+    let node = root_node(path)?;
+
+    XATTRS
+        .with_borrow(|xattrs| xattrs.get(node, name))
+        .ok_or(wasi::ERRNO_NOENT.raw() as i32)
+}
+
+/// List the names of the extended attributes of a file or a directory in the sorted order
+#[cfg(feature = "xattrs")]
+pub fn list_xattrs(path: &str) -> Result<Vec<String>, i32> {
+    let node = root_node(path)?;
+
+    let mut names = XATTRS.with_borrow(|xattrs| xattrs.list(node));
+    names.retain(|name| !name.starts_with(SYSTEM_XATTR_PREFIX));
+
+    Ok(names)
+}
+
+/// Remove an extended attribute of a file or a directory, returns `ERRNO_NOENT` if the attribute is not set
+#[cfg(feature = "xattrs")]
+pub fn remove_xattr(path: &str, name: &str) -> i32 {
+    if let Err(errno) = check_xattr_name(name) {
+        return errno;
+    }
+
+    match root_node(path) {
+        Ok(node) => {
+            if XATTRS.with_borrow_mut(|xattrs| xattrs.remove(node, name)) {
+                wasi::ERRNO_SUCCESS.raw() as i32
+            } else {
+                wasi::ERRNO_NOENT.raw() as i32
+            }
+        }
+        Err(errno) => errno,
+    }
+}
+
+// Metadata of the entry about to be removed, only needed if there are extended attributes or content hashes to delete with it.
+#[cfg(feature = "xattrs")]
+pub(crate) fn removed_entry(fs: &mut FileSystem, parent_fd: Fd, path: &str) -> Option<Metadata> {
+    if XATTRS.with_borrow(|xattrs| xattrs.is_empty()) && !is_content_hashing() {
+        return None;
+    }
+
+    fs.open_metadata(parent_fd, path).ok()
+}
+
+// Delete the extended attributes of the removed node unless it has other links.
+#[cfg(feature = "xattrs")]
+pub(crate) fn forget_removed_entry(removed: Option<Metadata>) {
+    if let Some(metadata) = removed {
+        if metadata.link_count <= 1 {
+            XATTRS.with_borrow_mut(|xattrs| xattrs.remove_node(metadata.node));
+            #[cfg(feature = "content_hashes")]
+            CONTENT_HASHES.with_borrow_mut(|hashes| hashes.forget(metadata.node));
+        }
+    }
+}
+
+#[cfg(not(feature = "xattrs"))]
+pub(crate) fn removed_entry(_: &mut FileSystem, _: Fd, _: &str) -> Option<Metadata> {
+    None
+}
+
+#[cfg(not(feature = "xattrs"))]
+pub(crate) fn forget_removed_entry(_: Option<Metadata>) {}
+
+#[cfg(test)]
+mod tests {
+    use super::{XattrKey, Xattrs};
+    use ic_stable_structures::{DefaultMemoryImpl, Storable};
+    use std::borrow::Cow;
+
+    fn check_store(xattrs: &mut Xattrs) {
+        assert!(xattrs.is_empty());
+
+        xattrs.set(2, "user.mime", b"text/plain");
+        xattrs.set(2, "user.checksum", b"1234");
+        xattrs.set(3, "user.mime", b"image/png");
+
+        assert_eq!(xattrs.get(2, "user.mime"), Some(b"text/plain".to_vec()));
+        assert_eq!(xattrs.get(2, "user.owner"), None);
+        assert_eq!(xattrs.list(2), vec!["user.checksum", "user.mime"]);
+
+        assert!(xattrs.remove(2, "user.checksum"));
+        assert!(!xattrs.remove(2, "user.checksum"));
+
+        xattrs.remove_node(2);
+        assert!(xattrs.list(2).is_empty());
+        assert_eq!(xattrs.list(3), vec!["user.mime"]);
+    }
+
+    #[test]
+    fn heap_and_stable_stores() {
+        check_store(&mut Xattrs::new());
+        check_store(&mut Xattrs::with_memory(Box::new(
+            DefaultMemoryImpl::default(),
+        )));
+
+        let key = XattrKey {
+            node: 42,
+            name: "user.name".to_string(),
+        };
+        assert_eq!(
+            XattrKey::from_bytes(Cow::Owned(key.to_bytes().to_vec())),
+            key
+        );
+    }
+}
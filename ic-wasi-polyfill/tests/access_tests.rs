@@ -0,0 +1,285 @@
+#![cfg(feature = "access_rules")]
+
+mod common;
+
+use common::*;
+use ic_wasi_polyfill::access::{AccessRule, Principals};
+use ic_wasi_polyfill::wasi;
+use ic_wasi_polyfill::*;
+
+const ROOT_FD: u32 = 3;
+
+const ACCES: i32 = wasi::ERRNO_ACCES.raw() as i32;
+
+const CONTROLLER: &str = "aaaaa-aa";
+const USER: &str = "2vxsx-fae";
+
+fn open(
+    parent_fd: wasi::Fd,
+    file_name: &str,
+    oflags: wasi::Oflags,
+    rights: wasi::Rights,
+) -> (i32, wasi::Fd) {
+    let mut fd = 0;
+    let ret = unsafe {
+        __ic_custom_path_open(
+            parent_fd,
+            0,
+            file_name.as_ptr(),
+            file_name.len() as i32,
+            oflags as i32,
+            rights,
+            rights,
+            0,
+            &mut fd,
+        )
+    };
+
+    (ret, fd)
+}
+
+fn create_dir(parent_fd: wasi::Fd, path: &str) -> i32 {
+    unsafe { __ic_custom_path_create_directory(parent_fd, path.as_ptr(), path.len() as i32) }
+}
+
+fn unlink(parent_fd: wasi::Fd, path: &str) -> i32 {
+    unsafe { __ic_custom_path_unlink_file(parent_fd as i32, path.as_ptr(), path.len() as i32) }
+}
+
+fn rename(old_path: &str, new_path: &str) -> i32 {
+    unsafe {
+        __ic_custom_path_rename(
+            ROOT_FD as i32,
+            old_path.as_ptr(),
+            old_path.len() as i32,
+            ROOT_FD as i32,
+            new_path.as_ptr(),
+            new_path.len() as i32,
+        )
+    }
+}
+
+fn init_policies() {
+    init(&[], &[]);
+    set_mock_controllers(&[CONTROLLER]);
+    set_mock_caller(CONTROLLER);
+
+    assert_eq!(create_dir(ROOT_FD, "config"), 0);
+    assert_eq!(create_dir(ROOT_FD, "private"), 0);
+    fd_close(create_test_file(ROOT_FD, "config/settings.txt"));
+    fd_close(create_test_file(ROOT_FD, "private/data.txt"));
+
+    set_access_rule("/config", AccessRule::writable_by(Principals::Controllers));
+    set_access_rule(
+        "private",
+        AccessRule {
+            read: Principals::Only(vec![CONTROLLER.to_string()]),
+            write: Principals::Only(vec![CONTROLLER.to_string()]),
+            list: Principals::Nobody,
+        },
+    );
+}
+
+#[test]
+fn test_only_controllers_write_config() {
+    init_policies();
+
+    set_mock_caller(USER);
+
+    // reading is allowed
+    let (ret, fd) = open(ROOT_FD, "config/settings.txt", 0, wasi::RIGHTS_FD_READ);
+    assert_eq!(ret, 0);
+    fd_close(fd);
+
+    assert_eq!(
+        open(ROOT_FD, "config/settings.txt", 0, DEFAULT_RIGHTS).0,
+        ACCES
+    );
+    assert_eq!(
+        open(
+            ROOT_FD,
+            "config/new.txt",
+            wasi::OFLAGS_CREAT,
+            wasi::RIGHTS_FD_READ
+        )
+        .0,
+        ACCES
+    );
+    assert_eq!(create_dir(ROOT_FD, "config/dir"), ACCES);
+    assert_eq!(unlink(ROOT_FD, "config/settings.txt"), ACCES);
+    assert_eq!(rename("config/settings.txt", "settings.txt"), ACCES);
+
+    // relative paths cannot bypass the rule
+    assert_eq!(create_dir(ROOT_FD, "data"), 0);
+    assert_eq!(rename("data", "./data/../config/data"), ACCES);
+
+    let (ret, dir_fd) = open(
+        ROOT_FD,
+        "config",
+        wasi::OFLAGS_DIRECTORY,
+        wasi::RIGHTS_FD_READDIR,
+    );
+    assert_eq!(ret, 0);
+    assert_eq!(unlink(dir_fd, "settings.txt"), ACCES);
+
+    // other paths are not restricted
+    fd_close(create_test_file(ROOT_FD, "configs.txt"));
+    assert_eq!(rename("configs.txt", "data/configs.txt"), 0);
+
+    set_mock_caller(CONTROLLER);
+    assert_eq!(unlink(dir_fd, "settings.txt"), 0);
+    fd_close(dir_fd);
+
+    let (ret, fd) = open(
+        ROOT_FD,
+        "config/new.txt",
+        wasi::OFLAGS_CREAT,
+        DEFAULT_RIGHTS,
+    );
+    assert_eq!(ret, 0);
+    fd_close(fd);
+
+    clear_access_rules();
+}
+
+#[test]
+fn test_rules_for_listed_principals() {
+    init_policies();
+
+    set_mock_caller(USER);
+    assert_eq!(
+        open(ROOT_FD, "private/data.txt", 0, wasi::RIGHTS_FD_READ).0,
+        ACCES
+    );
+
+    set_mock_caller(CONTROLLER);
+    let (ret, fd) = open(ROOT_FD, "private/data.txt", 0, wasi::RIGHTS_FD_READ);
+    assert_eq!(ret, 0);
+    fd_close(fd);
+
+    // listing is denied to everyone
+    assert_eq!(
+        open(
+            ROOT_FD,
+            "private",
+            wasi::OFLAGS_DIRECTORY,
+            wasi::RIGHTS_FD_READDIR
+        )
+        .0,
+        ACCES
+    );
+
+    remove_access_rule("private");
+    let (ret, fd) = open(
+        ROOT_FD,
+        "private",
+        wasi::OFLAGS_DIRECTORY,
+        wasi::RIGHTS_FD_READDIR,
+    );
+    assert_eq!(ret, 0);
+    fd_close(fd);
+
+    clear_access_rules();
+}
+
+#[test]
+fn test_link_requires_read_access() {
+    init_policies();
+
+    set_mock_caller(USER);
+    let old_path = "private/data.txt";
+    let new_path = "data.txt";
+    let ret = unsafe {
+        __ic_custom_path_link(
+            ROOT_FD,
+            0,
+            old_path.as_ptr(),
+            old_path.len() as i32,
+            ROOT_FD,
+            new_path.as_ptr(),
+            new_path.len() as i32,
+        )
+    };
+    assert_eq!(ret, ACCES);
+
+    clear_access_rules();
+}
+
+#[test]
+fn test_listing_is_checked_for_any_directory() {
+    init_policies();
+
+    // opening a directory without `OFLAGS_DIRECTORY` still lists it
+    assert_eq!(
+        open(ROOT_FD, "private", 0, wasi::RIGHTS_FD_READDIR).0,
+        ACCES
+    );
+
+    // the descriptors opened before the rule are checked when listing
+    remove_access_rule("private");
+    let (ret, dir_fd) = open(ROOT_FD, "private", 0, wasi::RIGHTS_FD_READDIR);
+    assert_eq!(ret, 0);
+
+    set_access_rule(
+        "private",
+        AccessRule {
+            read: Principals::Anyone,
+            write: Principals::Anyone,
+            list: Principals::Nobody,
+        },
+    );
+
+    let mut buf = [0u8; 128];
+    let mut used = 0;
+    let ret =
+        unsafe { __ic_custom_fd_readdir(dir_fd, buf.as_mut_ptr(), buf.len() as i32, 0, &mut used) };
+    assert_eq!(ret, ACCES);
+    fd_close(dir_fd);
+
+    clear_access_rules();
+}
+
+#[test]
+fn test_descriptors_follow_renamed_directories() {
+    init_policies();
+
+    assert_eq!(create_dir(ROOT_FD, "public"), 0);
+    assert_eq!(create_dir(ROOT_FD, "public/dir"), 0);
+    let (ret, dir_fd) = open(
+        ROOT_FD,
+        "public/dir",
+        wasi::OFLAGS_DIRECTORY,
+        wasi::RIGHTS_FD_READDIR,
+    );
+    assert_eq!(ret, 0);
+
+    assert_eq!(rename("public", "config/public"), 0);
+
+    // the descriptor now refers to a directory under the config rule
+    set_mock_caller(USER);
+    assert_eq!(
+        open(dir_fd, "file.txt", wasi::OFLAGS_CREAT, DEFAULT_RIGHTS).0,
+        ACCES
+    );
+
+    set_mock_caller(CONTROLLER);
+    fd_close(dir_fd);
+
+    clear_access_rules();
+}
+
+#[cfg(feature = "trace_wasi_calls")]
+#[test]
+fn test_denied_calls_are_traced() {
+    init_policies();
+    clear_trace();
+
+    set_mock_caller(USER);
+    assert_eq!(unlink(ROOT_FD, "config/settings.txt"), ACCES);
+
+    let records = take_trace_records();
+    assert_eq!(records.last().unwrap().function, "path_unlink_file");
+    assert_eq!(records.last().unwrap().errno, ACCES);
+
+    clear_access_rules();
+}
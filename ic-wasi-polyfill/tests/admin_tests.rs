@@ -0,0 +1,94 @@
+#![cfg(feature = "admin")]
+
+mod common;
+
+use common::*;
+use ic_wasi_polyfill::admin::{self, AdminEntry, AdminUsage, EntryKind};
+use ic_wasi_polyfill::*;
+
+const ROOT_FD: u32 = 3;
+
+const CONTROLLER: &str = "aaaaa-aa";
+const USER: &str = "2vxsx-fae";
+
+// the exported methods are only compiled, the canister entry points cannot be called on the host
+ic_wasi_polyfill::export_admin_interface!();
+
+#[test]
+fn test_edit_files() {
+    init(&[], &[]);
+
+    assert_eq!(admin::mkdir("docs"), Ok(()));
+    assert_eq!(admin::write_range("docs/a.txt", 0, b"hello"), Ok(5));
+    assert_eq!(admin::write_range("/docs/a.txt", 5, b" world"), Ok(6));
+    fd_close(create_test_file_with_content(
+        ROOT_FD,
+        "docs/b.txt",
+        vec!["abc".to_string()],
+    ));
+
+    assert_eq!(
+        admin::read_range("docs/a.txt", 6, 100),
+        Ok(b"world".to_vec())
+    );
+    assert_eq!(
+        admin::read_range("docs/a.txt", 0, 100),
+        Ok(b"hello world".to_vec())
+    );
+
+    let stat = admin::stat("docs/a.txt").unwrap();
+    assert_eq!(
+        (stat.kind, stat.size, stat.link_count),
+        (EntryKind::File, 11, 1)
+    );
+    assert_eq!(admin::stat("/").unwrap().kind, EntryKind::Directory);
+
+    assert_eq!(
+        admin::list_dir("docs"),
+        Ok(vec![
+            AdminEntry {
+                name: "a.txt".to_string(),
+                kind: EntryKind::File,
+                size: 11,
+            },
+            AdminEntry {
+                name: "b.txt".to_string(),
+                kind: EntryKind::File,
+                size: 3,
+            },
+        ])
+    );
+    assert_eq!(
+        admin::usage(),
+        AdminUsage {
+            files: 2,
+            directories: 2,
+            bytes: 14,
+        }
+    );
+
+    assert_eq!(admin::rename("docs/b.txt", "b.txt"), Ok(()));
+    assert_eq!(admin::list_dir("").unwrap().len(), 2);
+
+    // directories must be empty to be deleted
+    assert!(admin::delete("docs").is_err());
+    assert_eq!(admin::delete("docs/a.txt"), Ok(()));
+    assert_eq!(admin::delete("docs"), Ok(()));
+    assert!(admin::stat("docs").is_err());
+
+    assert!(admin::read_range("missing.txt", 0, 1).is_err());
+    assert!(admin::read_range("b.txt", 0, admin::MAX_TRANSFER_SIZE + 1).is_err());
+    assert!(admin::delete("/").is_err());
+}
+
+#[test]
+fn test_controller_guard() {
+    init(&[], &[]);
+    set_mock_controllers(&[CONTROLLER]);
+
+    set_mock_caller(USER);
+    assert!(admin::caller_is_controller().is_err());
+
+    set_mock_caller(CONTROLLER);
+    assert_eq!(admin::caller_is_controller(), Ok(()));
+}
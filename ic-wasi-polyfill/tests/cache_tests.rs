@@ -0,0 +1,166 @@
+#![cfg(feature = "cache")]
+
+mod common;
+
+use common::*;
+use ic_wasi_polyfill::cache::{CACHE_BLOCK_SIZE, READ_AHEAD_BLOCKS};
+use ic_wasi_polyfill::wasi;
+use ic_wasi_polyfill::*;
+
+const ROOT_FD: u32 = 3;
+
+fn advise(fd: wasi::Fd, offset: u64, len: u64, advice: wasi::Advice) {
+    assert_eq!(
+        __ic_custom_fd_advise(fd, offset as i64, len as i64, advice.raw() as i32),
+        0
+    );
+}
+
+fn pattern(len: usize, seed: u8) -> Vec<u8> {
+    (0..len)
+        .map(|i| (i as u8).wrapping_mul(31) ^ seed)
+        .collect()
+}
+
+// Read a file with the cache disabled, from the underlying storage.
+fn stored_content(fd: wasi::Fd, len: usize) -> Vec<u8> {
+    let budget = CACHE.with(|cache| cache.borrow().budget());
+
+    set_cache_budget(0);
+    let data = pread(fd, 0, len);
+    set_cache_budget(budget);
+
+    data
+}
+
+#[test]
+fn test_write_back_on_sync_and_close() {
+    init(&[], &[]);
+    set_cache_budget(16 * CACHE_BLOCK_SIZE);
+
+    let fd = create_test_file_with_content(ROOT_FD, "file.bin", vec![]);
+    let data = pattern(3 * CACHE_BLOCK_SIZE + 100, 1);
+    pwrite(fd, 0, &data);
+
+    let stats = get_cache_stats();
+    assert_eq!(stats.written_back, 0);
+    assert_eq!(stats.dirty_bytes, 4 * CACHE_BLOCK_SIZE as u64);
+
+    // the cached writes are read back before they are stored
+    assert_eq!(pread(fd, 0, data.len() + 10), data);
+    assert_eq!(get_cache_stats().hits, 4);
+
+    assert_eq!(__ic_custom_fd_sync(fd), 0);
+    let stats = get_cache_stats();
+    assert_eq!((stats.written_back, stats.dirty_bytes), (4, 0));
+
+    pwrite(fd, 10, b"changed");
+    assert_eq!(__ic_custom_fd_datasync(fd), 0);
+    assert_eq!(get_cache_stats().written_back, 5);
+
+    pwrite(fd, 5000, b"closed");
+    fd_close(fd);
+    assert_eq!(get_cache_stats().written_back, 6);
+
+    let mut expected = data.clone();
+    expected[10..17].copy_from_slice(b"changed");
+    expected[5000..5006].copy_from_slice(b"closed");
+
+    let fd = open("file.bin");
+    assert_eq!(stored_content(fd, expected.len()), expected);
+    fd_close(fd);
+}
+
+#[test]
+fn test_eviction_and_truncation() {
+    init(&[], &[]);
+    set_cache_budget(2 * CACHE_BLOCK_SIZE);
+
+    let fd = create_test_file_with_content(ROOT_FD, "file.bin", vec![]);
+    let data = pattern(5 * CACHE_BLOCK_SIZE, 7);
+    pwrite(fd, 0, &data);
+
+    // the blocks over the budget are written back as they are evicted
+    let stats = get_cache_stats();
+    assert_eq!((stats.evictions, stats.written_back), (3, 3));
+    assert_eq!(stats.cached_bytes, 2 * CACHE_BLOCK_SIZE as u64);
+    assert_eq!(pread(fd, 0, data.len()), data);
+
+    // the cached data above the new size is dropped, growing the file again reads zeros
+    pwrite(fd, 4 * CACHE_BLOCK_SIZE as u64, b"dirty");
+    pwrite(fd, 0, &data[..10]);
+    assert_eq!(__ic_custom_fd_filestat_set_size(fd, 100), 0);
+    assert_eq!(__ic_custom_fd_filestat_set_size(fd, 200), 0);
+
+    let mut expected = data[..100].to_vec();
+    expected.resize(200, 0);
+    assert_eq!(pread(fd, 0, 1000), expected);
+
+    fd_close(fd);
+    assert_eq!(get_cache_stats().dirty_bytes, 0);
+}
+
+#[test]
+fn test_advice() {
+    init(&[], &[]);
+
+    let fd = create_test_file_with_content(ROOT_FD, "file.bin", vec![]);
+    let data = pattern(32 * CACHE_BLOCK_SIZE, 3);
+    pwrite(fd, 0, &data);
+
+    // without the cache the advice is accepted and ignored
+    advise(fd, 0, 0, wasi::ADVICE_WILLNEED);
+    assert_eq!(get_cache_stats(), Default::default());
+
+    set_cache_budget(16 * CACHE_BLOCK_SIZE);
+
+    advise(fd, 0, 0, wasi::ADVICE_SEQUENTIAL);
+    assert_eq!(pread(fd, 0, 100), data[..100]);
+
+    let stats = get_cache_stats();
+    assert_eq!((stats.misses, stats.prefetched), (1, READ_AHEAD_BLOCKS));
+    assert_eq!(
+        pread(fd, 0, (READ_AHEAD_BLOCKS as usize + 1) * CACHE_BLOCK_SIZE),
+        data[..(READ_AHEAD_BLOCKS as usize + 1) * CACHE_BLOCK_SIZE]
+    );
+    assert_eq!(get_cache_stats().misses, 1);
+
+    advise(fd, 0, 0, wasi::ADVICE_NORMAL);
+    advise(fd, 0, 0, wasi::ADVICE_DONTNEED);
+    assert_eq!(get_cache_stats().cached_bytes, 0);
+
+    // the pinned range stays cached while the rest of the file is read through the cache
+    reset_cache_stats();
+    let pinned = 20 * CACHE_BLOCK_SIZE as u64;
+    advise(
+        fd,
+        pinned,
+        2 * CACHE_BLOCK_SIZE as u64,
+        wasi::ADVICE_WILLNEED,
+    );
+    assert_eq!(get_cache_stats().prefetched, 2);
+
+    assert_eq!(pread(fd, 0, data.len()), data);
+    assert_eq!(
+        pread(fd, pinned, 10),
+        data[pinned as usize..pinned as usize + 10]
+    );
+
+    let stats = get_cache_stats();
+    assert_eq!((stats.misses, stats.hits), (30, 3));
+    assert!(stats.evictions > 0);
+
+    advise(
+        fd,
+        pinned,
+        2 * CACHE_BLOCK_SIZE as u64,
+        wasi::ADVICE_DONTNEED,
+    );
+    assert_eq!(
+        pread(fd, pinned, 10),
+        data[pinned as usize..pinned as usize + 10]
+    );
+    assert_eq!(get_cache_stats().misses, 31);
+
+    fd_close(fd);
+}
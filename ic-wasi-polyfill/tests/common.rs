@@ -1,10 +1,14 @@
 #![allow(dead_code)]
 
+use ic_stable_structures::memory_manager::MemoryManager;
+use ic_stable_structures::DefaultMemoryImpl;
+use ic_wasi_polyfill::memories::{DEFAULT_MEMORY_INDEX_RANGE, DEFAULT_POLYFILL_MEMORY_INDEX_RANGE};
 use ic_wasi_polyfill::wasi;
 
 use ic_wasi_polyfill::wasi::Fd;
 use ic_wasi_polyfill::wasi_helpers::DIRENT_SIZE;
 use ic_wasi_polyfill::*;
+use stable_fs::fs::{FdStat, OpenFlags};
 
 pub mod libc {
     use ic_wasi_polyfill::wasi::Fd;
@@ -183,3 +187,100 @@ pub fn read_file_to_string(file: &str) -> String {
     // Convert to UTF-8 String (returning String or panic if invalid UTF-8)
     String::from_utf8(buf_to_read).expect("Invalid UTF-8 in file")
 }
+
+// Keep the file system and the polyfill data in the memory, as an application opting in to the polyfill memories.
+pub fn init_with_all_memories(memory: DefaultMemoryImpl) {
+    let memory_manager = MemoryManager::init(memory);
+    init_with_polyfill_memories(
+        &[],
+        &[],
+        &memory_manager,
+        DEFAULT_MEMORY_INDEX_RANGE,
+        DEFAULT_POLYFILL_MEMORY_INDEX_RANGE,
+    );
+}
+
+// Open a file of the root directory, the call must succeed.
+pub fn open_with(
+    path: &str,
+    oflags: wasi::Oflags,
+    rights: wasi::Rights,
+    fdflags: wasi::Fdflags,
+) -> Fd {
+    let mut fd = 0;
+    let ret = unsafe {
+        __ic_custom_path_open(
+            3,
+            0,
+            path.as_ptr(),
+            path.len() as i32,
+            oflags as i32,
+            rights,
+            rights,
+            fdflags as i32,
+            &mut fd,
+        )
+    };
+    assert_eq!(ret, 0);
+
+    fd
+}
+
+pub fn open(path: &str) -> Fd {
+    open_with(path, 0, DEFAULT_RIGHTS, 0)
+}
+
+pub fn pwrite(fd: Fd, offset: u64, data: &[u8]) {
+    let src = [wasi::Ciovec {
+        buf: data.as_ptr(),
+        buf_len: data.len(),
+    }];
+    let mut written = 0;
+
+    let ret = unsafe { __ic_custom_fd_pwrite(fd, src.as_ptr(), 1, offset as i64, &mut written) };
+    assert_eq!(ret, 0);
+    assert_eq!(written, data.len());
+}
+
+pub fn pread(fd: Fd, offset: u64, len: usize) -> Vec<u8> {
+    let mut data = vec![0u8; len];
+    let dst = [wasi::Iovec {
+        buf: data.as_mut_ptr(),
+        buf_len: data.len(),
+    }];
+    let mut read = 0;
+
+    let ret = unsafe { __ic_custom_fd_pread(fd, dst.as_ptr(), 1, offset as i64, &mut read) };
+    assert_eq!(ret, 0);
+
+    data.truncate(read);
+    data
+}
+
+// Create or replace a file of the root directory through the WASI functions.
+pub fn create_file(path: &str, content: impl AsRef<[u8]>) {
+    let fd = open_with(
+        path,
+        wasi::OFLAGS_CREAT | wasi::OFLAGS_TRUNC,
+        DEFAULT_RIGHTS,
+        0,
+    );
+    pwrite(fd, 0, content.as_ref());
+    fd_close(fd);
+}
+
+// Content of a file of the root directory read from the file system directly.
+pub fn content(path: &str) -> Vec<u8> {
+    FS.with_borrow_mut(|fs| {
+        let root_fd = fs.root_fd();
+        let metadata = fs.open_metadata(root_fd, path).unwrap();
+        let fd = fs
+            .open(root_fd, path, FdStat::default(), OpenFlags::empty(), 0)
+            .unwrap();
+        let mut buf = vec![0u8; metadata.size as usize];
+        fs.read(fd, &mut buf).unwrap();
+        fs.close(fd).unwrap();
+
+        buf
+    })
+}
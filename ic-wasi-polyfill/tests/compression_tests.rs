@@ -0,0 +1,194 @@
+#![cfg(feature = "compression")]
+
+mod common;
+
+use common::*;
+use ic_stable_structures::memory_manager::MemoryManager;
+use ic_stable_structures::DefaultMemoryImpl;
+use ic_wasi_polyfill::memories::FS_MEMORY_INDEX_COUNT;
+use ic_wasi_polyfill::wasi;
+use ic_wasi_polyfill::*;
+
+const ROOT_FD: u32 = 3;
+
+fn file_size(fd: wasi::Fd) -> u64 {
+    let mut stat = wasi::Filestat {
+        dev: 0,
+        ino: 0,
+        filetype: wasi::FILETYPE_UNKNOWN,
+        nlink: 0,
+        size: 0,
+        atim: 0,
+        mtim: 0,
+        ctim: 0,
+    };
+
+    assert_eq!(unsafe { __ic_custom_fd_filestat_get(fd, &mut stat) }, 0);
+
+    stat.size
+}
+
+fn log_lines(count: usize) -> Vec<u8> {
+    (0..count)
+        .map(|i| format!("{i:08} INFO request handled\n"))
+        .collect::<String>()
+        .into_bytes()
+}
+
+#[test]
+fn test_compressed_files_by_pattern() {
+    init_with_all_memories(DefaultMemoryImpl::default());
+    add_compression_pattern("logs/*.log");
+
+    let dir = "logs";
+    assert_eq!(
+        unsafe { __ic_custom_path_create_directory(ROOT_FD, dir.as_ptr(), dir.len() as i32) },
+        0
+    );
+
+    let fd = create_test_file_with_content(ROOT_FD, "logs/app.log", vec![]);
+    let other = create_test_file_with_content(ROOT_FD, "data.bin", vec![]);
+    assert_eq!(is_file_compressed("logs/app.log"), Ok(true));
+    assert_eq!(is_file_compressed("data.bin"), Ok(false));
+
+    // the data spans several blocks
+    let data = log_lines(2000);
+    pwrite(fd, 0, &data);
+    pwrite(other, 0, &data);
+
+    assert_eq!(file_size(fd), data.len() as u64);
+    assert_eq!(pread(fd, 0, data.len() + 10), data);
+    assert_eq!(pread(fd, 16380, 100), data[16380..16480]);
+
+    let physical = get_file_physical_size("logs/app.log").unwrap();
+    assert!(physical < data.len() as u64 / 4);
+    assert_eq!(get_file_physical_size("data.bin"), Ok(data.len() as u64));
+
+    // shrinking drops the data, growing fills the gap with zeros
+    assert_eq!(__ic_custom_fd_filestat_set_size(fd, 20000), 0);
+    pwrite(fd, 40000, b"end");
+    assert_eq!(file_size(fd), 40003);
+    assert_eq!(
+        pread(fd, 19995, 10),
+        [&data[19995..20000], &[0u8; 5][..]].concat()
+    );
+    assert_eq!(pread(fd, 39998, 10), b"\0\0end");
+
+    fd_close(fd);
+    fd_close(other);
+
+    clear_compression_patterns();
+    fd_close(create_test_file(ROOT_FD, "logs/new.log"));
+    assert_eq!(is_file_compressed("logs/new.log"), Ok(false));
+}
+
+#[test]
+fn test_set_file_compression() {
+    init_with_all_memories(DefaultMemoryImpl::default());
+
+    let fd = create_test_file_with_content(ROOT_FD, "data.json", vec![]);
+    let data = log_lines(1000);
+    pwrite(fd, 0, &data);
+
+    assert_eq!(set_file_compression("data.json", true), 0);
+    assert_eq!(is_file_compressed("data.json"), Ok(true));
+    assert_eq!(pread(fd, 0, data.len()), data);
+    assert!(get_file_physical_size("data.json").unwrap() < data.len() as u64);
+
+    assert_eq!(set_file_compression("data.json", false), 0);
+    assert_eq!(is_file_compressed("data.json"), Ok(false));
+    assert_eq!(pread(fd, 0, data.len()), data);
+    assert_eq!(get_file_physical_size("data.json"), Ok(data.len() as u64));
+
+    fd_close(fd);
+
+    let dir = "dir";
+    assert_eq!(
+        unsafe { __ic_custom_path_create_directory(ROOT_FD, dir.as_ptr(), dir.len() as i32) },
+        0
+    );
+    assert_eq!(
+        set_file_compression("dir", true),
+        wasi::ERRNO_ISDIR.raw() as i32
+    );
+    assert_eq!(
+        set_file_compression("missing.json", true),
+        wasi::ERRNO_NOENT.raw() as i32
+    );
+}
+
+#[test]
+fn test_compressed_files_in_stable_memory() {
+    let fs_memory = DefaultMemoryImpl::default();
+    let memory = DefaultMemoryImpl::default();
+
+    init_with_memory(&[], &[], fs_memory.clone());
+    init_transforms_with_memory(memory.clone());
+
+    let fd = create_test_file(ROOT_FD, "file.txt");
+    fd_close(fd);
+    assert_eq!(set_file_compression("file.txt", true), 0);
+
+    // loading the memories again, as after an upgrade
+    init_with_memory(&[], &[], fs_memory);
+    init_transforms_with_memory(memory);
+
+    assert_eq!(is_file_compressed("file.txt"), Ok(true));
+    assert_eq!(
+        read_file_to_string("file.txt"),
+        "This is a sample text.1234567890"
+    );
+}
+
+#[test]
+fn test_compressed_files_in_polyfill_memories() {
+    let fs_memory = DefaultMemoryImpl::default();
+    init_with_all_memories(fs_memory.clone());
+
+    let fd = create_test_file_with_content(ROOT_FD, "data.json", vec![]);
+    let data = log_lines(1000);
+    pwrite(fd, 0, &data);
+    fd_close(fd);
+    assert_eq!(set_file_compression("data.json", true), 0);
+
+    // another file system does not see the blocks
+    init_with_all_memories(DefaultMemoryImpl::default());
+    assert_eq!(
+        get_file_physical_size("data.json"),
+        Err(wasi::ERRNO_NOENT.raw() as i32)
+    );
+
+    // loading the memory again, as after an upgrade
+    init_with_all_memories(fs_memory);
+
+    assert_eq!(is_file_compressed("data.json"), Ok(true));
+    assert!(get_file_physical_size("data.json").unwrap() < data.len() as u64);
+    let fd = open("data.json");
+    assert_eq!(pread(fd, 0, data.len()), data);
+    fd_close(fd);
+}
+
+#[test]
+fn test_compression_needs_stable_memory() {
+    let memory_manager = MemoryManager::init(DefaultMemoryImpl::default());
+    init_with_memory_manager(&[], &[], &memory_manager, 200..200 + FS_MEMORY_INDEX_COUNT);
+
+    fd_close(create_test_file(ROOT_FD, "file.txt"));
+
+    // the blocks would be kept on the heap while the file system persists
+    assert_eq!(
+        set_file_compression("file.txt", true),
+        wasi::ERRNO_NOTSUP.raw() as i32
+    );
+
+    add_compression_pattern("*.log");
+    fd_close(create_test_file(ROOT_FD, "app.log"));
+    assert_eq!(is_file_compressed("app.log"), Ok(false));
+
+    init_transforms_with_memory(DefaultMemoryImpl::default());
+    assert_eq!(set_file_compression("file.txt", true), 0);
+    assert_eq!(
+        read_file_to_string("file.txt"),
+        "This is a sample text.1234567890"
+    );
+}
@@ -0,0 +1,138 @@
+#![cfg(feature = "content_hashes")]
+
+mod common;
+
+use common::*;
+use ic_wasi_polyfill::content_hash::HASH_XATTR;
+use ic_wasi_polyfill::wasi;
+use ic_wasi_polyfill::*;
+use sha2::{Digest, Sha256};
+
+const ROOT_FD: u32 = 3;
+
+fn write(fd: wasi::Fd, data: &[u8]) {
+    let src = [wasi::Ciovec {
+        buf: data.as_ptr(),
+        buf_len: data.len(),
+    }];
+    let mut written = 0;
+
+    assert_eq!(
+        unsafe { __ic_custom_fd_write(fd, src.as_ptr(), 1, &mut written) },
+        0
+    );
+    assert_eq!(written, data.len());
+}
+
+fn stored_hash(file_name: &str) -> Option<Vec<u8>> {
+    let node = FS.with_borrow_mut(|fs| {
+        let root_fd = fs.root_fd();
+        fs.open_metadata(root_fd, file_name).unwrap().node
+    });
+
+    XATTRS.with_borrow(|xattrs| xattrs.get(node, HASH_XATTR))
+}
+
+fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+#[test]
+fn test_hash_updated_on_close() {
+    init(&[], &[]);
+    set_content_hashing(true);
+
+    let fd = create_test_file_with_content(ROOT_FD, "log.txt", vec![]);
+    write(fd, b"first line\n");
+    write(fd, b"second line\n");
+    assert_eq!(stored_hash("log.txt"), None);
+    fd_close(fd);
+
+    let expected = sha256(b"first line\nsecond line\n");
+    assert_eq!(stored_hash("log.txt"), Some(expected.to_vec()));
+    assert_eq!(get_file_hash("log.txt"), Ok(expected));
+    assert_eq!(get_xattr("log.txt", HASH_XATTR), Ok(expected.to_vec()));
+
+    // the first change removes the stored hash
+    let fd = open_with("log.txt", 0, DEFAULT_RIGHTS, wasi::FDFLAGS_APPEND);
+    write(fd, b"third line\n");
+    assert_eq!(stored_hash("log.txt"), None);
+
+    let expected = sha256(b"first line\nsecond line\nthird line\n");
+    assert_eq!(get_file_hash("log.txt"), Ok(expected));
+    write(fd, b"fourth line\n");
+    fd_close(fd);
+
+    assert_eq!(
+        get_file_hash("log.txt"),
+        Ok(sha256(
+            b"first line\nsecond line\nthird line\nfourth line\n"
+        ))
+    );
+
+    // the system attribute is not listed and cannot be set
+    assert_eq!(list_xattrs("log.txt"), Ok(vec![]));
+    assert_eq!(
+        set_xattr("log.txt", HASH_XATTR, &[0; 32]),
+        wasi::ERRNO_PERM.raw() as i32
+    );
+}
+
+#[test]
+fn test_hash_after_overwrite_and_resize() {
+    init(&[], &[]);
+    set_content_hashing(true);
+
+    let fd = create_test_file_with_content(ROOT_FD, "data.bin", vec!["0123456789".to_string()]);
+    pwrite(fd, 2, b"abc");
+    fd_close(fd);
+    assert_eq!(get_file_hash("data.bin"), Ok(sha256(b"01abc56789")));
+
+    let fd = open("data.bin");
+    assert_eq!(__ic_custom_fd_filestat_set_size(fd, 4), 0);
+    pwrite(fd, 4, b"xy");
+    fd_close(fd);
+    assert_eq!(stored_hash("data.bin"), Some(sha256(b"01abxy").to_vec()));
+
+    // truncating on open starts a new hash
+    let fd = create_test_file_with_content(ROOT_FD, "data.bin", vec!["new".to_string()]);
+    fd_close(fd);
+    assert_eq!(stored_hash("data.bin"), Some(sha256(b"new").to_vec()));
+
+    let dir = "dir";
+    assert_eq!(
+        unsafe { __ic_custom_path_create_directory(ROOT_FD, dir.as_ptr(), dir.len() as i32) },
+        0
+    );
+    assert_eq!(get_file_hash("dir"), Err(wasi::ERRNO_ISDIR.raw() as i32));
+    assert_eq!(
+        get_file_hash("missing.bin"),
+        Err(wasi::ERRNO_NOENT.raw() as i32)
+    );
+}
+
+#[test]
+fn test_hash_without_content_hashing() {
+    init(&[], &[]);
+
+    let fd = create_test_file_with_content(ROOT_FD, "file.txt", vec!["content".to_string()]);
+    fd_close(fd);
+
+    // the hash is computed on demand and not stored
+    assert_eq!(stored_hash("file.txt"), None);
+    assert_eq!(get_file_hash("file.txt"), Ok(sha256(b"content")));
+    assert_eq!(stored_hash("file.txt"), None);
+
+    set_content_hashing(true);
+    assert_eq!(get_file_hash("file.txt"), Ok(sha256(b"content")));
+    assert!(stored_hash("file.txt").is_some());
+
+    // the stored hash is removed by a change made while hashing is disabled
+    set_content_hashing(false);
+    let fd = open("file.txt");
+    pwrite(fd, 0, b"C");
+    fd_close(fd);
+
+    assert_eq!(stored_hash("file.txt"), None);
+    assert_eq!(get_file_hash("file.txt"), Ok(sha256(b"Content")));
+}
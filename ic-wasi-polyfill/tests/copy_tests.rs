@@ -0,0 +1,140 @@
+mod common;
+
+use common::*;
+use ic_wasi_polyfill::wasi;
+use ic_wasi_polyfill::*;
+
+const CHUNK: usize = 4096;
+
+fn tell(fd: wasi::Fd) -> u64 {
+    let mut position = 0;
+    assert_eq!(unsafe { __ic_custom_fd_tell(fd, &mut position) }, 0);
+    position
+}
+
+#[test]
+fn test_copy_file_with_progress() {
+    init(&[], &[]);
+
+    let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    create_file("source.bin", &data);
+    create_file("target.bin", b"the old content is replaced");
+
+    let mut reports = Vec::new();
+    let mut progress = |copied, total| {
+        reports.push((copied, total));
+        true
+    };
+    assert_eq!(
+        copy_file("source.bin", "target.bin", Some(&mut progress)),
+        Ok(200_000)
+    );
+    assert_eq!(content("target.bin"), data);
+
+    // the progress is reported after each chunk of the output
+    let chunk = FS.with_borrow(|fs| fs.storage.chunk_size()) as u64;
+    let expected: Vec<(u64, u64)> = (1..=200_000u64.div_ceil(chunk))
+        .map(|count| ((count * chunk).min(200_000), 200_000))
+        .collect();
+    assert_eq!(reports, expected);
+
+    // the copy stops after the first piece and continues from there
+    let mut stop = |_, _| false;
+    assert_eq!(
+        copy_file("source.bin", "copy.bin", Some(&mut stop)),
+        Ok(chunk)
+    );
+
+    let source = open_with("source.bin", wasi::OFLAGS_CREAT, DEFAULT_RIGHTS, 0);
+    let target = open_with("copy.bin", wasi::OFLAGS_CREAT, DEFAULT_RIGHTS, 0);
+    assert_eq!(
+        copy_file_range(source, Some(chunk), target, Some(chunk), u64::MAX, None),
+        Ok(200_000 - chunk)
+    );
+    fd_close(source);
+    fd_close(target);
+
+    assert_eq!(content("copy.bin"), data);
+    assert_eq!(
+        copy_file("missing.bin", "copy.bin", None),
+        Err(wasi::ERRNO_NOENT.raw() as i32)
+    );
+}
+
+#[test]
+fn test_copy_ranges() {
+    init(&[], &[]);
+
+    create_file("digits.txt", b"0123456789");
+    let input = open_with("digits.txt", wasi::OFLAGS_CREAT, DEFAULT_RIGHTS, 0);
+    let output = open_with("out.txt", wasi::OFLAGS_CREAT, DEFAULT_RIGHTS, 0);
+
+    // the missing offsets are the descriptor positions, which are advanced
+    assert_eq!(
+        copy_file_range(input, Some(2), output, None, 5, None),
+        Ok(5)
+    );
+    assert_eq!((tell(input), tell(output)), (0, 5));
+
+    let mut position = 0;
+    assert_eq!(
+        unsafe { __ic_custom_fd_seek(input, 8, wasi::WHENCE_SET.raw() as i32, &mut position) },
+        0
+    );
+
+    // the copy ends at the end of the input file
+    let mut copied = 0;
+    assert_eq!(
+        unsafe { raw_copy_file_range(input, -1, output, -1, 10, &mut copied) },
+        0
+    );
+    assert_eq!(copied, 2);
+    assert_eq!((tell(input), tell(output)), (10, 7));
+    assert_eq!(content("out.txt"), b"2345689");
+
+    // overlapping ranges of the same file
+    assert_eq!(
+        copy_file_range(input, Some(0), input, Some(4), 5, None),
+        Err(wasi::ERRNO_INVAL.raw() as i32)
+    );
+    assert_eq!(
+        copy_file_range(input, Some(4), input, Some(0), 5, None),
+        Err(wasi::ERRNO_INVAL.raw() as i32)
+    );
+
+    let same = open_with("digits.txt", wasi::OFLAGS_CREAT, DEFAULT_RIGHTS, 0);
+    assert_eq!(
+        copy_file_range(input, Some(0), same, Some(10), 5, None),
+        Ok(5)
+    );
+    assert_eq!(content("digits.txt"), b"012345678901234");
+
+    // the chunks of zeros past the end of the output extend it, the data after them is copied
+    let mut data = vec![0u8; 3 * CHUNK];
+    data[2 * CHUNK + 1] = 7;
+    create_file("sparse.bin", &data);
+    let sparse = open_with("sparse.bin", wasi::OFLAGS_CREAT, DEFAULT_RIGHTS, 0);
+    let copy = open_with("sparse_copy.bin", wasi::OFLAGS_CREAT, DEFAULT_RIGHTS, 0);
+    assert_eq!(
+        copy_file_range(sparse, Some(1), copy, Some(0), u64::MAX, None),
+        Ok(3 * CHUNK as u64 - 1)
+    );
+    assert_eq!(content("sparse_copy.bin"), data[1..]);
+    fd_close(sparse);
+    fd_close(copy);
+
+    let append = open_with(
+        "append.txt",
+        wasi::OFLAGS_CREAT,
+        DEFAULT_RIGHTS,
+        wasi::FDFLAGS_APPEND,
+    );
+    assert_eq!(
+        copy_file_range(input, Some(0), append, Some(0), 5, None),
+        Err(wasi::ERRNO_BADF.raw() as i32)
+    );
+
+    for fd in [input, output, same, append] {
+        fd_close(fd);
+    }
+}
@@ -0,0 +1,162 @@
+#![cfg(feature = "encryption")]
+
+mod common;
+
+use common::*;
+use ic_stable_structures::{DefaultMemoryImpl, Memory};
+use ic_wasi_polyfill::transform::{RotationReport, RotationStatus};
+use ic_wasi_polyfill::wasi;
+use ic_wasi_polyfill::*;
+
+const ROOT_FD: u32 = 3;
+
+const IO: i32 = wasi::ERRNO_IO.raw() as i32;
+
+const SECRET: &str = "top secret: the launch code is 0000";
+
+fn read(fd: wasi::Fd) -> (i32, String) {
+    let mut data = vec![0u8; 100];
+    let dst = [wasi::Iovec {
+        buf: data.as_mut_ptr(),
+        buf_len: data.len(),
+    }];
+    let mut read = 0;
+
+    let ret = unsafe { __ic_custom_fd_pread(fd, dst.as_ptr(), 1, 0, &mut read) };
+
+    data.truncate(read);
+    (ret, String::from_utf8_lossy(&data).into_owned())
+}
+
+fn contains(memory: &DefaultMemoryImpl, text: &str) -> bool {
+    let mut bytes = vec![0u8; (memory.size() * 65536) as usize];
+    memory.read(0, &mut bytes);
+
+    bytes.windows(text.len()).any(|w| w == text.as_bytes())
+}
+
+#[test]
+fn test_encrypted_files() {
+    let fs_memory = DefaultMemoryImpl::default();
+    let memory = DefaultMemoryImpl::default();
+
+    init_with_memory(&[], &[], fs_memory.clone());
+    init_transforms_with_memory(memory.clone());
+
+    fd_close(create_test_file_with_content(
+        ROOT_FD,
+        "plain.txt",
+        vec![SECRET.to_string()],
+    ));
+    assert_eq!(set_file_encryption("plain.txt", true), IO);
+
+    set_encryption_key(1, &[7; 32]);
+    fd_close(create_test_file_with_content(
+        ROOT_FD,
+        "secret.txt",
+        vec![SECRET.to_string()],
+    ));
+    assert_eq!(is_file_encrypted("secret.txt"), Ok(true));
+    assert_eq!(is_file_encrypted("plain.txt"), Ok(false));
+
+    assert_eq!(set_file_encryption("plain.txt", true), 0);
+    assert_eq!(read_file_to_string("plain.txt"), SECRET);
+    assert_eq!(read_file_to_string("secret.txt"), SECRET);
+
+    // the content does not reach the memories unencrypted
+    assert!(!contains(&fs_memory, SECRET));
+    assert!(!contains(&memory, SECRET));
+
+    // reading without the right key fails
+    let fd = open("secret.txt");
+    assert_eq!(remove_encryption_key(1), 0);
+    assert_eq!(remove_encryption_key(1), wasi::ERRNO_NOENT.raw() as i32);
+    assert_eq!(read(fd).0, IO);
+
+    set_encryption_key(1, &[8; 32]);
+    assert_eq!(read(fd).0, IO);
+
+    set_encryption_key(1, &[7; 32]);
+    assert_eq!(read(fd), (0, SECRET.to_string()));
+    fd_close(fd);
+
+    // compression is applied before the encryption
+    #[cfg(feature = "compression")]
+    {
+        assert_eq!(set_file_compression("secret.txt", true), 0);
+        assert_eq!(read_file_to_string("secret.txt"), SECRET);
+    }
+
+    // a failed conversion keeps the file encrypted
+    assert_eq!(remove_encryption_key(1), 0);
+    assert_eq!(set_file_encryption("plain.txt", false), IO);
+    assert_eq!(is_file_encrypted("plain.txt"), Ok(true));
+    set_encryption_key(1, &[7; 32]);
+    assert_eq!(read_file_to_string("plain.txt"), SECRET);
+
+    assert_eq!(set_file_encryption("plain.txt", false), 0);
+    assert!(contains(&fs_memory, SECRET));
+}
+
+#[test]
+fn test_key_rotation() {
+    let fs_memory = DefaultMemoryImpl::default();
+    let memory = DefaultMemoryImpl::default();
+
+    init_with_memory(&[], &[], fs_memory.clone());
+    init_transforms_with_memory(memory.clone());
+
+    set_encryption_key(1, &[1; 32]);
+    fd_close(create_test_file_with_content(
+        ROOT_FD,
+        "old.txt",
+        vec![SECRET.to_string()],
+    ));
+
+    set_encryption_key(2, &[2; 32]);
+    fd_close(create_test_file_with_content(
+        ROOT_FD,
+        "new.txt",
+        vec![SECRET.to_string()],
+    ));
+
+    // both keys are used until the files are encrypted again
+    assert_eq!(read_file_to_string("old.txt"), SECRET);
+
+    // one block per call, the blocks of new.txt already use the current key
+    let mut calls = 1;
+    let status = loop {
+        match rotate_encryption_key(0) {
+            Ok(RotationStatus::InProgress) => calls += 1,
+            status => break status,
+        }
+    };
+    assert!(calls > 2);
+    assert_eq!(
+        status,
+        Ok(RotationStatus::Done(RotationReport {
+            files: 1,
+            blocks: 1
+        }))
+    );
+    assert_eq!(
+        rotate_encryption_key(u64::MAX),
+        Ok(RotationStatus::Done(RotationReport::default()))
+    );
+    assert_eq!(remove_encryption_key(1), 0);
+    assert_eq!(read_file_to_string("old.txt"), SECRET);
+
+    // loading the memories again, as after an upgrade, the keys are provided by the application
+    init_with_memory(&[], &[], fs_memory);
+    init_transforms_with_memory(memory);
+
+    let fd = open("new.txt");
+    assert_eq!(remove_encryption_key(2), 0);
+    assert_eq!(read(fd).0, IO);
+    assert_eq!(rotate_encryption_key(u64::MAX), Err(IO));
+
+    set_encryption_key(2, &[2; 32]);
+    assert_eq!(read(fd), (0, SECRET.to_string()));
+    fd_close(fd);
+    assert_eq!(read_file_to_string("old.txt"), SECRET);
+}
@@ -0,0 +1,157 @@
+mod common;
+
+#[cfg(feature = "cache")]
+use common::*;
+#[cfg(feature = "cache")]
+use ic_wasi_polyfill::cache::CACHE_BLOCK_SIZE;
+use ic_wasi_polyfill::wasi;
+use ic_wasi_polyfill::*;
+
+#[cfg(feature = "cache")]
+fn write(fd: wasi::Fd, data: &[u8]) -> i32 {
+    let src = [wasi::Ciovec {
+        buf: data.as_ptr(),
+        buf_len: data.len(),
+    }];
+    let mut written = 0;
+
+    unsafe { __ic_custom_fd_write(fd, src.as_ptr(), 1, &mut written) }
+}
+
+fn read(fd: wasi::Fd, len: usize) -> Result<Vec<u8>, i32> {
+    let mut data = vec![0u8; len];
+    let dst = [wasi::Iovec {
+        buf: data.as_mut_ptr(),
+        buf_len: data.len(),
+    }];
+    let mut read = 0;
+
+    match unsafe { __ic_custom_fd_read(fd, dst.as_ptr(), 1, &mut read) } {
+        0 => {
+            data.truncate(read);
+            Ok(data)
+        }
+        errno => Err(errno),
+    }
+}
+
+fn fdstat(fd: wasi::Fd) -> wasi::Fdstat {
+    let mut stat = wasi::Fdstat {
+        fs_filetype: wasi::FILETYPE_UNKNOWN,
+        fs_flags: 0,
+        fs_rights_base: 0,
+        fs_rights_inheriting: 0,
+    };
+
+    assert_eq!(unsafe { __ic_custom_fd_fdstat_get(fd, &mut stat) }, 0);
+
+    stat
+}
+
+#[cfg(feature = "cache")]
+fn dirty_bytes() -> u64 {
+    get_cache_stats().dirty_bytes
+}
+
+#[cfg(feature = "cache")]
+#[test]
+fn test_sync_writes() {
+    init(&[], &[]);
+    set_cache_budget(16 * CACHE_BLOCK_SIZE);
+
+    let plain = open_with("plain.txt", wasi::OFLAGS_CREAT, DEFAULT_RIGHTS, 0);
+    assert_eq!(write(plain, b"cached"), 0);
+    assert_eq!(dirty_bytes(), CACHE_BLOCK_SIZE as u64);
+
+    // the synchronized writes are stored before they return
+    let sync = open_with(
+        "sync.txt",
+        wasi::OFLAGS_CREAT,
+        DEFAULT_RIGHTS,
+        wasi::FDFLAGS_SYNC,
+    );
+    let dsync = open_with(
+        "dsync.txt",
+        wasi::OFLAGS_CREAT,
+        DEFAULT_RIGHTS,
+        wasi::FDFLAGS_DSYNC,
+    );
+    assert_eq!(write(sync, b"stored"), 0);
+    assert_eq!(write(dsync, b"stored"), 0);
+    assert_eq!(dirty_bytes(), CACHE_BLOCK_SIZE as u64);
+    assert_eq!(get_cache_stats().written_back, 2);
+
+    assert_eq!(
+        __ic_custom_fd_fdstat_set_flags(plain, wasi::FDFLAGS_DSYNC as i32),
+        0
+    );
+    assert_eq!(fdstat(plain).fs_flags, wasi::FDFLAGS_DSYNC);
+    assert_eq!(write(plain, b" and stored"), 0);
+    assert_eq!(dirty_bytes(), 0);
+
+    for fd in [plain, sync, dsync] {
+        fd_close(fd);
+    }
+}
+
+#[cfg(feature = "cache")]
+#[test]
+fn test_rsync_reads() {
+    init(&[], &[]);
+    set_cache_budget(16 * CACHE_BLOCK_SIZE);
+
+    let writer = open_with("file.txt", wasi::OFLAGS_CREAT, DEFAULT_RIGHTS, 0);
+    assert_eq!(write(writer, b"pending"), 0);
+    assert_eq!(dirty_bytes(), CACHE_BLOCK_SIZE as u64);
+
+    let reader = open_with("file.txt", wasi::OFLAGS_CREAT, DEFAULT_RIGHTS, 0);
+    assert_eq!(read(reader, 100), Ok(b"pending".to_vec()));
+    assert_eq!(dirty_bytes(), CACHE_BLOCK_SIZE as u64);
+
+    // the pending writes of the file are stored before the read
+    let synced_reader = open_with(
+        "file.txt",
+        wasi::OFLAGS_CREAT,
+        DEFAULT_RIGHTS,
+        wasi::FDFLAGS_RSYNC,
+    );
+    assert_eq!(read(synced_reader, 100), Ok(b"pending".to_vec()));
+    assert_eq!(dirty_bytes(), 0);
+
+    for fd in [writer, reader, synced_reader] {
+        fd_close(fd);
+    }
+}
+
+#[test]
+fn test_nonblocking_stdin() {
+    init(&[], &[]);
+
+    // the empty input cannot be waited for, the end of file is returned
+    assert_eq!(read(0, 10), Ok(vec![]));
+    assert_eq!(read(1, 10), Err(wasi::ERRNO_INVAL.raw() as i32));
+
+    assert_eq!(
+        __ic_custom_fd_fdstat_set_flags(0, wasi::FDFLAGS_NONBLOCK as i32),
+        0
+    );
+    let stat = fdstat(0);
+    assert_eq!(stat.fs_filetype, wasi::FILETYPE_CHARACTER_DEVICE);
+    assert_eq!(stat.fs_flags, wasi::FDFLAGS_NONBLOCK);
+
+    assert_eq!(read(0, 10), Err(wasi::ERRNO_AGAIN.raw() as i32));
+
+    push_stdin(b"input");
+    assert_eq!(read(0, 3), Ok(b"inp".to_vec()));
+    assert_eq!(read(0, 3), Ok(b"ut".to_vec()));
+    assert_eq!(read(0, 3), Err(wasi::ERRNO_AGAIN.raw() as i32));
+
+    push_stdin(b"end");
+    close_stdin();
+    assert_eq!(read(0, 10), Ok(b"end".to_vec()));
+    assert_eq!(read(0, 10), Ok(vec![]));
+
+    // blocking again, the input is empty
+    assert_eq!(__ic_custom_fd_fdstat_set_flags(0, 0), 0);
+    assert_eq!(read(0, 10), Ok(vec![]));
+}
@@ -0,0 +1,175 @@
+#![cfg(feature = "fsck")]
+
+mod common;
+
+use common::*;
+use ic_wasi_polyfill::fsck::{FsckProblem, FsckReport, FsckStatus};
+use ic_wasi_polyfill::*;
+use stable_fs::storage::types::Node;
+
+const ROOT_FD: u32 = 3;
+
+fn create_dir(path: &str) {
+    assert_eq!(
+        unsafe { __ic_custom_path_create_directory(ROOT_FD, path.as_ptr(), path.len() as i32) },
+        0
+    );
+}
+
+fn node(path: &str) -> Node {
+    FS.with_borrow_mut(|fs| {
+        let root_fd = fs.root_fd();
+        fs.open_metadata(root_fd, path).unwrap().node
+    })
+}
+
+fn run_fsck(repair: bool, instruction_limit: u64) -> (FsckReport, usize) {
+    let mut calls = 0;
+
+    loop {
+        calls += 1;
+
+        if let FsckStatus::Done(report) = fsck(repair, instruction_limit).unwrap() {
+            return (report, calls);
+        }
+    }
+}
+
+fn problems(report: &FsckReport) -> Vec<(Node, FsckProblem, bool)> {
+    let mut problems: Vec<_> = report
+        .issues
+        .iter()
+        .map(|issue| (issue.node, issue.problem.clone(), issue.repaired))
+        .collect();
+    problems.sort_by_key(|(node, _, _)| *node);
+    problems
+}
+
+fn expected_problems(
+    expected: &[(Node, FsckProblem)],
+    repaired: bool,
+) -> Vec<(Node, FsckProblem, bool)> {
+    let mut problems: Vec<_> = expected
+        .iter()
+        .map(|(node, problem)| (*node, problem.clone(), repaired))
+        .collect();
+    problems.sort_by_key(|(node, _, _)| *node);
+    problems
+}
+
+fn build_tree() {
+    create_dir("dir");
+    create_dir("dir/sub");
+    fd_close(create_test_file(ROOT_FD, "a.txt"));
+    fd_close(create_test_file(ROOT_FD, "dir/b.txt"));
+    fd_close(create_test_file(ROOT_FD, "dir/sub/c.txt"));
+}
+
+#[test]
+fn test_consistent_file_system() {
+    init(&[], &[]);
+    build_tree();
+
+    let (report, calls) = run_fsck(false, u64::MAX);
+    assert_eq!(calls, 1);
+    assert!(report.is_consistent());
+    assert_eq!(report.nodes, 6);
+    assert_eq!(report.directories, 3);
+}
+
+#[test]
+fn test_report_and_repair() {
+    init(&[], &[]);
+    build_tree();
+
+    let a = node("a.txt");
+    let b = node("dir/b.txt");
+    let c = node("dir/sub/c.txt");
+    let dir = node("dir");
+    let sub = node("dir/sub");
+
+    FS.with_borrow_mut(|fs| {
+        let storage = fs.storage.as_mut();
+
+        // wrong link count
+        let mut metadata = storage.get_metadata(a).unwrap();
+        metadata.link_count = 3;
+        storage.put_metadata(a, &metadata).unwrap();
+
+        // the node of an entry is removed
+        storage.rm_file(b).unwrap();
+
+        // the entry of a node is removed
+        let mut index = None;
+        storage.with_direntries(sub, Some(0), &mut |i, entry| {
+            index = Some(*i).filter(|_| entry.node == c);
+            index.is_none()
+        });
+        storage.rm_direntry(sub, index.unwrap());
+    });
+
+    let expected = vec![
+        (
+            a,
+            FsckProblem::LinkCountMismatch {
+                stored: 3,
+                actual: 1,
+            },
+        ),
+        (
+            dir,
+            FsckProblem::DirectorySizeMismatch {
+                stored: 2,
+                actual: 1,
+            },
+        ),
+        (
+            b,
+            FsckProblem::DanglingEntry {
+                parent: dir,
+                name: "b.txt".to_string(),
+            },
+        ),
+        (
+            sub,
+            FsckProblem::DirectorySizeMismatch {
+                stored: 1,
+                actual: 0,
+            },
+        ),
+        (c, FsckProblem::OrphanedNode),
+    ];
+
+    // the dangling entry is counted in the directory size until it is removed
+    let (report, _) = run_fsck(false, u64::MAX);
+    let mut reported = expected.clone();
+    reported.remove(1);
+    assert_eq!(problems(&report), expected_problems(&reported, false));
+
+    // a step per call
+    let (report, calls) = run_fsck(true, 0);
+    assert!(calls > 5);
+    assert_eq!(problems(&report), expected_problems(&expected, true));
+
+    let (report, _) = run_fsck(false, u64::MAX);
+    assert!(report.is_consistent(), "{report:?}");
+
+    assert_eq!(
+        read_file_to_string(&format!("lost+found/#{c}")),
+        "This is a sample text.1234567890"
+    );
+}
+
+#[test]
+fn test_cancel_fsck() {
+    init(&[], &[]);
+    build_tree();
+
+    assert_eq!(fsck(false, 0), Ok(FsckStatus::InProgress));
+    assert_eq!(fsck(false, 0), Ok(FsckStatus::InProgress));
+    cancel_fsck();
+
+    let (report, calls) = run_fsck(false, 0);
+    assert!(report.is_consistent());
+    assert!(calls > 2);
+}
@@ -0,0 +1,420 @@
+mod common;
+
+use std::ptr::{null, null_mut};
+
+use common::*;
+use ic_wasi_polyfill::unsupported::UnsupportedCallPolicy;
+use ic_wasi_polyfill::wasi;
+use ic_wasi_polyfill::*;
+
+const ROOT_FD: u32 = 3;
+
+const FAULT: i32 = wasi::ERRNO_FAULT.raw() as i32;
+const INVAL: i32 = wasi::ERRNO_INVAL.raw() as i32;
+
+fn init_hardened() -> wasi::Fd {
+    init(&[], &[("PATH", "/usr/bin")]);
+    set_hardened_mode(true);
+    set_unsupported_call_policy(UnsupportedCallPolicy::ReturnError);
+
+    create_test_file(ROOT_FD, "file.txt")
+}
+
+#[test]
+fn test_invalid_whence_without_hardened_mode() {
+    init(&[], &[]);
+    set_hardened_mode(false);
+    assert!(!is_hardened_mode());
+
+    let fd = create_test_file(ROOT_FD, "file.txt");
+
+    let mut pos = 0;
+    let ret = unsafe { __ic_custom_fd_seek(fd, 0, 17, &mut pos) };
+    assert_eq!(ret, INVAL);
+}
+
+#[test]
+fn test_null_pointers() {
+    let fd = init_hardened();
+
+    unsafe {
+        assert_eq!(__ic_custom_fd_write(fd, null(), 1, null_mut()), FAULT);
+        assert_eq!(__ic_custom_fd_read(fd, null(), 1, null_mut()), FAULT);
+        assert_eq!(__ic_custom_fd_pwrite(fd, null(), 1, 0, null_mut()), FAULT);
+        assert_eq!(__ic_custom_fd_pread(fd, null(), 1, 0, null_mut()), FAULT);
+        assert_eq!(__ic_custom_fd_seek(fd, 0, 0, null_mut()), FAULT);
+        assert_eq!(__ic_custom_fd_tell(fd, null_mut()), FAULT);
+        assert_eq!(__ic_custom_fd_filestat_get(fd, null_mut()), FAULT);
+        assert_eq!(__ic_custom_fd_fdstat_get(fd, null_mut()), FAULT);
+        assert_eq!(
+            __ic_custom_fd_prestat_get(ROOT_FD as i32, null_mut()),
+            FAULT
+        );
+        assert_eq!(
+            __ic_custom_fd_prestat_dir_name(ROOT_FD as i32, null_mut(), 1),
+            FAULT
+        );
+        assert_eq!(
+            __ic_custom_fd_readdir(ROOT_FD, null_mut(), 100, 0, null_mut()),
+            FAULT
+        );
+        assert_eq!(__ic_custom_random_get(null_mut(), 10), FAULT);
+        assert_eq!(__ic_custom_environ_get(null_mut(), null_mut()), FAULT);
+        assert_eq!(__ic_custom_environ_sizes_get(null_mut(), null_mut()), FAULT);
+        assert_eq!(__ic_custom_args_sizes_get(null_mut(), null_mut()), FAULT);
+        assert_eq!(__ic_custom_clock_res_get(0, null_mut()), FAULT);
+        assert_eq!(__ic_custom_clock_time_get(0, 0, null_mut()), FAULT);
+
+        assert_eq!(
+            __ic_custom_path_open(ROOT_FD, 0, null(), 4, 0, 0, 0, 0, null_mut()),
+            FAULT
+        );
+        assert_eq!(__ic_custom_path_create_directory(ROOT_FD, null(), 4), FAULT);
+        assert_eq!(
+            __ic_custom_path_filestat_get(ROOT_FD as i32, 0, null(), 4, null_mut()),
+            FAULT
+        );
+        assert_eq!(
+            __ic_custom_path_filestat_set_times(ROOT_FD as i32, 0, null(), 4, 0, 0, 0),
+            FAULT
+        );
+        assert_eq!(
+            __ic_custom_path_link(ROOT_FD, 0, null(), 4, ROOT_FD, null(), 4),
+            FAULT
+        );
+        #[cfg(not(feature = "skip_unimplemented_functions"))]
+        assert_eq!(
+            __ic_custom_path_readlink(ROOT_FD as i32, null(), 4, 0, 0, null_mut()),
+            FAULT
+        );
+        assert_eq!(__ic_custom_path_remove_directory(ROOT_FD, null(), 4), FAULT);
+        assert_eq!(
+            __ic_custom_path_rename(ROOT_FD as i32, null(), 4, ROOT_FD as i32, null(), 4),
+            FAULT
+        );
+        #[cfg(not(feature = "skip_unimplemented_functions"))]
+        assert_eq!(
+            __ic_custom_path_symlink(null(), 4, ROOT_FD as i32, null(), 4),
+            FAULT
+        );
+        assert_eq!(
+            __ic_custom_path_unlink_file(ROOT_FD as i32, null(), 4),
+            FAULT
+        );
+
+        #[cfg(not(feature = "skip_unimplemented_functions"))]
+        {
+            assert_eq!(
+                __ic_custom_poll_oneoff(null(), null_mut(), 1, null_mut()),
+                FAULT
+            );
+            assert_eq!(__ic_custom_sock_accept(fd as i32, 0, null_mut()), FAULT);
+            assert_eq!(
+                __ic_custom_sock_recv(fd as i32, null(), 1, 0, null_mut(), null_mut()),
+                FAULT
+            );
+            assert_eq!(
+                __ic_custom_sock_send(fd as i32, null(), 1, 0, null_mut()),
+                FAULT
+            );
+        }
+    }
+}
+
+#[test]
+fn test_empty_slices_may_be_null() {
+    let fd = init_hardened();
+
+    let mut res = 0;
+    #[cfg(not(feature = "skip_unimplemented_functions"))]
+    let mut nevents = 0;
+
+    unsafe {
+        assert_eq!(__ic_custom_fd_write(fd, null(), 0, &mut res), 0);
+        assert_eq!(res, 0);
+        assert_eq!(__ic_custom_fd_read(fd, null(), 0, &mut res), 0);
+        assert_eq!(__ic_custom_random_get(null_mut(), 0), 0);
+        assert_eq!(
+            __ic_custom_fd_prestat_dir_name(ROOT_FD as i32, null_mut(), 0),
+            0
+        );
+        #[cfg(not(feature = "skip_unimplemented_functions"))]
+        assert_eq!(
+            __ic_custom_poll_oneoff(null(), null_mut(), 0, &mut nevents),
+            wasi::ERRNO_NOSYS.raw() as i32
+        );
+    }
+
+    assert_eq!(__ic_custom_args_get(null_mut(), null_mut()), 0);
+}
+
+#[test]
+fn test_misaligned_and_overflowing_pointers() {
+    let fd = init_hardened();
+
+    let mut value = [0u64; 2];
+    let misaligned = (value.as_mut_ptr() as *mut u8).wrapping_add(1) as *mut u64;
+    let end = usize::MAX as *mut u8;
+
+    unsafe {
+        assert_eq!(__ic_custom_fd_seek(fd, 0, 0, misaligned), FAULT);
+        assert_eq!(__ic_custom_fd_tell(fd, misaligned), FAULT);
+        assert_eq!(__ic_custom_clock_time_get(0, 0, misaligned), FAULT);
+        assert_eq!(__ic_custom_random_get(end, 10), FAULT);
+
+        let name = "file.txt";
+        assert_eq!(
+            __ic_custom_path_create_directory(ROOT_FD, end, name.len() as i32),
+            FAULT
+        );
+
+        let buf = [0u8; 4];
+        let iovs = [
+            wasi::Ciovec {
+                buf: buf.as_ptr(),
+                buf_len: 4,
+            },
+            wasi::Ciovec {
+                buf: end,
+                buf_len: 4,
+            },
+        ];
+        let mut res = 0;
+        assert_eq!(__ic_custom_fd_write(fd, iovs.as_ptr(), 2, &mut res), FAULT);
+    }
+}
+
+#[test]
+fn test_negative_lengths() {
+    let fd = init_hardened();
+
+    let buf = [0u8; 4];
+    let mut bytes = [0u8; 4];
+    let iov = wasi::Ciovec {
+        buf: buf.as_ptr(),
+        buf_len: buf.len(),
+    };
+    let dst_iov = wasi::Iovec {
+        buf: bytes.as_mut_ptr(),
+        buf_len: bytes.len(),
+    };
+    let mut res = 0;
+    let mut new_fd = 0;
+    let name = "file.txt";
+
+    unsafe {
+        assert_eq!(__ic_custom_fd_write(fd, &iov, -1, &mut res), INVAL);
+        assert_eq!(__ic_custom_fd_read(fd, &dst_iov, -1, &mut res), INVAL);
+        assert_eq!(__ic_custom_fd_pwrite(fd, &iov, -1, 0, &mut res), INVAL);
+        assert_eq!(__ic_custom_fd_pread(fd, &dst_iov, -1, 0, &mut res), INVAL);
+        assert_eq!(
+            __ic_custom_fd_readdir(ROOT_FD, bytes.as_mut_ptr(), -5, 0, &mut res),
+            INVAL
+        );
+        assert_eq!(
+            __ic_custom_fd_prestat_dir_name(ROOT_FD as i32, bytes.as_mut_ptr(), -1),
+            INVAL
+        );
+        assert_eq!(
+            __ic_custom_path_open(ROOT_FD, 0, name.as_ptr(), -1, 0, 0, 0, 0, &mut new_fd),
+            INVAL
+        );
+        assert_eq!(
+            __ic_custom_path_unlink_file(ROOT_FD as i32, name.as_ptr(), i32::MIN),
+            INVAL
+        );
+    }
+
+    assert_eq!(__ic_custom_fd_filestat_set_size(fd, -1), INVAL);
+    assert_eq!(__ic_custom_fd_allocate(fd, -1, 10), INVAL);
+}
+
+#[test]
+fn test_invalid_enum_values() {
+    let fd = init_hardened();
+
+    let mut pos = 0;
+    let mut new_fd = 0;
+    let mut time = 0;
+    let name = "file.txt";
+
+    unsafe {
+        assert_eq!(__ic_custom_fd_seek(fd, 0, 3, &mut pos), INVAL);
+        assert_eq!(__ic_custom_fd_seek(fd, 0, 256, &mut pos), INVAL);
+        assert_eq!(__ic_custom_clock_time_get(17, 0, &mut time), INVAL);
+        assert_eq!(__ic_custom_clock_res_get(-1, &mut time), INVAL);
+        assert_eq!(
+            __ic_custom_path_open(
+                ROOT_FD,
+                0,
+                name.as_ptr(),
+                name.len() as i32,
+                0x100,
+                0,
+                0,
+                0,
+                &mut new_fd
+            ),
+            INVAL
+        );
+        assert_eq!(
+            __ic_custom_path_open(
+                ROOT_FD,
+                0,
+                name.as_ptr(),
+                name.len() as i32,
+                0,
+                0,
+                0,
+                0x40,
+                &mut new_fd
+            ),
+            INVAL
+        );
+        assert_eq!(
+            __ic_custom_path_filestat_set_times(
+                ROOT_FD as i32,
+                2,
+                name.as_ptr(),
+                name.len() as i32,
+                0,
+                0,
+                0
+            ),
+            INVAL
+        );
+    }
+
+    #[cfg(not(feature = "skip_unimplemented_functions"))]
+    {
+        let mut nevents = 0;
+        assert_eq!(
+            unsafe { __ic_custom_poll_oneoff(null(), null_mut(), -1, &mut nevents) },
+            INVAL
+        );
+        assert_eq!(__ic_custom_sock_shutdown(fd as i32, 4), INVAL);
+    }
+
+    assert_eq!(__ic_custom_fd_advise(fd, 0, 0, 6), INVAL);
+    assert_eq!(__ic_custom_fd_fdstat_set_flags(fd, 0x20), INVAL);
+    assert_eq!(__ic_custom_fd_filestat_set_times(fd, 0, 0, 0x10), INVAL);
+
+    // valid values still work
+    assert_eq!(unsafe { __ic_custom_fd_seek(fd, 0, 2, &mut pos) }, 0);
+    assert_eq!(pos, 32);
+}
+
+#[test]
+fn test_garbage_arguments_do_not_trap() {
+    let fd = init_hardened();
+
+    let mut buf = [0u8; 64];
+    let mut res = 0usize;
+    let mut pos = 0u64;
+    let mut new_fd = 0u32;
+
+    let garbage_fds = [0, 1, 2, fd, 1000, u32::MAX];
+    let garbage_ints = [i32::MIN, -1, 0, 1, 7, 0x7fff, i32::MAX];
+    let garbage_i64 = [i64::MIN, -1, 0, 1, i64::MAX];
+    // the host memory is not bounded, the lengths passing the checks must fit into the buffer
+    let garbage_lens = [i32::MIN, -1, 0, 1, buf.len() as i32];
+
+    for fd in garbage_fds {
+        for int in garbage_ints {
+            for long in garbage_i64 {
+                for len in garbage_lens {
+                    // the results depend on the arguments, the calls should return without panicking
+                    unsafe {
+                        __ic_custom_fd_readdir(fd, buf.as_mut_ptr(), len, long, &mut res);
+                        __ic_custom_fd_prestat_dir_name(fd as i32, buf.as_mut_ptr(), len);
+                        __ic_custom_path_open(
+                            fd,
+                            int,
+                            buf.as_ptr(),
+                            len,
+                            int,
+                            long as u64,
+                            long as u64,
+                            int,
+                            &mut new_fd,
+                        );
+                        __ic_custom_path_filestat_set_times(
+                            fd as i32,
+                            int,
+                            buf.as_ptr(),
+                            len,
+                            long,
+                            long,
+                            int,
+                        );
+                        #[cfg(not(feature = "skip_unimplemented_functions"))]
+                        __ic_custom_path_readlink(fd as i32, buf.as_ptr(), len, int, int, &mut res);
+                    }
+                }
+
+                unsafe {
+                    __ic_custom_fd_seek(fd, long, int, &mut pos);
+                    __ic_custom_clock_time_get(int, long, &mut pos);
+                }
+
+                __ic_custom_fd_advise(fd, long, long, int);
+                __ic_custom_fd_allocate(fd, long, long);
+                __ic_custom_fd_fdstat_set_flags(fd, int);
+                __ic_custom_fd_fdstat_set_rights(fd as i32, long, long);
+                __ic_custom_fd_filestat_set_times(fd, long, long, int);
+
+                #[cfg(not(feature = "skip_unimplemented_functions"))]
+                {
+                    __ic_custom_proc_raise(int);
+                    __ic_custom_sock_shutdown(fd as i32, int);
+                }
+            }
+
+            __ic_custom_fd_renumber(fd, int as u32);
+        }
+    }
+}
+
+#[cfg(feature = "trace_wasi_calls")]
+#[test]
+fn test_rejected_calls_are_traced() {
+    let fd = init_hardened();
+    clear_trace();
+
+    let mut pos = 0;
+    let ret = unsafe { __ic_custom_fd_seek(fd, 0, 17, &mut pos) };
+    assert_eq!(ret, INVAL);
+
+    let records = take_trace_records();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].function, "fd_seek");
+    assert_eq!(records[0].errno, INVAL);
+    assert_eq!(records[0].fd, None);
+}
+
+#[cfg(feature = "record_wasi_calls")]
+#[test]
+fn test_rejected_calls_are_recorded_and_skipped_on_replay() {
+    use ic_wasi_polyfill::recorder::decode_recording;
+    use ic_wasi_polyfill::replay::replay_wasi_calls_on_current_fs;
+
+    let fd = init_hardened();
+    start_recording();
+
+    let mut written = 0;
+    let ret = unsafe { __ic_custom_fd_write(fd, null(), 1, &mut written) };
+    assert_eq!(ret, FAULT);
+
+    stop_recording();
+    let log = take_recording();
+
+    let records = decode_recording(&log).unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].function, "fd_write");
+    assert_eq!(records[0].errno, FAULT);
+    assert!(records[0].is_rejected());
+
+    let report = replay_wasi_calls_on_current_fs(&log).unwrap();
+    assert_eq!(report.calls, 0);
+    assert!(report.mismatches.is_empty());
+}
@@ -0,0 +1,212 @@
+#![cfg(feature = "hooks")]
+
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use common::*;
+use ic_wasi_polyfill::hooks::{HookOutcome, WasiCall};
+use ic_wasi_polyfill::wasi;
+use ic_wasi_polyfill::*;
+
+#[test]
+fn test_hook_replaces_implementation() {
+    init(&[], &[]);
+
+    set_hook("clock_time_get", |call| {
+        if let WasiCall::ClockTimeGet { time, .. } = call {
+            **time = 42;
+        }
+        HookOutcome::Return(wasi::ERRNO_SUCCESS.raw() as i32)
+    });
+
+    set_hook("random_get", |call| {
+        if let WasiCall::RandomGet { buf } = call {
+            buf.fill(7);
+        }
+        HookOutcome::Return(wasi::ERRNO_SUCCESS.raw() as i32)
+    });
+
+    let mut time = 0;
+    let ret = unsafe { __ic_custom_clock_time_get(0, 0, &mut time) };
+    assert_eq!(ret, 0);
+    assert_eq!(time, 42);
+
+    let mut buf = [0u8; 4];
+    let ret = unsafe { __ic_custom_random_get(buf.as_mut_ptr(), buf.len()) };
+    assert_eq!(ret, 0);
+    assert_eq!(buf, [7, 7, 7, 7]);
+
+    remove_hook("clock_time_get");
+
+    let ret = unsafe { __ic_custom_clock_time_get(0, 0, &mut time) };
+    assert_eq!(ret, 0);
+    assert_ne!(time, 42);
+
+    clear_hooks();
+}
+
+#[test]
+fn test_hook_intercepts_stdout() {
+    init(&[], &[]);
+
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let captured = output.clone();
+
+    set_hook("fd_write", move |call| match call {
+        WasiCall::FdWrite {
+            fd: 1,
+            bufs,
+            written,
+        } => {
+            let mut captured = captured.borrow_mut();
+            for buf in bufs.iter() {
+                captured.extend_from_slice(buf);
+            }
+            **written = bufs.iter().map(|b| b.len()).sum();
+            HookOutcome::Return(wasi::ERRNO_SUCCESS.raw() as i32)
+        }
+        _ => HookOutcome::Continue,
+    });
+
+    let text = "hello";
+    let iov = wasi::Ciovec {
+        buf: text.as_ptr(),
+        buf_len: text.len(),
+    };
+    let mut written = 0;
+    let ret = unsafe { __ic_custom_fd_write(1, &iov, 1, &mut written) };
+    assert_eq!(ret, 0);
+    assert_eq!(written, 5);
+    assert_eq!(*output.borrow(), b"hello".to_vec());
+
+    // other descriptors use the built-in implementation
+    let fd = create_test_file(3, "file.txt");
+    fd_close(fd);
+    assert_eq!(
+        read_file_to_string("file.txt"),
+        "This is a sample text.1234567890"
+    );
+    assert_eq!(output.borrow().len(), 5);
+
+    clear_hooks();
+}
+
+#[test]
+fn test_hook_can_call_builtin() {
+    init(&[], &[]);
+
+    let opened = Rc::new(RefCell::new(Vec::new()));
+    let paths = opened.clone();
+
+    assert_eq!(
+        set_hook("path_open", move |call| {
+            let WasiCall::PathOpen {
+                fd,
+                path,
+                oflags,
+                rights_base,
+                rights_inheriting,
+                fdflags,
+                opened_fd,
+                ..
+            } = call
+            else {
+                return HookOutcome::Continue;
+            };
+
+            paths.borrow_mut().push(path.to_string());
+
+            // redirect the file to a different directory
+            let redirected = format!("sandbox/{path}");
+            let ret = unsafe {
+                __ic_custom_path_open(
+                    *fd,
+                    0,
+                    redirected.as_ptr(),
+                    redirected.len() as i32,
+                    *oflags,
+                    *rights_base,
+                    *rights_inheriting,
+                    *fdflags,
+                    &mut **opened_fd,
+                )
+            };
+
+            HookOutcome::Return(ret)
+        }),
+        0
+    );
+
+    let dir = "sandbox";
+    let ret = unsafe { __ic_custom_path_create_directory(3, dir.as_ptr(), dir.len() as i32) };
+    assert_eq!(ret, 0);
+
+    let fd = create_test_file(3, "file.txt");
+    fd_close(fd);
+
+    // the nested call is not intercepted again
+    assert_eq!(*opened.borrow(), vec!["file.txt"]);
+
+    remove_hook("path_open");
+
+    assert_eq!(
+        read_file_to_string("sandbox/file.txt"),
+        "This is a sample text.1234567890"
+    );
+}
+
+#[test]
+fn test_hook_unknown_function() {
+    assert_eq!(
+        set_hook("fd_unknown", |_| HookOutcome::Continue),
+        wasi::ERRNO_INVAL.raw() as i32
+    );
+}
+
+#[test]
+fn test_hook_removes_itself() {
+    init(&[], &[]);
+
+    let calls = Rc::new(RefCell::new(0));
+    let counter = calls.clone();
+
+    // a one-shot hook
+    set_hook("sched_yield", move |_| {
+        *counter.borrow_mut() += 1;
+        remove_hook("sched_yield");
+        HookOutcome::Return(wasi::ERRNO_PERM.raw() as i32)
+    });
+
+    assert_eq!(__ic_custom_sched_yield(), wasi::ERRNO_PERM.raw() as i32);
+    assert_eq!(__ic_custom_sched_yield(), 0);
+    assert_eq!(*calls.borrow(), 1);
+}
+
+#[test]
+fn test_hook_skips_the_polyfill_descriptors() {
+    init(&[], &[]);
+
+    let fd = create_test_file(3, "source.txt");
+    fd_close(fd);
+
+    let calls = Rc::new(RefCell::new(0));
+    let counter = calls.clone();
+
+    set_hook("fd_close", move |_| {
+        *counter.borrow_mut() += 1;
+        HookOutcome::Continue
+    });
+
+    // the descriptors opened by the copy are closed without the fd_close hook
+    copy_file("source.txt", "target.txt", None).unwrap();
+
+    assert_eq!(*calls.borrow(), 0);
+    assert_eq!(
+        read_file_to_string("target.txt"),
+        read_file_to_string("source.txt")
+    );
+
+    clear_hooks();
+}
@@ -0,0 +1,182 @@
+#![cfg(feature = "http")]
+
+mod common;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use common::*;
+use ic_certification::{HashTree, LookupResult};
+use ic_wasi_polyfill::http::{HttpRequest, HttpResponse};
+use ic_wasi_polyfill::wasi;
+use ic_wasi_polyfill::*;
+use sha2::{Digest, Sha256};
+
+const ROOT_FD: u32 = 3;
+
+const INDEX: &str = "<html>hello</html>";
+const DATA: &str = "0123456789";
+
+fn create_dir(path: &str) {
+    assert_eq!(
+        unsafe { __ic_custom_path_create_directory(ROOT_FD, path.as_ptr(), path.len() as i32) },
+        0
+    );
+}
+
+fn request(method: &str, url: &str, headers: &[(&str, &str)]) -> HttpResponse {
+    http_request(&HttpRequest {
+        method: method.to_string(),
+        url: url.to_string(),
+        headers: headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect(),
+        body: vec![],
+    })
+}
+
+fn get(url: &str) -> HttpResponse {
+    request("GET", url, &[])
+}
+
+// Check the certificate header against the certified data, returns the certified hash of the URL.
+fn certified_hash(response: &HttpResponse, url: &str) -> Option<Vec<u8>> {
+    let certificate = response.header("IC-Certificate")?;
+    let tree = certificate.split("tree=:").nth(1)?.trim_end_matches(':');
+    let tree: HashTree = serde_cbor::from_slice(&BASE64.decode(tree).unwrap()).unwrap();
+
+    assert_eq!(
+        tree.digest().to_vec(),
+        MOCK_CERTIFIED_DATA.with_borrow(|certified| certified.clone())
+    );
+
+    match tree.lookup_path([b"http_assets".as_ref(), url.as_bytes()]) {
+        LookupResult::Found(hash) => Some(hash.to_vec()),
+        _ => None,
+    }
+}
+
+fn sha256(data: &str) -> Vec<u8> {
+    Sha256::digest(data.as_bytes()).to_vec()
+}
+
+#[test]
+fn test_serve_files() {
+    init(&[], &[]);
+
+    create_dir("www");
+    create_file("www/index.html", INDEX);
+    create_file("www/data.txt", DATA);
+    create_file("secret.txt", "secret");
+
+    assert_eq!(init_http_directory("www"), 0);
+
+    let response = get("/");
+    assert_eq!(response.status_code, 200);
+    assert_eq!(response.body, INDEX.as_bytes());
+    assert_eq!(
+        response.header("Content-Type"),
+        Some("text/html; charset=utf-8")
+    );
+    assert_eq!(certified_hash(&response, "/"), Some(sha256(INDEX)));
+
+    let response = get("/data.txt?version=2");
+    assert_eq!(response.body, DATA.as_bytes());
+    assert_eq!(response.header("Content-Length"), Some("10"));
+    assert_eq!(certified_hash(&response, "/data.txt"), Some(sha256(DATA)));
+
+    // the content hash is the entity tag
+    let etag = response.header("ETag").unwrap().to_string();
+    let response = request("GET", "/data.txt", &[("If-None-Match", &etag)]);
+    assert_eq!(response.status_code, 304);
+    assert!(response.body.is_empty());
+
+    let response = request("HEAD", "/data.txt", &[]);
+    assert_eq!(response.status_code, 200);
+    assert_eq!(response.header("Content-Length"), Some("10"));
+    assert!(response.body.is_empty());
+
+    let response = request("GET", "/data.txt", &[("Range", "bytes=2-5")]);
+    assert_eq!(response.status_code, 206);
+    assert_eq!(response.body, b"2345");
+    assert_eq!(response.header("Content-Range"), Some("bytes 2-5/10"));
+    assert_eq!(response.header("IC-Certificate"), None);
+
+    let response = request("GET", "/data.txt", &[("range", "bytes=-3")]);
+    assert_eq!(response.body, b"789");
+
+    let response = request("GET", "/data.txt", &[("Range", "bytes=10-")]);
+    assert_eq!(response.status_code, 416);
+    assert_eq!(response.header("Content-Range"), Some("bytes */10"));
+
+    assert_eq!(get("/missing.txt").status_code, 404);
+    assert_eq!(get("/../secret.txt").status_code, 404);
+    assert_eq!(get("/%2E%2E/secret.txt").status_code, 404);
+    assert_eq!(request("POST", "/data.txt", &[]).status_code, 405);
+}
+
+#[test]
+fn test_certification_follows_changes() {
+    init(&[], &[]);
+
+    create_dir("www");
+    assert_eq!(init_http_directory("www"), 0);
+    assert_eq!(
+        init_http_directory("missing"),
+        wasi::ERRNO_NOENT.raw() as i32
+    );
+
+    // the file is certified when it is closed
+    let fd = create_test_file_with_content(ROOT_FD, "www/page.html", vec![INDEX.to_string()]);
+    assert_eq!(certified_hash(&get("/page.html"), "/page.html"), None);
+    fd_close(fd);
+    assert_eq!(
+        certified_hash(&get("/page.html"), "/page.html"),
+        Some(sha256(INDEX))
+    );
+
+    create_file("www/page.html", DATA);
+    let response = get("/page.html");
+    assert_eq!(response.body, DATA.as_bytes());
+    assert_eq!(certified_hash(&response, "/page.html"), Some(sha256(DATA)));
+
+    // renamed files are certified under the new URL
+    let (old, new) = ("www/page.html", "www/moved.html");
+    assert_eq!(
+        unsafe {
+            __ic_custom_path_rename(
+                ROOT_FD as i32,
+                old.as_ptr(),
+                old.len() as i32,
+                ROOT_FD as i32,
+                new.as_ptr(),
+                new.len() as i32,
+            )
+        },
+        0
+    );
+
+    assert_eq!(get("/page.html").status_code, 404);
+    let response = get("/moved.html");
+    assert_eq!(certified_hash(&response, "/moved.html"), Some(sha256(DATA)));
+    assert_eq!(certified_hash(&response, "/page.html"), None);
+
+    // removed files are no longer certified
+    let certified = MOCK_CERTIFIED_DATA.with_borrow(|certified| certified.clone());
+    assert_eq!(
+        unsafe { __ic_custom_path_unlink_file(ROOT_FD as i32, new.as_ptr(), new.len() as i32) },
+        0
+    );
+    assert_ne!(
+        MOCK_CERTIFIED_DATA.with_borrow(|certified| certified.clone()),
+        certified
+    );
+
+    // the files outside of the directory do not change the certified data
+    let certified = MOCK_CERTIFIED_DATA.with_borrow(|certified| certified.clone());
+    create_file("other.txt", DATA);
+    assert_eq!(
+        MOCK_CERTIFIED_DATA.with_borrow(|certified| certified.clone()),
+        certified
+    );
+}
@@ -1487,3 +1487,88 @@ fn test_mounts() {
     let str = read_file_to_string(file_name);
     assert_eq!(str, hello_message2);
 }
+
+#[test]
+#[cfg(not(feature = "skip_unimplemented_functions"))]
+fn test_unsupported_calls_return_errors() {
+    use ic_wasi_polyfill::unsupported::UnsupportedCallPolicy;
+
+    init(&[], &[]);
+
+    set_unsupported_call_policy(UnsupportedCallPolicy::ReturnError);
+    assert_eq!(
+        get_unsupported_call_policy(),
+        UnsupportedCallPolicy::ReturnError
+    );
+
+    let path = "link";
+    let target = "file.txt";
+    let ret = unsafe {
+        __ic_custom_path_symlink(
+            target.as_ptr(),
+            target.len() as i32,
+            3,
+            path.as_ptr(),
+            path.len() as i32,
+        )
+    };
+    assert_eq!(ret, wasi::ERRNO_NOSYS.raw() as i32);
+
+    let mut buf = [0u8; 16];
+    let mut res = 0;
+    let ret = unsafe {
+        __ic_custom_path_readlink(
+            3,
+            path.as_ptr(),
+            path.len() as i32,
+            buf.as_mut_ptr() as i32,
+            buf.len() as i32,
+            &mut res,
+        )
+    };
+    assert_eq!(ret, wasi::ERRNO_NOSYS.raw() as i32);
+
+    assert_eq!(__ic_custom_proc_raise(0), wasi::ERRNO_NOSYS.raw() as i32);
+
+    let mut nevents = 0;
+    let ret =
+        unsafe { __ic_custom_poll_oneoff(std::ptr::null(), std::ptr::null_mut(), 0, &mut nevents) };
+    assert_eq!(ret, wasi::ERRNO_NOSYS.raw() as i32);
+
+    set_unsupported_call_policy(UnsupportedCallPolicy::ReturnErrorAndLog);
+
+    let mut fd = 0;
+    assert_eq!(
+        __ic_custom_sock_accept(3, 0, &mut fd),
+        wasi::ERRNO_NOTSUP.raw() as i32
+    );
+    assert_eq!(
+        __ic_custom_sock_accept(3, 0, &mut fd),
+        wasi::ERRNO_NOTSUP.raw() as i32
+    );
+    let mut flags = 0;
+    assert_eq!(
+        unsafe { __ic_custom_sock_recv(3, std::ptr::null(), 0, 0, &mut res, &mut flags) },
+        wasi::ERRNO_NOTSUP.raw() as i32
+    );
+    assert_eq!(
+        unsafe { __ic_custom_sock_send(3, std::ptr::null(), 0, 0, &mut res) },
+        wasi::ERRNO_NOTSUP.raw() as i32
+    );
+    assert_eq!(
+        __ic_custom_sock_shutdown(3, 0),
+        wasi::ERRNO_NOTSUP.raw() as i32
+    );
+}
+
+#[test]
+#[cfg(not(any(
+    feature = "unsupported_functions_return_errors",
+    feature = "skip_unimplemented_functions"
+)))]
+fn test_unsupported_calls_panic_by_default() {
+    use ic_wasi_polyfill::unsupported::UnsupportedCallPolicy;
+
+    // the extern "C" functions abort the test process on panic, only check the policy
+    assert_eq!(get_unsupported_call_policy(), UnsupportedCallPolicy::Panic);
+}
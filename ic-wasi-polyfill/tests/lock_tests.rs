@@ -0,0 +1,93 @@
+#![cfg(feature = "file_locks")]
+
+mod common;
+
+use common::*;
+use ic_wasi_polyfill::locks::LockType;
+use ic_wasi_polyfill::wasi;
+use ic_wasi_polyfill::*;
+
+const ROOT_FD: u32 = 3;
+
+const AGAIN: i32 = wasi::ERRNO_AGAIN.raw() as i32;
+const BADF: i32 = wasi::ERRNO_BADF.raw() as i32;
+const INVAL: i32 = wasi::ERRNO_INVAL.raw() as i32;
+
+fn open_file(file_name: &str) -> wasi::Fd {
+    let mut fd = 0;
+    let ret = unsafe {
+        __ic_custom_path_open(
+            ROOT_FD,
+            0,
+            file_name.as_ptr(),
+            file_name.len() as i32,
+            0,
+            DEFAULT_RIGHTS,
+            DEFAULT_RIGHTS,
+            0,
+            &mut fd,
+        )
+    };
+    assert_eq!(ret, 0);
+
+    fd
+}
+
+#[test]
+fn test_shared_and_exclusive_locks() {
+    init(&[], &[]);
+
+    let fd1 = create_test_file(ROOT_FD, "test.db");
+    let fd2 = open_file("test.db");
+    let other = create_test_file(ROOT_FD, "other.db");
+
+    assert_eq!(lock_file(fd1, LockType::Shared, 0, 0), 0);
+    assert_eq!(lock_file(fd2, LockType::Shared, 0, 0), 0);
+    assert_eq!(test_file_lock(fd2, LockType::Exclusive, 0, 1), AGAIN);
+    assert_eq!(lock_file(fd2, LockType::Exclusive, 0, 1), AGAIN);
+
+    // the locks are kept per file
+    assert_eq!(lock_file(other, LockType::Exclusive, 0, 0), 0);
+
+    // the byte ranges are locked separately
+    assert_eq!(unlock_file(fd1, 100, 0), 0);
+    assert_eq!(lock_file(fd2, LockType::Exclusive, 100, 10), 0);
+    assert_eq!(test_file_lock(fd1, LockType::Shared, 105, 1), AGAIN);
+    assert_eq!(test_file_lock(fd1, LockType::Shared, 110, 1), 0);
+
+    assert_eq!(unlock_file(fd1, 0, 0), 0);
+    assert_eq!(lock_file(fd2, LockType::Exclusive, 0, 100), 0);
+
+    assert_eq!(lock_file(fd1, LockType::Shared, 200, u64::MAX), INVAL);
+    assert_eq!(raw_lock_file(fd1, 2, 0, 0), INVAL);
+
+    fd_close(fd1);
+    fd_close(fd2);
+    fd_close(other);
+}
+
+#[test]
+fn test_locks_released_on_close() {
+    init(&[], &[]);
+
+    let fd1 = create_test_file(ROOT_FD, "test.db");
+    let fd2 = open_file("test.db");
+
+    assert_eq!(raw_lock_file(fd1, 1, 0, 0), 0);
+    assert_eq!(raw_test_file_lock(fd2, 0, 10, 10), AGAIN);
+
+    fd_close(fd1);
+    assert_eq!(raw_test_file_lock(fd2, 0, 10, 10), 0);
+    assert_eq!(raw_unlock_file(fd1, 0, 0), BADF);
+
+    // the lock follows the renumbered descriptor
+    let fd3 = open_file("test.db");
+    assert_eq!(raw_lock_file(fd3, 1, 0, 0), 0);
+    let fd4 = open_file("test.db");
+    assert_eq!(__ic_custom_fd_renumber(fd3, fd4), 0);
+    assert_eq!(raw_test_file_lock(fd2, 0, 0, 0), AGAIN);
+
+    fd_close(fd4);
+    assert_eq!(raw_lock_file(fd2, 1, 0, 0), 0);
+    fd_close(fd2);
+}
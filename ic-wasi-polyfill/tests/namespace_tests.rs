@@ -0,0 +1,184 @@
+#![cfg(feature = "caller_namespaces")]
+
+mod common;
+
+use common::*;
+use ic_wasi_polyfill::wasi;
+use ic_wasi_polyfill::*;
+
+const ROOT_FD: u32 = 3;
+
+const NOENT: i32 = wasi::ERRNO_NOENT.raw() as i32;
+const PERM: i32 = wasi::ERRNO_PERM.raw() as i32;
+const BADF: i32 = wasi::ERRNO_BADF.raw() as i32;
+
+fn open_file(file_name: &str) -> (i32, wasi::Fd) {
+    let mut fd = 0;
+    let ret = unsafe {
+        __ic_custom_path_open(
+            ROOT_FD,
+            0,
+            file_name.as_ptr(),
+            file_name.len() as i32,
+            0,
+            DEFAULT_RIGHTS,
+            DEFAULT_RIGHTS,
+            0,
+            &mut fd,
+        )
+    };
+
+    (ret, fd)
+}
+
+#[test]
+fn test_callers_are_isolated() {
+    init(&[], &[]);
+    enable_caller_namespaces("users");
+
+    set_mock_caller("aaaaa-aa");
+    assert_eq!(get_caller_namespace(), Some("users/aaaaa-aa".to_string()));
+
+    let fd = create_test_file(ROOT_FD, "file.txt");
+    fd_close(fd);
+    assert!(read_directory(ROOT_FD).contains(&"file.txt".to_string()));
+
+    set_mock_caller("2vxsx-fae");
+    assert_eq!(open_file("file.txt").0, NOENT);
+    assert!(!read_directory(ROOT_FD).contains(&"file.txt".to_string()));
+
+    let fd = create_test_file(ROOT_FD, "other.txt");
+    fd_close(fd);
+
+    // switching back rebinds the first caller's directory
+    set_mock_caller("aaaaa-aa");
+    assert_eq!(
+        read_file_to_string("file.txt"),
+        "This is a sample text.1234567890"
+    );
+    assert_eq!(open_file("other.txt").0, NOENT);
+
+    // without the namespaces the whole tree is visible
+    disable_caller_namespaces();
+    assert_eq!(get_caller_namespace(), None);
+    assert_eq!(
+        read_file_to_string("users/aaaaa-aa/file.txt"),
+        "This is a sample text.1234567890"
+    );
+    assert_eq!(
+        read_file_to_string("users/2vxsx-fae/other.txt"),
+        "This is a sample text.1234567890"
+    );
+}
+
+#[test]
+fn test_parent_directory_cannot_escape() {
+    init(&[], &[]);
+
+    let fd = create_test_file(ROOT_FD, "secret.txt");
+    fd_close(fd);
+
+    enable_caller_namespaces("users");
+    set_mock_caller("aaaaa-aa");
+
+    assert_eq!(open_file("../secret.txt").0, PERM);
+    assert_eq!(open_file("../../secret.txt").0, PERM);
+    assert_eq!(open_file("/secret.txt").0, PERM);
+
+    let dir = "dir";
+    let ret = unsafe { __ic_custom_path_create_directory(ROOT_FD, dir.as_ptr(), dir.len() as i32) };
+    assert_eq!(ret, 0);
+    assert_eq!(open_file("dir/../../secret.txt").0, PERM);
+
+    let fd = create_test_file(ROOT_FD, "dir/file.txt");
+    fd_close(fd);
+
+    // going up within the namespace is allowed
+    assert_eq!(
+        read_file_to_string("dir/../dir/file.txt"),
+        "This is a sample text.1234567890"
+    );
+
+    let old_path = "dir/file.txt";
+    let new_path = "../moved.txt";
+    let ret = unsafe {
+        __ic_custom_path_rename(
+            ROOT_FD as i32,
+            old_path.as_ptr(),
+            old_path.len() as i32,
+            ROOT_FD as i32,
+            new_path.as_ptr(),
+            new_path.len() as i32,
+        )
+    };
+    assert_eq!(ret, PERM);
+
+    disable_caller_namespaces();
+}
+
+#[test]
+fn test_root_descriptor_is_remapped() {
+    init(&[], &[]);
+
+    let mut stat = wasi::Fdstat {
+        fs_filetype: wasi::FILETYPE_UNKNOWN,
+        fs_flags: 0,
+        fs_rights_base: 0,
+        fs_rights_inheriting: 0,
+    };
+    assert_eq!(unsafe { __ic_custom_fd_fdstat_get(ROOT_FD, &mut stat) }, 0);
+    let root_flags = stat.fs_flags;
+
+    enable_caller_namespaces("users");
+    set_mock_caller("aaaaa-aa");
+
+    let notsup = wasi::ERRNO_NOTSUP.raw() as i32;
+
+    // the namespace directory cannot be closed or replaced
+    assert_eq!(__ic_custom_fd_close(ROOT_FD), notsup);
+    assert_eq!(__ic_custom_fd_renumber(ROOT_FD, 10), notsup);
+
+    let fd = create_test_file(ROOT_FD, "file.txt");
+    assert_eq!(__ic_custom_fd_renumber(fd, ROOT_FD), notsup);
+    fd_close(fd);
+
+    // the flags are changed and read on the caller's directory
+    let flags = wasi::FDFLAGS_SYNC;
+    assert_eq!(__ic_custom_fd_fdstat_set_flags(ROOT_FD, flags as i32), 0);
+
+    assert_eq!(unsafe { __ic_custom_fd_fdstat_get(ROOT_FD, &mut stat) }, 0);
+    assert_eq!(stat.fs_flags, flags);
+
+    disable_caller_namespaces();
+
+    assert_eq!(unsafe { __ic_custom_fd_fdstat_get(ROOT_FD, &mut stat) }, 0);
+    assert_eq!(stat.fs_flags, root_flags);
+}
+
+#[test]
+fn test_descriptors_of_other_callers_are_bad() {
+    init(&[], &[]);
+    enable_caller_namespaces("users");
+
+    set_mock_caller("aaaaa-aa");
+    let fd = create_test_file(ROOT_FD, "file.txt");
+
+    set_mock_caller("2vxsx-fae");
+    let mut buf = [0u8; 10];
+    let iov = wasi::Iovec {
+        buf: buf.as_mut_ptr(),
+        buf_len: buf.len(),
+    };
+    let mut read = 0;
+    let ret = unsafe { __ic_custom_fd_pread(fd, &iov, 1, 0, &mut read) };
+    assert_eq!(ret, BADF);
+    assert_eq!(__ic_custom_fd_renumber(fd, 100), BADF);
+    assert_eq!(__ic_custom_fd_close(fd), BADF);
+
+    // the owner can still use and close the descriptor
+    set_mock_caller("aaaaa-aa");
+    let ret = unsafe { __ic_custom_fd_pread(fd, &iov, 1, 0, &mut read) };
+    assert_eq!(ret, 0);
+    assert_eq!(&buf[..read], b"This is a ");
+    assert_eq!(__ic_custom_fd_close(fd), 0);
+}
@@ -394,3 +394,53 @@ fn test_path_rename() {
         wasi::path_unlink_file(dir_fd, "source").expect("removing a file");
     }
 }
+
+#[test]
+fn test_invalid_utf8_path() {
+    use ic_wasi_polyfill::*;
+
+    init(&[], &[]);
+
+    let name = b"invalid\xff\xfe.txt";
+    let mut fd = 0;
+    let ret = unsafe {
+        __ic_custom_path_open(
+            3,
+            0,
+            name.as_ptr(),
+            name.len() as i32,
+            wasi::OFLAGS_CREAT as i32,
+            common::DEFAULT_RIGHTS,
+            common::DEFAULT_RIGHTS,
+            0,
+            &mut fd,
+        )
+    };
+
+    if !cfg!(feature = "byte_paths") {
+        assert_eq!(ret, wasi::ERRNO_ILSEQ.raw() as i32);
+
+        let ret = unsafe { __ic_custom_path_create_directory(3, name.as_ptr(), name.len() as i32) };
+        assert_eq!(ret, wasi::ERRNO_ILSEQ.raw() as i32);
+
+        return;
+    }
+
+    // byte paths are stored losslessly and returned verbatim
+    assert_eq!(ret, 0);
+    common::fd_close(fd);
+
+    let mut bytes = vec![0u8; 1000];
+    let mut used = 0;
+    let ret =
+        unsafe { __ic_custom_fd_readdir(3, bytes.as_mut_ptr(), bytes.len() as i32, 0, &mut used) };
+    assert_eq!(ret, 0);
+
+    // skip "." and ".."
+    let entry = wasi_helpers::DIRENT_SIZE * 2 + 1 + 2;
+    let d_namlen = bytes[entry + 16] as usize;
+    let start = entry + wasi_helpers::DIRENT_SIZE;
+
+    assert_eq!(&bytes[start..start + d_namlen], name);
+    assert_eq!(used, start + d_namlen);
+}
@@ -0,0 +1,109 @@
+#![cfg(feature = "permissions")]
+
+mod common;
+
+use common::*;
+use ic_wasi_polyfill::permissions::{MODE_EXECUTE, MODE_READ, MODE_WRITE};
+use ic_wasi_polyfill::wasi;
+use ic_wasi_polyfill::*;
+
+const ROOT_FD: u32 = 3;
+
+const ACCES: i32 = wasi::ERRNO_ACCES.raw() as i32;
+
+const OWNER: &str = "aaaaa-aa";
+const CONTROLLER: &str = "rwlgt-iiaaa-aaaaa-aaaaa-cai";
+const USER: &str = "2vxsx-fae";
+
+fn open(file_name: &str, oflags: wasi::Oflags, rights: wasi::Rights) -> (i32, wasi::Fd) {
+    let mut fd = 0;
+    let ret = unsafe {
+        __ic_custom_path_open(
+            ROOT_FD,
+            0,
+            file_name.as_ptr(),
+            file_name.len() as i32,
+            oflags as i32,
+            rights,
+            rights,
+            0,
+            &mut fd,
+        )
+    };
+
+    (ret, fd)
+}
+
+fn check_open(file_name: &str, oflags: wasi::Oflags, rights: wasi::Rights) -> i32 {
+    let (ret, fd) = open(file_name, oflags, rights);
+    if ret == 0 {
+        fd_close(fd);
+    }
+    ret
+}
+
+#[test]
+fn test_read_only_file() {
+    init(&[], &[]);
+    fd_close(create_test_file(ROOT_FD, "file.txt"));
+
+    assert_eq!(get_file_mode("file.txt"), Ok(None));
+    assert_eq!(check_open("file.txt", 0, DEFAULT_RIGHTS), 0);
+
+    assert_eq!(set_file_mode("file.txt", 0o444), 0);
+    assert_eq!(get_file_mode("file.txt"), Ok(Some(0o444)));
+
+    assert_eq!(check_open("file.txt", 0, wasi::RIGHTS_FD_READ), 0);
+    assert_eq!(check_open("file.txt", 0, DEFAULT_RIGHTS), ACCES);
+    assert_eq!(
+        check_open("file.txt", wasi::OFLAGS_TRUNC, wasi::RIGHTS_FD_READ),
+        ACCES
+    );
+    assert_eq!(check_file_access("file.txt", MODE_READ), 0);
+    assert_eq!(check_file_access("file.txt", MODE_WRITE), ACCES);
+    assert_eq!(check_file_access("file.txt", MODE_EXECUTE), ACCES);
+    assert_eq!(check_file_access("file.txt", 0), 0);
+    assert_eq!(
+        check_file_access("missing.txt", 0),
+        wasi::ERRNO_NOENT.raw() as i32
+    );
+
+    // the mode is kept as a reserved attribute
+    assert_eq!(list_xattrs("file.txt"), Ok(vec![]));
+    assert_eq!(
+        set_xattr("file.txt", "system.mode", &[0xff; 4]),
+        wasi::ERRNO_PERM.raw() as i32
+    );
+
+    assert_eq!(
+        set_file_mode("file.txt", 0o10000),
+        wasi::ERRNO_INVAL.raw() as i32
+    );
+    assert_eq!(set_file_mode("file.txt", 0o644), 0);
+    assert_eq!(check_open("file.txt", 0, DEFAULT_RIGHTS), 0);
+}
+
+#[test]
+fn test_owner_controller_and_others() {
+    init(&[], &[]);
+    set_mock_controllers(&[CONTROLLER]);
+
+    fd_close(create_test_file(ROOT_FD, "file.txt"));
+    assert_eq!(set_file_owner("file.txt", OWNER), 0);
+    assert_eq!(get_file_owner("file.txt"), Ok(Some(OWNER.to_string())));
+    assert_eq!(set_file_mode("file.txt", 0o740), 0);
+
+    set_mock_caller(OWNER);
+    assert_eq!(check_open("file.txt", 0, DEFAULT_RIGHTS), 0);
+    assert_eq!(check_file_access("file.txt", MODE_EXECUTE), 0);
+
+    set_mock_caller(CONTROLLER);
+    assert_eq!(check_open("file.txt", 0, wasi::RIGHTS_FD_READ), 0);
+    assert_eq!(check_open("file.txt", 0, DEFAULT_RIGHTS), ACCES);
+
+    set_mock_caller(USER);
+    assert_eq!(check_open("file.txt", 0, wasi::RIGHTS_FD_READ), ACCES);
+
+    // new files are not restricted
+    assert_eq!(check_open("new.txt", wasi::OFLAGS_CREAT, DEFAULT_RIGHTS), 0);
+}
@@ -0,0 +1,141 @@
+mod common;
+
+use common::*;
+use ic_wasi_polyfill::wasi;
+use ic_wasi_polyfill::*;
+
+const ROOT_FD: u32 = 3;
+
+const ROFS: i32 = wasi::ERRNO_ROFS.raw() as i32;
+
+fn open_file(file_name: &str, oflags: wasi::Oflags) -> (i32, wasi::Fd) {
+    let mut fd = 0;
+    let ret = unsafe {
+        __ic_custom_path_open(
+            ROOT_FD,
+            0,
+            file_name.as_ptr(),
+            file_name.len() as i32,
+            oflags as i32,
+            DEFAULT_RIGHTS,
+            DEFAULT_RIGHTS,
+            0,
+            &mut fd,
+        )
+    };
+
+    (ret, fd)
+}
+
+#[test]
+fn test_writes_rejected_in_query() {
+    init(&[], &[]);
+    set_reject_query_writes(true);
+    assert!(is_rejecting_query_writes());
+
+    // prepare files in an update call
+    let fd = create_test_file(ROOT_FD, "file.txt");
+
+    set_mock_replicated_execution(false);
+
+    let text = "query data";
+    let iov = wasi::Ciovec {
+        buf: text.as_ptr(),
+        buf_len: text.len(),
+    };
+    let mut written = 0;
+    assert_eq!(
+        unsafe { __ic_custom_fd_write(fd, &iov, 1, &mut written) },
+        ROFS
+    );
+    assert_eq!(
+        unsafe { __ic_custom_fd_pwrite(fd, &iov, 1, 0, &mut written) },
+        ROFS
+    );
+    assert_eq!(__ic_custom_fd_filestat_set_size(fd, 0), ROFS);
+    assert_eq!(__ic_custom_fd_filestat_set_times(fd, 0, 0, 0), ROFS);
+
+    // writing to stdout is still allowed
+    assert_eq!(unsafe { __ic_custom_fd_write(1, &iov, 1, &mut written) }, 0);
+
+    assert_eq!(open_file("cache.txt", wasi::OFLAGS_CREAT).0, ROFS);
+    assert_eq!(open_file("file.txt", wasi::OFLAGS_TRUNC).0, ROFS);
+
+    let old_path = "file.txt";
+    let new_path = "renamed.txt";
+    let dir = "dir";
+    unsafe {
+        assert_eq!(
+            __ic_custom_path_rename(
+                ROOT_FD as i32,
+                old_path.as_ptr(),
+                old_path.len() as i32,
+                ROOT_FD as i32,
+                new_path.as_ptr(),
+                new_path.len() as i32,
+            ),
+            ROFS
+        );
+        assert_eq!(
+            __ic_custom_path_unlink_file(ROOT_FD as i32, old_path.as_ptr(), old_path.len() as i32),
+            ROFS
+        );
+        assert_eq!(
+            __ic_custom_path_create_directory(ROOT_FD, dir.as_ptr(), dir.len() as i32),
+            ROFS
+        );
+    }
+
+    // reading is allowed
+    fd_close(fd);
+    let (ret, fd) = open_file("file.txt", 0);
+    assert_eq!(ret, 0);
+    fd_close(fd);
+    assert_eq!(
+        read_file_to_string("file.txt"),
+        "This is a sample text.1234567890"
+    );
+
+    // the same calls succeed in the replicated execution
+    set_mock_replicated_execution(true);
+    let (ret, fd) = open_file("cache.txt", wasi::OFLAGS_CREAT);
+    assert_eq!(ret, 0);
+    assert_eq!(
+        unsafe { __ic_custom_fd_write(fd, &iov, 1, &mut written) },
+        0
+    );
+    fd_close(fd);
+}
+
+#[test]
+fn test_writes_allowed_in_query_when_disabled() {
+    init(&[], &[]);
+    set_reject_query_writes(false);
+    set_mock_replicated_execution(false);
+
+    let (ret, fd) = open_file("cache.txt", wasi::OFLAGS_CREAT);
+    assert_eq!(ret, 0);
+    fd_close(fd);
+
+    set_mock_replicated_execution(true);
+}
+
+#[test]
+fn test_writes_rejected_in_replicated_query() {
+    init(&[], &[]);
+    set_reject_query_writes(true);
+    set_mock_replicated_execution(true);
+
+    // a query method called as an update runs in the replicated mode
+    set_query_call(true);
+
+    let (ret, _) = open_file("cache.txt", wasi::OFLAGS_CREAT);
+    assert_eq!(ret, ROFS);
+
+    // the mark is discarded with the other changes of the query
+    set_query_call(false);
+
+    let (ret, fd) = open_file("cache.txt", wasi::OFLAGS_CREAT);
+    assert_eq!(ret, 0);
+    fd_close(fd);
+}
@@ -0,0 +1,258 @@
+#![cfg(feature = "record_wasi_calls")]
+
+mod common;
+
+use common::*;
+#[cfg(feature = "hooks")]
+use ic_wasi_polyfill::hooks::HookOutcome;
+use ic_wasi_polyfill::recorder::{decode_recording, encode_record, CallRecord};
+use ic_wasi_polyfill::replay::{replay_wasi_calls, replay_wasi_calls_on_current_fs};
+use ic_wasi_polyfill::unsupported::UnsupportedCallPolicy;
+use ic_wasi_polyfill::wasi;
+use ic_wasi_polyfill::*;
+
+const ROOT_FD: u32 = 3;
+
+fn run_workload() {
+    let dir = "dir";
+    let ret = unsafe { __ic_custom_path_create_directory(ROOT_FD, dir.as_ptr(), dir.len() as i32) };
+    assert_eq!(ret, 0);
+
+    let fd = create_test_file(ROOT_FD, "dir/file.txt");
+
+    let mut pos: wasi::Filesize = 0;
+    let ret = unsafe { __ic_custom_fd_seek(fd, 5, wasi::WHENCE_SET.raw() as i32, &mut pos) };
+    assert_eq!(ret, 0);
+
+    let mut buf = [0u8; 10];
+    let iov = wasi::Iovec {
+        buf: buf.as_mut_ptr(),
+        buf_len: buf.len(),
+    };
+    let mut read: wasi::Size = 0;
+    let ret = unsafe { __ic_custom_fd_read(fd, &iov, 1, &mut read) };
+    assert_eq!(ret, 0);
+    fd_close(fd);
+
+    assert_eq!(
+        read_directory(ROOT_FD),
+        vec![".", "..", "dir"],
+        "unexpected root folder"
+    );
+
+    let old_path = "dir/file.txt";
+    let new_path = "renamed.txt";
+    let ret = unsafe {
+        __ic_custom_path_rename(
+            ROOT_FD as i32,
+            old_path.as_ptr(),
+            old_path.len() as i32,
+            ROOT_FD as i32,
+            new_path.as_ptr(),
+            new_path.len() as i32,
+        )
+    };
+    assert_eq!(ret, 0);
+
+    // fails, the file was renamed
+    let ret = unsafe {
+        __ic_custom_path_unlink_file(ROOT_FD as i32, old_path.as_ptr(), old_path.len() as i32)
+    };
+    assert_eq!(ret, wasi::ERRNO_NOENT.raw() as i32);
+}
+
+#[test]
+fn test_record_and_replay() {
+    init(&[], &[]);
+
+    start_recording();
+    run_workload();
+    let log = take_recording();
+
+    let records = decode_recording(&log).unwrap();
+    let functions: Vec<_> = records.iter().map(|r| r.function.as_str()).collect();
+
+    assert_eq!(
+        functions,
+        vec![
+            "path_create_directory",
+            "path_open",
+            "fd_write",
+            "fd_seek",
+            "fd_read",
+            "fd_close",
+            "fd_readdir",
+            "path_rename",
+            "path_unlink_file"
+        ]
+    );
+    assert_eq!(records[4].output, b"is a sampl".to_vec());
+
+    let report = replay_wasi_calls(&log).unwrap();
+
+    assert_eq!(report.calls, records.len());
+    assert!(report.is_consistent(), "{:?}", report.mismatches);
+
+    // the replayed calls produced the same file system state
+    assert_eq!(
+        read_file_to_string("renamed.txt"),
+        "This is a sample text.1234567890"
+    );
+}
+
+#[test]
+fn test_replay_reports_mismatches() {
+    init(&[], &[]);
+
+    start_recording();
+    run_workload();
+    let log = take_recording();
+
+    // pretend the unlink call succeeded in the recorded run
+    let mut records = decode_recording(&log).unwrap();
+    records.last_mut().unwrap().errno = 0;
+
+    let mut tampered = log[0..5].to_vec();
+    for record in records.iter() {
+        encode_record(record, &mut tampered);
+    }
+
+    let report = replay_wasi_calls(&tampered).unwrap();
+
+    assert_eq!(report.mismatches.len(), 1);
+    assert_eq!(report.mismatches[0].function, "path_unlink_file");
+    assert_eq!(report.mismatches[0].index, records.len() - 1);
+    assert_eq!(
+        report.mismatches[0].actual_errno,
+        wasi::ERRNO_NOENT.raw() as i32
+    );
+
+    // replaying the same sequence again on the current state diverges right away
+    let report = replay_wasi_calls_on_current_fs(&log).unwrap();
+    assert_eq!(report.mismatches[0].function, "path_create_directory");
+
+    let unknown = CallRecord {
+        function: "proc_exit".to_string(),
+        ..Default::default()
+    };
+    let mut log = log[0..5].to_vec();
+    encode_record(&unknown, &mut log);
+
+    assert!(replay_wasi_calls(&log).is_err());
+}
+
+#[test]
+fn test_record_and_replay_environment_calls() {
+    init(&[], &[("PATH", "/usr/bin")]);
+    set_unsupported_call_policy(UnsupportedCallPolicy::ReturnError);
+
+    start_recording();
+
+    let mut count: wasi::Size = 0;
+    let mut size: wasi::Size = 0;
+    assert_eq!(
+        unsafe { __ic_custom_environ_sizes_get(&mut count, &mut size) },
+        0
+    );
+
+    let mut entries = vec![std::ptr::null_mut::<u8>(); count];
+    let mut buf = vec![0u8; size];
+    assert_eq!(
+        unsafe { __ic_custom_environ_get(entries.as_mut_ptr(), buf.as_mut_ptr()) },
+        0
+    );
+
+    let mut resolution = 0;
+    assert_eq!(unsafe { __ic_custom_clock_res_get(0, &mut resolution) }, 0);
+
+    let mut time = 0;
+    assert_eq!(unsafe { __ic_custom_clock_time_get(0, 0, &mut time) }, 0);
+
+    let mut random = [0u8; 8];
+    assert_eq!(
+        unsafe { __ic_custom_random_get(random.as_mut_ptr(), random.len()) },
+        0
+    );
+
+    #[cfg(not(feature = "skip_unimplemented_functions"))]
+    {
+        let mut fd = 0;
+        assert_eq!(
+            __ic_custom_sock_accept(ROOT_FD as i32, 0, &mut fd),
+            wasi::ERRNO_NOTSUP.raw() as i32
+        );
+    }
+    assert_eq!(__ic_custom_sched_yield(), 0);
+
+    let log = take_recording();
+
+    let records = decode_recording(&log).unwrap();
+    let functions: Vec<_> = records.iter().map(|r| r.function.as_str()).collect();
+
+    #[allow(unused_mut)]
+    let mut expected = vec![
+        "environ_sizes_get",
+        "environ_get",
+        "clock_res_get",
+        "clock_time_get",
+        "random_get",
+        "sock_accept",
+        "sched_yield",
+    ];
+    #[cfg(feature = "skip_unimplemented_functions")]
+    expected.retain(|function| *function != "sock_accept");
+
+    assert_eq!(functions, expected);
+    assert_eq!(records[1].output, b"PATH=/usr/bin\0".to_vec());
+
+    // the hooks belong to the replaced file system and do not change the replayed calls
+    #[cfg(feature = "hooks")]
+    set_hook("sched_yield", |_| {
+        HookOutcome::Return(wasi::ERRNO_PERM.raw() as i32)
+    });
+
+    let report = replay_wasi_calls(&log).unwrap();
+
+    assert_eq!(report.calls, records.len());
+    assert!(report.is_consistent(), "{:?}", report.mismatches);
+    assert_eq!(__ic_custom_sched_yield(), 0);
+}
+
+#[test]
+fn test_store_recording() {
+    init(&[], &[]);
+
+    start_recording();
+    fd_close(create_test_file(ROOT_FD, "file.txt"));
+
+    assert_eq!(store_recording("recording.bin"), 0);
+
+    let fd = create_test_file(ROOT_FD, "after.txt");
+    fd_close(fd);
+
+    // calls made after storing the recording are not recorded
+    assert!(take_recording().is_empty());
+
+    let file_name = "recording.bin";
+    let mut stat = wasi::Filestat {
+        dev: 0,
+        ino: 0,
+        filetype: wasi::FILETYPE_UNKNOWN,
+        nlink: 0,
+        size: 0,
+        atim: 0,
+        mtim: 0,
+        ctim: 0,
+    };
+    let ret = unsafe {
+        __ic_custom_path_filestat_get(
+            ROOT_FD as i32,
+            0,
+            file_name.as_ptr(),
+            file_name.len() as i32,
+            &mut stat,
+        )
+    };
+    assert_eq!(ret, 0);
+    assert!(stat.size > 5);
+}
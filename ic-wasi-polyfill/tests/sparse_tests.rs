@@ -0,0 +1,177 @@
+#![cfg(feature = "sparse_files")]
+
+mod common;
+
+use common::*;
+use ic_stable_structures::DefaultMemoryImpl;
+#[cfg(feature = "cache")]
+use ic_wasi_polyfill::cache::CACHE_BLOCK_SIZE;
+use ic_wasi_polyfill::transform::BLOCK_SIZE;
+use ic_wasi_polyfill::wasi;
+use ic_wasi_polyfill::*;
+
+const ROOT_FD: u32 = 3;
+
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8 + 1).collect()
+}
+
+fn allocation(path: &str) -> (u64, u64) {
+    let allocation = get_file_allocation(path).unwrap();
+    (allocation.logical_size, allocation.allocated_size)
+}
+
+#[test]
+fn test_punch_hole() {
+    init_with_all_memories(DefaultMemoryImpl::default());
+
+    let fd = open_with("log.bin", wasi::OFLAGS_CREAT, DEFAULT_RIGHTS, 0);
+    let mut data = pattern(4 * BLOCK_SIZE);
+    pwrite(fd, 0, &data);
+
+    let (logical, allocated) = allocation("log.bin");
+    assert_eq!(logical, data.len() as u64);
+    assert!(allocated >= logical);
+
+    // the blocks within the range are released, the blocks at its ends are cleared
+    assert_eq!(punch_hole(fd, 10_000, 40_000), 0);
+    data[10_000..50_000].fill(0);
+
+    assert_eq!(pread(fd, 0, data.len() + 10), data);
+    assert_eq!(allocation("log.bin"), (logical, 2 * BLOCK_SIZE as u64));
+
+    // writing into the hole allocates its block again
+    pwrite(fd, 20_000, b"back");
+    data[20_000..20_004].copy_from_slice(b"back");
+    assert_eq!(allocation("log.bin"), (logical, 3 * BLOCK_SIZE as u64));
+
+    // the range above the end of the file is ignored, the size is kept
+    assert_eq!(punch_hole(fd, 0, u64::MAX), 0);
+    assert_eq!(allocation("log.bin"), (logical, 0));
+    assert_eq!(pread(fd, 0, data.len() + 10), vec![0u8; data.len()]);
+
+    pwrite(fd, logical, b"appended");
+    assert_eq!(pread(fd, logical, 100), b"appended");
+    assert_eq!(allocation("log.bin"), (logical + 8, BLOCK_SIZE as u64));
+
+    fd_close(fd);
+}
+
+#[test]
+fn test_punch_hole_releases_plain_file_data() {
+    init_with_all_memories(DefaultMemoryImpl::default());
+
+    let fd = open_with("big.bin", wasi::OFLAGS_CREAT, DEFAULT_RIGHTS, 0);
+    let mut data = pattern(8 * BLOCK_SIZE);
+    pwrite(fd, 0, &data);
+    assert_eq!(
+        allocation("big.bin"),
+        (data.len() as u64, data.len() as u64)
+    );
+
+    // the first hole moves the file into blocks, the head of the file is released
+    assert_eq!(punch_hole(fd, 0, 2 * BLOCK_SIZE as u64), 0);
+    data[..2 * BLOCK_SIZE].fill(0);
+    assert_eq!(pread(fd, 0, data.len()), data);
+    assert_eq!(
+        allocation("big.bin"),
+        (data.len() as u64, 6 * BLOCK_SIZE as u64)
+    );
+
+    // a hole in the middle releases the blocks within it
+    assert_eq!(
+        punch_hole(fd, 3 * BLOCK_SIZE as u64 - 5, 2 * BLOCK_SIZE as u64 + 10),
+        0
+    );
+    data[3 * BLOCK_SIZE - 5..5 * BLOCK_SIZE + 5].fill(0);
+    assert_eq!(pread(fd, 0, data.len()), data);
+    assert_eq!(
+        allocation("big.bin"),
+        (data.len() as u64, 4 * BLOCK_SIZE as u64)
+    );
+
+    // the blocks are encrypted like those of the other files
+    #[cfg(feature = "encryption")]
+    {
+        set_encryption_key(1, &[7; 32]);
+        assert_eq!(set_file_encryption("big.bin", true), 0);
+        assert_eq!(
+            allocation("big.bin"),
+            (data.len() as u64, 4 * BLOCK_SIZE as u64)
+        );
+        assert_eq!(pread(fd, 0, data.len()), data);
+    }
+
+    fd_close(fd);
+}
+
+#[test]
+fn test_punch_hole_needs_stable_blocks() {
+    // the blocks of a stable file system without a polyfill memory would be lost on upgrade
+    init_with_memory(&[], &[], DefaultMemoryImpl::default());
+
+    let fd = open_with("file.bin", wasi::OFLAGS_CREAT, DEFAULT_RIGHTS, 0);
+    pwrite(fd, 0, &pattern(2 * BLOCK_SIZE));
+
+    assert_eq!(
+        punch_hole(fd, 0, BLOCK_SIZE as u64),
+        wasi::ERRNO_NOTSUP.raw() as i32
+    );
+    assert_eq!(pread(fd, 0, 2 * BLOCK_SIZE), pattern(2 * BLOCK_SIZE));
+
+    fd_close(fd);
+}
+
+#[cfg(feature = "cache")]
+#[test]
+fn test_punch_hole_through_transforms_and_cache() {
+    init_with_all_memories(DefaultMemoryImpl::default());
+    set_cache_budget(16 * CACHE_BLOCK_SIZE);
+
+    let fd = open_with("data.txt", wasi::OFLAGS_CREAT, DEFAULT_RIGHTS, 0);
+    let mut data = b"compressible line\n".repeat(3000);
+    pwrite(fd, 0, &data);
+    #[cfg(feature = "compression")]
+    assert_eq!(set_file_compression("data.txt", true), 0);
+
+    // the cached writes are stored before the hole is punched
+    pwrite(fd, 100, b"cached");
+    data[100..106].copy_from_slice(b"cached");
+    assert!(get_cache_stats().dirty_bytes > 0);
+
+    assert_eq!(punch_hole(fd, 103, BLOCK_SIZE as u64), 0);
+    data[103..103 + BLOCK_SIZE].fill(0);
+
+    assert_eq!(get_cache_stats().dirty_bytes, 0);
+    #[cfg(feature = "compression")]
+    assert_eq!(is_file_compressed("data.txt"), Ok(true));
+    assert_eq!(pread(fd, 0, data.len()), data);
+
+    fd_close(fd);
+}
+
+#[test]
+fn test_punch_hole_errors() {
+    init_with_all_memories(DefaultMemoryImpl::default());
+
+    let fd = open_with("file.bin", wasi::OFLAGS_CREAT, DEFAULT_RIGHTS, 0);
+    pwrite(fd, 0, &pattern(100));
+
+    let read_only = open_with("file.bin", wasi::OFLAGS_CREAT, wasi::RIGHTS_FD_READ, 0);
+    assert_eq!(punch_hole(read_only, 0, 10), wasi::ERRNO_BADF.raw() as i32);
+    assert_eq!(punch_hole(ROOT_FD, 0, 10), wasi::ERRNO_INVAL.raw() as i32);
+    assert_eq!(punch_hole(1000, 0, 10), wasi::ERRNO_BADF.raw() as i32);
+
+    // an empty range does not change the file
+    assert_eq!(punch_hole(fd, 50, 0), 0);
+    assert_eq!(pread(fd, 0, 200), pattern(100));
+
+    assert_eq!(
+        get_file_allocation("missing.bin"),
+        Err(wasi::ERRNO_NOENT.raw() as i32)
+    );
+
+    for fd in [fd, read_only] {
+        fd_close(fd);
+    }
+}
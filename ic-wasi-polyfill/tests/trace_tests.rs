@@ -0,0 +1,112 @@
+#![cfg(feature = "trace_wasi_calls")]
+
+mod common;
+
+use common::*;
+#[cfg(feature = "hooks")]
+use ic_wasi_polyfill::hooks::HookOutcome;
+use ic_wasi_polyfill::tracer::TraceFilter;
+use ic_wasi_polyfill::wasi;
+use ic_wasi_polyfill::*;
+
+#[test]
+fn test_trace_records_calls() {
+    init(&[], &[]);
+    clear_trace();
+
+    let root_fd = 3;
+    let fd = create_test_file(root_fd, "trace.txt");
+    fd_close(fd);
+
+    let records = get_trace_records();
+    let functions: Vec<_> = records.iter().map(|r| r.function).collect();
+
+    assert_eq!(functions, vec!["path_open", "fd_write", "fd_close"]);
+
+    assert_eq!(records[0].path.as_deref(), Some("trace.txt"));
+    assert_eq!(records[0].fd, Some(root_fd));
+    assert_eq!(records[1].fd, Some(fd));
+    assert_eq!(records[1].errno, 0);
+
+    // closing a second time fails with a bad descriptor
+    fd_close(fd);
+
+    let records = take_trace_records();
+    assert_eq!(records.last().unwrap().errno, wasi::ERRNO_BADF.raw() as i32);
+    assert!(get_trace_records().is_empty());
+}
+
+#[test]
+fn test_trace_filter_and_capacity() {
+    init(&[], &[]);
+    clear_trace();
+
+    set_trace_filter(TraceFilter {
+        functions: vec![],
+        fd: None,
+        path_prefix: Some("logs/".to_string()),
+    });
+
+    let root_fd = 3;
+    let dir = "logs";
+    unsafe { __ic_custom_path_create_directory(root_fd, dir.as_ptr(), dir.len() as i32) };
+
+    fd_close(create_test_file(root_fd, "logs/a.txt"));
+    fd_close(create_test_file(root_fd, "other.txt"));
+    fd_close(create_test_file(root_fd, "logs/b.txt"));
+
+    let paths: Vec<_> = get_trace_records()
+        .into_iter()
+        .map(|r| r.path.unwrap())
+        .collect();
+    assert_eq!(paths, vec!["logs/a.txt", "logs/b.txt"]);
+
+    set_trace_filter(TraceFilter::default());
+    set_trace_capacity(2);
+
+    fd_close(create_test_file(root_fd, "c.txt"));
+
+    let functions: Vec<_> = get_trace_records().iter().map(|r| r.function).collect();
+    assert_eq!(functions, vec!["fd_write", "fd_close"]);
+
+    set_tracing_enabled(false);
+    clear_trace();
+    fd_close(create_test_file(root_fd, "d.txt"));
+    assert!(get_trace_records().is_empty());
+}
+
+#[test]
+fn test_trace_remaining_calls() {
+    init(&[], &[]);
+    clear_trace();
+
+    assert_eq!(__ic_custom_sched_yield(), 0);
+
+    let mut resolution = 0;
+    unsafe { __ic_custom_clock_res_get(0, &mut resolution) };
+
+    let records = take_trace_records();
+    let functions: Vec<_> = records.iter().map(|r| r.function).collect();
+    assert_eq!(functions, vec!["sched_yield", "clock_res_get"]);
+}
+
+#[test]
+#[cfg(feature = "hooks")]
+fn test_trace_hooked_calls() {
+    init(&[], &[]);
+    clear_trace();
+
+    set_hook("fd_close", |_| {
+        HookOutcome::Return(wasi::ERRNO_PERM.raw() as i32)
+    });
+
+    let ret = __ic_custom_fd_close(3);
+    assert_eq!(ret, wasi::ERRNO_PERM.raw() as i32);
+
+    clear_hooks();
+
+    let records = take_trace_records();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].function, "fd_close");
+    assert_eq!(records[0].errno, wasi::ERRNO_PERM.raw() as i32);
+}
@@ -0,0 +1,156 @@
+#![cfg(feature = "transactions")]
+
+mod common;
+
+use common::*;
+use ic_stable_structures::DefaultMemoryImpl;
+#[cfg(feature = "hooks")]
+use ic_wasi_polyfill::hooks::HookOutcome;
+use ic_wasi_polyfill::transaction::BACKUP_DIR;
+use ic_wasi_polyfill::wasi;
+use ic_wasi_polyfill::*;
+
+const ROOT_FD: u32 = 3;
+
+fn exists(path: &str) -> bool {
+    FS.with_borrow_mut(|fs| {
+        let root_fd = fs.root_fd();
+        fs.open_metadata(root_fd, path).is_ok()
+    })
+}
+
+#[test]
+fn test_commit_applies_all_changes() {
+    init(&[], &[]);
+
+    create_file("config.txt", "version 1");
+    create_file("old.txt", "old");
+    create_file("draft.txt", "draft");
+
+    let id = begin_transaction().unwrap();
+    assert_eq!(open_transactions(), vec![id]);
+
+    assert_eq!(transaction_write(id, "data/new.txt", 0, b"new"), 0);
+    assert_eq!(transaction_write(id, "config.txt", 8, b"2"), 0);
+    assert_eq!(transaction_rename(id, "draft.txt", "final.txt"), 0);
+    assert_eq!(transaction_delete(id, "old.txt"), 0);
+
+    // the staged changes are not visible before the commit
+    assert_eq!(content("config.txt"), b"version 1");
+    assert!(exists("old.txt"));
+
+    // the parent directory is missing, the applied changes are reverted
+    assert_eq!(commit_transaction(id), wasi::ERRNO_NOENT.raw() as i32);
+    assert_eq!(content("config.txt"), b"version 1");
+    assert!(exists("draft.txt") && !exists("final.txt"));
+    assert!(open_transactions().is_empty());
+
+    let dir = "data";
+    assert_eq!(
+        unsafe { __ic_custom_path_create_directory(ROOT_FD, dir.as_ptr(), dir.len() as i32) },
+        0
+    );
+
+    let id = begin_transaction().unwrap();
+    assert_eq!(transaction_write(id, "config.txt", 8, b"2"), 0);
+    assert_eq!(transaction_write(id, "data/new.txt", 0, b"new"), 0);
+    assert_eq!(transaction_rename(id, "draft.txt", "final.txt"), 0);
+    assert_eq!(transaction_delete(id, "old.txt"), 0);
+    assert_eq!(commit_transaction(id), 0);
+
+    assert_eq!(content("config.txt"), b"version 2");
+    assert_eq!(content("data/new.txt"), b"new");
+    assert_eq!(content("final.txt"), b"draft");
+    assert!(!exists("draft.txt") && !exists("old.txt"));
+
+    // the kept files are removed with their directory
+    assert!(!exists(BACKUP_DIR));
+
+    assert_eq!(commit_transaction(id), wasi::ERRNO_INVAL.raw() as i32);
+}
+
+#[test]
+fn test_failed_commit_restores_replaced_files() {
+    init(&[], &[]);
+
+    create_file("a.txt", "aaa");
+    create_file("b.txt", "bbb");
+    create_file("c.txt", "ccc");
+
+    let id = begin_transaction().unwrap();
+    assert_eq!(transaction_rename(id, "a.txt", "b.txt"), 0);
+    assert_eq!(transaction_delete(id, "c.txt"), 0);
+    assert_eq!(transaction_write(id, "c.txt", 0, b"c"), 0);
+    assert_eq!(transaction_write(id, "b.txt", 10, b"tail"), 0);
+    assert_eq!(transaction_delete(id, "missing.txt"), 0);
+
+    assert_eq!(commit_transaction(id), wasi::ERRNO_NOENT.raw() as i32);
+
+    assert_eq!(content("a.txt"), b"aaa");
+    assert_eq!(content("b.txt"), b"bbb");
+    assert_eq!(content("c.txt"), b"ccc");
+    assert!(!exists(BACKUP_DIR));
+}
+
+#[cfg(feature = "hooks")]
+#[test]
+#[should_panic(expected = "could not be reverted")]
+fn test_failed_revert_traps() {
+    init(&[], &[]);
+
+    create_file("a.txt", "aaa");
+
+    let id = begin_transaction().unwrap();
+    assert_eq!(transaction_rename(id, "a.txt", "b.txt"), 0);
+    assert_eq!(transaction_delete(id, "missing.txt"), 0);
+
+    // the rename is applied, renaming the file back fails
+    let mut renames = 0;
+    set_hook("path_rename", move |_| {
+        renames += 1;
+
+        if renames > 1 {
+            HookOutcome::Return(wasi::ERRNO_IO.raw() as i32)
+        } else {
+            HookOutcome::Continue
+        }
+    });
+
+    commit_transaction(id);
+}
+
+#[test]
+fn test_rollback_and_recovery() {
+    let memory = DefaultMemoryImpl::default();
+    init_with_all_memories(memory.clone());
+
+    create_file("file.txt", "before");
+
+    let discarded = begin_transaction().unwrap();
+    assert_eq!(transaction_write(discarded, "file.txt", 0, b"after!"), 0);
+    assert_eq!(rollback_transaction(discarded), 0);
+    assert_eq!(
+        transaction_write(discarded, "file.txt", 0, b"x"),
+        wasi::ERRNO_INVAL.raw() as i32
+    );
+    assert_eq!(content("file.txt"), b"before");
+
+    let id = begin_transaction().unwrap();
+    assert_eq!(transaction_write(id, "file.txt", 0, b"after!"), 0);
+
+    // the journals are not in the file system
+    assert!(!exists(BACKUP_DIR));
+
+    // another file system has its own journals
+    init_with_all_memories(DefaultMemoryImpl::default());
+    assert!(open_transactions().is_empty());
+
+    // the open transactions are reloaded after an upgrade
+    init_with_all_memories(memory);
+
+    assert_eq!(open_transactions(), vec![id]);
+    assert!(begin_transaction().unwrap() > id);
+
+    assert_eq!(commit_transaction(id), 0);
+    assert_eq!(content("file.txt"), b"after!");
+}
@@ -0,0 +1,174 @@
+#![cfg(feature = "watches")]
+
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use common::*;
+use ic_wasi_polyfill::wasi;
+use ic_wasi_polyfill::watch::*;
+use ic_wasi_polyfill::*;
+
+const ROOT_FD: u32 = 3;
+
+fn write(fd: wasi::Fd, data: &[u8]) {
+    let src = [wasi::Ciovec {
+        buf: data.as_ptr(),
+        buf_len: data.len(),
+    }];
+    let mut written = 0;
+
+    assert_eq!(
+        unsafe { __ic_custom_fd_write(fd, src.as_ptr(), 1, &mut written) },
+        0
+    );
+}
+
+fn create_dir(path: &str) {
+    assert_eq!(
+        unsafe { __ic_custom_path_create_directory(ROOT_FD, path.as_ptr(), path.len() as i32) },
+        0
+    );
+}
+
+fn rename(from: &str, to: &str) {
+    assert_eq!(
+        unsafe {
+            __ic_custom_path_rename(
+                ROOT_FD as i32,
+                from.as_ptr(),
+                from.len() as i32,
+                ROOT_FD as i32,
+                to.as_ptr(),
+                to.len() as i32,
+            )
+        },
+        0
+    );
+}
+
+fn unlink(path: &str) {
+    assert_eq!(
+        unsafe { __ic_custom_path_unlink_file(ROOT_FD as i32, path.as_ptr(), path.len() as i32) },
+        0
+    );
+}
+
+fn file_size(path: &str) -> u64 {
+    let mut stat = wasi::Filestat {
+        dev: 0,
+        ino: 0,
+        filetype: wasi::FILETYPE_UNKNOWN,
+        nlink: 0,
+        size: 0,
+        atim: 0,
+        mtim: 0,
+        ctim: 0,
+    };
+
+    assert_eq!(
+        unsafe {
+            __ic_custom_path_filestat_get(
+                ROOT_FD as i32,
+                0,
+                path.as_ptr(),
+                path.len() as i32,
+                &mut stat,
+            )
+        },
+        0
+    );
+
+    stat.size
+}
+
+fn event(kind: WatchEventKind, path: &str) -> WatchEvent {
+    WatchEvent::new(kind, path.to_string(), false)
+}
+
+#[test]
+fn test_queued_events() {
+    init(&[], &[]);
+
+    create_dir("docs");
+    let id = add_watch_queue("docs", WATCH_ALL);
+
+    let fd = create_test_file(ROOT_FD, "docs/a.txt");
+    write(fd, b"hello");
+    write(fd, b" world");
+    fd_close(fd);
+
+    // the files outside of the prefix are not reported
+    fd_close(create_test_file(ROOT_FD, "other.txt"));
+    create_dir("docs/sub");
+
+    rename("docs/a.txt", "docs/b.txt");
+    rename("other.txt", "docs/c.txt");
+    unlink("docs/b.txt");
+
+    assert_eq!(
+        take_watch_events(id),
+        Ok(vec![
+            event(WatchEventKind::Create, "docs/a.txt"),
+            event(WatchEventKind::Modify, "docs/a.txt"),
+            event(WatchEventKind::CloseWrite, "docs/a.txt"),
+            WatchEvent::new(WatchEventKind::Create, "docs/sub".to_string(), true),
+            WatchEvent::rename("docs/a.txt".to_string(), "docs/b.txt".to_string(), false),
+            WatchEvent::rename("other.txt".to_string(), "docs/c.txt".to_string(), false),
+            event(WatchEventKind::Delete, "docs/b.txt"),
+        ])
+    );
+    assert_eq!(take_watch_events(id), Ok(vec![]));
+
+    // opening without changes is not reported
+    let fd = open("docs/c.txt");
+    write(fd, b"");
+    fd_close(fd);
+    assert_eq!(take_watch_events(id), Ok(vec![]));
+
+    assert_eq!(remove_watch(id), 0);
+    assert_eq!(take_watch_events(id), Err(wasi::ERRNO_INVAL.raw() as i32));
+    assert_eq!(remove_watch(id), wasi::ERRNO_INVAL.raw() as i32);
+}
+
+#[test]
+fn test_callback_events() {
+    init(&[], &[]);
+
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let sizes = Rc::new(RefCell::new(Vec::new()));
+
+    let (events_ref, sizes_ref) = (events.clone(), sizes.clone());
+    let id = add_watch("", WATCH_CLOSE_WRITE | WATCH_DELETE, move |event| {
+        events_ref.borrow_mut().push(event.clone());
+
+        // the callback can use the file system, its own changes are not reported to it
+        if event.kind == WatchEventKind::CloseWrite {
+            sizes_ref.borrow_mut().push(file_size(&event.path));
+            fd_close(create_test_file(ROOT_FD, "index.txt"));
+        }
+    });
+
+    let fd = create_test_file_with_content(ROOT_FD, "file.txt", vec![]);
+    write(fd, b"12345");
+    assert!(events.borrow().is_empty());
+    fd_close(fd);
+
+    unlink("file.txt");
+
+    assert_eq!(
+        *events.borrow(),
+        vec![
+            event(WatchEventKind::CloseWrite, "file.txt"),
+            event(WatchEventKind::Delete, "file.txt"),
+        ]
+    );
+    assert_eq!(*sizes.borrow(), vec![5]);
+
+    assert_eq!(take_watch_events(id), Err(wasi::ERRNO_INVAL.raw() as i32));
+    assert_eq!(remove_watch(id), 0);
+
+    fd_close(create_test_file(ROOT_FD, "file.txt"));
+    assert_eq!(events.borrow().len(), 2);
+}
@@ -0,0 +1,149 @@
+#![cfg(feature = "xattrs")]
+
+mod common;
+
+use common::*;
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+use ic_stable_structures::{DefaultMemoryImpl, Memory};
+use ic_wasi_polyfill::memories::DEFAULT_POLYFILL_MEMORY_INDEX_RANGE;
+use ic_wasi_polyfill::wasi;
+use ic_wasi_polyfill::*;
+
+const ROOT_FD: u32 = 3;
+
+const NOENT: i32 = wasi::ERRNO_NOENT.raw() as i32;
+
+fn rename(old_path: &str, new_path: &str) -> i32 {
+    unsafe {
+        __ic_custom_path_rename(
+            ROOT_FD as i32,
+            old_path.as_ptr(),
+            old_path.len() as i32,
+            ROOT_FD as i32,
+            new_path.as_ptr(),
+            new_path.len() as i32,
+        )
+    }
+}
+
+fn unlink(path: &str) -> i32 {
+    unsafe { __ic_custom_path_unlink_file(ROOT_FD as i32, path.as_ptr(), path.len() as i32) }
+}
+
+#[test]
+fn test_set_get_list_remove() {
+    init(&[], &[]);
+
+    fd_close(create_test_file(ROOT_FD, "file.txt"));
+    let dir = "dir";
+    assert_eq!(
+        unsafe { __ic_custom_path_create_directory(ROOT_FD, dir.as_ptr(), dir.len() as i32) },
+        0
+    );
+
+    assert_eq!(set_xattr("file.txt", "user.mime", b"text/plain"), 0);
+    assert_eq!(set_xattr("file.txt", "user.owner", b"aaaaa-aa"), 0);
+    assert_eq!(set_xattr("dir", "user.mime", b"inode/directory"), 0);
+
+    assert_eq!(
+        get_xattr("file.txt", "user.mime"),
+        Ok(b"text/plain".to_vec())
+    );
+    assert_eq!(get_xattr("file.txt", "user.checksum"), Err(NOENT));
+    assert_eq!(
+        list_xattrs("file.txt"),
+        Ok(vec!["user.mime".to_string(), "user.owner".to_string()])
+    );
+    assert_eq!(list_xattrs("dir"), Ok(vec!["user.mime".to_string()]));
+
+    assert_eq!(remove_xattr("file.txt", "user.owner"), 0);
+    assert_eq!(remove_xattr("file.txt", "user.owner"), NOENT);
+    assert_eq!(list_xattrs("file.txt"), Ok(vec!["user.mime".to_string()]));
+
+    assert_eq!(set_xattr("missing.txt", "user.mime", b""), NOENT);
+    assert_eq!(
+        set_xattr("file.txt", "", b""),
+        wasi::ERRNO_INVAL.raw() as i32
+    );
+    assert_eq!(
+        set_xattr("file.txt", &"a".repeat(256), b""),
+        wasi::ERRNO_NAMETOOLONG.raw() as i32
+    );
+    assert_eq!(
+        set_xattr("file.txt", "user.big", &vec![0u8; 65537]),
+        wasi::ERRNO_2BIG.raw() as i32
+    );
+}
+
+#[test]
+fn test_rename_and_unlink() {
+    init(&[], &[]);
+
+    fd_close(create_test_file(ROOT_FD, "file.txt"));
+    fd_close(create_test_file(ROOT_FD, "other.txt"));
+    assert_eq!(set_xattr("file.txt", "user.mime", b"text/plain"), 0);
+    assert_eq!(set_xattr("other.txt", "user.mime", b"text/csv"), 0);
+
+    // the attributes move with the file, the replaced file's attributes are deleted
+    assert_eq!(rename("file.txt", "other.txt"), 0);
+    assert_eq!(
+        get_xattr("other.txt", "user.mime"),
+        Ok(b"text/plain".to_vec())
+    );
+
+    fd_close(create_test_file(ROOT_FD, "new.txt"));
+    assert_eq!(list_xattrs("new.txt"), Ok(vec![]));
+
+    assert_eq!(unlink("other.txt"), 0);
+    fd_close(create_test_file(ROOT_FD, "other.txt"));
+    assert_eq!(list_xattrs("other.txt"), Ok(vec![]));
+    assert_eq!(list_xattrs("new.txt"), Ok(vec![]));
+}
+
+#[test]
+fn test_xattrs_in_stable_memory() {
+    init(&[], &[]);
+
+    let memory = DefaultMemoryImpl::default();
+    init_xattrs_with_memory(memory.clone());
+
+    fd_close(create_test_file(ROOT_FD, "file.txt"));
+    assert_eq!(set_xattr("file.txt", "user.checksum", b"1234"), 0);
+
+    // loading the memory again, as after an upgrade
+    init_xattrs_with_memory(memory);
+    assert_eq!(get_xattr("file.txt", "user.checksum"), Ok(b"1234".to_vec()));
+}
+
+#[test]
+fn test_xattrs_kept_in_polyfill_memories() {
+    let memory = DefaultMemoryImpl::default();
+    init_with_all_memories(memory.clone());
+
+    fd_close(create_test_file(ROOT_FD, "file.txt"));
+    assert_eq!(set_xattr("file.txt", "user.checksum", b"1234"), 0);
+
+    // another file system forgets the attributes
+    init_with_all_memories(DefaultMemoryImpl::default());
+    fd_close(create_test_file(ROOT_FD, "file.txt"));
+    assert_eq!(get_xattr("file.txt", "user.checksum"), Err(NOENT));
+
+    // loading the memory again, as after an upgrade
+    init_with_all_memories(memory);
+    assert_eq!(get_xattr("file.txt", "user.checksum"), Ok(b"1234".to_vec()));
+}
+
+#[test]
+fn test_init_with_memory_keeps_xattrs_on_heap() {
+    let memory = DefaultMemoryImpl::default();
+    init_with_memory(&[], &[], memory.clone());
+
+    fd_close(create_test_file(ROOT_FD, "file.txt"));
+    assert_eq!(set_xattr("file.txt", "user.checksum", b"1234"), 0);
+
+    // the memory indices after the file system range are left to the application
+    let memory_manager = MemoryManager::init(memory);
+    for id in DEFAULT_POLYFILL_MEMORY_INDEX_RANGE {
+        assert_eq!(memory_manager.get(MemoryId::new(id)).size(), 0);
+    }
+}
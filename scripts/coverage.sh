@@ -1,4 +1,4 @@
 #!/bin/bash
 
 
-cargo llvm-cov --features report_wasi_calls,transient,skip_unimplemented_functions --ignore-filename-regex='(wasi_mock\.rs|bindings|canisters)' --workspace --html
\ No newline at end of file
+cargo llvm-cov --features report_wasi_calls,trace_wasi_calls,record_wasi_calls,transient,skip_unimplemented_functions --ignore-filename-regex='(wasi_mock\.rs|bindings|canisters)' --workspace --html
\ No newline at end of file